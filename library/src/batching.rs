@@ -0,0 +1,256 @@
+/*!
+Helper for sending many small messages as a single data channel message.
+
+Real-time applications often produce lots of small state updates, e.g. every frame.
+Sending each of them separately adds per-message overhead, so [`MessageBatcher`] collects them
+and sends them all at once when [`MessageBatcher::flush`] is called,
+or periodically after [`MessageBatcher::start_timer`]. Receiving side recovers individual messages
+with [`split_batch`].
+
+When only the latest of some messages matters, e.g. position updates of the same entity,
+[`MessageBatcher::set_coalesce_key`] makes each queued message replace the earlier one with the same key,
+so updates that outpace the flushes don't waste bandwidth.
+
+# Example
+
+```no_run
+use wasm_peers::batching::{split_batch, MessageBatcher};
+use wasm_peers::one_to_one::NetworkManager;
+use wasm_peers::{ConnectionType, SessionId};
+
+let mut network_manager = NetworkManager::new(
+    "ws://0.0.0.0:9001/one-to-one",
+    SessionId::new("some-session-id".to_string()),
+    ConnectionType::Local,
+)
+.unwrap();
+let network_manager_clone = network_manager.clone();
+let batcher = MessageBatcher::new(move |batch| network_manager_clone.send_message(batch));
+let batcher_clone = batcher.clone();
+let on_open = move || batcher_clone.start_timer(16).unwrap();
+let on_message = |batch: String| {
+    for message in split_batch(&batch).unwrap() {
+        // handle each message separately
+    }
+};
+network_manager.start(on_open, on_message).unwrap();
+
+batcher.queue("player moved");
+batcher.queue("player jumped");
+```
+*/
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+
+use crate::utils::global_function;
+
+type SendFunction = Rc<dyn Fn(&str) -> Result<(), JsValue>>;
+type KeyFunction = Rc<dyn Fn(&str) -> Option<String>>;
+
+struct MessageBatcherInner {
+    send: SendFunction,
+    coalesce_key: Option<KeyFunction>,
+    /// Queued messages with their coalescing keys.
+    messages: Vec<(Option<String>, String)>,
+    timer: Option<(JsValue, Closure<dyn FnMut()>)>,
+}
+
+/// Collects messages and sends them as a single batch using provided send function.
+///
+/// This class is a pointer to the underlying resource and can be cloned freely.
+#[derive(Clone)]
+pub struct MessageBatcher {
+    inner: Rc<RefCell<MessageBatcherInner>>,
+}
+
+impl MessageBatcher {
+    /// Creates a batcher sending its batches with given function,
+    /// e.g. [`crate::one_to_one::NetworkManager::send_message`].
+    pub fn new(send: impl Fn(&str) -> Result<(), JsValue> + 'static) -> Self {
+        MessageBatcher {
+            inner: Rc::new(RefCell::new(MessageBatcherInner {
+                send: Rc::new(send),
+                coalesce_key: None,
+                messages: Vec::new(),
+                timer: None,
+            })),
+        }
+    }
+
+    /// Sets the function assigning keys to queued messages, e.g. id of the entity they update.
+    /// Message with a key replaces the one with the same key queued before it,
+    /// so only the latest of them is sent, after the other queued messages.
+    /// Messages without a key are always sent. Applies to messages queued from now on.
+    pub fn set_coalesce_key(&self, coalesce_key: impl Fn(&str) -> Option<String> + 'static) {
+        self.inner.borrow_mut().coalesce_key = Some(Rc::new(coalesce_key));
+    }
+
+    /// Adds message to the next batch.
+    pub fn queue(&self, message: &str) {
+        let coalesce_key = self.inner.borrow().coalesce_key.clone();
+        let key = coalesce_key.and_then(|coalesce_key| coalesce_key(message));
+        let mut inner = self.inner.borrow_mut();
+        if key.is_some() {
+            inner.messages.retain(|(queued_key, _)| *queued_key != key);
+        }
+        inner.messages.push((key, message.to_string()));
+    }
+
+    /// Number of messages waiting for the next flush.
+    pub fn queued_count(&self) -> usize {
+        self.inner.borrow().messages.len()
+    }
+
+    /// Sends all queued messages as a single batch. Does nothing if there are none.
+    ///
+    /// # Errors
+    /// This function errors if sending the batch fails, in which case the messages are dropped.
+    pub fn flush(&self) -> Result<(), JsValue> {
+        let messages = std::mem::take(&mut self.inner.borrow_mut().messages);
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let messages: Vec<_> = messages
+            .into_iter()
+            .map(|(_key, message)| message)
+            .collect();
+        let send = self.inner.borrow().send.clone();
+        send(&encode_batch(&messages))
+    }
+
+    /// Flushes the batcher every `interval_ms` milliseconds, until [`MessageBatcher::stop_timer`] is called.
+    /// Replaces previously started timer.
+    /// `setInterval` is taken from the global object, so it works both in window and in workers.
+    ///
+    /// # Errors
+    /// This function errors if the timer can't be set.
+    pub fn start_timer(&self, interval_ms: u32) -> Result<(), JsValue> {
+        self.stop_timer()?;
+        let batcher = self.clone();
+        let on_interval = Closure::wrap(Box::new(move || {
+            batcher
+                .flush()
+                .unwrap_or_else(|error| log::error!("failed to flush batch: {:?}", error));
+        }) as Box<dyn FnMut()>);
+        let set_interval = global_function("setInterval")?;
+        let handle = set_interval.call2(
+            &JsValue::NULL,
+            on_interval.as_ref().unchecked_ref(),
+            &JsValue::from(interval_ms),
+        )?;
+        self.inner.borrow_mut().timer = Some((handle, on_interval));
+        Ok(())
+    }
+
+    /// Stops the timer started with [`MessageBatcher::start_timer`], if any.
+    /// Queued messages stay in the batcher until next flush.
+    ///
+    /// # Errors
+    /// This function errors if the timer can't be cleared.
+    pub fn stop_timer(&self) -> Result<(), JsValue> {
+        let timer = self.inner.borrow_mut().timer.take();
+        if let Some((handle, _on_interval)) = timer {
+            global_function("clearInterval")?.call1(&JsValue::NULL, &handle)?;
+        }
+        Ok(())
+    }
+}
+
+/// Combines messages into a single one, each prefixed with its length,
+/// so that messages can contain any characters.
+fn encode_batch(messages: &[String]) -> String {
+    let mut batch = String::new();
+    for message in messages {
+        batch.push_str(&message.len().to_string());
+        batch.push(':');
+        batch.push_str(message);
+    }
+    batch
+}
+
+/// Splits a batch sent by [`MessageBatcher`] back into individual messages.
+///
+/// # Errors
+/// This function errors if the message is not a valid batch.
+pub fn split_batch(batch: &str) -> Result<Vec<String>, JsValue> {
+    let invalid_batch = || JsValue::from_str("message is not a valid batch");
+    let mut messages = Vec::new();
+    let mut rest = batch;
+    while !rest.is_empty() {
+        let (length, tail) = rest.split_once(':').ok_or_else(invalid_batch)?;
+        let length = length.parse::<usize>().map_err(|_| invalid_batch())?;
+        let message = tail.get(..length).ok_or_else(invalid_batch)?;
+        messages.push(message.to_string());
+        rest = &tail[length..];
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_batch_round_trips_messages_with_separators() {
+        let messages = vec!["".to_string(), "1:2".to_string(), "zażółć".to_string()];
+
+        assert_eq!(split_batch(&encode_batch(&messages)).unwrap(), messages);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_flush_sends_queued_messages_once() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let sent_clone = sent.clone();
+        let batcher = MessageBatcher::new(move |batch| {
+            sent_clone.borrow_mut().push(batch.to_string());
+            Ok(())
+        });
+
+        batcher.queue("first");
+        batcher.queue("second");
+        batcher.flush().unwrap();
+        batcher.flush().unwrap();
+
+        assert_eq!(*sent.borrow(), vec!["5:first6:second".to_string()]);
+        assert_eq!(batcher.queued_count(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_updates_with_same_key_are_coalesced() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let sent_clone = sent.clone();
+        let batcher = MessageBatcher::new(move |batch| {
+            sent_clone.borrow_mut().push(batch.to_string());
+            Ok(())
+        });
+        batcher.set_coalesce_key(|message| {
+            message
+                .split_once('=')
+                .map(|(entity, _position)| entity.to_string())
+        });
+
+        for position in 0..100 {
+            batcher.queue(&format!("player={}", position));
+        }
+        batcher.queue("chat message");
+        batcher.queue("enemy=7");
+        batcher.queue("player=100");
+        batcher.flush().unwrap();
+
+        assert_eq!(
+            split_batch(&sent.borrow()[0]).unwrap(),
+            vec!["chat message", "enemy=7", "player=100"]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_truncated_batch_is_rejected() {
+        assert!(split_batch("10:short").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Callback set by the application, shared between the state storing it and whoever calls it.
+///
+/// Callbacks may use the object calling them, e.g. send a message from `on_message`,
+/// so they have to be called after the borrow of that object's state is released:
+/// take them out of the state with [`Callback::of`] or clone them, and call the clone.
+pub(crate) struct Callback<F: ?Sized>(Rc<RefCell<F>>);
+
+impl<F: ?Sized> Callback<F> {
+    /// Callback returned by `callback` from `state`, with the borrow of `state` already released.
+    pub(crate) fn of<S>(
+        state: &RefCell<S>,
+        callback: impl FnOnce(&S) -> &Option<Self>,
+    ) -> Option<Self> {
+        callback(&state.borrow()).clone()
+    }
+}
+
+impl<F: ?Sized> Clone for Callback<F> {
+    fn clone(&self) -> Self {
+        Callback(self.0.clone())
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+#[cfg(feature = "one-to-one")]
+impl<R> Callback<dyn FnMut() -> R> {
+    pub(crate) fn new(callback: impl FnMut() -> R + 'static) -> Self {
+        Callback(Rc::new(RefCell::new(callback)))
+    }
+
+    pub(crate) fn call(&self) -> R {
+        (self.0.borrow_mut())()
+    }
+}
+
+impl<A, R> Callback<dyn FnMut(A) -> R> {
+    pub(crate) fn new(callback: impl FnMut(A) -> R + 'static) -> Self {
+        Callback(Rc::new(RefCell::new(callback)))
+    }
+
+    pub(crate) fn call(&self, a: A) -> R {
+        (self.0.borrow_mut())(a)
+    }
+}
+
+impl<A, B, R> Callback<dyn FnMut(A, B) -> R> {
+    pub(crate) fn new(callback: impl FnMut(A, B) -> R + 'static) -> Self {
+        Callback(Rc::new(RefCell::new(callback)))
+    }
+
+    pub(crate) fn call(&self, a: A, b: B) -> R {
+        (self.0.borrow_mut())(a, b)
+    }
+}
+
+impl<A, B, C, R> Callback<dyn FnMut(A, B, C) -> R> {
+    pub(crate) fn new(callback: impl FnMut(A, B, C) -> R + 'static) -> Self {
+        Callback(Rc::new(RefCell::new(callback)))
+    }
+
+    pub(crate) fn call(&self, a: A, b: B, c: C) -> R {
+        (self.0.borrow_mut())(a, b, c)
+    }
+}
@@ -0,0 +1,117 @@
+/*!
+Runtime detection of what the current build and browser support, so applications can adapt,
+e.g. hide a video call button when the browser can't capture media.
+
+Browser APIs are detected by checking that they're defined, without calling them,
+so no permission prompts are shown. This crate itself only uses data channels,
+media related capabilities are reported for applications that add media on their own.
+*/
+
+use js_sys::{Array, Function, Reflect};
+use serde::Serialize;
+use wasm_bindgen::{JsCast, JsValue};
+
+/// Capabilities detected by [`client_capabilities`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ClientCapabilities {
+    /// Topologies this build of the crate was compiled with, e.g. `"one-to-one"`.
+    pub topologies: Vec<&'static str>,
+    /// `RTCPeerConnection` is available, without it no topology works.
+    pub peer_connection: bool,
+    /// `WebSocket` is available, required to reach the signaling server.
+    pub websocket: bool,
+    /// `BroadcastChannel` is available, required by [`crate::local_discovery::BrowserDiscovery`].
+    pub broadcast_channel: bool,
+    /// `navigator.mediaDevices.getUserMedia` is available, for capturing camera and microphone.
+    pub user_media: bool,
+    /// Insertable streams, either `RTCRtpScriptTransform` or `RTCRtpSender.createEncodedStreams`,
+    /// e.g. for end-to-end encrypted media.
+    pub insertable_streams: bool,
+    /// `WebTransport` is available.
+    pub web_transport: bool,
+    /// Mime types of audio codecs the browser can send, e.g. `"audio/opus"`.
+    pub audio_codecs: Vec<String>,
+    /// Mime types of video codecs the browser can send, e.g. `"video/VP8"`.
+    pub video_codecs: Vec<String>,
+}
+
+/// Detects capabilities of the current build and browser.
+pub fn client_capabilities() -> ClientCapabilities {
+    let global = js_sys::global();
+    let navigator = Reflect::get(&global, &JsValue::from_str("navigator")).unwrap_or_default();
+    let media_devices =
+        Reflect::get(&navigator, &JsValue::from_str("mediaDevices")).unwrap_or_default();
+    let rtp_sender = Reflect::get(&global, &JsValue::from_str("RTCRtpSender")).unwrap_or_default();
+    let rtp_sender_prototype =
+        Reflect::get(&rtp_sender, &JsValue::from_str("prototype")).unwrap_or_default();
+
+    ClientCapabilities {
+        topologies: [
+            (cfg!(feature = "one-to-one"), "one-to-one"),
+            (cfg!(feature = "one-to-many"), "one-to-many"),
+            (cfg!(feature = "many-to-many"), "many-to-many"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, topology)| enabled.then_some(topology))
+        .collect(),
+        peer_connection: is_defined(&global, "RTCPeerConnection"),
+        websocket: is_defined(&global, "WebSocket"),
+        broadcast_channel: is_defined(&global, "BroadcastChannel"),
+        user_media: is_defined(&media_devices, "getUserMedia"),
+        insertable_streams: is_defined(&global, "RTCRtpScriptTransform")
+            || is_defined(&rtp_sender_prototype, "createEncodedStreams"),
+        web_transport: is_defined(&global, "WebTransport"),
+        audio_codecs: codecs(&rtp_sender, "audio"),
+        video_codecs: codecs(&rtp_sender, "video"),
+    }
+}
+
+fn is_defined(target: &JsValue, name: &str) -> bool {
+    target.is_object()
+        && Reflect::get(target, &JsValue::from_str(name)).is_ok_and(|value| !value.is_undefined())
+}
+
+/// Unique mime types from `RTCRtpSender.getCapabilities(kind)`, empty if it's unavailable.
+fn codecs(rtp_sender: &JsValue, kind: &str) -> Vec<String> {
+    let get_capabilities = match Reflect::get(rtp_sender, &JsValue::from_str("getCapabilities"))
+        .ok()
+        .and_then(|get_capabilities| get_capabilities.dyn_into::<Function>().ok())
+    {
+        Some(get_capabilities) => get_capabilities,
+        None => return Vec::new(),
+    };
+    let capabilities = get_capabilities
+        .call1(rtp_sender, &JsValue::from_str(kind))
+        .unwrap_or_default();
+    let codecs = Reflect::get(&capabilities, &JsValue::from_str("codecs")).unwrap_or_default();
+    if !Array::is_array(&codecs) {
+        return Vec::new();
+    }
+    let mut mime_types = Vec::new();
+    for codec in Array::from(&codecs).iter() {
+        let mime_type = Reflect::get(&codec, &JsValue::from_str("mimeType"))
+            .ok()
+            .and_then(|mime_type| mime_type.as_string());
+        if let Some(mime_type) = mime_type {
+            if !mime_types.contains(&mime_type) {
+                mime_types.push(mime_type);
+            }
+        }
+    }
+    mime_types
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_browser_supports_what_the_crate_needs() {
+        let capabilities = client_capabilities();
+        assert!(capabilities.peer_connection);
+        assert!(capabilities.websocket);
+        assert!(capabilities.topologies.contains(&"one-to-one"));
+    }
+}
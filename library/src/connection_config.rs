@@ -0,0 +1,257 @@
+/*!
+Saving a connection setup and rebuilding connections from it, e.g. a user's preferred `ICE` servers.
+
+[`ConnectionConfig`] gathers what a one-to-one connection is configured with, see
+[`crate::one_to_one::NetworkManager::connection_config`], and serializes it to versioned `JSON`.
+`TURN` username and credential are secrets, often short-lived ones, so they're never serialized.
+The `JSON` holds an id chosen by the application instead, and on restore the application
+looks up the credentials by that id, e.g. asks its backend for fresh ones.
+Restored configuration is validated the same way as when it's set on a connection.
+
+```no_run
+use wasm_peers::connection_config::{ConnectionConfig, TurnCredentials};
+use wasm_peers::one_to_one::NetworkManager;
+use wasm_peers::SessionId;
+
+fn reconnect(saved: &str) -> NetworkManager {
+    let config = ConnectionConfig::from_json(saved, |_credentials_id| {
+        Some(TurnCredentials {
+            username: "fresh-username".to_string(),
+            credential: "fresh-credential".to_string(),
+        })
+    })
+    .unwrap();
+    let mut network_manager = NetworkManager::new(
+        "ws://0.0.0.0:9001/one-to-one",
+        SessionId::new("some-session-id".to_string()),
+        config.connection_type.clone(),
+    )
+    .unwrap();
+    network_manager.set_connection_config(config).unwrap();
+    network_manager
+}
+```
+*/
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::{ConnectionType, DataChannelConfig, IceOptions};
+
+/// Version of the serialized form written by [`ConnectionConfig::to_json`],
+/// other versions are rejected by [`ConnectionConfig::from_json`].
+pub const CONNECTION_CONFIG_VERSION: u32 = 1;
+
+/// Configuration of a one-to-one connection, as set before [`crate::one_to_one::NetworkManager::start`].
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// `ICE` servers the connection uses, with `TURN` credentials if there are any.
+    pub connection_type: ConnectionType,
+    /// Options of the data channel opened in [`crate::one_to_one::NetworkManager::start`].
+    pub data_channel: DataChannelConfig,
+    /// Which `ICE` candidates the connection gathers and accepts.
+    pub ice_options: IceOptions,
+}
+
+/// Secrets of a [`ConnectionType::StunAndTurn`], left out of the serialized configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnCredentials {
+    /// Username of [`ConnectionType::StunAndTurn`].
+    pub username: String,
+    /// Credential of [`ConnectionType::StunAndTurn`], e.g. a password or a short-lived token.
+    pub credential: String,
+}
+
+#[derive(Deserialize)]
+struct StoredVersion {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredConfig {
+    version: u32,
+    ice_servers: StoredIceServers,
+    data_channel: StoredDataChannel,
+    ice_options: IceOptions,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum StoredIceServers {
+    Local,
+    Stun {
+        urls: String,
+    },
+    StunAndTurn {
+        stun_urls: String,
+        turn_urls: String,
+        credentials_id: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredDataChannel {
+    #[serde(default)]
+    protocol: Option<String>,
+    /// Browser's name of the priority, e.g. `"very-low"`.
+    #[serde(default)]
+    priority: Option<String>,
+}
+
+impl ConnectionConfig {
+    /// Serializes the configuration, `TURN` credentials replaced by `credentials_id`.
+    ///
+    /// # Errors
+    /// This function errors if serialization fails.
+    pub fn to_json(&self, credentials_id: &str) -> Result<String, JsValue> {
+        let ice_servers = match &self.connection_type {
+            ConnectionType::Local => StoredIceServers::Local,
+            ConnectionType::Stun { urls } => StoredIceServers::Stun { urls: urls.clone() },
+            ConnectionType::StunAndTurn {
+                stun_urls,
+                turn_urls,
+                ..
+            } => StoredIceServers::StunAndTurn {
+                stun_urls: stun_urls.clone(),
+                turn_urls: turn_urls.clone(),
+                credentials_id: credentials_id.to_string(),
+            },
+        };
+        let stored = StoredConfig {
+            version: CONNECTION_CONFIG_VERSION,
+            ice_servers,
+            data_channel: StoredDataChannel {
+                protocol: self.data_channel.protocol.clone(),
+                priority: self
+                    .data_channel
+                    .priority
+                    .map(|priority| priority.as_str().to_string()),
+            },
+            ice_options: self.ice_options.clone(),
+        };
+        serde_json_wasm::to_string(&stored).map_err(|error| JsValue::from_str(&error.to_string()))
+    }
+
+    /// Restores configuration serialized with [`ConnectionConfig::to_json`]. `credentials` is called
+    /// with the id `TURN` credentials were replaced by, only if the configuration uses `TURN`.
+    ///
+    /// # Errors
+    /// This function errors if the `JSON` is malformed or of another version, if the configuration
+    /// is invalid, or if `credentials` doesn't return credentials for the id.
+    pub fn from_json(
+        json: &str,
+        credentials: impl FnOnce(&str) -> Option<TurnCredentials>,
+    ) -> Result<Self, JsValue> {
+        let invalid = |error: serde_json_wasm::de::Error| {
+            JsValue::from_str(&format!("invalid connection config: {}", error))
+        };
+        let StoredVersion { version } = serde_json_wasm::from_str(json).map_err(invalid)?;
+        if version != CONNECTION_CONFIG_VERSION {
+            return Err(JsValue::from_str(&format!(
+                "unsupported connection config version: {}, expected {}",
+                version, CONNECTION_CONFIG_VERSION
+            )));
+        }
+        let stored: StoredConfig = serde_json_wasm::from_str(json).map_err(invalid)?;
+
+        let connection_type = match stored.ice_servers {
+            StoredIceServers::Local => ConnectionType::Local,
+            StoredIceServers::Stun { urls } => ConnectionType::Stun { urls },
+            StoredIceServers::StunAndTurn {
+                stun_urls,
+                turn_urls,
+                credentials_id,
+            } => {
+                let TurnCredentials {
+                    username,
+                    credential,
+                } = credentials(&credentials_id).ok_or_else(|| {
+                    JsValue::from_str(&format!("no TURN credentials for id: {:?}", credentials_id))
+                })?;
+                ConnectionType::StunAndTurn {
+                    stun_urls,
+                    turn_urls,
+                    username,
+                    credential,
+                }
+            }
+        };
+        let data_channel = DataChannelConfig {
+            protocol: stored.data_channel.protocol,
+            priority: stored
+                .data_channel
+                .priority
+                .map(|priority| priority.parse())
+                .transpose()?,
+        };
+        data_channel.validate()?;
+        Ok(ConnectionConfig {
+            connection_type,
+            data_channel,
+            ice_options: stored.ice_options,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+    use crate::DataChannelPriority;
+
+    fn config() -> ConnectionConfig {
+        ConnectionConfig {
+            connection_type: ConnectionType::StunAndTurn {
+                stun_urls: "stun:stun.example.com".to_string(),
+                turn_urls: "turn:turn.example.com".to_string(),
+                username: "secret-username".to_string(),
+                credential: "secret-credential".to_string(),
+            },
+            data_channel: DataChannelConfig {
+                protocol: Some("chat".to_string()),
+                priority: Some(DataChannelPriority::High),
+            },
+            ice_options: IceOptions {
+                relay_only: true,
+                ..IceOptions::default()
+            },
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_config_round_trips_without_credentials() {
+        let json = config().to_json("saved-turn").unwrap();
+        assert!(!json.contains("secret"));
+
+        let restored = ConnectionConfig::from_json(&json, |credentials_id| {
+            assert_eq!(credentials_id, "saved-turn");
+            Some(TurnCredentials {
+                username: "fresh-username".to_string(),
+                credential: "fresh-credential".to_string(),
+            })
+        })
+        .unwrap();
+
+        assert!(matches!(
+            restored.connection_type,
+            ConnectionType::StunAndTurn { ref username, .. } if username == "fresh-username"
+        ));
+        assert_eq!(
+            restored.data_channel.priority,
+            Some(DataChannelPriority::High)
+        );
+        assert_eq!(restored.ice_options, config().ice_options);
+        assert!(ConnectionConfig::from_json(&json, |_| None).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_other_versions_are_rejected() {
+        let json = config()
+            .to_json("saved-turn")
+            .unwrap()
+            .replace("\"version\":1", "\"version\":2");
+
+        assert!(ConnectionConfig::from_json(&json, |_| None).is_err());
+    }
+}
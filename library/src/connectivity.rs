@@ -0,0 +1,195 @@
+/*!
+Self-test of the current network before joining a session, so applications can warn users upfront,
+e.g. "your network may block connections".
+
+[`check_connectivity`] gathers `ICE` candidates of a throwaway peer connection with the given
+[`ConnectionType`] and classifies what they allow, without contacting the signaling server or any peer.
+Whether two peers actually connect also depends on the other peer's network,
+so the verdict tells what this peer can offer, not a guarantee.
+
+```no_run
+use wasm_peers::connectivity::{check_connectivity, Connectivity};
+use wasm_peers::ConnectionType;
+
+async fn warn_user() {
+    let connection_type = ConnectionType::Stun {
+        urls: "stun:openrelay.metered.ca:80".to_string(),
+    };
+    let report = check_connectivity(&connection_type, 5_000).await.unwrap();
+    if report.verdict == Connectivity::Blocked {
+        log::warn!("your network may block connections");
+    }
+}
+```
+*/
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use js_sys::{Array, Promise};
+use serde::Serialize;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::RtcPeerConnectionIceEvent;
+
+use crate::utils::{candidate_type, create_peer_connection, create_sdp_offer, timeout_promise};
+use crate::ConnectionType;
+
+/// What the candidates gathered by [`check_connectivity`] allow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Connectivity {
+    /// `STUN` server returned a public address, peers can likely connect directly.
+    DirectPossible,
+    /// Only a `TURN` server could be reached, connections have to be relayed through it.
+    RelayNeeded,
+    /// Only local addresses were gathered, so only peers on the same network can connect.
+    LocalOnly,
+    /// No candidates were gathered, connections aren't possible.
+    Blocked,
+}
+
+/// Result of [`check_connectivity`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConnectivityReport {
+    pub verdict: Connectivity,
+    /// Candidates with local addresses.
+    pub host_candidates: usize,
+    /// Candidates with public addresses returned by a `STUN` server.
+    pub server_reflexive_candidates: usize,
+    /// Candidates with addresses on a `TURN` server.
+    pub relay_candidates: usize,
+    /// Whether a relay candidate was gathered, `None` if the connection type has no `TURN` server.
+    pub turn_reachable: Option<bool>,
+    /// `false` if the timeout passed before the browser finished gathering candidates,
+    /// in which case slower servers could still have been reached.
+    pub gathering_complete: bool,
+}
+
+/// Gathers candidates with given connection type for at most `timeout_ms` milliseconds
+/// and reports what connections they allow.
+pub async fn check_connectivity(
+    connection_type: &ConnectionType,
+    timeout_ms: u32,
+) -> Result<ConnectivityReport, JsValue> {
+    let peer_connection = create_peer_connection(connection_type)?;
+    // candidates are only gathered for an offer with something to connect
+    let _data_channel = peer_connection.create_data_channel("connectivity-check");
+
+    let candidates = Rc::new(RefCell::new(Vec::new()));
+    let mut on_ice_candidate = None;
+    let gathered = Promise::new(&mut |resolve, _reject| {
+        let candidates = candidates.clone();
+        on_ice_candidate = Some(Closure::wrap(Box::new(
+            move |ev: RtcPeerConnectionIceEvent| match ev.candidate() {
+                Some(candidate) => candidates.borrow_mut().push(candidate.candidate()),
+                // gathering is complete
+                None => {
+                    let _ = resolve.call1(&JsValue::NULL, &JsValue::TRUE);
+                }
+            },
+        )
+            as Box<dyn FnMut(RtcPeerConnectionIceEvent)>));
+    });
+    peer_connection.set_onicecandidate(
+        on_ice_candidate
+            .as_ref()
+            .map(|on_ice_candidate| on_ice_candidate.as_ref().unchecked_ref()),
+    );
+
+    let result = match create_sdp_offer(&peer_connection).await {
+        Ok(_) => JsFuture::from(Promise::race(&Array::of2(
+            &gathered,
+            &timeout_promise(timeout_ms),
+        )))
+        .await
+        .map(|gathering_complete| gathering_complete.is_truthy()),
+        Err(error) => Err(error),
+    };
+    peer_connection.set_onicecandidate(None);
+    peer_connection.close();
+
+    let gathering_complete = result?;
+    let has_turn = matches!(connection_type, ConnectionType::StunAndTurn { .. });
+    let candidates = candidates.borrow();
+    Ok(classify(
+        candidates.iter().map(String::as_str),
+        has_turn,
+        gathering_complete,
+    ))
+}
+
+fn classify<'a>(
+    candidates: impl Iterator<Item = &'a str>,
+    has_turn: bool,
+    gathering_complete: bool,
+) -> ConnectivityReport {
+    let (mut host_candidates, mut server_reflexive_candidates, mut relay_candidates) = (0, 0, 0);
+    for candidate in candidates {
+        match candidate_type(candidate) {
+            Some("host") => host_candidates += 1,
+            Some("srflx" | "prflx") => server_reflexive_candidates += 1,
+            Some("relay") => relay_candidates += 1,
+            _ => {}
+        }
+    }
+    let verdict = if server_reflexive_candidates > 0 {
+        Connectivity::DirectPossible
+    } else if relay_candidates > 0 {
+        Connectivity::RelayNeeded
+    } else if host_candidates > 0 {
+        Connectivity::LocalOnly
+    } else {
+        Connectivity::Blocked
+    };
+    ConnectivityReport {
+        verdict,
+        host_candidates,
+        server_reflexive_candidates,
+        relay_candidates,
+        turn_reachable: has_turn.then_some(relay_candidates > 0),
+        gathering_complete,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    const HOST: &str = "candidate:1 1 udp 2122260223 192.168.1.2 54321 typ host generation 0";
+    const RELAY: &str =
+        "candidate:3 1 udp 41885439 203.0.113.7 3478 typ relay raddr 198.51.100.4 rport 61234";
+
+    #[wasm_bindgen_test]
+    fn test_verdict_follows_best_candidate_type() {
+        let relayed = classify([HOST, RELAY].into_iter(), true, true);
+        assert_eq!(relayed.verdict, Connectivity::RelayNeeded);
+        assert_eq!(relayed.host_candidates, 1);
+        assert_eq!(relayed.relay_candidates, 1);
+        assert_eq!(relayed.turn_reachable, Some(true));
+
+        let local = classify([HOST].into_iter(), false, false);
+        assert_eq!(local.verdict, Connectivity::LocalOnly);
+        assert_eq!(local.turn_reachable, None);
+
+        let direct = classify(
+            [
+                HOST,
+                "candidate:2 1 udp 1686052607 198.51.100.4 61234 typ srflx raddr 0.0.0.0 rport 0",
+            ]
+            .into_iter(),
+            true,
+            true,
+        );
+        assert_eq!(direct.verdict, Connectivity::DirectPossible);
+        assert_eq!(direct.turn_reachable, Some(false));
+
+        assert_eq!(
+            classify(std::iter::empty(), false, true).verdict,
+            Connectivity::Blocked
+        );
+    }
+}
@@ -0,0 +1,144 @@
+/*!
+Framing of the messages helpers exchange over the same channel as the application.
+
+Helpers such as [`crate::request`], [`crate::file_transfer`], [`crate::stream`] and [`crate::gossip`]
+send their messages with the application's send function, so they start them with the [`CONTROL`]
+character followed by a letter naming their kind, and return other messages to the application
+unchanged from their `handle_message`:
+
+| Prefix     | Message                               |
+|------------|---------------------------------------|
+| `\u{1}q`   | request of [`crate::request`]         |
+| `\u{1}r`   | response of [`crate::request`]        |
+| `\u{1}f`   | file chunk of [`crate::file_transfer`] |
+| `\u{1}c`   | cancelled file of [`crate::file_transfer`] |
+| `\u{1}s`   | stream data of [`crate::stream`]      |
+| `\u{1}e`   | stream end of [`crate::stream`]       |
+| `\u{1}g`   | gossip of [`crate::gossip`]           |
+
+Application messages starting with [`CONTROL`] would be taken for helper messages,
+so applications that may send such messages should [`escape`] each message before sending it
+and [`unescape`] each message the helpers return. Other messages are left as they are by both,
+so plain text needs no escaping.
+
+[`crate::one_to_one::NetworkManager`] additionally frames everything it sends over its data channel,
+helper messages included, with a letter of its own: `x` for application messages,
+`h` for presence heartbeats, `q` for connection quality reports, `c` for clock probes
+and `f` for fragments of long messages. These never reach the application.
+*/
+
+use std::borrow::Cow;
+
+/// Character starting every helper message.
+pub const CONTROL: char = '\u{1}';
+
+/// Application message of [`crate::one_to_one::NetworkManager`] data channel.
+#[cfg(feature = "one-to-one")]
+pub(crate) const APPLICATION_FRAME: char = 'x';
+/// Presence heartbeat, see [`crate::one_to_one::NetworkManager::start_presence_heartbeats`].
+#[cfg(feature = "one-to-one")]
+pub(crate) const HEARTBEAT_FRAME: &str = "h";
+/// Connection quality report, see [`crate::one_to_one::NetworkManager::start_quality_reports`].
+#[cfg(feature = "one-to-one")]
+pub(crate) const QUALITY_REPORT_FRAME: char = 'q';
+/// Clock probe, see [`crate::one_to_one::NetworkManager::start_clock_sync`].
+#[cfg(feature = "one-to-one")]
+pub(crate) const CLOCK_PROBE_FRAME: char = 'c';
+/// Fragment of a long message, see [`crate::one_to_one::NetworkManager::set_fragmentation_threshold`].
+#[cfg(feature = "one-to-one")]
+pub(crate) const FRAGMENT_FRAME: char = 'f';
+
+/// Kind of a helper message, see the table in the [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Request,
+    Response,
+    FileChunk,
+    FileCancel,
+    StreamData,
+    StreamEnd,
+    Gossip,
+}
+
+impl Kind {
+    const ALL: [Kind; 7] = [
+        Kind::Request,
+        Kind::Response,
+        Kind::FileChunk,
+        Kind::FileCancel,
+        Kind::StreamData,
+        Kind::StreamEnd,
+        Kind::Gossip,
+    ];
+
+    fn letter(self) -> char {
+        match self {
+            Kind::Request => 'q',
+            Kind::Response => 'r',
+            Kind::FileChunk => 'f',
+            Kind::FileCancel => 'c',
+            Kind::StreamData => 's',
+            Kind::StreamEnd => 'e',
+            Kind::Gossip => 'g',
+        }
+    }
+}
+
+/// Helper message of given kind carrying `body`.
+pub(crate) fn encode(kind: Kind, body: &str) -> String {
+    format!("{}{}{}", CONTROL, kind.letter(), body)
+}
+
+/// Kind and body of a helper message, `None` for other messages, escaped ones included.
+pub(crate) fn decode(message: &str) -> Option<(Kind, &str)> {
+    let mut rest = message.strip_prefix(CONTROL)?.chars();
+    let letter = rest.next()?;
+    let kind = Kind::ALL.into_iter().find(|kind| kind.letter() == letter)?;
+    Some((kind, rest.as_str()))
+}
+
+/// Makes an application message safe to send alongside helper messages,
+/// restored with [`unescape`]. Messages not starting with [`CONTROL`] are returned as they are.
+pub fn escape(message: &str) -> Cow<'_, str> {
+    if message.starts_with(CONTROL) {
+        Cow::Owned(format!("{}{}", CONTROL, message))
+    } else {
+        Cow::Borrowed(message)
+    }
+}
+
+/// Restores an application message escaped with [`escape`].
+pub fn unescape(message: &str) -> &str {
+    match message.strip_prefix(CONTROL) {
+        Some(escaped) if escaped.starts_with(CONTROL) => escaped,
+        _ => message,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_helper_messages_round_trip() {
+        for kind in Kind::ALL {
+            assert_eq!(decode(&encode(kind, "1:body")), Some((kind, "1:body")));
+        }
+        assert_eq!(decode("hello"), None);
+        assert_eq!(decode("\u{1}z1:body"), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_escaped_messages_arent_taken_for_helper_messages() {
+        let message = "\u{1}q1:not a request";
+
+        let escaped = escape(message);
+
+        assert_eq!(decode(&escaped), None);
+        assert_eq!(unescape(&escaped), message);
+        assert_eq!(escape("hello"), "hello");
+        assert_eq!(unescape("hello"), "hello");
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+
+use wasm_bindgen::JsValue;
+use wasm_peers_protocol::UserId;
+
+/// Errors of operations that callers may want to tell apart,
+/// converted to a `JsValue` with its message where one is expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Network manager has to be started before connecting to peers.
+    NotStarted,
+    /// There already is a connection with the peer, or it's being established.
+    AlreadyConnected(UserId),
+    /// Peer isn't in session, e.g. because it already left it.
+    PeerUnavailable(UserId),
+    /// Creating the connection with the peer or sending it an offer failed, with the reason.
+    PeerUnreachable(UserId, String),
+    /// Signaling message couldn't be serialized, with the reason.
+    Serialization(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotStarted => f.write_str("network manager is not started yet"),
+            Error::AlreadyConnected(user_id) => write!(f, "already connected to user {}", user_id),
+            Error::PeerUnavailable(user_id) => {
+                write!(f, "user {} is not available in session", user_id)
+            }
+            Error::PeerUnreachable(user_id, reason) => {
+                write!(f, "failed to connect to user {}: {}", user_id, reason)
+            }
+            Error::Serialization(reason) => {
+                write!(f, "failed to serialize signaling message: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for JsValue {
+    fn from(error: Error) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
@@ -0,0 +1,408 @@
+/*!
+Helper for sending large files over a data channel, with progress reporting on both sides.
+
+Files are too large to be sent as a single data channel message, so [`FileTransfer::send_file`]
+splits them into chunks of [`CHUNK_SIZE`] bytes and the receiving side puts them back together.
+Sending pauses while too much data waits to be sent, as reported by the function provided
+to [`FileTransfer::new`], so a large file doesn't end up buffered in memory all at once.
+Both peers need to pass received messages through [`FileTransfer::handle_message`].
+
+Chunks are [helper messages](crate::control) encoded in base64,
+other messages are passed through unchanged. Data channel must be ordered, which it is by default.
+
+# Example
+
+```no_run
+use wasm_peers::file_transfer::FileTransfer;
+use wasm_peers::one_to_one::NetworkManager;
+use wasm_peers::{ConnectionType, SessionId};
+
+let mut network_manager = NetworkManager::new(
+    "ws://0.0.0.0:9001/one-to-one",
+    SessionId::new("some-session-id".to_string()),
+    ConnectionType::Local,
+)
+.unwrap();
+let network_manager_clone = network_manager.clone();
+let network_manager_pending = network_manager.clone();
+let files = FileTransfer::new(
+    move |message| network_manager_clone.send_message(message),
+    move || {
+        network_manager_pending.queued_amount()
+            + network_manager_pending.buffered_amount().unwrap_or(0) as usize
+    },
+);
+files.set_on_file(|_transfer_id, file| log::info!("received {} bytes", file.len()));
+let files_clone = files.clone();
+network_manager.set_on_disconnect(move |_reason| files_clone.cancel_all());
+let files_clone = files.clone();
+let on_open = move || {
+    let files = files_clone.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        let file = vec![0; 10 * 1024 * 1024];
+        let result = files
+            .send_file(&file, |sent, total| log::info!("sent {}/{}", sent, total))
+            .await;
+        log::info!("file sent: {:?}", result);
+    });
+};
+let files_clone = files.clone();
+let on_message = move |message: String| {
+    if let Ok(Some(message)) = files_clone.handle_message(&message) {
+        // handle regular message
+    }
+};
+network_manager.start(on_open, on_message).unwrap();
+```
+*/
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::callback::Callback;
+use crate::control::{self, Kind};
+use crate::utils::timeout_promise;
+
+/// Number of bytes of the file sent in a single message,
+/// small enough to stay below message size limits of all browsers.
+pub const CHUNK_SIZE: usize = 16 * 1024;
+/// Sending pauses while more than this many bytes wait to be sent.
+pub const MAX_PENDING_BYTES: usize = 1024 * 1024;
+/// How often sending checks whether the pending data drained.
+const PENDING_POLL_INTERVAL_MS: u32 = 20;
+
+type SendFunction = Rc<dyn Fn(&str) -> Result<(), JsValue>>;
+type PendingFunction = Rc<dyn Fn() -> usize>;
+type FileCallback = Callback<dyn FnMut(u64, Vec<u8>)>;
+type ProgressCallback = Callback<dyn FnMut(u64, usize, usize)>;
+
+struct IncomingFile {
+    total: usize,
+    data: Vec<u8>,
+}
+
+struct FileTransferInner {
+    send: SendFunction,
+    pending: PendingFunction,
+    next_id: u64,
+    /// Outgoing transfers that weren't cancelled.
+    sending: HashSet<u64>,
+    incoming: HashMap<u64, IncomingFile>,
+    on_file: Option<FileCallback>,
+    on_receive_progress: Option<ProgressCallback>,
+}
+
+/// Sends files and receives the ones sent by the other peer, using provided send function.
+///
+/// This class is a pointer to the underlying resource and can be cloned freely.
+#[derive(Clone)]
+pub struct FileTransfer {
+    inner: Rc<RefCell<FileTransferInner>>,
+}
+
+impl FileTransfer {
+    /// Creates a file transfer sending its messages with given function,
+    /// e.g. [`crate::one_to_one::NetworkManager::send_message`].
+    /// `pending` returns the number of bytes sent, but still waiting to be sent over the network,
+    /// e.g. sum of [`crate::one_to_one::NetworkManager::queued_amount`]
+    /// and [`crate::one_to_one::NetworkManager::buffered_amount`].
+    pub fn new(
+        send: impl Fn(&str) -> Result<(), JsValue> + 'static,
+        pending: impl Fn() -> usize + 'static,
+    ) -> Self {
+        FileTransfer {
+            inner: Rc::new(RefCell::new(FileTransferInner {
+                send: Rc::new(send),
+                pending: Rc::new(pending),
+                next_id: 0,
+                sending: HashSet::new(),
+                incoming: HashMap::new(),
+                on_file: None,
+                on_receive_progress: None,
+            })),
+        }
+    }
+
+    /// Sets the callback receiving complete files sent by the other peer, with their transfer id.
+    pub fn set_on_file(&self, on_file: impl FnMut(u64, Vec<u8>) + 'static) {
+        self.inner.borrow_mut().on_file = Some(FileCallback::new(on_file));
+    }
+
+    /// Sets the callback called after each received chunk
+    /// with the transfer id, number of bytes received so far and the size of the file.
+    pub fn set_on_receive_progress(
+        &self,
+        on_receive_progress: impl FnMut(u64, usize, usize) + 'static,
+    ) {
+        self.inner.borrow_mut().on_receive_progress =
+            Some(ProgressCallback::new(on_receive_progress));
+    }
+
+    /// Sends the file in chunks, calling `on_progress` after each of them
+    /// with the number of bytes sent so far and the size of the file.
+    /// Resolves once the last chunk is handed over to the send function.
+    ///
+    /// # Errors
+    /// This function errors if sending any of the chunks fails,
+    /// or if the transfer is cancelled with [`FileTransfer::cancel_all`].
+    /// Either way the other peer is told to drop the part it received, if possible.
+    pub async fn send_file(
+        &self,
+        file: &[u8],
+        mut on_progress: impl FnMut(usize, usize),
+    ) -> Result<(), JsValue> {
+        let id = {
+            let mut inner = self.inner.borrow_mut();
+            inner.next_id += 1;
+            let id = inner.next_id;
+            inner.sending.insert(id);
+            id
+        };
+        let result = self.send_chunks(id, file, &mut on_progress).await;
+        self.inner.borrow_mut().sending.remove(&id);
+        if result.is_err() {
+            let send = self.inner.borrow().send.clone();
+            let _ = send(&control::encode(Kind::FileCancel, &id.to_string()));
+        }
+        result
+    }
+
+    async fn send_chunks(
+        &self,
+        id: u64,
+        file: &[u8],
+        on_progress: &mut impl FnMut(usize, usize),
+    ) -> Result<(), JsValue> {
+        let total = file.len();
+        let mut offset = 0;
+        loop {
+            self.wait_for_pending(id).await?;
+            let end = (offset + CHUNK_SIZE).min(total);
+            let chunk = encode_base64(&file[offset..end]);
+            let send = self.inner.borrow().send.clone();
+            send(&control::encode(
+                Kind::FileChunk,
+                &format!("{}:{}:{}:{}", id, offset, total, chunk),
+            ))?;
+            offset = end;
+            on_progress(offset, total);
+            if offset == total {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn wait_for_pending(&self, id: u64) -> Result<(), JsValue> {
+        loop {
+            if !self.inner.borrow().sending.contains(&id) {
+                return Err(JsValue::from_str("file transfer cancelled"));
+            }
+            let pending = self.inner.borrow().pending.clone();
+            if pending() <= MAX_PENDING_BYTES {
+                return Ok(());
+            }
+            JsFuture::from(timeout_promise(PENDING_POLL_INTERVAL_MS)).await?;
+        }
+    }
+
+    /// Handles file chunks, returns other messages to be processed by the application.
+    ///
+    /// # Errors
+    /// This function errors if the message is a malformed chunk or arrives out of order,
+    /// in which case the file it belongs to is dropped.
+    pub fn handle_message(&self, message: &str) -> Result<Option<String>, JsValue> {
+        match control::decode(message) {
+            Some((Kind::FileChunk, chunk)) => {
+                self.receive_chunk(chunk)?;
+                Ok(None)
+            }
+            Some((Kind::FileCancel, id)) => {
+                let id = id
+                    .parse()
+                    .map_err(|_| JsValue::from_str("malformed file transfer cancel"))?;
+                self.inner.borrow_mut().incoming.remove(&id);
+                Ok(None)
+            }
+            _ => Ok(Some(message.to_string())),
+        }
+    }
+
+    fn receive_chunk(&self, chunk: &str) -> Result<(), JsValue> {
+        let invalid_chunk = || JsValue::from_str("malformed file chunk");
+        let mut parts = chunk.splitn(4, ':');
+        let mut next_number = || {
+            parts
+                .next()
+                .and_then(|number| number.parse::<usize>().ok())
+                .ok_or_else(invalid_chunk)
+        };
+        let (id, offset, total) = (next_number()? as u64, next_number()?, next_number()?);
+        let data = parts
+            .next()
+            .and_then(decode_base64)
+            .ok_or_else(invalid_chunk)?;
+
+        let (received, complete) = {
+            let mut inner = self.inner.borrow_mut();
+            if offset == 0 {
+                let file = IncomingFile {
+                    total,
+                    data: Vec::new(),
+                };
+                inner.incoming.insert(id, file);
+            }
+            let file = inner
+                .incoming
+                .get_mut(&id)
+                .ok_or_else(|| JsValue::from_str("chunk of unknown file transfer"))?;
+            if offset != file.data.len() || total != file.total || offset + data.len() > total {
+                inner.incoming.remove(&id);
+                return Err(JsValue::from_str("file chunk out of order"));
+            }
+            file.data.extend_from_slice(&data);
+            let received = file.data.len();
+            let complete = if received == total {
+                inner.incoming.remove(&id).map(|file| file.data)
+            } else {
+                None
+            };
+            (received, complete)
+        };
+
+        if let Some(on_receive_progress) =
+            Callback::of(&self.inner, |inner| &inner.on_receive_progress)
+        {
+            on_receive_progress.call(id, received, total);
+        }
+        if let Some(file) = complete {
+            if let Some(on_file) = Callback::of(&self.inner, |inner| &inner.on_file) {
+                on_file.call(id, file);
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of files being received, whose last chunk didn't arrive yet.
+    pub fn incoming_count(&self) -> usize {
+        self.inner.borrow().incoming.len()
+    }
+
+    /// Cancels files being sent and drops the ones partially received, e.g. when the other peer leaves.
+    pub fn cancel_all(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.sending.clear();
+        inner.incoming.clear();
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn encode_base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bytes = [
+            group[0],
+            *group.get(1).unwrap_or(&0),
+            *group.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index <= group.len() {
+                let sextet = (bits >> (18 - 6 * index)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[sextet as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub(crate) fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.as_bytes();
+    if !encoded.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut data = Vec::with_capacity(encoded.len() / 4 * 3);
+    for group in encoded.chunks(4) {
+        let padding = group.iter().rev().take_while(|&&byte| byte == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+        let mut bits = 0;
+        for &byte in &group[..4 - padding] {
+            let sextet = BASE64_ALPHABET.iter().position(|&symbol| symbol == byte)?;
+            bits = bits << 6 | sextet as u32;
+        }
+        bits <<= 6 * padding;
+        data.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_base64_round_trips_all_lengths() {
+        for length in 0..8 {
+            let data: Vec<u8> = (0..length).map(|byte| byte * 37).collect();
+
+            assert_eq!(decode_base64(&encode_base64(&data)), Some(data));
+        }
+        assert_eq!(encode_base64(b"ab"), "YWI=");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_large_file_arrives_intact() {
+        let received = Rc::new(RefCell::new(None));
+        let received_clone = received.clone();
+        let receiver = FileTransfer::new(|_| Ok(()), || 0);
+        receiver.set_on_file(move |_id, file| *received_clone.borrow_mut() = Some(file));
+        let receiver_clone = receiver.clone();
+        let sender = FileTransfer::new(
+            move |message| receiver_clone.handle_message(message).map(|_| ()),
+            || 0,
+        );
+        let file: Vec<u8> = (0..3 * 1024 * 1024 + 7).map(|i| (i % 251) as u8).collect();
+        let mut progress = Vec::new();
+
+        sender
+            .send_file(&file, |sent, _total| progress.push(sent))
+            .await
+            .unwrap();
+
+        assert_eq!(received.borrow().as_deref(), Some(file.as_slice()));
+        assert_eq!(progress.last(), Some(&file.len()));
+        assert_eq!(receiver.incoming_count(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_cancel_drops_partially_received_file() {
+        let receiver = FileTransfer::new(|_| Ok(()), || 0);
+
+        receiver.handle_message("\u{1}f1:0:6:YWI=").unwrap();
+        assert_eq!(receiver.incoming_count(), 1);
+        receiver.handle_message("\u{1}c1").unwrap();
+
+        assert_eq!(receiver.incoming_count(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_chunk_out_of_order_is_rejected() {
+        let receiver = FileTransfer::new(|_| Ok(()), || 0);
+
+        receiver.handle_message("\u{1}f1:0:6:YWI=").unwrap();
+
+        assert!(receiver.handle_message("\u{1}f1:4:6:YWI=").is_err());
+        assert_eq!(receiver.incoming_count(), 0);
+    }
+}
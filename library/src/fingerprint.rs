@@ -0,0 +1,93 @@
+/*!
+`DTLS` certificate fingerprints of a connection, for verifying that it goes to the intended peer.
+
+Signaling server passes `SDP` between the peers and could swap it for its own, putting itself
+in the middle of the connection. Each peer's `SDP` carries the fingerprint of the certificate
+that secures the connection, so peers comparing the fingerprints over another channel,
+e.g. reading them out loud in a call, know that nobody is in the middle,
+the same way secure messengers compare safety numbers.
+
+```no_run
+use wasm_peers::one_to_one::NetworkManager;
+
+fn verify(network_manager: &NetworkManager, fingerprint_read_out_by_peer: &str) {
+    if !network_manager.verify_remote_fingerprint(fingerprint_read_out_by_peer) {
+        panic!("connection doesn't go to the intended peer");
+    }
+}
+```
+*/
+
+use serde::Serialize;
+
+/// Fingerprint of a `DTLS` certificate, as found in an `a=fingerprint` line of `SDP`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DtlsFingerprint {
+    /// Hash function, e.g. `sha-256`.
+    pub algorithm: String,
+    /// Uppercase hex bytes separated by colons, e.g. `AB:CD:...`.
+    pub value: String,
+}
+
+impl DtlsFingerprint {
+    /// Whether the fingerprint has the given value, ignoring case, colons and whitespace,
+    /// so values typed in by the user match too. Compares in constant time for values of the same length.
+    pub fn matches(&self, expected: &str) -> bool {
+        let actual = normalize(&self.value);
+        let expected = normalize(expected);
+        if actual.is_empty() || actual.len() != expected.len() {
+            return false;
+        }
+        actual
+            .iter()
+            .zip(&expected)
+            .fold(0, |difference, (actual, expected)| {
+                difference | (actual ^ expected)
+            })
+            == 0
+    }
+}
+
+fn normalize(value: &str) -> Vec<u8> {
+    value
+        .bytes()
+        .filter(|byte| *byte != b':' && !byte.is_ascii_whitespace())
+        .map(|byte| byte.to_ascii_uppercase())
+        .collect()
+}
+
+/// Returns the first fingerprint in the `SDP`, browsers use the same certificate for all of its media sections.
+#[cfg(feature = "one-to-one")]
+pub(crate) fn parse_fingerprint(sdp: &str) -> Option<DtlsFingerprint> {
+    sdp.lines().find_map(|line| {
+        let (algorithm, value) = line
+            .trim()
+            .strip_prefix("a=fingerprint:")?
+            .split_once(' ')?;
+        Some(DtlsFingerprint {
+            algorithm: algorithm.to_ascii_lowercase(),
+            value: value.trim().to_ascii_uppercase(),
+        })
+    })
+}
+
+#[cfg(all(test, feature = "one-to-one"))]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_fingerprint_is_parsed_and_matches_typed_in_value() {
+        let sdp = "v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\na=fingerprint:SHA-256 ab:cd:01:23\r\na=setup:actpass\r\n";
+
+        let fingerprint = parse_fingerprint(sdp).unwrap();
+
+        assert_eq!(fingerprint.algorithm, "sha-256");
+        assert_eq!(fingerprint.value, "AB:CD:01:23");
+        assert!(fingerprint.matches("abcd 0123"));
+        assert!(!fingerprint.matches("AB:CD:01:24"));
+        assert!(!fingerprint.matches("AB:CD:01"));
+        assert!(parse_fingerprint("v=0\r\n").is_none());
+    }
+}
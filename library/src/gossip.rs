@@ -0,0 +1,304 @@
+/*!
+Helper for broadcasting messages to every peer of a mesh, even ones this peer isn't connected to.
+
+[`Gossip::publish`] sends the message to all connected peers, and each peer passing received
+messages through [`Gossip::handle_message`] delivers it to the application and forwards it
+to its own peers, except the one it came from. This way a message reaches everyone
+connected to the mesh through any path, e.g. when some direct connections failed.
+
+Each message carries a random id and a time to live, decreased at each hop and capped
+at [`MAX_GOSSIP_TTL`]. Peers remember ids of the last [`MAX_SEEN_MESSAGES`] messages,
+so a message that comes back around a loop is dropped instead of being delivered
+and forwarded again.
+
+Gossip messages are [helper messages](crate::control),
+other messages are passed through unchanged.
+
+# Example
+
+```no_run
+use wasm_peers::gossip::Gossip;
+use wasm_peers::many_to_many::NetworkManager;
+use wasm_peers::{ConnectionType, SessionId};
+
+let mut network_manager = NetworkManager::new(
+    "ws://0.0.0.0:9001/one-to-many",
+    SessionId::new("some-session-id".to_string()),
+    ConnectionType::Local,
+)
+.unwrap();
+let peers_manager = network_manager.clone();
+let send_manager = network_manager.clone();
+let gossip = Gossip::new(
+    move || peers_manager.channels().into_iter().map(|(user_id, _)| user_id).collect(),
+    move |user_id, message| send_manager.send_message(user_id, message),
+);
+gossip.set_on_gossip(|message| log::info!("everyone got: {}", message));
+let gossip_clone = gossip.clone();
+let on_open = move |_user_id| {
+    let _ = gossip_clone.publish("new peer in the mesh", 4);
+};
+let gossip_clone = gossip.clone();
+let on_message = move |user_id, message: String| {
+    if let Ok(Some(message)) = gossip_clone.handle_message(user_id, &message) {
+        // handle regular message
+    }
+};
+network_manager.start(on_open, on_message);
+```
+*/
+
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
+
+use wasm_bindgen::JsValue;
+
+use crate::callback::Callback;
+use crate::control::{self, Kind};
+use crate::UserId;
+
+/// Highest number of hops a gossip message can make, higher values are lowered to it.
+pub const MAX_GOSSIP_TTL: u8 = 16;
+
+/// Number of message ids remembered for dropping duplicates, the oldest are forgotten first.
+pub const MAX_SEEN_MESSAGES: usize = 1024;
+
+type PeersFunction = Rc<dyn Fn() -> Vec<UserId>>;
+type SendFunction = Rc<dyn Fn(UserId, &str) -> Result<(), JsValue>>;
+type GossipCallback = Callback<dyn FnMut(String)>;
+
+struct GossipInner {
+    peers: PeersFunction,
+    send: SendFunction,
+    seen: HashSet<String>,
+    /// Ids in `seen`, from the oldest.
+    seen_order: VecDeque<String>,
+    on_gossip: Option<GossipCallback>,
+}
+
+impl GossipInner {
+    /// Returns `false` if the id was already seen.
+    fn remember(&mut self, id: &str) -> bool {
+        if self.seen.contains(id) {
+            return false;
+        }
+        if self.seen_order.len() == MAX_SEEN_MESSAGES {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(id.to_string());
+        self.seen_order.push_back(id.to_string());
+        true
+    }
+}
+
+/// Floods messages through the mesh and delivers the ones flooded by other peers once,
+/// using provided functions to list connected peers and send to them.
+///
+/// This class is a pointer to the underlying resource and can be cloned freely.
+#[derive(Clone)]
+pub struct Gossip {
+    inner: Rc<RefCell<GossipInner>>,
+}
+
+impl Gossip {
+    /// Creates a gossip helper listing connected peers with `peers` and sending to them with `send`,
+    /// e.g. [`crate::many_to_many::NetworkManager::send_message`].
+    pub fn new(
+        peers: impl Fn() -> Vec<UserId> + 'static,
+        send: impl Fn(UserId, &str) -> Result<(), JsValue> + 'static,
+    ) -> Self {
+        Gossip {
+            inner: Rc::new(RefCell::new(GossipInner {
+                peers: Rc::new(peers),
+                send: Rc::new(send),
+                seen: HashSet::new(),
+                seen_order: VecDeque::new(),
+                on_gossip: None,
+            })),
+        }
+    }
+
+    /// Sets the callback receiving messages published by other peers, each one only once.
+    pub fn set_on_gossip(&self, on_gossip: impl FnMut(String) + 'static) {
+        self.inner.borrow_mut().on_gossip = Some(GossipCallback::new(on_gossip));
+    }
+
+    /// Floods the message to all peers of the mesh reachable within `ttl` hops,
+    /// at most [`MAX_GOSSIP_TTL`]. Publishing peer doesn't receive its own message.
+    ///
+    /// # Errors
+    /// This function errors if sending to any of the connected peers fails,
+    /// the message is still sent to the others.
+    pub fn publish(&self, body: &str, ttl: u8) -> Result<(), JsValue> {
+        let ttl = ttl.min(MAX_GOSSIP_TTL);
+        if ttl == 0 {
+            return Ok(());
+        }
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        self.inner.borrow_mut().remember(&id);
+        self.send_to_peers(None, &id, ttl, body)
+    }
+
+    /// Delivers and forwards gossip messages, returns other messages to be processed by the application.
+    /// `from` is the peer the message was received from, it isn't sent the message back.
+    ///
+    /// # Errors
+    /// This function errors if the message is a malformed gossip message,
+    /// or if forwarding it to any of the connected peers fails.
+    pub fn handle_message(&self, from: UserId, message: &str) -> Result<Option<String>, JsValue> {
+        let gossip = match control::decode(message) {
+            Some((Kind::Gossip, gossip)) => gossip,
+            _ => return Ok(Some(message.to_string())),
+        };
+        let (id, ttl, body) = split_header(gossip)?;
+        if !self.inner.borrow_mut().remember(id) {
+            return Ok(None);
+        }
+        if let Some(on_gossip) = Callback::of(&self.inner, |inner| &inner.on_gossip) {
+            on_gossip.call(body.to_string());
+        }
+        let ttl = ttl.min(MAX_GOSSIP_TTL) - 1;
+        if ttl == 0 {
+            return Ok(None);
+        }
+        self.send_to_peers(Some(from), id, ttl, body)?;
+        Ok(None)
+    }
+
+    fn send_to_peers(
+        &self,
+        except: Option<UserId>,
+        id: &str,
+        ttl: u8,
+        body: &str,
+    ) -> Result<(), JsValue> {
+        let (peers, send) = {
+            let inner = self.inner.borrow();
+            (inner.peers.clone(), inner.send.clone())
+        };
+        let message = control::encode(Kind::Gossip, &format!("{}:{}:{}", id, ttl, body));
+        let mut result = Ok(());
+        for user_id in peers() {
+            if Some(user_id) == except {
+                continue;
+            }
+            if let Err(error) = send(user_id, &message) {
+                log::warn!(
+                    "failed to send gossip message to {:?}: {:?}",
+                    user_id,
+                    error
+                );
+                result = Err(error);
+            }
+        }
+        result
+    }
+}
+
+fn split_header(message: &str) -> Result<(&str, u8, &str), JsValue> {
+    let invalid_message = || JsValue::from_str("malformed gossip message");
+    let (id, rest) = message.split_once(':').ok_or_else(invalid_message)?;
+    let (ttl, body) = rest.split_once(':').ok_or_else(invalid_message)?;
+    let ttl: u8 = ttl.parse().map_err(|_| invalid_message())?;
+    if id.is_empty() || ttl == 0 {
+        return Err(invalid_message());
+    }
+    Ok((id, ttl, body))
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    type Sent = Rc<RefCell<Vec<(UserId, String)>>>;
+
+    fn recording_gossip(peers: Vec<usize>) -> (Gossip, Sent, Rc<RefCell<Vec<String>>>) {
+        let sent: Sent = Rc::new(RefCell::new(Vec::new()));
+        let sent_clone = sent.clone();
+        let gossip = Gossip::new(
+            move || peers.iter().copied().map(UserId::new).collect(),
+            move |user_id, message| {
+                sent_clone.borrow_mut().push((user_id, message.to_string()));
+                Ok(())
+            },
+        );
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let received_clone = received.clone();
+        gossip.set_on_gossip(move |message| received_clone.borrow_mut().push(message));
+        (gossip, sent, received)
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_is_delivered_once_and_forwarded_with_lower_ttl() {
+        let (gossip, sent, received) = recording_gossip(vec![1, 2, 3]);
+
+        assert_eq!(
+            gossip
+                .handle_message(UserId::new(1), "\u{1}gabc:3:hi")
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            gossip
+                .handle_message(UserId::new(2), "\u{1}gabc:3:hi")
+                .unwrap(),
+            None
+        );
+
+        assert_eq!(*received.borrow(), vec!["hi".to_string()]);
+        assert_eq!(
+            *sent.borrow(),
+            vec![
+                (UserId::new(2), "\u{1}gabc:2:hi".to_string()),
+                (UserId::new(3), "\u{1}gabc:2:hi".to_string()),
+            ]
+        );
+        assert_eq!(
+            gossip
+                .handle_message(UserId::new(1), "hello")
+                .unwrap()
+                .as_deref(),
+            Some("hello")
+        );
+        assert!(gossip
+            .handle_message(UserId::new(1), "\u{1}gabc:0:hi")
+            .is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_last_hop_isnt_forwarded_and_ttl_is_capped() {
+        let (gossip, sent, received) = recording_gossip(vec![1, 2]);
+
+        gossip
+            .handle_message(UserId::new(1), "\u{1}glast:1:bye")
+            .unwrap();
+        assert_eq!(*received.borrow(), vec!["bye".to_string()]);
+        assert!(sent.borrow().is_empty());
+
+        gossip.publish("far", 200).unwrap();
+        let (_, published) = &sent.borrow()[0];
+        assert!(published.contains(&format!(":{}:far", MAX_GOSSIP_TTL)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_seen_ids_are_bounded() {
+        let (gossip, _sent, received) = recording_gossip(Vec::new());
+
+        for id in 0..=MAX_SEEN_MESSAGES {
+            let message = format!("\u{1}g{}:1:{}", id, id);
+            gossip.handle_message(UserId::new(1), &message).unwrap();
+        }
+        assert_eq!(gossip.inner.borrow().seen.len(), MAX_SEEN_MESSAGES);
+
+        // the oldest id was forgotten, so it's delivered again
+        gossip
+            .handle_message(UserId::new(1), "\u{1}g0:1:0")
+            .unwrap();
+        assert_eq!(received.borrow().len(), MAX_SEEN_MESSAGES + 2);
+    }
+}
@@ -18,6 +18,17 @@ and [many-to-many] that creates connection for each pair of peers and allows sen
 
 */
 
+pub mod batching;
+mod callback;
+pub mod capabilities;
+pub mod connection_config;
+pub mod connectivity;
+pub mod control;
+mod error;
+pub mod file_transfer;
+pub mod fingerprint;
+pub mod gossip;
+pub mod local_discovery;
 #[deny(missing_docs)]
 #[warn(clippy::pedantic)]
 #[cfg(feature = "many-to-many")]
@@ -26,9 +37,17 @@ pub mod many_to_many;
 pub mod one_to_many;
 #[cfg(feature = "one-to-one")]
 pub mod one_to_one;
+pub mod request;
+pub mod stream;
 mod utils;
 
-pub use utils::ConnectionType;
+pub use capabilities::{client_capabilities, ClientCapabilities};
+pub use error::Error;
+pub use utils::{
+    ChannelInfo, ConnectionFallbackPolicy, ConnectionQuality, ConnectionType, DataChannelConfig,
+    DataChannelPriority, Diagnostics, IceOptions, SelectedCandidatePair,
+    FAILOVER_ATTEMPT_TIMEOUT_MS,
+};
 pub use wasm_peers_protocol::{SessionId, UserId};
 
 /// Returns a new `SessionId` instance that can be used to identify a session by signaling server.
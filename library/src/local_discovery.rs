@@ -0,0 +1,261 @@
+/*!
+Helper for advertising and discovering sessions of peers on the same local network,
+without a remote signaling server.
+
+Peers periodically [announce](Announcement) the session they're in, together with the address
+of a signaling server running on the local network, e.g. on one of the peers' machines,
+and peers discovering the announcement join the session through that server.
+Only session ids and server addresses are exchanged this way, `SDP` and `ICE` candidates
+still go through the announced signaling server as usual.
+
+# Platform limitations
+
+Browsers offer no way of sending or receiving local network broadcasts, so in the browser
+[`BrowserDiscovery`] can only reach other tabs, windows and workers of the same origin
+in the same browser, using a `BroadcastChannel`. That's enough to find sessions opened in other tabs,
+but peers on other devices have to learn the session id another way, e.g. from a QR code.
+
+Native targets get [`UdpDiscovery`], which broadcasts announcements as UDP datagrams
+on a port agreed on by the application. Broadcasts don't cross routers,
+and some networks, e.g. guest networks or ones with client isolation, drop them entirely.
+
+Announcements aren't authenticated, anyone on the network can send them,
+so they should be treated as suggestions, e.g. shown to the user to pick from.
+
+# Example
+
+```no_run
+use wasm_peers::local_discovery::{Announcement, BrowserDiscovery};
+use wasm_peers::get_random_session_id;
+
+let discovery = BrowserDiscovery::new().unwrap();
+discovery.set_on_announcement(|announcement| {
+    log::info!("found session: {:?}", announcement);
+});
+discovery
+    .announce(&Announcement {
+        session_id: get_random_session_id(),
+        signaling_server_url: Some("ws://192.168.1.10:9001/one-to-one".to_string()),
+        name: "living room".to_string(),
+    })
+    .unwrap();
+```
+*/
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_peers_protocol::SessionId;
+use web_sys::{BroadcastChannel, MessageEvent};
+
+/// Announcements start with this prefix, so that unrelated traffic on the channel or port is ignored.
+const ANNOUNCEMENT_PREFIX: &str = "wasm-peers/1:";
+
+/// Longest announcement accepted, longer ones are dropped.
+pub const MAX_ANNOUNCEMENT_LENGTH: usize = 1024;
+
+/// Name of the `BroadcastChannel` used by [`BrowserDiscovery`].
+const BROADCAST_CHANNEL_NAME: &str = "wasm-peers-discovery";
+
+/// Session advertised to other peers on the local network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Announcement {
+    /// Session peers should join to connect with the announcing peer.
+    pub session_id: SessionId,
+    /// Signaling server peers should join the session through,
+    /// `None` if they're expected to know it already.
+    pub signaling_server_url: Option<String>,
+    /// Human readable name of the session, e.g. for a list of sessions to join.
+    pub name: String,
+}
+
+fn encode(announcement: &Announcement) -> Result<String, String> {
+    let announcement =
+        serde_json_wasm::to_string(announcement).map_err(|error| error.to_string())?;
+    let announcement = format!("{}{}", ANNOUNCEMENT_PREFIX, announcement);
+    if announcement.len() > MAX_ANNOUNCEMENT_LENGTH {
+        return Err(format!(
+            "announcement is too long: {} bytes, maximum is {}",
+            announcement.len(),
+            MAX_ANNOUNCEMENT_LENGTH
+        ));
+    }
+    Ok(announcement)
+}
+
+/// Returns `None` if the message isn't a valid announcement.
+fn decode(message: &str) -> Option<Announcement> {
+    if message.len() > MAX_ANNOUNCEMENT_LENGTH {
+        return None;
+    }
+    serde_json_wasm::from_str(message.strip_prefix(ANNOUNCEMENT_PREFIX)?).ok()
+}
+
+type MessageHandler = Closure<dyn FnMut(MessageEvent)>;
+
+/// Discovery between tabs and workers of the same origin in the browser,
+/// see [platform limitations](crate::local_discovery#platform-limitations).
+///
+/// This class is a pointer to the underlying resource and can be cloned freely.
+#[derive(Debug, Clone)]
+pub struct BrowserDiscovery {
+    channel: BroadcastChannel,
+    on_message: Rc<RefCell<Option<MessageHandler>>>,
+}
+
+impl BrowserDiscovery {
+    /// Opens the broadcast channel shared by all pages of the origin.
+    ///
+    /// # Errors
+    /// This function errors if the browser doesn't support `BroadcastChannel`.
+    pub fn new() -> Result<Self, JsValue> {
+        Ok(BrowserDiscovery {
+            channel: BroadcastChannel::new(BROADCAST_CHANNEL_NAME)?,
+            on_message: Rc::new(RefCell::new(None)),
+        })
+    }
+
+    /// Sends the announcement to every other page listening, but not to this one.
+    /// Pages opened later don't receive it, so it should be repeated periodically.
+    ///
+    /// # Errors
+    /// This function errors if the announcement is longer than [`MAX_ANNOUNCEMENT_LENGTH`] bytes
+    /// or if the channel is closed.
+    pub fn announce(&self, announcement: &Announcement) -> Result<(), JsValue> {
+        let announcement = encode(announcement).map_err(|error| JsValue::from_str(&error))?;
+        self.channel.post_message(&JsValue::from_str(&announcement))
+    }
+
+    /// Sets a callback called with each announcement received from other pages,
+    /// replacing the previous one.
+    pub fn set_on_announcement(&self, mut on_announcement: impl FnMut(Announcement) + 'static) {
+        let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
+            if let Some(announcement) = event.data().as_string().as_deref().and_then(decode) {
+                on_announcement(announcement);
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        self.channel
+            .set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        // keep the closure alive for as long as the channel uses it
+        *self.on_message.borrow_mut() = Some(on_message);
+    }
+
+    /// Stops announcing and receiving announcements.
+    pub fn close(&self) {
+        self.channel.set_onmessage(None);
+        self.channel.close();
+        self.on_message.borrow_mut().take();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::UdpDiscovery;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::io;
+    use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+    use std::time::Duration;
+
+    use super::{decode, encode, Announcement, MAX_ANNOUNCEMENT_LENGTH};
+
+    /// Discovery over UDP broadcasts on the local network, for native targets,
+    /// see [platform limitations](crate::local_discovery#platform-limitations).
+    #[derive(Debug)]
+    pub struct UdpDiscovery {
+        socket: UdpSocket,
+        port: u16,
+    }
+
+    impl UdpDiscovery {
+        /// Listens for announcements broadcast to given port, on all network interfaces.
+        /// Each peer of the application must use the same port.
+        ///
+        /// # Errors
+        /// This function errors if binding to the port fails, e.g. because it's taken.
+        pub fn bind(port: u16) -> io::Result<Self> {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+            socket.set_broadcast(true)?;
+            Ok(UdpDiscovery { socket, port })
+        }
+
+        /// Broadcasts the announcement to the local network.
+        /// Peers starting later don't receive it, so it should be repeated periodically.
+        ///
+        /// # Errors
+        /// This function errors if the announcement is longer than [`MAX_ANNOUNCEMENT_LENGTH`] bytes
+        /// or if sending it fails.
+        pub fn announce(&self, announcement: &Announcement) -> io::Result<()> {
+            let announcement = encode(announcement)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+            self.socket
+                .send_to(announcement.as_bytes(), (Ipv4Addr::BROADCAST, self.port))?;
+            Ok(())
+        }
+
+        /// Waits for the next announcement with the address it came from, skipping invalid ones.
+        /// Peer's own announcements are received too.
+        /// Returns `None` if there was none within the timeout.
+        ///
+        /// # Errors
+        /// This function errors if receiving from the socket fails.
+        pub fn next_announcement(
+            &self,
+            timeout: Duration,
+        ) -> io::Result<Option<(SocketAddr, Announcement)>> {
+            self.socket.set_read_timeout(Some(timeout))?;
+            let mut buffer = [0; MAX_ANNOUNCEMENT_LENGTH];
+            loop {
+                let (length, sender) = match self.socket.recv_from(&mut buffer) {
+                    Ok(received) => received,
+                    Err(error)
+                        if matches!(
+                            error.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        return Ok(None)
+                    }
+                    Err(error) => return Err(error),
+                };
+                if let Some(announcement) =
+                    std::str::from_utf8(&buffer[..length]).ok().and_then(decode)
+                {
+                    return Ok(Some((sender, announcement)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_announcement_round_trips_and_foreign_messages_are_ignored() {
+        let announcement = Announcement {
+            session_id: SessionId::new("dummy-session-id".to_string()),
+            signaling_server_url: Some("ws://192.168.1.10:9001/one-to-one".to_string()),
+            name: "living room".to_string(),
+        };
+
+        let encoded = encode(&announcement).unwrap();
+
+        assert_eq!(decode(&encoded), Some(announcement));
+        assert_eq!(decode(r#"{"session_id":"dummy-session-id"}"#), None);
+        assert_eq!(
+            decode(&format!(
+                "{}{}",
+                ANNOUNCEMENT_PREFIX,
+                " ".repeat(MAX_ANNOUNCEMENT_LENGTH)
+            )),
+            None
+        );
+    }
+}
@@ -13,7 +13,7 @@ In this example we create 3 peers that all establish connection with each other.
 Each of the peers will send a `ping` message to each new connection.
 Also each peer will respond with a `pong` response.
 Overall we will expect 6 `ping` and 6 `pong` messages (3 connections, both peers in each).
-```
+```no_run
 use wasm_peers::many_to_many::NetworkManager;
 use wasm_peers::{ConnectionType, SessionId};
 use std::cell::RefCell;
@@ -62,10 +62,12 @@ peer_generator();
  */
 
 use wasm_bindgen::JsValue;
+use wasm_peers_protocol::one_to_many::SessionInfo;
 use wasm_peers_protocol::{SessionId, UserId};
 
 use crate::one_to_many::NetworkManager as OneToManyNetworkManager;
-use crate::ConnectionType;
+pub use crate::one_to_many::{PeerHandle, Transport};
+use crate::{ChannelInfo, ConnectionType, DataChannelConfig, Error};
 
 /// Abstraction over `WebRTC` peer-to-peer connection.
 /// Structure representing equal peer in many-to-many topology.
@@ -109,6 +111,94 @@ impl NetworkManager {
         })
     }
 
+    /// Same as [`NetworkManager::new`], but accepts a list of signaling server addresses
+    /// for redundancy. They are tried in order and the first one that accepts
+    /// the connection is used for the whole signaling.
+    ///
+    /// All peers in the session must end up connected to the same signaling server instance
+    /// for the signaling to work, so lists on all sides should be ordered the same way.
+    /// Servers not accepting the connection within [`crate::FAILOVER_ATTEMPT_TIMEOUT_MS`] count as failed.
+    ///
+    /// # Errors
+    /// This function errors if none of the signaling servers accepts the connection.
+    pub async fn new_with_failover(
+        signaling_server_urls: &[&str],
+        session_id: SessionId,
+        connection_type: ConnectionType,
+    ) -> Result<Self, JsValue> {
+        Ok(NetworkManager {
+            inner: OneToManyNetworkManager::new_with_failover(
+                signaling_server_urls,
+                session_id,
+                connection_type,
+                true,
+            )
+            .await?,
+        })
+    }
+
+    /// Overrides options used when creating data channels to other peers.
+    /// Must be called before [`NetworkManager::start`] to take effect.
+    ///
+    /// # Errors
+    /// This function errors if configured protocol exceeds the length allowed by the specification.
+    pub fn set_data_channel_config(
+        &mut self,
+        data_channel_config: DataChannelConfig,
+    ) -> Result<(), JsValue> {
+        self.inner.set_data_channel_config(data_channel_config)
+    }
+
+    /// Limits how many peers are negotiated with at the same time, as browsers struggle
+    /// with many simultaneous offers. Peers joining while the limit is reached wait
+    /// until earlier connections are established or fail. It doesn't apply to [`NetworkManager::connect_to`].
+    /// Defaults to [`DEFAULT_MAX_PENDING_NEGOTIATIONS`](crate::one_to_many::DEFAULT_MAX_PENDING_NEGOTIATIONS), values below 1 are treated as 1.
+    /// Must be called before [`NetworkManager::start`] to take effect.
+    pub fn set_max_pending_negotiations(&mut self, max_pending_negotiations: usize) {
+        self.inner
+            .set_max_pending_negotiations(max_pending_negotiations);
+    }
+
+    /// Sets how long a negotiation with a peer can take before its place is given
+    /// to a queued one, e.g. when the peer never answers.
+    /// The connection isn't closed, so a late answer still establishes it. Defaults to
+    /// [`DEFAULT_NEGOTIATION_TIMEOUT_MS`](crate::one_to_many::DEFAULT_NEGOTIATION_TIMEOUT_MS) milliseconds.
+    pub fn set_negotiation_timeout(&mut self, timeout_ms: u32) {
+        self.inner.set_negotiation_timeout(timeout_ms);
+    }
+
+    /// Stops connecting to each peer joining the session automatically.
+    /// Instead, joining peers are listed in [`NetworkManager::available_peers`]
+    /// and connections are made one by one with [`NetworkManager::connect_to`].
+    /// Must be called before [`NetworkManager::start`] to take effect.
+    ///
+    /// Signaling server only notifies peers already in session about the ones joining,
+    /// so connections are always made by the peers that joined earlier.
+    pub fn set_manual_connect(&mut self) {
+        self.inner.set_manual_connect();
+    }
+
+    /// Lists peers that joined the session, but aren't connected to yet in manual connect mode.
+    /// Peers are removed once connected to, or when they leave the session.
+    #[must_use]
+    pub fn available_peers(&self) -> Vec<UserId> {
+        self.inner.available_peers()
+    }
+
+    /// Starts connecting to a single peer in manual connect mode, see [`NetworkManager::set_manual_connect`].
+    /// Resolves with a handle to the peer once the offer is sent, and the peer is no longer
+    /// listed in [`NetworkManager::available_peers`]. Once the connection opens,
+    /// `on_open_callback` is called with its [`UserId`] as usual and the handle can send messages.
+    ///
+    /// # Errors
+    /// This function errors with [`Error::NotStarted`] if network manager isn't started yet,
+    /// [`Error::PeerUnavailable`] if the peer isn't available in session, [`Error::AlreadyConnected`]
+    /// if it's already connected or being connected to, and [`Error::PeerUnreachable`]
+    /// if creating the connection or sending the offer fails, in which case the peer stays available.
+    pub async fn connect_to(&self, user_id: UserId) -> Result<PeerHandle, Error> {
+        self.inner.connect_to(user_id).await
+    }
+
     /// Second part of the setup that begins the actual connection.
     /// Requires specifying a callbacks that are guaranteed to run
     /// when a new connection opens and on each message received.
@@ -131,8 +221,155 @@ impl NetworkManager {
         self.inner.send_message(user_id, message)
     }
 
+    /// Sends message to a single peer over the data channel if it's open, or through the signaling server
+    /// relay otherwise, e.g. while the connection is still being established or after it failed.
+    /// Either way the message is passed to the peer's `on_message_callback`.
+    /// Returns the transport that carried the message.
+    ///
+    /// # Errors
+    /// This function errors if there is no open data channel and relay fallback is disabled
+    /// with [`NetworkManager::set_relay_fallback`], if the relayed message is longer than
+    /// [`MAX_RELAYED_DATA_LENGTH`](crate::one_to_many::MAX_RELAYED_DATA_LENGTH) bytes,
+    /// or if sending it fails.
+    pub fn send(&self, user_id: UserId, message: &str) -> Result<Transport, JsValue> {
+        self.inner.send(user_id, message)
+    }
+
+    /// Returns the transport [`NetworkManager::send`] would currently use for given peer,
+    /// `None` if it would fail.
+    #[must_use]
+    pub fn transport(&self, user_id: UserId) -> Option<Transport> {
+        self.inner.transport(user_id)
+    }
+
+    /// Enables or disables falling back to the signaling server relay in [`NetworkManager::send`],
+    /// e.g. to keep the relay traffic off the server. Enabled by default.
+    pub fn set_relay_fallback(&mut self, enabled: bool) {
+        self.inner.set_relay_fallback(enabled);
+    }
+
     /// Convenience method that sends the same message to all connected peers.
     pub fn send_message_to_all(&self, message: &str) {
         self.inner.send_message_to_all(message);
     }
+
+    /// Returns the sub-protocol of the data channel established with given peer.
+    ///
+    /// # Errors
+    /// This function errors if there is no data channel established with given peer yet.
+    pub fn data_channel_protocol(&self, user_id: UserId) -> Result<String, JsValue> {
+        self.inner.data_channel_protocol(user_id)
+    }
+
+    /// Sends data through the signaling server to the listed peers in session,
+    /// e.g. to a single team in a game. Peers that aren't in session are skipped.
+    /// Unlike data channel messages, it works before connections with the peers are established.
+    ///
+    /// # Errors
+    /// This function errors if data is longer than
+    /// [`MAX_RELAYED_DATA_LENGTH`](crate::one_to_many::MAX_RELAYED_DATA_LENGTH) bytes
+    /// or if sending the request to signaling server fails.
+    pub fn relay_to(&self, user_ids: &[UserId], data: &[u8]) -> Result<(), JsValue> {
+        self.inner.relay_to(user_ids, data)
+    }
+
+    /// Sets a callback called with data relayed by other peers using [`NetworkManager::relay_to`].
+    pub fn set_on_relayed(&mut self, on_relayed: impl FnMut(UserId, Vec<u8>) + 'static) {
+        self.inner.set_on_relayed(on_relayed);
+    }
+
+    /// Subscribes to data published to the topic by other peers in session with [`NetworkManager::publish`],
+    /// passed to the callback set with [`NetworkManager::set_on_published`].
+    /// Signaling server limits how many topics each peer and session can have, responding with an error over the limit.
+    ///
+    /// # Errors
+    /// This function errors if topic is longer than
+    /// [`MAX_TOPIC_LENGTH`](wasm_peers_protocol::one_to_many::MAX_TOPIC_LENGTH) bytes
+    /// or if sending the request to signaling server fails.
+    pub fn subscribe(&self, topic: &str) -> Result<(), JsValue> {
+        self.inner.subscribe(topic)
+    }
+
+    /// Stops receiving data published to the topic.
+    ///
+    /// # Errors
+    /// This function errors if sending the request to signaling server fails.
+    pub fn unsubscribe(&self, topic: &str) -> Result<(), JsValue> {
+        self.inner.unsubscribe(topic)
+    }
+
+    /// Sends data through the signaling server to the peers in session subscribed to the topic,
+    /// without having to subscribe to it.
+    /// Like [`NetworkManager::relay_to`], it works before connections with the peers are established.
+    ///
+    /// # Errors
+    /// This function errors if topic is longer than
+    /// [`MAX_TOPIC_LENGTH`](wasm_peers_protocol::one_to_many::MAX_TOPIC_LENGTH) bytes,
+    /// data is longer than [`MAX_RELAY_LENGTH`](wasm_peers_protocol::one_to_many::MAX_RELAY_LENGTH) bytes
+    /// or if sending the request to signaling server fails.
+    pub fn publish(&self, topic: &str, data: &[u8]) -> Result<(), JsValue> {
+        self.inner.publish(topic, data)
+    }
+
+    /// Sets a callback called with the sender, topic and data published with [`NetworkManager::publish`]
+    /// to topics this peer is subscribed to.
+    pub fn set_on_published(
+        &mut self,
+        on_published: impl FnMut(UserId, String, Vec<u8>) + 'static,
+    ) {
+        self.inner.set_on_published(on_published);
+    }
+
+    /// Lists data channels established with other peers with their current state.
+    #[must_use]
+    pub fn channels(&self) -> Vec<(UserId, ChannelInfo)> {
+        self.inner.channels()
+    }
+
+    /// Hands over owner rights of the session to another peer, e.g. before leaving.
+    /// Only the current owner, initially the first peer to join, can do it,
+    /// signaling server responds with an error otherwise.
+    /// If the owner leaves without doing it, the session stays without an owner.
+    ///
+    /// # Errors
+    /// This function errors if sending the request to signaling server fails.
+    pub fn transfer_ownership(&self, new_owner: UserId) -> Result<(), JsValue> {
+        self.inner.transfer_ownership(new_owner)
+    }
+
+    /// Closes the session for every peer in it, unlike leaving it. By default only the owner can do it,
+    /// signaling server responds with an error otherwise. Peers are told with the callback set with
+    /// [`NetworkManager::set_on_session_closed`], connections between them are left to the application.
+    ///
+    /// # Errors
+    /// This function errors if sending the request to signaling server fails.
+    pub fn close_session(&self) -> Result<(), JsValue> {
+        self.inner.close_session()
+    }
+
+    /// Sets a callback called with the reason when the session is closed for everyone,
+    /// e.g. with [`NetworkManager::close_session`].
+    pub fn set_on_session_closed(&mut self, on_session_closed: impl FnMut(String) + 'static) {
+        self.inner.set_on_session_closed(on_session_closed);
+    }
+
+    /// Asks signaling server for the current state of the session, e.g. after the view of it
+    /// may have become stale. Response is passed to the callback set with [`NetworkManager::set_on_session_status`].
+    ///
+    /// # Errors
+    /// This function errors if sending the request to signaling server fails.
+    pub fn query_session(&self) -> Result<(), JsValue> {
+        self.inner.query_session()
+    }
+
+    /// Sets a callback called with the state of the session requested with [`NetworkManager::query_session`].
+    pub fn set_on_session_status(&mut self, on_session_status: impl FnMut(SessionInfo) + 'static) {
+        self.inner.set_on_session_status(on_session_status);
+    }
+
+    /// Sets a callback called with each notice the signaling server operator sends to all users,
+    /// e.g. about upcoming maintenance, to be shown to the user.
+    pub fn set_on_server_notice(&mut self, on_server_notice: impl FnMut(String) + 'static) {
+        self.inner.set_on_server_notice(on_server_notice);
+    }
 }
@@ -5,7 +5,7 @@ use wasm_bindgen::{JsCast, JsValue};
 use wasm_peers_protocol::one_to_many::SignalMessage;
 use wasm_peers_protocol::{SessionId, UserId};
 use web_sys::{
-    MessageEvent, RtcDataChannel, RtcDataChannelEvent, RtcPeerConnection,
+    MessageEvent, RtcDataChannel, RtcDataChannelEvent, RtcIceConnectionState, RtcPeerConnection,
     RtcPeerConnectionIceEvent, WebSocket,
 };
 
@@ -92,14 +92,21 @@ pub(crate) fn set_websocket_on_message(
 /// once web socket is open, send a request to start or join a session
 pub(crate) fn set_websocket_on_open(websocket: &WebSocket, session_id: SessionId, is_host: bool) {
     let websocket_clone = websocket.clone();
-    let onopen_callback = Closure::wrap(Box::new(move |_| {
+    let send_session_join = move || {
         let signal_message = SignalMessage::SessionJoin(session_id.clone(), is_host);
         let signal_message =
             serde_json_wasm::to_string(&signal_message).expect("failed serializing SignalMessage");
         websocket_clone
             .send_with_str(&signal_message)
             .expect("failed sending start-or-join message to the websocket");
-    }) as Box<dyn FnMut(JsValue)>);
+    };
+    // websocket connected to up front (e.g. during failover) won't fire onopen again
+    if websocket.ready_state() == WebSocket::OPEN {
+        send_session_join();
+        return;
+    }
+    let onopen_callback =
+        Closure::wrap(Box::new(move |_| send_session_join()) as Box<dyn FnMut(JsValue)>);
     websocket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
     onopen_callback.forget();
 }
@@ -178,13 +185,22 @@ pub(crate) fn set_data_channel_on_open(
 
 pub(crate) fn set_peer_connection_on_ice_connection_state_change(
     peer_connection: &RtcPeerConnection,
+    peer_id: UserId,
+    network_manager: NetworkManager,
 ) {
     let peer_connection_clone = peer_connection.clone();
     let on_ice_connection_state_change = Closure::wrap(Box::new(move || {
-        debug!(
-            "connection state change: {:?}",
-            peer_connection_clone.ice_connection_state()
-        )
+        let ice_connection_state = peer_connection_clone.ice_connection_state();
+        debug!("connection state change: {:?}", ice_connection_state);
+        if matches!(
+            ice_connection_state,
+            RtcIceConnectionState::Connected
+                | RtcIceConnectionState::Completed
+                | RtcIceConnectionState::Failed
+                | RtcIceConnectionState::Closed
+        ) {
+            network_manager.negotiation_finished(peer_id);
+        }
     }) as Box<dyn FnMut()>);
     peer_connection.set_oniceconnectionstatechange(Some(
         on_ice_connection_state_change.as_ref().unchecked_ref(),
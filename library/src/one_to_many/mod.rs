@@ -16,7 +16,7 @@ This example shows three peers connecting, with one being a dedicated host.
 Host waits for both peers to connect and only then sends `ping` messages to both
 and clients independently respond with `pong` messages.
 
-```
+```no_run
 use wasm_peers::one_to_many::{MiniClient, MiniServer};
 use wasm_peers::ConnectionType;
 use std::cell::RefCell;
@@ -82,23 +82,59 @@ client_generator();
 */
 
 mod callbacks;
+mod negotiation_queue;
+mod relay_frame;
 mod websocket_handler;
 
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
 
+use log::{debug, error, info, warn};
 use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use wasm_peers_protocol::one_to_many::{SessionInfo, SignalMessage};
+#[cfg(feature = "many-to-many")]
+use wasm_peers_protocol::one_to_many::{MAX_RELAY_LENGTH, MAX_TOPIC_LENGTH};
 use wasm_peers_protocol::{SessionId, UserId};
-use web_sys::{RtcDataChannel, RtcPeerConnection, WebSocket};
+#[cfg(feature = "many-to-many")]
+use web_sys::RtcDataChannelState;
+use web_sys::{RtcDataChannel, RtcIceCandidate, RtcPeerConnection, WebSocket};
 
+use crate::callback::Callback;
 use crate::one_to_many::callbacks::{set_websocket_on_message, set_websocket_on_open};
-use crate::ConnectionType;
+use crate::one_to_many::negotiation_queue::NegotiationQueue;
+pub use crate::one_to_many::negotiation_queue::{
+    DEFAULT_MAX_PENDING_NEGOTIATIONS, DEFAULT_NEGOTIATION_TIMEOUT_MS,
+};
+use crate::one_to_many::relay_frame::RelayFrame;
+pub use crate::one_to_many::relay_frame::MAX_RELAYED_DATA_LENGTH;
+use crate::utils::{
+    get_data_channel_protocol, open_websocket, open_websocket_with_failover, serialize_message,
+    timeout_promise, FAILOVER_ATTEMPT_TIMEOUT_MS,
+};
+#[cfg(feature = "many-to-many")]
+use crate::Error;
+use crate::{ChannelInfo, ConnectionType, DataChannelConfig};
+
+/// Way a message sent with `send` reaches the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Directly, over the open data channel with the peer.
+    DataChannel,
+    /// Through the signaling server, while there is no open data channel with the peer.
+    Relay,
+}
 
 #[derive(Debug, Clone)]
 struct Connection {
     peer_connection: RtcPeerConnection,
     data_channel: Option<RtcDataChannel>,
+    /// Candidates received before the remote description was set.
+    held_ice_candidates: Vec<RtcIceCandidate>,
 }
 
 impl Connection {
@@ -106,6 +142,7 @@ impl Connection {
         Connection {
             peer_connection,
             data_channel,
+            held_ice_candidates: Vec::new(),
         }
     }
 }
@@ -115,8 +152,87 @@ struct NetworkManagerInner {
     session_id: SessionId,
     websocket: WebSocket,
     connection_type: ConnectionType,
+    data_channel_config: DataChannelConfig,
     is_host: bool,
     connections: HashMap<UserId, Connection>,
+    on_relayed: Option<RelayedCallback>,
+    on_published: Option<PublishedCallback>,
+    /// Callback passed to `start`, also called with messages that fell back to the relay.
+    on_message: Option<PeerMessageCallback>,
+    #[cfg(feature = "many-to-many")]
+    /// Whether `send` falls back to the relay when there is no open data channel.
+    relay_fallback: bool,
+    on_session_status: Option<SessionStatusCallback>,
+    on_session_closed: Option<MessageCallback>,
+    on_server_notice: Option<MessageCallback>,
+    /// Don't connect to peers joining the session until asked to with `connect_to`.
+    manual_connect: bool,
+    /// Peers that joined the session, but aren't connected to in manual connect mode.
+    available_peers: HashSet<UserId>,
+    #[cfg(feature = "many-to-many")]
+    /// Peers `connect_to` is sending an offer to.
+    connecting: HashSet<UserId>,
+    connector: Option<Connector>,
+    negotiations: NegotiationQueue,
+}
+
+type ConnectFn = dyn Fn(UserId) -> Pin<Box<dyn Future<Output = Result<(), JsValue>>>>;
+
+/// Starts connecting to a peer with callbacks provided to `start`.
+#[derive(Clone)]
+struct Connector(Rc<ConnectFn>);
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Connector")
+    }
+}
+
+type MessageCallback = Callback<dyn FnMut(String)>;
+type PeerMessageCallback = Callback<dyn FnMut(UserId, String)>;
+type RelayedCallback = Callback<dyn FnMut(UserId, Vec<u8>)>;
+type PublishedCallback = Callback<dyn FnMut(UserId, String, Vec<u8>)>;
+type SessionStatusCallback = Callback<dyn FnMut(SessionInfo)>;
+
+#[cfg(feature = "many-to-many")]
+fn check_topic(topic: &str) -> Result<(), JsValue> {
+    if topic.len() > MAX_TOPIC_LENGTH {
+        return Err(JsValue::from_str(&format!(
+            "topic is too long: {} bytes, maximum is {}",
+            topic.len(),
+            MAX_TOPIC_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+/// Connection with a single peer made with `connect_to`, see [`crate::many_to_many::NetworkManager::connect_to`].
+/// It doesn't keep the network manager alive.
+#[derive(Debug, Clone)]
+pub struct PeerHandle {
+    user_id: UserId,
+    network_manager: Weak<RefCell<NetworkManagerInner>>,
+}
+
+impl PeerHandle {
+    /// Id of the peer, the same one that `on_open_callback` and `on_message_callback` are called with.
+    #[must_use]
+    pub fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    /// Sends a message to the peer over the data channel, once it opens.
+    ///
+    /// # Errors
+    /// This function errors if the network manager was dropped,
+    /// or the data channel isn't open yet or failed to send the message.
+    pub fn send_message(&self, message: &str) -> Result<(), JsValue> {
+        let inner = self
+            .network_manager
+            .upgrade()
+            .ok_or_else(|| JsValue::from_str("network manager was dropped"))?;
+        NetworkManager { inner }.send_message(self.user_id, message)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -131,18 +247,72 @@ impl NetworkManager {
         connection_type: ConnectionType,
         is_host: bool,
     ) -> Result<Self, JsValue> {
-        let websocket = WebSocket::new(signaling_server_url)?;
-        websocket.set_binary_type(web_sys::BinaryType::Arraybuffer);
+        let websocket = open_websocket(signaling_server_url)?;
+
+        Ok(Self::with_websocket(
+            websocket,
+            session_id,
+            connection_type,
+            is_host,
+        ))
+    }
 
-        Ok(NetworkManager {
+    pub(crate) async fn new_with_failover(
+        signaling_server_urls: &[&str],
+        session_id: SessionId,
+        connection_type: ConnectionType,
+        is_host: bool,
+    ) -> Result<Self, JsValue> {
+        let websocket =
+            open_websocket_with_failover(signaling_server_urls, FAILOVER_ATTEMPT_TIMEOUT_MS)
+                .await?;
+        Ok(Self::with_websocket(
+            websocket,
+            session_id,
+            connection_type,
+            is_host,
+        ))
+    }
+
+    fn with_websocket(
+        websocket: WebSocket,
+        session_id: SessionId,
+        connection_type: ConnectionType,
+        is_host: bool,
+    ) -> Self {
+        NetworkManager {
             inner: Rc::new(RefCell::new(NetworkManagerInner {
                 session_id,
                 websocket,
                 connection_type,
+                data_channel_config: DataChannelConfig::default(),
                 is_host,
                 connections: HashMap::new(),
+                on_relayed: None,
+                on_published: None,
+                on_message: None,
+                #[cfg(feature = "many-to-many")]
+                relay_fallback: true,
+                on_session_status: None,
+                on_session_closed: None,
+                on_server_notice: None,
+                manual_connect: false,
+                available_peers: HashSet::new(),
+                #[cfg(feature = "many-to-many")]
+                connecting: HashSet::new(),
+                connector: None,
+                negotiations: NegotiationQueue::default(),
             })),
-        })
+        }
+    }
+
+    pub(crate) fn set_data_channel_config(
+        &mut self,
+        data_channel_config: DataChannelConfig,
+    ) -> Result<(), JsValue> {
+        data_channel_config.validate()?;
+        self.inner.borrow_mut().data_channel_config = data_channel_config;
+        Ok(())
     }
 
     pub(crate) fn start(
@@ -154,6 +324,34 @@ impl NetworkManager {
         let session_id = self.inner.borrow().session_id.clone();
         let is_host = self.inner.borrow().is_host;
 
+        // connector is kept in the network manager, so it holds a weak reference to it
+        let network_manager = Rc::downgrade(&self.inner);
+        let websocket_clone = websocket.clone();
+        let session_id_clone = session_id.clone();
+        let on_open_callback_clone = on_open_callback.clone();
+        let on_message_callback_clone = on_message_callback.clone();
+        let connect = move |peer_id| {
+            let inner = match network_manager.upgrade() {
+                Some(inner) => inner,
+                None => {
+                    return Box::pin(async { Err(JsValue::from_str("network manager was dropped")) })
+                        as Pin<Box<dyn Future<Output = Result<(), JsValue>>>>
+                }
+            };
+            Box::pin(websocket_handler::connect_to_peer(
+                NetworkManager { inner },
+                websocket_clone.clone(),
+                session_id_clone.clone(),
+                peer_id,
+                on_open_callback_clone.clone(),
+                on_message_callback_clone.clone(),
+                is_host,
+            )) as Pin<Box<dyn Future<Output = Result<(), JsValue>>>>
+        };
+        self.inner.borrow_mut().connector = Some(Connector(Rc::new(connect)));
+        self.inner.borrow_mut().on_message =
+            Some(PeerMessageCallback::new(on_message_callback.clone()));
+
         set_websocket_on_open(&websocket, session_id, is_host);
         set_websocket_on_message(
             &websocket,
@@ -164,8 +362,9 @@ impl NetworkManager {
         );
     }
 
-    pub(crate) fn send_message(&self, user_id: UserId, message: &str) -> Result<(), JsValue> {
-        self.inner
+    fn data_channel(&self, user_id: UserId) -> Result<RtcDataChannel, JsValue> {
+        Ok(self
+            .inner
             .borrow()
             .connections
             .get(&user_id)
@@ -175,12 +374,370 @@ impl NetworkManager {
             .ok_or_else(|| {
                 JsValue::from_str(&format!("no data channel setup yet for user {}", user_id))
             })?
+            .clone())
+    }
+
+    fn peer_connection(&self, user_id: UserId) -> Result<RtcPeerConnection, JsValue> {
+        Ok(self
+            .inner
+            .borrow()
+            .connections
+            .get(&user_id)
+            .ok_or_else(|| JsValue::from_str(&format!("no connection for user {}", user_id)))?
+            .peer_connection
+            .clone())
+    }
+
+    pub(crate) fn data_channel_protocol(&self, user_id: UserId) -> Result<String, JsValue> {
+        get_data_channel_protocol(&self.data_channel(user_id)?)
+    }
+
+    pub(crate) fn send_message(&self, user_id: UserId, message: &str) -> Result<(), JsValue> {
+        self.data_channel(user_id)?
             // this is an ugly fix to the fact, that if you send empty string as message
             // webrtc fails with a cryptic "The operation failed for an operation-specific reason"
             // message
             .send_with_str(&format!("x{}", message))
     }
 
+    pub(crate) fn channels(&self) -> Vec<(UserId, ChannelInfo)> {
+        self.inner
+            .borrow()
+            .connections
+            .iter()
+            .filter_map(|(user_id, connection)| {
+                Some((*user_id, ChannelInfo::of(connection.data_channel.as_ref()?)))
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) fn relay_to(&self, user_ids: &[UserId], data: &[u8]) -> Result<(), JsValue> {
+        self.send_relayed(user_ids, relay_frame::encode_data(data)?)
+    }
+
+    #[cfg(feature = "many-to-many")]
+    fn send_relayed(&self, user_ids: &[UserId], frame: Vec<u8>) -> Result<(), JsValue> {
+        let inner = self.inner.borrow();
+        let signal_message =
+            SignalMessage::RelayTo(inner.session_id.clone(), user_ids.to_vec(), frame);
+        let signal_message = serialize_message(&signal_message)?;
+        inner.websocket.send_with_str(&signal_message)
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) fn subscribe(&self, topic: &str) -> Result<(), JsValue> {
+        check_topic(topic)?;
+        let session_id = self.inner.borrow().session_id.clone();
+        self.send_signal(&SignalMessage::Subscribe(session_id, topic.to_string()))
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) fn unsubscribe(&self, topic: &str) -> Result<(), JsValue> {
+        let session_id = self.inner.borrow().session_id.clone();
+        self.send_signal(&SignalMessage::Unsubscribe(session_id, topic.to_string()))
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) fn publish(&self, topic: &str, data: &[u8]) -> Result<(), JsValue> {
+        check_topic(topic)?;
+        if data.len() > MAX_RELAY_LENGTH {
+            return Err(JsValue::from_str(&format!(
+                "published data is too long: {} bytes, maximum is {}",
+                data.len(),
+                MAX_RELAY_LENGTH
+            )));
+        }
+        let session_id = self.inner.borrow().session_id.clone();
+        self.send_signal(&SignalMessage::Publish(
+            session_id,
+            topic.to_string(),
+            data.to_vec(),
+        ))
+    }
+
+    #[cfg(feature = "many-to-many")]
+    fn send_signal(&self, signal_message: &SignalMessage) -> Result<(), JsValue> {
+        let signal_message = serialize_message(signal_message)?;
+        self.inner.borrow().websocket.send_with_str(&signal_message)
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) fn set_on_published(
+        &mut self,
+        on_published: impl FnMut(UserId, String, Vec<u8>) + 'static,
+    ) {
+        self.inner.borrow_mut().on_published = Some(PublishedCallback::new(on_published));
+    }
+
+    pub(crate) fn on_published(&self, sender_id: UserId, topic: String, data: Vec<u8>) {
+        match Callback::of(&self.inner, |inner| &inner.on_published) {
+            Some(on_published) => on_published.call(sender_id, topic, data),
+            None => debug!("no callback set for published data, ignoring it"),
+        }
+    }
+
+    #[cfg(feature = "many-to-many")]
+    /// Returns the transport `send` would use for the peer right now,
+    /// `None` if there is no open data channel and relay fallback is disabled.
+    pub(crate) fn transport(&self, user_id: UserId) -> Option<Transport> {
+        let inner = self.inner.borrow();
+        let channel_open = inner
+            .connections
+            .get(&user_id)
+            .and_then(|connection| connection.data_channel.as_ref())
+            .is_some_and(|data_channel| data_channel.ready_state() == RtcDataChannelState::Open);
+        if channel_open {
+            Some(Transport::DataChannel)
+        } else if inner.relay_fallback {
+            Some(Transport::Relay)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) fn set_relay_fallback(&mut self, enabled: bool) {
+        self.inner.borrow_mut().relay_fallback = enabled;
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) fn send(&self, user_id: UserId, message: &str) -> Result<Transport, JsValue> {
+        let error = match self.transport(user_id) {
+            Some(Transport::DataChannel) => match self.send_message(user_id, message) {
+                Ok(()) => return Ok(Transport::DataChannel),
+                Err(error) => error,
+            },
+            Some(Transport::Relay) => {
+                self.send_relayed(&[user_id], relay_frame::encode_message(message)?)?;
+                return Ok(Transport::Relay);
+            }
+            None => {
+                return Err(JsValue::from_str(&format!(
+                    "no open data channel with user {} and relay fallback is disabled",
+                    user_id
+                )))
+            }
+        };
+        if !self.inner.borrow().relay_fallback {
+            return Err(error);
+        }
+        debug!(
+            "sending to {:?} over data channel failed, falling back to relay: {:?}",
+            user_id, error
+        );
+        self.send_relayed(&[user_id], relay_frame::encode_message(message)?)?;
+        Ok(Transport::Relay)
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) fn set_on_relayed(&mut self, on_relayed: impl FnMut(UserId, Vec<u8>) + 'static) {
+        self.inner.borrow_mut().on_relayed = Some(RelayedCallback::new(on_relayed));
+    }
+
+    pub(crate) fn on_relayed(&self, sender_id: UserId, frame: Vec<u8>) {
+        match relay_frame::decode(frame) {
+            Ok(RelayFrame::Data(data)) => {
+                match Callback::of(&self.inner, |inner| &inner.on_relayed) {
+                    Some(on_relayed) => on_relayed.call(sender_id, data),
+                    None => debug!("no callback set for relayed data, ignoring it"),
+                }
+            }
+            Ok(RelayFrame::Message(message)) => {
+                match Callback::of(&self.inner, |inner| &inner.on_message) {
+                    Some(on_message) => on_message.call(sender_id, message),
+                    None => debug!("network manager is not started yet, ignoring relayed message"),
+                }
+            }
+            Err(error) => error!("invalid data relayed from {:?}: {:?}", sender_id, error),
+        }
+    }
+
+    pub(crate) fn set_max_pending_negotiations(&mut self, max_pending_negotiations: usize) {
+        self.inner
+            .borrow_mut()
+            .negotiations
+            .set_max_pending(max_pending_negotiations);
+    }
+
+    pub(crate) fn set_negotiation_timeout(&mut self, timeout_ms: u32) {
+        self.inner.borrow_mut().negotiations.set_timeout(timeout_ms);
+    }
+
+    /// Connects to the peer right away, or once enough of the pending negotiations finish.
+    pub(crate) async fn negotiate_with(&self, peer_id: UserId) -> Result<(), JsValue> {
+        let ticket = self.inner.borrow_mut().negotiations.start_or_queue(peer_id);
+        match ticket {
+            Some(ticket) => self.start_negotiation(peer_id, ticket).await,
+            None => {
+                debug!("too many pending negotiations, queueing {:?}", peer_id);
+                Ok(())
+            }
+        }
+    }
+
+    async fn start_negotiation(&self, peer_id: UserId, ticket: u64) -> Result<(), JsValue> {
+        self.watch_negotiation(peer_id, ticket);
+        let result = self.connect_pending(peer_id).await;
+        if result.is_err() {
+            // connection that failed to start never gets to the ICE states finishing its negotiation
+            self.negotiation_finished(peer_id);
+        }
+        result
+    }
+
+    /// Frees the place of a negotiation that doesn't finish in time, e.g. with a peer that never answers.
+    fn watch_negotiation(&self, peer_id: UserId, ticket: u64) {
+        let timeout_ms = self.inner.borrow().negotiations.timeout_ms();
+        let network_manager = self.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let _ = JsFuture::from(timeout_promise(timeout_ms)).await;
+            let timed_out = network_manager
+                .inner
+                .borrow()
+                .negotiations
+                .is_pending(peer_id, ticket);
+            if timed_out {
+                warn!("negotiation with {:?} timed out", peer_id);
+                network_manager.negotiation_finished(peer_id);
+            }
+        });
+    }
+
+    /// Called once the connection with the peer is established, fails or times out.
+    pub(crate) fn negotiation_finished(&self, peer_id: UserId) {
+        let next = self.inner.borrow_mut().negotiations.finish(peer_id);
+        if let Some((next, ticket)) = next {
+            let network_manager = self.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                network_manager
+                    .start_negotiation(next, ticket)
+                    .await
+                    .unwrap_or_else(|error| error!("failed to connect to {:?}: {:?}", next, error));
+            });
+        }
+    }
+
+    async fn connect_pending(&self, peer_id: UserId) -> Result<(), JsValue> {
+        let connector = self.inner.borrow().connector.clone();
+        match connector {
+            Some(Connector(connect)) => connect(peer_id).await,
+            None => Err(JsValue::from_str("network manager is not started yet")),
+        }
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) fn set_manual_connect(&mut self) {
+        self.inner.borrow_mut().manual_connect = true;
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) fn available_peers(&self) -> Vec<UserId> {
+        self.inner
+            .borrow()
+            .available_peers
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    #[cfg(feature = "many-to-many")]
+    pub(crate) async fn connect_to(&self, user_id: UserId) -> Result<PeerHandle, Error> {
+        let connector = {
+            let mut inner = self.inner.borrow_mut();
+            if inner.connections.contains_key(&user_id) || inner.connecting.contains(&user_id) {
+                return Err(Error::AlreadyConnected(user_id));
+            }
+            let connector = inner.connector.clone().ok_or(Error::NotStarted)?;
+            if !inner.available_peers.contains(&user_id) {
+                return Err(Error::PeerUnavailable(user_id));
+            }
+            inner.connecting.insert(user_id);
+            connector
+        };
+        let Connector(connect) = connector;
+        let result = connect(user_id).await;
+        let mut inner = self.inner.borrow_mut();
+        inner.connecting.remove(&user_id);
+        // peer stays available to retry if connecting fails
+        match result {
+            Ok(()) => {
+                inner.available_peers.remove(&user_id);
+                Ok(PeerHandle {
+                    user_id,
+                    network_manager: Rc::downgrade(&self.inner),
+                })
+            }
+            Err(error) => Err(Error::PeerUnreachable(user_id, format!("{:?}", error))),
+        }
+    }
+
+    /// Called when the peer leaves the session.
+    pub(crate) fn on_peer_left(&self, user_id: UserId) {
+        self.inner.borrow_mut().available_peers.remove(&user_id);
+    }
+
+    pub(crate) fn query_session(&self) -> Result<(), JsValue> {
+        let inner = self.inner.borrow();
+        let signal_message = SignalMessage::QuerySession(inner.session_id.clone());
+        let signal_message = serialize_message(&signal_message)?;
+        inner.websocket.send_with_str(&signal_message)
+    }
+
+    pub(crate) fn set_on_session_status(
+        &mut self,
+        on_session_status: impl FnMut(SessionInfo) + 'static,
+    ) {
+        self.inner.borrow_mut().on_session_status =
+            Some(SessionStatusCallback::new(on_session_status));
+    }
+
+    pub(crate) fn on_session_status(&self, info: SessionInfo) {
+        match Callback::of(&self.inner, |inner| &inner.on_session_status) {
+            Some(on_session_status) => on_session_status.call(info),
+            None => debug!("no callback set for session status, ignoring it"),
+        }
+    }
+
+    pub(crate) fn set_on_server_notice(&mut self, on_server_notice: impl FnMut(String) + 'static) {
+        self.inner.borrow_mut().on_server_notice = Some(MessageCallback::new(on_server_notice));
+    }
+
+    pub(crate) fn on_server_notice(&self, notice: String) {
+        match Callback::of(&self.inner, |inner| &inner.on_server_notice) {
+            Some(on_server_notice) => on_server_notice.call(notice),
+            None => info!("signaling server notice: {}", notice),
+        }
+    }
+
+    pub(crate) fn transfer_ownership(&self, new_owner: UserId) -> Result<(), JsValue> {
+        let inner = self.inner.borrow();
+        let signal_message = SignalMessage::TransferOwnership(inner.session_id.clone(), new_owner);
+        let signal_message = serialize_message(&signal_message)?;
+        inner.websocket.send_with_str(&signal_message)
+    }
+
+    pub(crate) fn close_session(&self) -> Result<(), JsValue> {
+        let inner = self.inner.borrow();
+        let signal_message = SignalMessage::CloseSession(inner.session_id.clone());
+        let signal_message = serialize_message(&signal_message)?;
+        inner.websocket.send_with_str(&signal_message)
+    }
+
+    pub(crate) fn set_on_session_closed(
+        &mut self,
+        on_session_closed: impl FnMut(String) + 'static,
+    ) {
+        self.inner.borrow_mut().on_session_closed = Some(MessageCallback::new(on_session_closed));
+    }
+
+    pub(crate) fn on_session_closed(&self, reason: String) {
+        match Callback::of(&self.inner, |inner| &inner.on_session_closed) {
+            Some(on_session_closed) => on_session_closed.call(reason),
+            None => info!("session closed: {}", reason),
+        }
+    }
+
     pub(crate) fn send_message_to_all(&self, message: &str) {
         for data_channel in self
             .inner
@@ -233,6 +790,59 @@ impl MiniServer {
         })
     }
 
+    /// Same as [`MiniServer::new`], but accepts a list of signaling server addresses
+    /// for redundancy. They are tried in order and the first one that accepts
+    /// the connection is used for the whole signaling.
+    ///
+    /// All peers in the session must end up connected to the same signaling server instance
+    /// for the signaling to work, so lists on all sides should be ordered the same way.
+    /// Servers not accepting the connection within [`crate::FAILOVER_ATTEMPT_TIMEOUT_MS`] count as failed.
+    pub async fn new_with_failover(
+        signaling_server_urls: &[&str],
+        session_id: SessionId,
+        connection_type: ConnectionType,
+    ) -> Result<Self, JsValue> {
+        Ok(MiniServer {
+            inner: NetworkManager::new_with_failover(
+                signaling_server_urls,
+                session_id,
+                connection_type,
+                true,
+            )
+            .await?,
+        })
+    }
+
+    /// Overrides options used when creating data channels to client-peers.
+    /// Must be called before [`MiniServer::start`] to take effect.
+    ///
+    /// # Errors
+    /// This function errors if configured protocol exceeds the length allowed by the specification.
+    pub fn set_data_channel_config(
+        &mut self,
+        data_channel_config: DataChannelConfig,
+    ) -> Result<(), JsValue> {
+        self.inner.set_data_channel_config(data_channel_config)
+    }
+
+    /// Limits how many clients are negotiated with at the same time, as browsers struggle
+    /// with many simultaneous offers. Clients joining while the limit is reached wait
+    /// until earlier connections are established or fail.
+    /// Defaults to [`DEFAULT_MAX_PENDING_NEGOTIATIONS`], values below 1 are treated as 1.
+    /// Must be called before [`MiniServer::start`] to take effect.
+    pub fn set_max_pending_negotiations(&mut self, max_pending_negotiations: usize) {
+        self.inner
+            .set_max_pending_negotiations(max_pending_negotiations);
+    }
+
+    /// Sets how long a negotiation with a client can take before its place is given
+    /// to a queued one, e.g. when the client never answers.
+    /// The connection isn't closed, so a late answer still establishes it.
+    /// Defaults to [`DEFAULT_NEGOTIATION_TIMEOUT_MS`] milliseconds.
+    pub fn set_negotiation_timeout(&mut self, timeout_ms: u32) {
+        self.inner.set_negotiation_timeout(timeout_ms);
+    }
+
     /// Second part of the setup that begins the actual connection.
     /// Requires specifying a callbacks that are guaranteed to run
     /// when the connection opens and on each message received.
@@ -255,6 +865,60 @@ impl MiniServer {
     pub fn send_message_to_all(&self, message: &str) {
         self.inner.send_message_to_all(message)
     }
+
+    /// Returns the sub-protocol of the data channel established with given client-peer.
+    pub fn data_channel_protocol(&self, user_id: UserId) -> Result<String, JsValue> {
+        self.inner.data_channel_protocol(user_id)
+    }
+
+    /// Lists data channels established with client-peers with their current state.
+    pub fn channels(&self) -> Vec<(UserId, ChannelInfo)> {
+        self.inner.channels()
+    }
+
+    /// Hands over owner rights of the session to another peer, e.g. before leaving.
+    /// Only the current owner, initially the first peer to join, can do it,
+    /// signaling server responds with an error otherwise.
+    /// If the owner leaves without doing it, the session stays without an owner.
+    pub fn transfer_ownership(&self, new_owner: UserId) -> Result<(), JsValue> {
+        self.inner.transfer_ownership(new_owner)
+    }
+
+    /// Closes the session for every peer in it, unlike leaving it. By default only the owner can do it,
+    /// signaling server responds with an error otherwise. Peers are told with the callback set with
+    /// [`MiniServer::set_on_session_closed`], connections between them are left to the application.
+    ///
+    /// # Errors
+    /// This function errors if sending the request to signaling server fails.
+    pub fn close_session(&self) -> Result<(), JsValue> {
+        self.inner.close_session()
+    }
+
+    /// Sets a callback called with the reason when the session is closed for everyone,
+    /// e.g. with [`MiniServer::close_session`].
+    pub fn set_on_session_closed(&mut self, on_session_closed: impl FnMut(String) + 'static) {
+        self.inner.set_on_session_closed(on_session_closed);
+    }
+
+    /// Asks signaling server for the current state of the session, e.g. after the view of it
+    /// may have become stale. Response is passed to the callback set with [`MiniServer::set_on_session_status`].
+    ///
+    /// # Errors
+    /// This function errors if sending the request to signaling server fails.
+    pub fn query_session(&self) -> Result<(), JsValue> {
+        self.inner.query_session()
+    }
+
+    /// Sets a callback called with the state of the session requested with [`MiniServer::query_session`].
+    pub fn set_on_session_status(&mut self, on_session_status: impl FnMut(SessionInfo) + 'static) {
+        self.inner.set_on_session_status(on_session_status);
+    }
+
+    /// Sets a callback called with each notice the signaling server operator sends to all users,
+    /// e.g. about upcoming maintenance, to be shown to the user.
+    pub fn set_on_server_notice(&mut self, on_server_notice: impl FnMut(String) + 'static) {
+        self.inner.set_on_server_notice(on_server_notice);
+    }
 }
 
 /// Abstraction over `WebRTC` peer-to-peer connection.
@@ -276,6 +940,23 @@ impl MiniClient {
         })
     }
 
+    /// Same as [`MiniServer::new_with_failover`]
+    pub async fn new_with_failover(
+        signaling_server_urls: &[&str],
+        session_id: SessionId,
+        connection_type: ConnectionType,
+    ) -> Result<Self, JsValue> {
+        Ok(MiniClient {
+            inner: NetworkManager::new_with_failover(
+                signaling_server_urls,
+                session_id,
+                connection_type,
+                false,
+            )
+            .await?,
+        })
+    }
+
     /// Same as [::start], but callbacks don't take `UserId` argument, as it will always be host.
     pub fn start(
         &mut self,
@@ -293,4 +974,189 @@ impl MiniClient {
         // TODO(tkarwowski): we always return success, but this is subject to change
         Ok(())
     }
+
+    /// Returns the sub-protocol of the data channel established with peer-server,
+    /// as chosen by the host.
+    pub fn data_channel_protocol(&self) -> Result<String, JsValue> {
+        let host_id = *self
+            .inner
+            .inner
+            .borrow()
+            .connections
+            .keys()
+            .next()
+            .ok_or_else(|| JsValue::from_str("no connection to host yet"))?;
+        self.inner.data_channel_protocol(host_id)
+    }
+
+    /// Same as [`MiniServer::transfer_ownership`]
+    pub fn transfer_ownership(&self, new_owner: UserId) -> Result<(), JsValue> {
+        self.inner.transfer_ownership(new_owner)
+    }
+
+    /// Same as [`MiniServer::close_session`]
+    pub fn close_session(&self) -> Result<(), JsValue> {
+        self.inner.close_session()
+    }
+
+    /// Same as [`MiniServer::set_on_session_closed`]
+    pub fn set_on_session_closed(&mut self, on_session_closed: impl FnMut(String) + 'static) {
+        self.inner.set_on_session_closed(on_session_closed);
+    }
+
+    /// Asks signaling server for the current state of the session, e.g. after the view of it
+    /// may have become stale. Response is passed to the callback set with [`MiniClient::set_on_session_status`].
+    ///
+    /// # Errors
+    /// This function errors if sending the request to signaling server fails.
+    pub fn query_session(&self) -> Result<(), JsValue> {
+        self.inner.query_session()
+    }
+
+    /// Sets a callback called with the state of the session requested with [`MiniClient::query_session`].
+    pub fn set_on_session_status(&mut self, on_session_status: impl FnMut(SessionInfo) + 'static) {
+        self.inner.set_on_session_status(on_session_status);
+    }
+
+    /// Sets a callback called with each notice the signaling server operator sends to all users,
+    /// e.g. about upcoming maintenance, to be shown to the user.
+    pub fn set_on_server_notice(&mut self, on_server_notice: impl FnMut(String) + 'static) {
+        self.inner.set_on_server_notice(on_server_notice);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    fn network_manager() -> NetworkManager {
+        NetworkManager::new(
+            "ws://127.0.0.1:9001/one-to-many",
+            SessionId::new("dummy-session-id".to_string()),
+            ConnectionType::Local,
+            true,
+        )
+        .unwrap()
+    }
+
+    #[cfg(feature = "many-to-many")]
+    fn set_connector(network_manager: &NetworkManager, result: Result<(), &'static str>) {
+        let connect = move |_peer_id| {
+            Box::pin(async move { result.map_err(JsValue::from_str) })
+                as Pin<Box<dyn Future<Output = Result<(), JsValue>>>>
+        };
+        network_manager.inner.borrow_mut().connector = Some(Connector(Rc::new(connect)));
+    }
+
+    #[cfg(feature = "many-to-many")]
+    #[wasm_bindgen_test]
+    async fn test_peer_stays_available_until_connected() {
+        let network_manager = network_manager();
+        let peer_id = UserId::new(1);
+        assert_eq!(
+            network_manager.connect_to(peer_id).await.unwrap_err(),
+            Error::NotStarted
+        );
+        network_manager
+            .inner
+            .borrow_mut()
+            .available_peers
+            .insert(peer_id);
+
+        set_connector(&network_manager, Err("no offer"));
+        assert!(matches!(
+            network_manager.connect_to(peer_id).await,
+            Err(Error::PeerUnreachable(user_id, _)) if user_id == peer_id
+        ));
+        assert_eq!(network_manager.available_peers(), vec![peer_id]);
+
+        set_connector(&network_manager, Ok(()));
+        let peer = network_manager.connect_to(peer_id).await.unwrap();
+        assert_eq!(peer.user_id(), peer_id);
+        assert!(network_manager.available_peers().is_empty());
+    }
+
+    #[cfg(feature = "many-to-many")]
+    #[wasm_bindgen_test]
+    async fn test_peer_leaving_is_no_longer_available() {
+        let network_manager = network_manager();
+        let peer_id = UserId::new(1);
+        set_connector(&network_manager, Ok(()));
+        network_manager
+            .inner
+            .borrow_mut()
+            .available_peers
+            .insert(peer_id);
+
+        network_manager.on_peer_left(peer_id);
+
+        assert!(network_manager.available_peers().is_empty());
+        assert_eq!(
+            network_manager.connect_to(peer_id).await.unwrap_err(),
+            Error::PeerUnavailable(peer_id)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_failed_negotiation_frees_its_place() {
+        let network_manager = network_manager();
+        network_manager
+            .inner
+            .borrow_mut()
+            .negotiations
+            .set_max_pending(1);
+
+        // not started yet, so connecting fails
+        assert!(network_manager
+            .negotiate_with(UserId::new(1))
+            .await
+            .is_err());
+
+        assert!(network_manager
+            .inner
+            .borrow_mut()
+            .negotiations
+            .start_or_queue(UserId::new(2))
+            .is_some());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_negotiation_without_answer_times_out() {
+        let mut network_manager = network_manager();
+        network_manager
+            .inner
+            .borrow_mut()
+            .negotiations
+            .set_max_pending(1);
+        network_manager.set_negotiation_timeout(10);
+        let connected = Rc::new(RefCell::new(Vec::new()));
+        let connected_clone = connected.clone();
+        let connect = move |peer_id| {
+            connected_clone.borrow_mut().push(peer_id);
+            Box::pin(async { Ok(()) }) as Pin<Box<dyn Future<Output = Result<(), JsValue>>>>
+        };
+        network_manager.inner.borrow_mut().connector = Some(Connector(Rc::new(connect)));
+
+        network_manager
+            .negotiate_with(UserId::new(1))
+            .await
+            .unwrap();
+        network_manager
+            .negotiate_with(UserId::new(2))
+            .await
+            .unwrap();
+        assert_eq!(*connected.borrow(), vec![UserId::new(1)]);
+        JsFuture::from(timeout_promise(50)).await.unwrap();
+
+        // neither peer answered, both gave up their place in turn
+        assert_eq!(*connected.borrow(), vec![UserId::new(1), UserId::new(2)]);
+        assert!(network_manager
+            .inner
+            .borrow_mut()
+            .negotiations
+            .start_or_queue(UserId::new(3))
+            .is_some());
+    }
 }
@@ -0,0 +1,121 @@
+use std::collections::{HashMap, VecDeque};
+
+use wasm_peers_protocol::UserId;
+
+/// Default maximum number of negotiations in progress at the same time,
+/// see [`crate::one_to_many::MiniServer::set_max_pending_negotiations`].
+pub const DEFAULT_MAX_PENDING_NEGOTIATIONS: usize = 4;
+
+/// Default time after which a negotiation that didn't finish frees its place for queued ones,
+/// see [`crate::one_to_many::MiniServer::set_negotiation_timeout`].
+pub const DEFAULT_NEGOTIATION_TIMEOUT_MS: u32 = 30_000;
+
+/// Paces negotiations with joining peers, as browsers struggle with many simultaneous offers.
+#[derive(Debug)]
+pub(crate) struct NegotiationQueue {
+    max_pending: usize,
+    timeout_ms: u32,
+    /// Ticket of each pending negotiation, telling it apart from later ones with the same peer.
+    pending: HashMap<UserId, u64>,
+    queued: VecDeque<UserId>,
+    next_ticket: u64,
+}
+
+impl Default for NegotiationQueue {
+    fn default() -> Self {
+        NegotiationQueue {
+            max_pending: DEFAULT_MAX_PENDING_NEGOTIATIONS,
+            timeout_ms: DEFAULT_NEGOTIATION_TIMEOUT_MS,
+            pending: HashMap::new(),
+            queued: VecDeque::new(),
+            next_ticket: 0,
+        }
+    }
+}
+
+impl NegotiationQueue {
+    pub(crate) fn set_max_pending(&mut self, max_pending: usize) {
+        self.max_pending = max_pending.max(1);
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    pub(crate) fn timeout_ms(&self) -> u32 {
+        self.timeout_ms
+    }
+
+    /// Returns the ticket of the negotiation if it can start right away,
+    /// otherwise it's queued until one of the pending ones finishes.
+    pub(crate) fn start_or_queue(&mut self, peer_id: UserId) -> Option<u64> {
+        if self.pending.len() < self.max_pending {
+            Some(self.start(peer_id))
+        } else {
+            self.queued.push_back(peer_id);
+            None
+        }
+    }
+
+    fn start(&mut self, peer_id: UserId) -> u64 {
+        self.next_ticket += 1;
+        self.pending.insert(peer_id, self.next_ticket);
+        self.next_ticket
+    }
+
+    /// Whether the negotiation the ticket was issued for is still pending.
+    pub(crate) fn is_pending(&self, peer_id: UserId, ticket: u64) -> bool {
+        self.pending.get(&peer_id) == Some(&ticket)
+    }
+
+    /// Marks negotiation with the peer as finished, either way,
+    /// and returns the next peer to negotiate with and its ticket, if any.
+    /// Does nothing for peers that aren't pending, e.g. when called again for the same peer.
+    pub(crate) fn finish(&mut self, peer_id: UserId) -> Option<(UserId, u64)> {
+        self.pending.remove(&peer_id)?;
+        let next = self.queued.pop_front()?;
+        Some((next, self.start(next)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_many_rapid_joins_are_paced() {
+        let mut queue = NegotiationQueue::default();
+        queue.set_max_pending(2);
+
+        let started: Vec<_> = (0..10)
+            .filter(|&id| queue.start_or_queue(UserId::new(id)).is_some())
+            .collect();
+        assert_eq!(started, vec![0, 1]);
+
+        assert_eq!(
+            queue.finish(UserId::new(1)).map(|(next, _)| next),
+            Some(UserId::new(2))
+        );
+        assert_eq!(queue.finish(UserId::new(1)), None);
+        assert_eq!(
+            queue.finish(UserId::new(0)).map(|(next, _)| next),
+            Some(UserId::new(3))
+        );
+        assert_eq!(queue.pending.len(), 2);
+        assert_eq!(queue.queued.len(), 6);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_tickets_tell_later_negotiations_with_same_peer_apart() {
+        let mut queue = NegotiationQueue::default();
+
+        let first = queue.start_or_queue(UserId::new(1)).unwrap();
+        queue.finish(UserId::new(1));
+        let second = queue.start_or_queue(UserId::new(1)).unwrap();
+
+        assert!(!queue.is_pending(UserId::new(1), first));
+        assert!(queue.is_pending(UserId::new(1), second));
+    }
+}
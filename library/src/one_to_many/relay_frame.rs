@@ -0,0 +1,79 @@
+use wasm_bindgen::JsValue;
+use wasm_peers_protocol::one_to_many::MAX_RELAY_LENGTH;
+
+/// Relayed payloads start with one of these bytes, telling data sent with `relay_to`
+/// from messages that fell back to the relay, so the latter reach `on_message_callback`.
+const DATA_PREFIX: u8 = b'r';
+const MESSAGE_PREFIX: u8 = b'm';
+
+/// Longest data that can be relayed, one byte is taken by the prefix.
+pub const MAX_RELAYED_DATA_LENGTH: usize = MAX_RELAY_LENGTH - 1;
+
+/// Payload received from the signaling server relay.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum RelayFrame {
+    Data(Vec<u8>),
+    Message(String),
+}
+
+#[cfg(feature = "many-to-many")]
+pub(crate) fn encode_data(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    encode(DATA_PREFIX, data)
+}
+
+#[cfg(feature = "many-to-many")]
+pub(crate) fn encode_message(message: &str) -> Result<Vec<u8>, JsValue> {
+    encode(MESSAGE_PREFIX, message.as_bytes())
+}
+
+#[cfg(feature = "many-to-many")]
+fn encode(prefix: u8, payload: &[u8]) -> Result<Vec<u8>, JsValue> {
+    if payload.len() > MAX_RELAYED_DATA_LENGTH {
+        return Err(JsValue::from_str(&format!(
+            "relayed data is too long: {} bytes, maximum is {}",
+            payload.len(),
+            MAX_RELAYED_DATA_LENGTH
+        )));
+    }
+    let mut frame = Vec::with_capacity(payload.len() + 1);
+    frame.push(prefix);
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+pub(crate) fn decode(mut frame: Vec<u8>) -> Result<RelayFrame, JsValue> {
+    match frame.first().copied() {
+        Some(DATA_PREFIX) => {
+            frame.remove(0);
+            Ok(RelayFrame::Data(frame))
+        }
+        Some(MESSAGE_PREFIX) => {
+            frame.remove(0);
+            String::from_utf8(frame)
+                .map(RelayFrame::Message)
+                .map_err(|error| JsValue::from_str(&error.to_string()))
+        }
+        _ => Err(JsValue::from_str("relayed data has unknown prefix")),
+    }
+}
+
+#[cfg(all(test, feature = "many-to-many"))]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_frames_round_trip_and_keep_kinds_apart() {
+        let data = encode_data(b"hello").unwrap();
+        let message = encode_message("hello").unwrap();
+
+        assert_eq!(decode(data).unwrap(), RelayFrame::Data(b"hello".to_vec()));
+        assert_eq!(
+            decode(message).unwrap(),
+            RelayFrame::Message("hello".to_string())
+        );
+        assert!(decode(Vec::new()).is_err());
+        assert!(encode_data(&vec![0; MAX_RELAYED_DATA_LENGTH + 1]).is_err());
+    }
+}
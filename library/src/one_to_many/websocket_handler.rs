@@ -1,11 +1,8 @@
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use wasm_bindgen::JsValue;
-use wasm_bindgen_futures::JsFuture;
 use wasm_peers_protocol::one_to_many::SignalMessage;
-use wasm_peers_protocol::UserId;
-use web_sys::{
-    RtcIceCandidate, RtcIceCandidateInit, RtcSdpType, RtcSessionDescriptionInit, WebSocket,
-};
+use wasm_peers_protocol::{SessionId, UserId};
+use web_sys::WebSocket;
 
 use crate::one_to_many::callbacks::{
     set_data_channel_on_error, set_data_channel_on_message, set_data_channel_on_open,
@@ -14,7 +11,10 @@ use crate::one_to_many::callbacks::{
     set_peer_connection_on_ice_gathering_state_change, set_peer_connection_on_negotiation_needed,
 };
 use crate::one_to_many::{Connection, NetworkManager};
-use crate::utils::{create_peer_connection, create_sdp_answer, create_sdp_offer, IceCandidate};
+use crate::utils::{
+    accepts_ice_candidates, add_ice_candidates, create_data_channel, create_peer_connection,
+    create_sdp_answer, create_sdp_offer, parse_ice_candidate, serialize_message, set_sdp_answer,
+};
 
 /// Basically a finite state machine spread across host, client and signaling server
 /// handling each step in session and then `WebRTC` setup.
@@ -35,49 +35,20 @@ pub(crate) async fn handle_websocket_message(
                 "peer received info that session with {:?} is ready {:?}",
                 peer_id, session_id
             );
-            let peer_connection =
-                create_peer_connection(&network_manager.inner.borrow().connection_type).unwrap();
-            set_peer_connection_on_data_channel(
-                &peer_connection,
-                peer_id,
-                network_manager.clone(),
-                on_open_callback.clone(),
-                on_message_callback.clone(),
-            );
-            set_peer_connection_on_ice_candidate(
-                &peer_connection,
-                peer_id,
-                websocket.clone(),
-                session_id.clone(),
-            );
-            set_peer_connection_on_ice_connection_state_change(&peer_connection);
-            set_peer_connection_on_ice_gathering_state_change(&peer_connection);
-            set_peer_connection_on_negotiation_needed(&peer_connection);
-
-            let data_channel =
-                peer_connection.create_data_channel(&format!("{}-{}", session_id, peer_id));
-            set_data_channel_on_open(&data_channel, peer_id, on_open_callback.clone());
-            set_data_channel_on_error(&data_channel);
-            set_data_channel_on_message(&data_channel, peer_id, on_message_callback.clone());
-
-            let offer = create_sdp_offer(&peer_connection).await?;
-            let signal_message = SignalMessage::SdpOffer(session_id, peer_id, offer);
-            let signal_message = serde_json_wasm::to_string(&signal_message)
-                .expect("failed to serialize SignalMessage");
-            websocket.send_with_str(&signal_message)?;
-            network_manager.inner.borrow_mut().connections.insert(
-                peer_id,
-                Connection::new(peer_connection.clone(), Some(data_channel.clone())),
-            );
-            debug!(
-                "(is_host: {}) sent an offer to {:?} successfully",
-                is_host, peer_id
-            );
+            if network_manager.inner.borrow().manual_connect {
+                network_manager
+                    .inner
+                    .borrow_mut()
+                    .available_peers
+                    .insert(peer_id);
+                return Ok(());
+            }
+            network_manager.negotiate_with(peer_id).await?;
         }
         SignalMessage::SdpOffer(session_id, user_id, offer) => {
             // non-host peer received an offer
             let peer_connection =
-                create_peer_connection(&network_manager.inner.borrow().connection_type).unwrap();
+                create_peer_connection(&network_manager.inner.borrow().connection_type)?;
             set_peer_connection_on_data_channel(
                 &peer_connection,
                 user_id,
@@ -91,7 +62,11 @@ pub(crate) async fn handle_websocket_message(
                 websocket.clone(),
                 session_id.clone(),
             );
-            set_peer_connection_on_ice_connection_state_change(&peer_connection);
+            set_peer_connection_on_ice_connection_state_change(
+                &peer_connection,
+                user_id,
+                network_manager.clone(),
+            );
             set_peer_connection_on_ice_gathering_state_change(&peer_connection);
             set_peer_connection_on_negotiation_needed(&peer_connection);
 
@@ -105,76 +80,91 @@ pub(crate) async fn handle_websocket_message(
                 is_host, user_id
             );
 
-            let answer = create_sdp_answer(&peer_connection, offer)
-                .await
-                .expect("failed to create SDP answer");
+            let answer = create_sdp_answer(&peer_connection, offer).await?;
             debug!(
                 "received an offer from {:?} and created an answer: {}",
                 user_id, answer
             );
             let signal_message = SignalMessage::SdpAnswer(session_id, user_id, answer);
-            let signal_message = serde_json_wasm::to_string(&signal_message)
-                .expect("failed to serialize SignalMessage");
-            websocket
-                .send_with_str(&signal_message)
-                .expect("failed to send SPD answer to signaling server");
+            let signal_message = serialize_message(&signal_message)?;
+            websocket.send_with_str(&signal_message)?;
+            add_held_ice_candidates(&network_manager, user_id).await?;
         }
         SignalMessage::SdpAnswer(session_id, user_id, answer) => {
-            let peer_connection = network_manager
-                .inner
-                .borrow()
-                .connections
-                .get(&user_id)
-                .unwrap_or_else(|| {
-                    panic!(
-                        "(is_host: {}) no connection to send answer for given user_id: {:?}",
-                        is_host, &user_id
-                    )
-                })
-                .peer_connection
-                .clone();
-            let mut remote_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
-            remote_session_description.sdp(&answer);
-            JsFuture::from(peer_connection.set_remote_description(&remote_session_description))
-                .await
-                .expect("failed to set remote description");
+            let peer_connection = network_manager.peer_connection(user_id)?;
+            set_sdp_answer(&peer_connection, &answer).await?;
             debug!(
                 "received answer from peer and set remote description: {}, {:?}",
                 answer, session_id
             );
+            add_held_ice_candidates(&network_manager, user_id).await?;
         }
         SignalMessage::IceCandidate(_session_id, user_id, ice_candidate) => {
-            let peer_connection = network_manager
-                .inner
-                .borrow()
-                .connections
-                .get(&user_id)
-                .unwrap_or_else(|| {
-                    panic!(
-                        "no connection to send ice candidate to for given user_id: {:?}",
-                        &user_id
-                    )
-                })
-                .peer_connection
-                .clone();
             debug!("peer received ice candidate: {}", &ice_candidate);
             // TODO(tkarwowski): IceCandidate should already be struct inside signal message
-            let ice_candidate = serde_json_wasm::from_str::<IceCandidate>(&ice_candidate)
-                .expect("failed to deserialize IceCandidate");
-
-            let mut rtc_candidate = RtcIceCandidateInit::new("");
-            rtc_candidate.candidate(&ice_candidate.candidate);
-            rtc_candidate.sdp_m_line_index(ice_candidate.sdp_m_line_index);
-            rtc_candidate.sdp_mid(ice_candidate.sdp_mid.as_deref());
-
-            let rtc_candidate =
-                RtcIceCandidate::new(&rtc_candidate).expect("failed to create new RtcIceCandidate");
-            JsFuture::from(
-                peer_connection.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&rtc_candidate)),
-            )
-            .await
-            .expect("failed to add ICE candidate");
-            debug!("added ice candidate {:?}", ice_candidate);
+            let ice_candidate = parse_ice_candidate(&ice_candidate)?;
+            let peer_connection = network_manager.peer_connection(user_id)?;
+            if accepts_ice_candidates(&peer_connection) {
+                add_ice_candidates(&peer_connection, vec![ice_candidate]).await?;
+            } else {
+                debug!("holding ice candidate until remote description is set");
+                if let Some(connection) = network_manager
+                    .inner
+                    .borrow_mut()
+                    .connections
+                    .get_mut(&user_id)
+                {
+                    connection.held_ice_candidates.push(ice_candidate);
+                }
+            }
+        }
+        SignalMessage::TransferOwnership(..)
+        | SignalMessage::RelayTo(..)
+        | SignalMessage::Subscribe(..)
+        | SignalMessage::Unsubscribe(..)
+        | SignalMessage::Publish(..)
+        | SignalMessage::QuerySession(..)
+        | SignalMessage::CloseSession(..) => {
+            error!(
+                "error, TransferOwnership, RelayTo, Subscribe, Unsubscribe, Publish, QuerySession and CloseSession should only be sent by peers to signaling server"
+            );
+        }
+        SignalMessage::SessionClosed(session_id, reason) => {
+            info!("session {:?} was closed: {}", session_id, reason);
+            network_manager.on_session_closed(reason);
+        }
+        SignalMessage::SessionStatus(session_id, info) => {
+            debug!("received status of session {:?}: {:?}", session_id, info);
+            network_manager.on_session_status(info);
+        }
+        SignalMessage::Relayed(_session_id, sender_id, data) => {
+            debug!("received {} bytes relayed from {:?}", data.len(), sender_id);
+            network_manager.on_relayed(sender_id, data);
+        }
+        SignalMessage::Published(_session_id, sender_id, topic, data) => {
+            debug!(
+                "received {} bytes published to {:?} by {:?}",
+                data.len(),
+                topic,
+                sender_id
+            );
+            network_manager.on_published(sender_id, topic, data);
+        }
+        SignalMessage::BandwidthExceeded(session_id) => {
+            warn!(
+                "signaling server dropped relayed data, bandwidth of session {:?} exceeded",
+                session_id
+            );
+        }
+        SignalMessage::OwnershipChanged(session_id, owner) => {
+            info!("owner of session {:?} is now {:?}", session_id, owner);
+        }
+        SignalMessage::PeerLeft(session_id, peer_id) => {
+            info!("peer {:?} left session {:?}", peer_id, session_id);
+            network_manager.on_peer_left(peer_id);
+        }
+        SignalMessage::ServerNotice(notice) => {
+            network_manager.on_server_notice(notice);
         }
         SignalMessage::Error(session_id, error) => {
             error!(
@@ -187,6 +177,86 @@ pub(crate) async fn handle_websocket_message(
     Ok(())
 }
 
+/// Creates a connection with the peer and sends it an offer.
+pub(crate) async fn connect_to_peer(
+    network_manager: NetworkManager,
+    websocket: WebSocket,
+    session_id: SessionId,
+    peer_id: UserId,
+    on_open_callback: impl FnMut(UserId) + Clone + 'static,
+    on_message_callback: impl FnMut(UserId, String) + Clone + 'static,
+    is_host: bool,
+) -> Result<(), JsValue> {
+    let peer_connection = create_peer_connection(&network_manager.inner.borrow().connection_type)?;
+    set_peer_connection_on_data_channel(
+        &peer_connection,
+        peer_id,
+        network_manager.clone(),
+        on_open_callback.clone(),
+        on_message_callback.clone(),
+    );
+    set_peer_connection_on_ice_candidate(
+        &peer_connection,
+        peer_id,
+        websocket.clone(),
+        session_id.clone(),
+    );
+    set_peer_connection_on_ice_connection_state_change(
+        &peer_connection,
+        peer_id,
+        network_manager.clone(),
+    );
+    set_peer_connection_on_ice_gathering_state_change(&peer_connection);
+    set_peer_connection_on_negotiation_needed(&peer_connection);
+
+    let data_channel = create_data_channel(
+        &peer_connection,
+        &format!("{}-{}", session_id, peer_id),
+        &network_manager.inner.borrow().data_channel_config,
+    );
+    set_data_channel_on_open(&data_channel, peer_id, on_open_callback.clone());
+    set_data_channel_on_error(&data_channel);
+    set_data_channel_on_message(&data_channel, peer_id, on_message_callback.clone());
+
+    let offer = async {
+        let offer = create_sdp_offer(&peer_connection).await?;
+        let signal_message = SignalMessage::SdpOffer(session_id, peer_id, offer);
+        let signal_message = serialize_message(&signal_message)?;
+        websocket.send_with_str(&signal_message)
+    };
+    if let Err(error) = offer.await {
+        peer_connection.close();
+        return Err(error);
+    }
+    network_manager.inner.borrow_mut().connections.insert(
+        peer_id,
+        Connection::new(peer_connection.clone(), Some(data_channel.clone())),
+    );
+    debug!(
+        "(is_host: {}) sent an offer to {:?} successfully",
+        is_host, peer_id
+    );
+    Ok(())
+}
+
+async fn add_held_ice_candidates(
+    network_manager: &NetworkManager,
+    user_id: UserId,
+) -> Result<(), JsValue> {
+    let (peer_connection, held_ice_candidates) = {
+        let mut inner = network_manager.inner.borrow_mut();
+        let connection = inner
+            .connections
+            .get_mut(&user_id)
+            .ok_or_else(|| JsValue::from_str(&format!("no connection for user {}", user_id)))?;
+        (
+            connection.peer_connection.clone(),
+            std::mem::take(&mut connection.held_ice_candidates),
+        )
+    };
+    add_ice_candidates(&peer_connection, held_ice_candidates).await
+}
+
 // #[cfg(test)]
 // mod test {
 //     use super::*;
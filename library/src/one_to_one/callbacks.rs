@@ -3,19 +3,23 @@ use log::{debug, error, info};
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::{JsCast, JsValue};
 use wasm_peers_protocol::one_to_one::SignalMessage;
-use wasm_peers_protocol::SessionId;
 use web_sys::{
-    MessageEvent, RtcDataChannel, RtcDataChannelEvent, RtcPeerConnection,
+    MessageEvent, RtcDataChannel, RtcDataChannelEvent, RtcIceConnectionState, RtcPeerConnection,
     RtcPeerConnectionIceEvent, WebSocket,
 };
 
-use crate::one_to_one::{websocket_handler, NetworkManager};
-use crate::utils::IceCandidate;
+use crate::one_to_one::outbound_queue::BUFFERED_AMOUNT_LOW_THRESHOLD;
+use crate::one_to_one::{
+    websocket_handler, DisconnectReason, NetworkManager, TransportError, DISCONNECTED_TIMEOUT_MS,
+};
+use crate::utils::{global_function, IceCandidate};
 
 /// also calls:
 /// * set_data_channel_on_open
 /// * set_data_channel_on_message
 /// * set_data_channel_on_error
+/// * set_data_channel_on_close
+/// * set_data_channel_on_buffered_amount_low
 pub(crate) fn set_peer_connection_on_data_channel(
     peer_connection: &RtcPeerConnection,
     network_manager: NetworkManager,
@@ -25,10 +29,24 @@ pub(crate) fn set_peer_connection_on_data_channel(
     let on_datachannel = Closure::wrap(Box::new(move |data_channel_event: RtcDataChannelEvent| {
         info!("received data channel");
         let data_channel = data_channel_event.channel();
+        if !network_manager.accepts_incoming_channel(&data_channel) {
+            info!("rejected data channel: {:?}", data_channel.label());
+            data_channel.close();
+            return;
+        }
+        // channel opened in `start` is labeled with the session id, others were added by renegotiating
+        let session_id = network_manager.inner.borrow().session_id.clone();
+        if data_channel.label() != session_id.as_str() {
+            set_data_channel_on_error(&data_channel, network_manager.clone());
+            network_manager.on_channel_added(data_channel);
+            return;
+        }
 
         set_data_channel_on_open(&data_channel, on_open_callback.clone());
-        set_data_channel_on_error(&data_channel);
+        set_data_channel_on_error(&data_channel, network_manager.clone());
+        set_data_channel_on_close(&data_channel, network_manager.clone());
         set_data_channel_on_message(&data_channel, on_message_callback.clone());
+        set_data_channel_on_buffered_amount_low(&data_channel, network_manager.clone());
 
         network_manager.inner.borrow_mut().data_channel = Some(data_channel);
     }) as Box<dyn FnMut(RtcDataChannelEvent)>);
@@ -37,7 +55,11 @@ pub(crate) fn set_peer_connection_on_data_channel(
 }
 
 /// handle message sent by signaling server
-pub(crate) fn set_websocket_on_message(websocket: &WebSocket, peer_connection: RtcPeerConnection) {
+pub(crate) fn set_websocket_on_message(
+    websocket: &WebSocket,
+    peer_connection: RtcPeerConnection,
+    network_manager: NetworkManager,
+) {
     {
         let websocket_clone = websocket.clone();
         let peer_connection_clone = peer_connection;
@@ -47,15 +69,18 @@ pub(crate) fn set_websocket_on_message(websocket: &WebSocket, peer_connection: R
                     Ok(message) => {
                         let websocket_clone = websocket_clone.clone();
                         let peer_connection_clone = peer_connection_clone.clone();
+                        let network_manager = network_manager.clone();
                         wasm_bindgen_futures::spawn_local(async move {
                             websocket_handler::handle_websocket_message(
                                 message,
                                 peer_connection_clone,
                                 websocket_clone,
+                                network_manager.clone(),
                             )
                             .await
                             .unwrap_or_else(|error| {
                                 error!("error handling websocket message: {:?}", error);
+                                network_manager.record_error(format!("{:?}", error));
                             })
                         });
                     }
@@ -70,26 +95,34 @@ pub(crate) fn set_websocket_on_message(websocket: &WebSocket, peer_connection: R
     }
 }
 
-/// once web socket is open, send a request to start or join a session
-pub(crate) fn set_websocket_on_open(websocket: &WebSocket, session_id: SessionId) {
-    {
-        let websocket_clone = websocket.clone();
-        let onopen_callback = Closure::wrap(Box::new(move |_| {
-            let signal_message = SignalMessage::SessionJoin(session_id.clone());
-            let signal_message = serde_json_wasm::to_string(&signal_message)
-                .expect("failed serializing SignalMessage");
-            websocket_clone
-                .send_with_str(&signal_message)
-                .expect("failed sending start-or-join message to the websocket");
-        }) as Box<dyn FnMut(JsValue)>);
-        websocket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
-        onopen_callback.forget();
+/// once web socket is open, send a request to start or join a session, or to find a match
+pub(crate) fn set_websocket_on_open(websocket: &WebSocket, join_message: &SignalMessage) {
+    let websocket_clone = websocket.clone();
+    let signal_message =
+        serde_json_wasm::to_string(join_message).expect("failed serializing SignalMessage");
+    let send_session_join = move || {
+        websocket_clone
+            .send_with_str(&signal_message)
+            .expect("failed sending start-or-join message to the websocket");
+    };
+    // websocket connected to up front (e.g. during failover) won't fire onopen again
+    if websocket.ready_state() == WebSocket::OPEN {
+        send_session_join();
+        return;
     }
+    let onopen_callback =
+        Closure::wrap(Box::new(move |_| send_session_join()) as Box<dyn FnMut(JsValue)>);
+    websocket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+    onopen_callback.forget();
 }
 
-pub(crate) fn set_peer_connection_on_negotiation_needed(peer_connection: &RtcPeerConnection) {
+pub(crate) fn set_peer_connection_on_negotiation_needed(
+    peer_connection: &RtcPeerConnection,
+    network_manager: NetworkManager,
+) {
     let on_negotiation_needed = Closure::wrap(Box::new(move || {
         debug!("on negotiation needed event occurred");
+        network_manager.inner.borrow_mut().negotiation_needed = true;
     }) as Box<dyn FnMut()>);
     peer_connection.set_onnegotiationneeded(Some(on_negotiation_needed.as_ref().unchecked_ref()));
     on_negotiation_needed.forget();
@@ -97,13 +130,13 @@ pub(crate) fn set_peer_connection_on_negotiation_needed(peer_connection: &RtcPee
 
 pub(crate) fn set_peer_connection_on_ice_gathering_state_change(
     peer_connection: &RtcPeerConnection,
+    network_manager: NetworkManager,
 ) {
     let peer_connection_clone = peer_connection.clone();
     let on_ice_gathering_state_change = Closure::wrap(Box::new(move || {
-        debug!(
-            "ice gathering state: {:?}",
-            peer_connection_clone.ice_gathering_state()
-        );
+        let ice_gathering_state = peer_connection_clone.ice_gathering_state();
+        debug!("ice gathering state: {:?}", ice_gathering_state);
+        network_manager.on_ice_gathering_state(ice_gathering_state);
     }) as Box<dyn FnMut()>);
     peer_connection.set_onicegatheringstatechange(Some(
         on_ice_gathering_state_change.as_ref().unchecked_ref(),
@@ -121,29 +154,56 @@ pub(crate) fn set_data_channel_on_message(
                 "message from datachannel (will call on_message): {:?}",
                 message
             );
-            on_message_callback(
-                message
-                    // this is an ugly fix to the fact, that if you send empty string as message
-                    // webrtc fails with a cryptic "The operation failed for an operation-specific reason"
-                    // message
-                    .strip_prefix('x')
-                    .expect("messages must have a fix-bug x prepended")
-                    .to_string(),
-            );
+            on_message_callback(message);
         }
     }) as Box<dyn FnMut(MessageEvent)>);
     data_channel.set_onmessage(Some(datachannel_on_message.as_ref().unchecked_ref()));
     datachannel_on_message.forget();
 }
 
-pub(crate) fn set_data_channel_on_error(data_channel: &RtcDataChannel) {
+/// flush messages queued by the crate, once browser's buffer drains
+pub(crate) fn set_data_channel_on_buffered_amount_low(
+    data_channel: &RtcDataChannel,
+    network_manager: NetworkManager,
+) {
+    data_channel.set_buffered_amount_low_threshold(BUFFERED_AMOUNT_LOW_THRESHOLD);
+    let on_buffered_amount_low = Closure::wrap(Box::new(move || {
+        network_manager
+            .flush_outbound_queue()
+            .unwrap_or_else(|error| error!("failed to flush outbound queue: {:?}", error));
+    }) as Box<dyn FnMut()>);
+    data_channel.set_onbufferedamountlow(Some(on_buffered_amount_low.as_ref().unchecked_ref()));
+    on_buffered_amount_low.forget();
+}
+
+pub(crate) fn set_data_channel_on_error(
+    data_channel: &RtcDataChannel,
+    network_manager: NetworkManager,
+) {
+    let label = data_channel.label();
     let onerror = Closure::wrap(Box::new(move |data_channel_error| {
-        error!("data channel error: {:?}", data_channel_error);
+        network_manager.on_transport_error(TransportError::from_event(
+            label.clone(),
+            &data_channel_error,
+        ));
     }) as Box<dyn FnMut(JsValue)>);
     data_channel.set_onerror(Some(onerror.as_ref().unchecked_ref()));
     onerror.forget();
 }
 
+/// only for the channel opened in `start`, cleans it up after a fatal transport error
+pub(crate) fn set_data_channel_on_close(
+    data_channel: &RtcDataChannel,
+    network_manager: NetworkManager,
+) {
+    let onclose = Closure::wrap(Box::new(move || {
+        debug!("data channel closed");
+        network_manager.on_data_channel_closed();
+    }) as Box<dyn FnMut()>);
+    data_channel.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+    onclose.forget();
+}
+
 pub(crate) fn set_data_channel_on_open(
     data_channel: &RtcDataChannel,
     mut on_open_callback: impl FnMut() + 'static,
@@ -156,15 +216,36 @@ pub(crate) fn set_data_channel_on_open(
     onopen_callback.forget();
 }
 
+/// also reports the peer as gone once the connection fails
 pub(crate) fn set_peer_connection_on_ice_connection_state_change(
     peer_connection: &RtcPeerConnection,
+    network_manager: NetworkManager,
 ) {
     let peer_connection_clone = peer_connection.clone();
     let on_ice_connection_state_change = Closure::wrap(Box::new(move || {
-        debug!(
-            "connection state change: {:?}",
-            peer_connection_clone.ice_connection_state()
-        )
+        let ice_connection_state = peer_connection_clone.ice_connection_state();
+        debug!("connection state change: {:?}", ice_connection_state);
+        network_manager.on_ice_connection_state(ice_connection_state);
+        match ice_connection_state {
+            // selected candidate pair may have changed, e.g. after an `ICE` restart
+            RtcIceConnectionState::Connected | RtcIceConnectionState::Completed => {
+                cancel_disconnected_timeout(&network_manager);
+                network_manager.refresh_relayed();
+            }
+            RtcIceConnectionState::Failed => {
+                cancel_disconnected_timeout(&network_manager);
+                network_manager.on_disconnect(DisconnectReason::ConnectionFailed);
+            }
+            RtcIceConnectionState::Disconnected => {
+                cancel_disconnected_timeout(&network_manager);
+                spawn_disconnected_timeout(peer_connection_clone.clone(), network_manager.clone())
+                    .unwrap_or_else(|error| {
+                        error!("failed to set disconnected timeout: {:?}", error);
+                    });
+            }
+            // closed only after local close, which reports the disconnect itself
+            _ => {}
+        }
     }) as Box<dyn FnMut()>);
     peer_connection.set_oniceconnectionstatechange(Some(
         on_ice_connection_state_change.as_ref().unchecked_ref(),
@@ -172,13 +253,62 @@ pub(crate) fn set_peer_connection_on_ice_connection_state_change(
     on_ice_connection_state_change.forget();
 }
 
+/// Reports the disconnect if the connection doesn't recover in time,
+/// cancelled with [`cancel_disconnected_timeout`] when it does.
+fn spawn_disconnected_timeout(
+    peer_connection: RtcPeerConnection,
+    network_manager: NetworkManager,
+) -> Result<(), JsValue> {
+    let network_manager_clone = network_manager.clone();
+    let on_timeout = Closure::once_into_js(move || {
+        network_manager_clone
+            .inner
+            .borrow_mut()
+            .disconnected_timeout = None;
+        if peer_connection.ice_connection_state() == RtcIceConnectionState::Disconnected {
+            network_manager_clone.on_disconnect(DisconnectReason::Timeout);
+        }
+    });
+    let handle = global_function("setTimeout")?.call2(
+        &JsValue::NULL,
+        &on_timeout,
+        &JsValue::from(DISCONNECTED_TIMEOUT_MS),
+    )?;
+    network_manager.inner.borrow_mut().disconnected_timeout = Some(handle);
+    Ok(())
+}
+
+/// Clears the timeout set by [`spawn_disconnected_timeout`], if it's still pending,
+/// so that an earlier disconnection isn't reported after the connection recovered.
+fn cancel_disconnected_timeout(network_manager: &NetworkManager) {
+    let handle = network_manager
+        .inner
+        .borrow_mut()
+        .disconnected_timeout
+        .take();
+    if let Some(handle) = handle {
+        if let Err(error) = global_function("clearTimeout")
+            .and_then(|clear_timeout| clear_timeout.call1(&JsValue::NULL, &handle))
+        {
+            error!("failed to clear disconnected timeout: {:?}", error);
+        }
+    }
+}
+
 pub(crate) fn set_peer_connection_on_ice_candidate(
     peer_connection: &RtcPeerConnection,
     websocket_clone: WebSocket,
-    session_id_clone: SessionId,
+    network_manager: NetworkManager,
 ) {
     let on_ice_candidate = Closure::wrap(Box::new(move |ev: RtcPeerConnectionIceEvent| {
         if let Some(candidate) = ev.candidate() {
+            if !network_manager.allows_local_candidate(&candidate.candidate()) {
+                debug!(
+                    "not signaling filtered candidate: {}",
+                    candidate.candidate()
+                );
+                return;
+            }
             let signaled_candidate = IceCandidate {
                 candidate: candidate.candidate(),
                 sdp_mid: candidate.sdp_mid(),
@@ -188,8 +318,9 @@ pub(crate) fn set_peer_connection_on_ice_candidate(
             let signaled_candidate = serde_json_wasm::to_string(&signaled_candidate)
                 .expect("failed to serialize IceCandidate");
 
-            let signal_message =
-                SignalMessage::IceCandidate(session_id_clone.clone(), signaled_candidate);
+            // session id is read on each candidate, as matchmaking only assigns it later
+            let session_id = network_manager.inner.borrow().session_id.clone();
+            let signal_message = SignalMessage::IceCandidate(session_id, signaled_candidate);
             let signal_message = serde_json_wasm::to_string(&signal_message)
                 .expect("failed to serialize SignalMessage");
 
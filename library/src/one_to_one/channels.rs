@@ -0,0 +1,85 @@
+use log::debug;
+use wasm_bindgen::JsValue;
+use web_sys::RtcDataChannel;
+
+use crate::callback::Callback;
+use crate::one_to_one::{ChannelCallback, IncomingChannelCallback, NetworkManager};
+use crate::utils::{create_data_channel, ChannelInfo, DataChannelConfig};
+
+impl NetworkManager {
+    /// Lists data channels of the connection with their current state,
+    /// including the ones added with [`NetworkManager::add_channel`] and the ones the other peer added,
+    /// see [`NetworkManager::set_on_channel_added`]. Empty until [`NetworkManager::start`] creates the data channel.
+    pub fn channels(&self) -> Vec<ChannelInfo> {
+        let inner = self.inner.borrow();
+        inner
+            .data_channel
+            .iter()
+            .chain(&inner.added_channels)
+            .map(ChannelInfo::of)
+            .collect()
+    }
+
+    /// Sets a callback called with each data channel the other peer adds to the established connection,
+    /// once it's accepted by [`NetworkManager::set_on_incoming_channel`].
+    /// Offers renegotiating the connection are answered on their own, following the perfect negotiation
+    /// pattern with the host as the impolite peer, so the application only has to use the channel.
+    /// Messages on added channels go to their own handlers, not to `on_message_callback`.
+    /// Without a callback, added channels are accepted but left unused.
+    pub fn set_on_channel_added(&mut self, on_channel_added: impl FnMut(RtcDataChannel) + 'static) {
+        self.inner.borrow_mut().on_channel_added = Some(ChannelCallback::new(on_channel_added));
+    }
+
+    pub(crate) fn on_channel_added(&self, data_channel: RtcDataChannel) {
+        let on_channel_added = {
+            let mut inner = self.inner.borrow_mut();
+            inner.added_channels.push(data_channel.clone());
+            inner.on_channel_added.clone()
+        };
+        match on_channel_added {
+            Some(callback) => callback.call(data_channel),
+            None => debug!("no callback set for added channels, ignoring it"),
+        }
+    }
+
+    /// Sets a hook deciding whether a data channel opened by the other peer is accepted,
+    /// e.g. to allow only the channels the application expects, by label and protocol.
+    /// Hook returns `true` to accept the channel, rejected ones are closed right away.
+    /// Every channel is accepted by default. The channel each peer opens in [`NetworkManager::start`]
+    /// is labeled with the session id and must be accepted for the connection to work.
+    pub fn set_on_incoming_channel(
+        &mut self,
+        mut on_incoming_channel: impl FnMut(&ChannelInfo) -> bool + 'static,
+    ) {
+        self.inner.borrow_mut().on_incoming_channel =
+            Some(IncomingChannelCallback::new(move |info: ChannelInfo| {
+                on_incoming_channel(&info)
+            }));
+    }
+
+    pub(crate) fn accepts_incoming_channel(&self, data_channel: &RtcDataChannel) -> bool {
+        match Callback::of(&self.inner, |inner| &inner.on_incoming_channel) {
+            Some(hook) => hook.call(ChannelInfo::of(data_channel)),
+            None => true,
+        }
+    }
+
+    /// Opens another data channel on the connection, e.g. for a separate kind of messages,
+    /// listed by [`NetworkManager::channels`]. The other peer gets it with [`NetworkManager::set_on_channel_added`]
+    /// once the connection is renegotiated with [`NetworkManager::renegotiate`].
+    /// Messages on it go to its own handlers, not to `on_message_callback`.
+    ///
+    /// # Errors
+    /// This function errors if configured protocol exceeds the length allowed by the specification.
+    pub fn add_channel(
+        &self,
+        label: &str,
+        data_channel_config: &DataChannelConfig,
+    ) -> Result<RtcDataChannel, JsValue> {
+        data_channel_config.validate()?;
+        let mut inner = self.inner.borrow_mut();
+        let data_channel = create_data_channel(&inner.peer_connection, label, data_channel_config);
+        inner.added_channels.push(data_channel.clone());
+        Ok(data_channel)
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::control::CLOCK_PROBE_FRAME;
+
+/// Shortest interval between clock probes, see [`crate::one_to_one::NetworkManager::start_clock_sync`].
+pub const MIN_CLOCK_SYNC_INTERVAL_MS: u32 = 1000;
+
+/// Probes longer than that are dropped, real ones take well under a hundred bytes.
+const MAX_PROBE_LENGTH: usize = 256;
+
+/// Number of latest samples the estimate is picked from, older ones are forgotten to follow clock drift.
+const SAMPLE_WINDOW: usize = 8;
+
+/// Offset of the other peer's clock, as estimated by [`crate::one_to_one::NetworkManager::peer_time_offset`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ClockOffset {
+    /// Milliseconds to add to local `Date.now()` to get the other peer's `Date.now()`.
+    pub offset_ms: f64,
+    /// Real offset is within this many milliseconds of `offset_ms`,
+    /// half of the round trip time of the probe the estimate comes from.
+    pub uncertainty_ms: f64,
+}
+
+/// Timestamps of a probe, in milliseconds since the epoch on the clock of the peer taking them.
+/// Request carries only `origin`, response echoes it with the other peer's timestamps added.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Probe {
+    /// When the requesting peer sent the request.
+    pub(crate) origin: f64,
+    /// When the responding peer received the request.
+    pub(crate) receive: Option<f64>,
+    /// When the responding peer sent the response.
+    pub(crate) transmit: Option<f64>,
+}
+
+pub(crate) fn encode_probe(probe: &Probe) -> Result<String, JsValue> {
+    let probe =
+        serde_json_wasm::to_string(probe).map_err(|error| JsValue::from_str(&error.to_string()))?;
+    Ok(format!("{}{}", CLOCK_PROBE_FRAME, probe))
+}
+
+/// Returns `None` if the message isn't a clock probe.
+pub(crate) fn decode_probe(message: &str) -> Option<Result<Probe, JsValue>> {
+    let probe = message.strip_prefix(CLOCK_PROBE_FRAME)?;
+    if probe.len() > MAX_PROBE_LENGTH {
+        return Some(Err(JsValue::from_str(&format!(
+            "clock probe is too long: {} bytes, maximum is {}",
+            probe.len(),
+            MAX_PROBE_LENGTH
+        ))));
+    }
+    Some(
+        serde_json_wasm::from_str::<Probe>(probe)
+            .map_err(|error| JsValue::from_str(&error.to_string())),
+    )
+}
+
+/// Estimates the offset of the other peer's clock the way `NTP` does,
+/// trusting the sample with the shortest round trip the most, as it was delayed the least.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClockSync {
+    /// Offset and round trip time of the latest samples.
+    samples: VecDeque<(f64, f64)>,
+}
+
+impl ClockSync {
+    /// Adds the sample from a response arriving at `destination`.
+    /// Probes that aren't responses, or with timestamps out of order, are ignored.
+    pub(crate) fn add_response(&mut self, probe: &Probe, destination: f64) {
+        let (receive, transmit) = match (probe.receive, probe.transmit) {
+            (Some(receive), Some(transmit)) => (receive, transmit),
+            _ => return,
+        };
+        let round_trip_time = (destination - probe.origin) - (transmit - receive);
+        if !round_trip_time.is_finite() || round_trip_time < 0.0 || transmit < receive {
+            return;
+        }
+        let offset = ((receive - probe.origin) + (transmit - destination)) / 2.0;
+        if self.samples.len() >= SAMPLE_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((offset, round_trip_time));
+    }
+
+    /// Returns `None` until the first response arrives.
+    pub(crate) fn offset(&self) -> Option<ClockOffset> {
+        self.samples
+            .iter()
+            .min_by(|(_, first), (_, second)| first.total_cmp(second))
+            .map(|(offset, round_trip_time)| ClockOffset {
+                offset_ms: *offset,
+                uncertainty_ms: round_trip_time / 2.0,
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_probe_round_trips() {
+        let probe = Probe {
+            origin: 1000.0,
+            receive: Some(1510.0),
+            transmit: Some(1512.0),
+        };
+
+        let message = encode_probe(&probe).unwrap();
+
+        assert_eq!(decode_probe(&message).unwrap().unwrap(), probe);
+        assert!(decode_probe(&format!("x{}", &message[1..])).is_none());
+        assert!(
+            decode_probe(&format!("c{}", " ".repeat(MAX_PROBE_LENGTH + 1)))
+                .unwrap()
+                .is_err()
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_offset_comes_from_fastest_round_trip() {
+        let mut clock_sync = ClockSync::default();
+        assert_eq!(clock_sync.offset(), None);
+
+        // peer's clock is 500 ms ahead, 10 ms each way
+        clock_sync.add_response(
+            &Probe {
+                origin: 1000.0,
+                receive: Some(1510.0),
+                transmit: Some(1512.0),
+            },
+            1022.0,
+        );
+        // slower and asymmetric, so less accurate
+        clock_sync.add_response(
+            &Probe {
+                origin: 2000.0,
+                receive: Some(2600.0),
+                transmit: Some(2600.0),
+            },
+            2120.0,
+        );
+        // not a response
+        clock_sync.add_response(
+            &Probe {
+                origin: 3000.0,
+                receive: None,
+                transmit: None,
+            },
+            3000.0,
+        );
+
+        assert_eq!(
+            clock_sync.offset(),
+            Some(ClockOffset {
+                offset_ms: 500.0,
+                uncertainty_ms: 10.0,
+            })
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+
+/// How congested the connection with the other peer is,
+/// see [`crate::one_to_one::NetworkManager::congestion_level`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CongestionLevel {
+    /// Messages leave about as fast as they are sent.
+    Low,
+    /// Messages pile up or the round trip time grows, sending less is advisable.
+    Medium,
+    /// Messages pile up quickly, sending should be cut down.
+    High,
+}
+
+/// Thresholds of the congestion heuristic, see [`crate::one_to_one::NetworkManager::start_congestion_monitor`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CongestionThresholds {
+    /// Bytes waiting to be sent, queued by the crate or buffered by the browser,
+    /// from which the connection is at least [`CongestionLevel::Medium`].
+    pub medium_pending_bytes: usize,
+    /// Bytes waiting to be sent from which the connection is [`CongestionLevel::High`].
+    pub high_pending_bytes: usize,
+    /// Number of the latest samples in which growing pending bytes raise the level by one.
+    pub trend_samples: usize,
+    /// How many times the lowest round trip time seen so far the current one has to be
+    /// to raise the level by one.
+    pub round_trip_time_ratio: f64,
+}
+
+impl Default for CongestionThresholds {
+    fn default() -> Self {
+        CongestionThresholds {
+            medium_pending_bytes: 256 * 1024,
+            high_pending_bytes: 1024 * 1024,
+            trend_samples: 4,
+            round_trip_time_ratio: 2.0,
+        }
+    }
+}
+
+/// Derives congestion level from samples of pending bytes and round trip time.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CongestionDetector {
+    thresholds: CongestionThresholds,
+    pending_bytes: VecDeque<usize>,
+    min_round_trip_time: Option<f64>,
+    level: Option<CongestionLevel>,
+}
+
+impl CongestionDetector {
+    pub(crate) fn set_thresholds(&mut self, thresholds: CongestionThresholds) {
+        self.thresholds = thresholds;
+        self.pending_bytes.clear();
+    }
+
+    pub(crate) fn level(&self) -> CongestionLevel {
+        self.level.unwrap_or(CongestionLevel::Low)
+    }
+
+    /// Adds a sample and returns the new level if it changed.
+    pub(crate) fn update(
+        &mut self,
+        pending_bytes: usize,
+        round_trip_time_ms: Option<f64>,
+    ) -> Option<CongestionLevel> {
+        self.pending_bytes.push_back(pending_bytes);
+        while self.pending_bytes.len() > self.thresholds.trend_samples.max(2) {
+            self.pending_bytes.pop_front();
+        }
+
+        let mut level = if pending_bytes >= self.thresholds.high_pending_bytes {
+            CongestionLevel::High
+        } else if pending_bytes >= self.thresholds.medium_pending_bytes {
+            CongestionLevel::Medium
+        } else {
+            CongestionLevel::Low
+        };
+        if self.pending_bytes_growing() {
+            level = raised(level);
+        }
+        if let Some(round_trip_time) = round_trip_time_ms {
+            let min_round_trip_time = self
+                .min_round_trip_time
+                .map_or(round_trip_time, |min| min.min(round_trip_time));
+            self.min_round_trip_time = Some(min_round_trip_time);
+            if round_trip_time > min_round_trip_time * self.thresholds.round_trip_time_ratio {
+                level = raised(level);
+            }
+        }
+
+        let changed = self.level() != level;
+        self.level = Some(level);
+        changed.then_some(level)
+    }
+
+    /// Growing means the window is full, never shrinks and ends higher than it started.
+    fn pending_bytes_growing(&self) -> bool {
+        self.pending_bytes.len() >= self.thresholds.trend_samples.max(2)
+            && self
+                .pending_bytes
+                .iter()
+                .zip(self.pending_bytes.iter().skip(1))
+                .all(|(previous, next)| previous <= next)
+            && self.pending_bytes.front() < self.pending_bytes.back()
+    }
+}
+
+fn raised(level: CongestionLevel) -> CongestionLevel {
+    match level {
+        CongestionLevel::Low => CongestionLevel::Medium,
+        CongestionLevel::Medium | CongestionLevel::High => CongestionLevel::High,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_level_follows_pending_bytes_thresholds() {
+        let mut detector = CongestionDetector::default();
+
+        assert_eq!(detector.update(0, None), None);
+        assert_eq!(
+            detector.update(300 * 1024, None),
+            Some(CongestionLevel::Medium)
+        );
+        assert_eq!(
+            detector.update(2 * 1024 * 1024, None),
+            Some(CongestionLevel::High)
+        );
+        assert_eq!(detector.update(0, None), Some(CongestionLevel::Low));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_steadily_growing_buffer_raises_level() {
+        let mut detector = CongestionDetector::default();
+
+        for pending_bytes in [1000, 2000, 3000] {
+            assert_eq!(detector.update(pending_bytes, None), None);
+        }
+
+        assert_eq!(detector.update(4000, None), Some(CongestionLevel::Medium));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_increasing_round_trip_time_raises_level() {
+        let mut detector = CongestionDetector::default();
+
+        assert_eq!(detector.update(0, Some(20.0)), None);
+        assert_eq!(detector.update(0, Some(30.0)), None);
+        assert_eq!(
+            detector.update(0, Some(50.0)),
+            Some(CongestionLevel::Medium)
+        );
+    }
+}
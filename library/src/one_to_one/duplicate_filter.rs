@@ -0,0 +1,79 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+
+/// Largest window accepted by [`crate::one_to_one::NetworkManager::set_duplicate_window`].
+/// Each remembered message takes a 64-bit hash, stored twice with the bookkeeping,
+/// so a full window costs around 100 KiB per network manager.
+pub const MAX_DUPLICATE_WINDOW: usize = 4096;
+
+/// Drops messages identical to one of the last `window` messages received.
+/// Only hashes are kept, a hash collision would drop a message that isn't a duplicate,
+/// which is vanishingly unlikely with 64-bit hashes and windows this small.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DuplicateFilter {
+    window: usize,
+    /// Hashes of the last messages, oldest first.
+    recent: VecDeque<u64>,
+    /// Same hashes as `recent`, for lookups.
+    known: HashSet<u64>,
+}
+
+impl DuplicateFilter {
+    /// Window of 0 disables the filter and forgets remembered messages.
+    pub(crate) fn set_window(&mut self, window: usize) {
+        self.window = window.min(MAX_DUPLICATE_WINDOW);
+        while self.recent.len() > self.window {
+            self.forget_oldest();
+        }
+    }
+
+    /// Returns `true` if the message was already received within the window,
+    /// otherwise remembers it.
+    pub(crate) fn is_duplicate(&mut self, message: &str) -> bool {
+        if self.window == 0 {
+            return false;
+        }
+        let mut hasher = DefaultHasher::new();
+        message.hash(&mut hasher);
+        let hash = hasher.finish();
+        if self.known.contains(&hash) {
+            return true;
+        }
+        if self.recent.len() >= self.window {
+            self.forget_oldest();
+        }
+        self.recent.push_back(hash);
+        self.known.insert(hash);
+        false
+    }
+
+    fn forget_oldest(&mut self) {
+        if let Some(hash) = self.recent.pop_front() {
+            self.known.remove(&hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_duplicates_are_dropped_only_within_window() {
+        let mut filter = DuplicateFilter::default();
+        assert!(!filter.is_duplicate("a"));
+        assert!(!filter.is_duplicate("a"));
+
+        filter.set_window(2);
+
+        assert!(!filter.is_duplicate("a"));
+        assert!(filter.is_duplicate("a"));
+        assert!(!filter.is_duplicate("b"));
+        assert!(!filter.is_duplicate("c"));
+        // "a" fell out of the window
+        assert!(!filter.is_duplicate("a"));
+    }
+}
@@ -0,0 +1,208 @@
+use log::warn;
+use wasm_bindgen::JsValue;
+
+use crate::control::FRAGMENT_FRAME;
+use crate::one_to_one::{FragmentationCallback, NetworkManager};
+
+/// Negotiated maximum message size below which text messages are fragmented by default,
+/// see [`crate::one_to_one::NetworkManager::set_fragmentation_threshold`].
+/// Browsers interoperate with messages of this size everywhere, so only constrained links negotiate less.
+pub const DEFAULT_FRAGMENTATION_THRESHOLD: usize = 16 * 1024;
+
+/// Longest message put back together from fragments, longer ones are dropped.
+pub const MAX_FRAGMENTED_MESSAGE_LENGTH: usize = 4 * 1024 * 1024;
+
+/// Room for the prefix and three `u32` fields with their separators, e.g. `f7:0:3:`.
+const MAX_HEADER_LENGTH: usize = 1 + 3 * (10 + 1);
+
+/// Part of a message too large for the negotiated maximum message size.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Fragment<'a> {
+    id: u32,
+    index: u32,
+    count: u32,
+    payload: &'a str,
+}
+
+/// Splits the message into fragments of at most `max_fragment_length` bytes, at character boundaries.
+pub(crate) fn split(
+    message: &str,
+    id: u32,
+    max_fragment_length: usize,
+) -> Result<Vec<String>, JsValue> {
+    let max_payload_length = max_fragment_length.saturating_sub(MAX_HEADER_LENGTH);
+    // any character has to fit, the longest ones take 4 bytes
+    if max_payload_length < 4 {
+        return Err(JsValue::from_str(&format!(
+            "maximum message size is too small to fragment messages: {} bytes",
+            max_fragment_length
+        )));
+    }
+    if message.len() > MAX_FRAGMENTED_MESSAGE_LENGTH {
+        return Err(JsValue::from_str(&format!(
+            "message is too large to fragment: {} bytes, maximum is {}",
+            message.len(),
+            MAX_FRAGMENTED_MESSAGE_LENGTH
+        )));
+    }
+    let mut payloads = Vec::new();
+    let mut rest = message;
+    while !rest.is_empty() {
+        let mut length = max_payload_length.min(rest.len());
+        while !rest.is_char_boundary(length) {
+            length -= 1;
+        }
+        let (payload, remaining) = rest.split_at(length);
+        payloads.push(payload);
+        rest = remaining;
+    }
+    let count = payloads.len();
+    Ok(payloads
+        .into_iter()
+        .enumerate()
+        .map(|(index, payload)| format!("{}{}:{}:{}:{}", FRAGMENT_FRAME, id, index, count, payload))
+        .collect())
+}
+
+/// Returns `None` if the message isn't a fragment.
+pub(crate) fn decode_fragment(message: &str) -> Option<Result<Fragment<'_>, JsValue>> {
+    let fragment = message.strip_prefix(FRAGMENT_FRAME)?;
+    let mut fields = fragment.splitn(4, ':');
+    let mut next_number = || fields.next().and_then(|field| field.parse::<u32>().ok());
+    let fragment = match (next_number(), next_number(), next_number(), fields.next()) {
+        (Some(id), Some(index), Some(count), Some(payload)) if index < count => Ok(Fragment {
+            id,
+            index,
+            count,
+            payload,
+        }),
+        _ => Err(JsValue::from_str("invalid message fragment")),
+    };
+    Some(fragment)
+}
+
+/// Puts fragments back together. Data channel is ordered, so fragments of a message
+/// arrive one after another, and a fragment out of order means the previous message is lost.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Reassembler {
+    /// Id and index of the next fragment expected.
+    next: Option<(u32, u32)>,
+    buffer: String,
+}
+
+impl Reassembler {
+    /// Returns the message once its last fragment arrives.
+    pub(crate) fn push(&mut self, fragment: &Fragment) -> Result<Option<String>, JsValue> {
+        if fragment.index == 0 {
+            self.buffer.clear();
+        } else if self.next != Some((fragment.id, fragment.index)) {
+            self.next = None;
+            self.buffer.clear();
+            return Err(JsValue::from_str("message fragment out of order"));
+        }
+        if self.buffer.len() + fragment.payload.len() > MAX_FRAGMENTED_MESSAGE_LENGTH {
+            self.next = None;
+            self.buffer.clear();
+            return Err(JsValue::from_str(&format!(
+                "fragmented message is too large, maximum is {} bytes",
+                MAX_FRAGMENTED_MESSAGE_LENGTH
+            )));
+        }
+        self.buffer.push_str(fragment.payload);
+        if fragment.index + 1 == fragment.count {
+            self.next = None;
+            return Ok(Some(std::mem::take(&mut self.buffer)));
+        }
+        self.next = Some((fragment.id, fragment.index + 1));
+        Ok(None)
+    }
+}
+
+impl NetworkManager {
+    /// Text messages longer than the negotiated [`NetworkManager::max_message_size`] are split into fragments
+    /// and put back together on the other side, if that maximum is below `threshold`, [`DEFAULT_FRAGMENTATION_THRESHOLD`] by default,
+    /// so sending keeps working on constrained links. `None` disables fragmentation, rejecting such messages instead.
+    /// Binary messages are never fragmented.
+    ///
+    /// The other peer needs a version of the crate that knows fragments to receive them,
+    /// and at most [`MAX_FRAGMENTED_MESSAGE_LENGTH`] bytes can be sent in fragments.
+    pub fn set_fragmentation_threshold(&mut self, threshold: Option<usize>) {
+        self.inner.borrow_mut().fragmentation_threshold = threshold;
+    }
+
+    /// Sets a callback called with the negotiated maximum message size when messages start being fragmented,
+    /// see [`NetworkManager::set_fragmentation_threshold`], e.g. to warn that the link is constrained.
+    /// It's called once per connection, on the first message that had to be fragmented.
+    pub fn set_on_fragmentation(&mut self, on_fragmentation: impl FnMut(usize) + 'static) {
+        self.inner.borrow_mut().on_fragmentation =
+            Some(FragmentationCallback::new(on_fragmentation));
+    }
+
+    /// Returns the length of fragments to split a text message of given length into,
+    /// `None` if it's sent whole. Reports fragmentation kicking in for the first time on the connection.
+    pub(super) fn fragment_length(&self, length: usize) -> Option<usize> {
+        let max_message_size = self.max_message_size()?;
+        let on_fragmentation = {
+            let mut inner = self.inner.borrow_mut();
+            let constrained = inner
+                .fragmentation_threshold
+                .is_some_and(|threshold| max_message_size < threshold);
+            if !constrained || length <= max_message_size {
+                return None;
+            }
+            if std::mem::replace(&mut inner.fragmentation_reported, true) {
+                return Some(max_message_size);
+            }
+            inner.on_fragmentation.clone()
+        };
+        warn!(
+            "maximum message size negotiated by the peers is only {} bytes, fragmenting messages",
+            max_message_size
+        );
+        if let Some(callback) = on_fragmentation {
+            callback.call(max_message_size);
+        }
+        Some(max_message_size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_fragments_fit_and_reassemble_multibyte_text() {
+        let message = "xzażółć gęślą jaźń 🦀".repeat(20);
+        let fragments = split(&message, 7, MAX_HEADER_LENGTH + 10).unwrap();
+        assert!(fragments.len() > 1);
+        assert!(fragments
+            .iter()
+            .all(|fragment| fragment.len() <= MAX_HEADER_LENGTH + 10));
+
+        let mut reassembler = Reassembler::default();
+        let mut reassembled = None;
+        for fragment in &fragments {
+            let fragment = decode_fragment(fragment).unwrap().unwrap();
+            assert!(reassembled.is_none());
+            reassembled = reassembler.push(&fragment).unwrap();
+        }
+        assert_eq!(reassembled.unwrap(), message);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fragment_out_of_order_is_rejected() {
+        let fragments = split(&"x".repeat(100), 1, MAX_HEADER_LENGTH + 10).unwrap();
+        let mut reassembler = Reassembler::default();
+
+        reassembler
+            .push(&decode_fragment(&fragments[0]).unwrap().unwrap())
+            .unwrap();
+        assert!(reassembler
+            .push(&decode_fragment(&fragments[2]).unwrap().unwrap())
+            .is_err());
+        assert!(split("x", 1, MAX_HEADER_LENGTH + 3).is_err());
+        assert!(decode_fragment("xf1:0:1:text").is_none());
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+
+/// Maximum number of messages held while receiving is paused,
+/// see [`crate::one_to_one::NetworkManager::pause_receive`].
+pub const MAX_PAUSED_MESSAGES: usize = 1024;
+
+/// Messages received while the application paused receiving,
+/// or while a message was being delivered.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InboundBuffer {
+    paused: bool,
+    /// Message callback is running, so messages it causes wait for it to return.
+    delivering: bool,
+    messages: VecDeque<String>,
+}
+
+impl InboundBuffer {
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub(crate) fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub(crate) fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub(crate) fn is_delivering(&self) -> bool {
+        self.delivering
+    }
+
+    pub(crate) fn set_delivering(&mut self, delivering: bool) {
+        self.delivering = delivering;
+    }
+
+    /// Holds the message until receiving is resumed.
+    /// Returns the oldest message if it had to be dropped to make room.
+    pub(crate) fn push(&mut self, message: String) -> Option<String> {
+        let dropped = if self.messages.len() >= MAX_PAUSED_MESSAGES {
+            self.messages.pop_front()
+        } else {
+            None
+        };
+        self.messages.push_back(message);
+        dropped
+    }
+
+    /// Returns the next held message, unless receiving is paused.
+    pub(crate) fn pop(&mut self) -> Option<String> {
+        if self.paused {
+            return None;
+        }
+        self.messages.pop_front()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_full_buffer_drops_oldest_message() {
+        let mut buffer = InboundBuffer::default();
+        buffer.pause();
+        for index in 0..MAX_PAUSED_MESSAGES {
+            assert_eq!(buffer.push(index.to_string()), None);
+        }
+
+        assert_eq!(buffer.push("newest".to_string()).as_deref(), Some("0"));
+        assert_eq!(buffer.pop(), None);
+        buffer.resume();
+        assert_eq!(buffer.pop().as_deref(), Some("1"));
+        assert_eq!(buffer.len(), MAX_PAUSED_MESSAGES - 1);
+    }
+}
@@ -0,0 +1,64 @@
+use log::info;
+use wasm_bindgen::JsValue;
+use wasm_peers_protocol::one_to_one::SignalMessage;
+
+use crate::callback::Callback;
+use crate::one_to_one::callbacks::{
+    set_peer_connection_on_ice_candidate, set_websocket_on_message, set_websocket_on_open,
+};
+use crate::one_to_one::{MessageCallback, NetworkManager};
+use crate::utils::open_websocket_with_failover;
+
+/// How long migrating waits for the new signaling server to accept the connection,
+/// see [`NetworkManager::migrate`].
+pub const MIGRATION_TIMEOUT_MS: u32 = 10_000;
+
+impl NetworkManager {
+    /// Sets a callback called with the url the signaling server asks to migrate to, e.g. before it shuts down,
+    /// leaving it to the application to call [`NetworkManager::migrate`], e.g. after checking the url.
+    /// Without a callback, the network manager migrates on its own.
+    pub fn set_on_migrate(&mut self, on_migrate: impl FnMut(String) + 'static) {
+        self.inner.borrow_mut().on_migrate = Some(MessageCallback::new(on_migrate));
+    }
+
+    pub(crate) async fn on_migrate(&self, signaling_server_url: String) -> Result<(), JsValue> {
+        match Callback::of(&self.inner, |inner| &inner.on_migrate) {
+            Some(callback) => {
+                callback.call(signaling_server_url);
+                Ok(())
+            }
+            None => self.migrate(&signaling_server_url).await,
+        }
+    }
+
+    /// Moves signaling to another signaling server and rejoins the session there,
+    /// without closing the peer connection, so an established connection carries on uninterrupted.
+    /// Both peers have to migrate to the same server, the one rejoining second gets the session ready
+    /// and peers renegotiate the existing connection.
+    /// If the new server doesn't accept the connection within [`MIGRATION_TIMEOUT_MS`],
+    /// signaling stays with the current server.
+    ///
+    /// # Errors
+    /// This function errors if connecting to the new signaling server fails or times out.
+    pub async fn migrate(&self, signaling_server_url: &str) -> Result<(), JsValue> {
+        let websocket =
+            open_websocket_with_failover(&[signaling_server_url], MIGRATION_TIMEOUT_MS).await?;
+        let (old_websocket, peer_connection, session_id) = {
+            let mut inner = self.inner.borrow_mut();
+            inner.migrating = true;
+            (
+                std::mem::replace(&mut inner.websocket, websocket.clone()),
+                inner.peer_connection.clone(),
+                inner.session_id.clone(),
+            )
+        };
+        info!("migrating to signaling server {}", signaling_server_url);
+        set_peer_connection_on_ice_candidate(&peer_connection, websocket.clone(), self.clone());
+        set_websocket_on_message(&websocket, peer_connection, self.clone());
+        set_websocket_on_open(&websocket, &SignalMessage::SessionJoin(session_id));
+        // old server only sees the connection close, which doesn't tell the other peer to disconnect
+        old_websocket.set_onmessage(None);
+        let _ = old_websocket.close();
+        Ok(())
+    }
+}
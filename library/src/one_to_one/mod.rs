@@ -8,7 +8,7 @@ with [::] method.
 
 This example shows two peers sending `ping` and `pong` messages to each other.
 
-```
+```no_run
 use wasm_peers::{ConnectionType, SessionId};
 use wasm_peers::one_to_one::NetworkManager;
 use web_sys::console;
@@ -52,23 +52,79 @@ client.start(client_on_open, client_on_message).unwrap();
 */
 
 use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
 use std::rc::Rc;
 
-use log::debug;
+use js_sys::{Array, Date, Promise};
+use log::{debug, error, info, warn};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
 use wasm_bindgen::JsValue;
-use wasm_peers_protocol::SessionId;
-use web_sys::{RtcDataChannel, RtcPeerConnection, WebSocket};
+use wasm_bindgen_futures::JsFuture;
+use wasm_peers_protocol::one_to_one::{SignalMessage, MAX_PEER_METADATA_LENGTH};
+use wasm_peers_protocol::{IsHost, SessionId};
+use web_sys::{
+    RtcDataChannel, RtcIceCandidate, RtcIceConnectionState, RtcIceGatheringState,
+    RtcPeerConnection, RtcSignalingState, WebSocket,
+};
 
+use crate::callback::Callback;
+use crate::connection_config::ConnectionConfig;
+use crate::fingerprint::{parse_fingerprint, DtlsFingerprint};
 use crate::one_to_one::callbacks::{
-    set_data_channel_on_error, set_data_channel_on_message, set_data_channel_on_open,
-    set_peer_connection_on_data_channel, set_peer_connection_on_ice_candidate,
-    set_peer_connection_on_ice_connection_state_change,
+    set_data_channel_on_buffered_amount_low, set_data_channel_on_close, set_data_channel_on_error,
+    set_data_channel_on_message, set_data_channel_on_open, set_peer_connection_on_data_channel,
+    set_peer_connection_on_ice_candidate, set_peer_connection_on_ice_connection_state_change,
     set_peer_connection_on_ice_gathering_state_change, set_peer_connection_on_negotiation_needed,
     set_websocket_on_message, set_websocket_on_open,
 };
-use crate::utils::{create_peer_connection, ConnectionType};
+use crate::utils::{
+    apply_ice_options, create_data_channel, create_ice_restart_offer, create_peer_connection,
+    create_sdp_offer, get_data_channel_protocol, get_max_message_size, get_selected_candidate_pair,
+    global_function, js_enum_name, open_websocket, open_websocket_with_failover, serialize_message,
+    set_ice_servers, timeout_promise, websocket_state_name, ChannelInfo, ConnectionFallbackPolicy,
+    ConnectionQuality, ConnectionType, DataChannelConfig, Diagnostics, IceOptions,
+    SelectedCandidatePair, FAILOVER_ATTEMPT_TIMEOUT_MS,
+};
+
+use crate::control::APPLICATION_FRAME;
+use crate::one_to_one::clock_sync::{decode_probe, ClockSync};
+pub use crate::one_to_one::clock_sync::{ClockOffset, MIN_CLOCK_SYNC_INTERVAL_MS};
+use crate::one_to_one::congestion::CongestionDetector;
+pub use crate::one_to_one::congestion::{CongestionLevel, CongestionThresholds};
+use crate::one_to_one::duplicate_filter::DuplicateFilter;
+pub use crate::one_to_one::duplicate_filter::MAX_DUPLICATE_WINDOW;
+use crate::one_to_one::fragmentation::{decode_fragment, split, Reassembler};
+pub use crate::one_to_one::fragmentation::{
+    DEFAULT_FRAGMENTATION_THRESHOLD, MAX_FRAGMENTED_MESSAGE_LENGTH,
+};
+use crate::one_to_one::inbound_buffer::InboundBuffer;
+pub use crate::one_to_one::inbound_buffer::MAX_PAUSED_MESSAGES;
+pub use crate::one_to_one::migration::MIGRATION_TIMEOUT_MS;
+use crate::one_to_one::outbound_queue::OutboundQueue;
+use crate::one_to_one::peer_quality::decode_report;
+pub use crate::one_to_one::peer_quality::MIN_QUALITY_REPORT_INTERVAL_MS;
+pub use crate::one_to_one::presence::MIN_PRESENCE_INTERVAL_MS;
+use crate::one_to_one::presence::{is_heartbeat, PresenceTracker};
+use crate::one_to_one::reconnect::{ReconnectEvent, ReconnectTracker};
+pub use crate::one_to_one::transport_error::TransportError;
 
 mod callbacks;
+mod channels;
+mod clock_sync;
+mod congestion;
+mod duplicate_filter;
+mod fragmentation;
+mod inbound_buffer;
+mod migration;
+mod monitoring;
+mod negotiation;
+mod outbound_queue;
+mod peer_quality;
+mod presence;
+mod reconnect;
+mod transport_error;
 mod websocket_handler;
 
 #[derive(Debug, Clone)]
@@ -76,9 +132,156 @@ pub(crate) struct NetworkManagerInner {
     session_id: SessionId,
     websocket: WebSocket,
     peer_connection: RtcPeerConnection,
+    /// Last `ICE` servers set on the connection, for [`NetworkManager::connection_config`].
+    connection_type: ConnectionType,
+    data_channel_config: DataChannelConfig,
+    pub(crate) ice_options: IceOptions,
+    /// When the first local candidate since the last `ICE` restart was gathered, for `gathering_timeout_ms`.
+    pub(crate) first_candidate_at: Option<f64>,
     pub(crate) data_channel: Option<RtcDataChannel>,
+    pub(crate) outbound_queue: OutboundQueue,
+    pub(crate) metadata: Option<String>,
+    /// Sent before anything else each time the data channel opens, see [`NetworkManager::set_first_message`].
+    first_message: Option<String>,
+    match_criteria: Option<String>,
+    on_peer_metadata: Option<MessageCallback>,
+    on_disconnect: Option<DisconnectCallback>,
+    pub(crate) disconnect_reported: bool,
+    /// Pending `setTimeout` reporting the disconnect unless `ICE` reconnects, see [`DISCONNECTED_TIMEOUT_MS`].
+    pub(crate) disconnected_timeout: Option<JsValue>,
+    inbound_buffer: InboundBuffer,
+    duplicate_filter: DuplicateFilter,
+    /// Candidates received before the remote description was set.
+    pub(crate) held_ice_candidates: Vec<RtcIceCandidate>,
+    last_error: Option<String>,
+    /// Whether the selected candidate pair goes through a `TURN` server, see [`NetworkManager::is_relayed`].
+    relayed: bool,
+    /// Whether this peer creates the offers, as told by the signaling server in `SessionReady`.
+    pub(crate) is_host: bool,
+    /// `ICE` restart requested while a negotiation was in progress, to start once it's done.
+    pub(crate) ice_restart_pending: bool,
+    /// Whether the browser reported that the connection needs renegotiating since the last negotiation,
+    /// see [`NetworkManager::renegotiate`].
+    pub(crate) negotiation_needed: bool,
+    /// Renegotiation requested while a negotiation was in progress, to start once it's done.
+    pub(crate) renegotiation_pending: bool,
+    congestion: CongestionDetector,
+    on_congestion_change: Option<CongestionCallback>,
+    on_bandwidth_estimate: Option<BandwidthCallback>,
+    congestion_timer: Option<IntervalTimer>,
+    on_peer_quality: Option<PeerQualityCallback>,
+    quality_report_timer: Option<IntervalTimer>,
+    clock_sync: ClockSync,
+    clock_sync_timer: Option<IntervalTimer>,
+    presence: PresenceTracker,
+    presence_timer: Option<IntervalTimer>,
+    on_peer_unresponsive: Option<NotifyCallback>,
+    on_ice_gathering_state_change: Option<IceGatheringCallback>,
+    /// See [`NetworkManager::set_fragmentation_threshold`].
+    fragmentation_threshold: Option<usize>,
+    /// Id of the next message sent in fragments.
+    next_fragmented_id: u32,
+    /// Whether [`NetworkManager::set_on_fragmentation`] was called for the current connection.
+    fragmentation_reported: bool,
+    reassembler: Reassembler,
+    on_fragmentation: Option<FragmentationCallback>,
+    /// Renews `ICE` servers of the pre-warmed connection, see [`NetworkManager::prewarm`].
+    ice_server_refresh_timer: Option<IntervalTimer>,
+    /// When the last quality report of the other peer arrived, to drop the ones arriving too often.
+    last_peer_quality_at: Option<f64>,
+    on_message: Option<MessageCallback>,
+    on_incoming_channel: Option<IncomingChannelCallback>,
+    /// Channels added by renegotiating, by either peer, besides the one opened in [`NetworkManager::start`].
+    pub(crate) added_channels: Vec<RtcDataChannel>,
+    on_channel_added: Option<ChannelCallback>,
+    on_receive_overflow: Option<MessageCallback>,
+    on_server_notice: Option<MessageCallback>,
+    on_same_network_hint: Option<NotifyCallback>,
+    on_migrate: Option<MessageCallback>,
+    reconnect_tracker: ReconnectTracker,
+    on_reconnecting: Option<NotifyCallback>,
+    on_reconnected: Option<NotifyCallback>,
+    /// `on_open_callback` of [`NetworkManager::start`], for data channels of replaced peer connections.
+    on_open: Option<NotifyCallback>,
+    /// See [`NetworkManager::set_wait_for_peer_return`].
+    wait_for_peer_return: bool,
+    /// Other peer is gone and awaited to rejoin the session.
+    peer_returning: bool,
+    /// Whether the session was ready before, so that the next `SessionReady` means the other peer rejoined.
+    was_ready: bool,
+    /// Migrating to another signaling server, which makes the session ready again with the same peer.
+    migrating: bool,
+    on_peer_reconnecting: Option<NotifyCallback>,
+    on_peer_reconnected: Option<NotifyCallback>,
+    role_resolver: Option<RoleResolver>,
+    on_transport_error: Option<TransportErrorCallback>,
+    /// Data channel opened in [`NetworkManager::start`] reported a fatal [`TransportError`],
+    /// so it's cleaned up once it closes.
+    transport_failed: bool,
+}
+
+type MessageCallback = Callback<dyn FnMut(String)>;
+type NotifyCallback = Callback<dyn FnMut()>;
+type IncomingChannelCallback = Callback<dyn FnMut(ChannelInfo) -> bool>;
+type ChannelCallback = Callback<dyn FnMut(RtcDataChannel)>;
+type DisconnectCallback = Callback<dyn FnMut(DisconnectReason)>;
+type BandwidthCallback = Callback<dyn FnMut(f64)>;
+type TransportErrorCallback = Callback<dyn FnMut(TransportError)>;
+type CongestionCallback = Callback<dyn FnMut(CongestionLevel)>;
+type PeerQualityCallback = Callback<dyn FnMut(ConnectionQuality)>;
+type FragmentationCallback = Callback<dyn FnMut(usize)>;
+type IceGatheringCallback = Callback<dyn FnMut(IceGatheringState)>;
+type RoleResolver = Callback<dyn FnMut(IsHost) -> String>;
+
+/// Handle of a `setInterval` timer, e.g. sampling congestion, with the closure it calls.
+#[derive(Clone)]
+struct IntervalTimer(Rc<(JsValue, Closure<dyn FnMut()>)>);
+
+impl fmt::Debug for IntervalTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IntervalTimer")
+    }
+}
+
+/// Why the connection with the other peer is gone, see [`NetworkManager::set_on_disconnect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// Peer left the session on purpose, e.g. by calling [`NetworkManager::close`].
+    PeerLeft,
+    /// Connection with the peer failed, e.g. because its browser crashed.
+    ConnectionFailed,
+    /// Connection with the peer was interrupted for longer than [`DISCONNECTED_TIMEOUT_MS`],
+    /// e.g. because its network went down.
+    Timeout,
+    /// Connection was closed on this side with [`NetworkManager::close`].
+    LocalClose,
+}
+
+/// Progress of gathering local `ICE` candidates, see [`NetworkManager::ice_gathering_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IceGatheringState {
+    /// Gathering hasn't started yet.
+    New,
+    /// Candidates are being gathered.
+    Gathering,
+    /// All candidates were gathered, until an `ICE` restart starts gathering again.
+    Complete,
+}
+
+impl From<RtcIceGatheringState> for IceGatheringState {
+    fn from(state: RtcIceGatheringState) -> Self {
+        match state {
+            RtcIceGatheringState::Gathering => IceGatheringState::Gathering,
+            RtcIceGatheringState::Complete => IceGatheringState::Complete,
+            _ => IceGatheringState::New,
+        }
+    }
 }
 
+/// How long the connection can stay interrupted before it's reported as [`DisconnectReason::Timeout`].
+/// Browsers usually recover from short interruptions on their own.
+pub const DISCONNECTED_TIMEOUT_MS: u32 = 10_000;
+
 /// Abstraction over `WebRTC` peer-to-peer connection.
 /// Structure representing one of two equal peers.
 ///
@@ -108,66 +311,888 @@ impl NetworkManager {
         session_id: SessionId,
         connection_type: ConnectionType,
     ) -> Result<Self, JsValue> {
-        let peer_connection = create_peer_connection(&connection_type)?;
+        let websocket = open_websocket(signaling_server_url)?;
 
-        let websocket = WebSocket::new(signaling_server_url)?;
-        websocket.set_binary_type(web_sys::BinaryType::Arraybuffer);
+        Self::with_websocket(websocket, session_id, connection_type)
+    }
+
+    /// Same as [`NetworkManager::new`], but accepts a list of signaling server addresses
+    /// for redundancy. They are tried in order and the first one that accepts
+    /// the connection is used for the whole signaling.
+    ///
+    /// Both peers must end up connected to the same signaling server instance for the signaling to work,
+    /// so lists on both sides should be the same, or at least ordered the same way.
+    /// Servers not accepting the connection within [`crate::FAILOVER_ATTEMPT_TIMEOUT_MS`] count as failed.
+    ///
+    /// # Errors
+    /// This function errors if none of the signaling servers accepts the connection.
+    pub async fn new_with_failover(
+        signaling_server_urls: &[&str],
+        session_id: SessionId,
+        connection_type: ConnectionType,
+    ) -> Result<Self, JsValue> {
+        let websocket =
+            open_websocket_with_failover(signaling_server_urls, FAILOVER_ATTEMPT_TIMEOUT_MS)
+                .await?;
+        Self::with_websocket(websocket, session_id, connection_type)
+    }
+
+    /// Same as [`NetworkManager::new`], but instead of joining a session with a known id,
+    /// asks signaling server to pair it with another peer looking for a match with the same `criteria`,
+    /// e.g. a game mode. Signaling server has to have matchmaking enabled.
+    ///
+    /// Session id is assigned by signaling server once a match is found,
+    /// see [`NetworkManager::session_id`].
+    pub fn new_with_matchmaking(
+        signaling_server_url: &str,
+        criteria: String,
+        connection_type: ConnectionType,
+    ) -> Result<Self, JsValue> {
+        let network_manager = Self::new(
+            signaling_server_url,
+            SessionId::new(String::new()),
+            connection_type,
+        )?;
+        network_manager.inner.borrow_mut().match_criteria = Some(criteria);
+        Ok(network_manager)
+    }
+
+    /// Returns id of the session, which is empty for matchmaking until a match is found.
+    pub fn session_id(&self) -> SessionId {
+        self.inner.borrow().session_id.clone()
+    }
+
+    /// Sets a function giving this peer its application role, e.g. `player1` or `player2`,
+    /// from whether it's the host, i.e. the peer creating the offers, which is the impolite one
+    /// when renegotiating. Signaling server tells both peers their opposite host status,
+    /// so with the same function on both sides, each peer gets the other role, see [`NetworkManager::app_role`].
+    pub fn set_role_resolver(&mut self, role_resolver: impl Fn(IsHost) -> String + 'static) {
+        self.inner.borrow_mut().role_resolver = Some(RoleResolver::new(role_resolver));
+    }
+
+    /// Returns the role given by [`NetworkManager::set_role_resolver`],
+    /// or `None` if there is no resolver or the session isn't ready yet.
+    /// Role may change if the session gets ready again, e.g. when the other peer rejoins.
+    pub fn app_role(&self) -> Option<String> {
+        let (role_resolver, is_host) = {
+            let inner = self.inner.borrow();
+            if !inner.was_ready {
+                return None;
+            }
+            (inner.role_resolver.clone()?, inner.is_host)
+        };
+        Some(role_resolver.call(is_host))
+    }
+
+    fn with_websocket(
+        websocket: WebSocket,
+        session_id: SessionId,
+        connection_type: ConnectionType,
+    ) -> Result<Self, JsValue> {
+        let peer_connection = create_peer_connection(&connection_type)?;
 
         Ok(NetworkManager {
             inner: Rc::new(RefCell::new(NetworkManagerInner {
                 session_id,
                 websocket,
                 peer_connection,
+                connection_type,
+                data_channel_config: DataChannelConfig::default(),
+                ice_options: IceOptions::default(),
+                first_candidate_at: None,
                 data_channel: None,
+                outbound_queue: OutboundQueue::default(),
+                metadata: None,
+                on_peer_metadata: None,
+                match_criteria: None,
+                on_disconnect: None,
+                disconnect_reported: false,
+                disconnected_timeout: None,
+                inbound_buffer: InboundBuffer::default(),
+                duplicate_filter: DuplicateFilter::default(),
+                held_ice_candidates: Vec::new(),
+                last_error: None,
+                relayed: false,
+                first_message: None,
+                is_host: false,
+                ice_restart_pending: false,
+                negotiation_needed: false,
+                renegotiation_pending: false,
+                congestion: CongestionDetector::default(),
+                on_congestion_change: None,
+                on_bandwidth_estimate: None,
+                congestion_timer: None,
+                on_peer_quality: None,
+                quality_report_timer: None,
+                clock_sync: ClockSync::default(),
+                clock_sync_timer: None,
+                presence: PresenceTracker::default(),
+                presence_timer: None,
+                on_peer_unresponsive: None,
+                on_ice_gathering_state_change: None,
+                fragmentation_threshold: Some(DEFAULT_FRAGMENTATION_THRESHOLD),
+                next_fragmented_id: 0,
+                fragmentation_reported: false,
+                reassembler: Reassembler::default(),
+                on_fragmentation: None,
+                ice_server_refresh_timer: None,
+                last_peer_quality_at: None,
+                on_message: None,
+                on_incoming_channel: None,
+                added_channels: Vec::new(),
+                on_channel_added: None,
+                on_receive_overflow: None,
+                on_server_notice: None,
+                on_same_network_hint: None,
+                on_migrate: None,
+                reconnect_tracker: ReconnectTracker::default(),
+                on_reconnecting: None,
+                on_reconnected: None,
+                on_open: None,
+                wait_for_peer_return: false,
+                peer_returning: false,
+                was_ready: false,
+                migrating: false,
+                on_peer_reconnecting: None,
+                on_peer_reconnected: None,
+                role_resolver: None,
+                on_transport_error: None,
+                transport_failed: false,
             })),
         })
     }
 
+    /// Overrides options used when creating the data channel.
+    /// Must be called before [`NetworkManager::start`] to take effect.
+    ///
+    /// # Errors
+    /// This function errors if configured protocol exceeds the length allowed by the specification.
+    pub fn set_data_channel_config(
+        &mut self,
+        data_channel_config: DataChannelConfig,
+    ) -> Result<(), JsValue> {
+        data_channel_config.validate()?;
+        self.inner.borrow_mut().data_channel_config = data_channel_config;
+        Ok(())
+    }
+
+    /// Sets options of `ICE` candidate gathering, replacing the previous ones.
+    /// Best called before [`NetworkManager::start`], as transport policy and candidate pool size
+    /// only apply to gathering that hasn't started yet, e.g. after [`NetworkManager::restart_ice`],
+    /// while candidate filtering applies to candidates gathered from now on.
+    /// See [`IceOptions`] for which options are best-effort.
+    ///
+    /// # Errors
+    /// This function errors if the browser rejects the configuration.
+    pub fn set_ice_options(&mut self, ice_options: IceOptions) -> Result<(), JsValue> {
+        let peer_connection = self.inner.borrow().peer_connection.clone();
+        apply_ice_options(&peer_connection, &ice_options)?;
+        self.inner.borrow_mut().ice_options = ice_options;
+        Ok(())
+    }
+
+    /// Replaces `ICE` servers the connection uses, e.g. with renewed `TURN` credentials.
+    /// Only affects candidates gathered from now on, so it's useful before [`NetworkManager::start`]
+    /// or before [`NetworkManager::restart_ice`].
+    ///
+    /// # Errors
+    /// This function errors if the browser rejects the configuration.
+    pub fn set_ice_servers(&self, connection_type: &ConnectionType) -> Result<(), JsValue> {
+        let peer_connection = self.inner.borrow().peer_connection.clone();
+        set_ice_servers(&peer_connection, connection_type)?;
+        self.inner.borrow_mut().connection_type = connection_type.clone();
+        Ok(())
+    }
+
+    /// Snapshot of the `ICE` servers, data channel config and `ICE` options the connection
+    /// is configured with, e.g. to save them with [`ConnectionConfig::to_json`].
+    pub fn connection_config(&self) -> ConnectionConfig {
+        let inner = self.inner.borrow();
+        ConnectionConfig {
+            connection_type: inner.connection_type.clone(),
+            data_channel: inner.data_channel_config.clone(),
+            ice_options: inner.ice_options.clone(),
+        }
+    }
+
+    /// Applies all of the configuration, e.g. restored with [`ConnectionConfig::from_json`],
+    /// same as [`NetworkManager::set_ice_servers`], [`NetworkManager::set_data_channel_config`]
+    /// and [`NetworkManager::set_ice_options`] would. Must be called before [`NetworkManager::start`]
+    /// for the data channel config to take effect.
+    ///
+    /// # Errors
+    /// This function errors if the data channel config is invalid or if the browser rejects the configuration.
+    pub fn set_connection_config(&mut self, config: ConnectionConfig) -> Result<(), JsValue> {
+        config.data_channel.validate()?;
+        self.set_ice_servers(&config.connection_type)?;
+        self.set_ice_options(config.ice_options)?;
+        self.set_data_channel_config(config.data_channel)
+    }
+
+    /// Starts gathering candidates right away, including `TURN` allocations,
+    /// so that they're ready once the user decides to connect and [`NetworkManager::start`] is called.
+    /// Call it as early as possible, e.g. when the page loads.
+    /// Sets [`IceOptions::candidate_pool_size`] to 1 unless a pool is configured already.
+    ///
+    /// `TURN` credentials are usually short-lived, so every `refresh_interval_ms` milliseconds,
+    /// which should be shorter than their lifetime, `refresh_ice_servers` is called for fresh ones
+    /// and the connection starts using them, regathering the pool. Refreshes are skipped
+    /// once the negotiation with the other peer starts, and stop when [`NetworkManager::close`] is called.
+    /// Failed refreshes are logged and the previous servers are kept until the next one.
+    ///
+    /// Browsers may still drop pooled candidates they consider stale, in which case
+    /// they're gathered again when connecting, as without pre-warming.
+    ///
+    /// # Errors
+    /// This function errors if `refresh_interval_ms` is 0, if the browser rejects the configuration
+    /// or if the timer can't be set.
+    pub fn prewarm<F, Fut>(
+        &mut self,
+        refresh_interval_ms: u32,
+        refresh_ice_servers: F,
+    ) -> Result<(), JsValue>
+    where
+        F: FnMut() -> Fut + 'static,
+        Fut: Future<Output = Result<ConnectionType, JsValue>> + 'static,
+    {
+        if refresh_interval_ms == 0 {
+            return Err(JsValue::from_str("refresh interval must not be zero"));
+        }
+        let mut ice_options = self.inner.borrow().ice_options.clone();
+        if ice_options.candidate_pool_size.unwrap_or_default() == 0 {
+            ice_options.candidate_pool_size = Some(1);
+            self.set_ice_options(ice_options)?;
+        }
+        self.stop_ice_server_refresh()?;
+
+        let network_manager = self.clone();
+        let refresh_ice_servers = Rc::new(RefCell::new(refresh_ice_servers));
+        let on_interval = Closure::wrap(Box::new(move || {
+            let peer_connection = network_manager.inner.borrow().peer_connection.clone();
+            if peer_connection.local_description().is_some()
+                || peer_connection.remote_description().is_some()
+            {
+                // pooled candidates were taken by the negotiation, refreshing them is pointless
+                return;
+            }
+            let refresh = (refresh_ice_servers.borrow_mut())();
+            let network_manager = network_manager.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                refresh
+                    .await
+                    .and_then(|connection_type| network_manager.set_ice_servers(&connection_type))
+                    .unwrap_or_else(|error| error!("failed to refresh ICE servers: {:?}", error));
+            });
+        }) as Box<dyn FnMut()>);
+        let set_interval = global_function("setInterval")?;
+        let handle = set_interval.call2(
+            &JsValue::NULL,
+            on_interval.as_ref().unchecked_ref(),
+            &JsValue::from(refresh_interval_ms),
+        )?;
+        self.inner.borrow_mut().ice_server_refresh_timer =
+            Some(IntervalTimer(Rc::new((handle, on_interval))));
+        Ok(())
+    }
+
+    fn stop_ice_server_refresh(&self) -> Result<(), JsValue> {
+        let timer = self.inner.borrow_mut().ice_server_refresh_timer.take();
+        if let Some(IntervalTimer(timer)) = timer {
+            global_function("clearInterval")?.call1(&JsValue::NULL, &timer.0)?;
+        }
+        Ok(())
+    }
+
+    /// Sets application-defined metadata, e.g. display name or app version,
+    /// sent to the other peer once both peers are in session.
+    /// Must be called before [`NetworkManager::start`] to take effect.
+    ///
+    /// # Errors
+    /// This function errors if metadata is longer than [`MAX_PEER_METADATA_LENGTH`] bytes.
+    pub fn set_metadata(&mut self, metadata: String) -> Result<(), JsValue> {
+        if metadata.len() > MAX_PEER_METADATA_LENGTH {
+            return Err(JsValue::from_str(&format!(
+                "metadata is too long: {} bytes, maximum is {}",
+                metadata.len(),
+                MAX_PEER_METADATA_LENGTH
+            )));
+        }
+        self.inner.borrow_mut().metadata = Some(metadata);
+        Ok(())
+    }
+
+    /// Sets a message sent to the other peer as soon as the data channel opens,
+    /// before `on_open_callback` is called, e.g. a hello or a role assignment.
+    /// Sending fails until the channel opens, so nothing sent with [`NetworkManager::send_message`]
+    /// can get ahead of it, and the channel is ordered, so it's also the first message the other peer receives.
+    /// It reaches the other peer's `on_message_callback` like any other message, no acknowledgement is needed,
+    /// as each peer listens for messages before its side of the channel opens.
+    /// It's sent again each time a data channel opens, e.g. after the other peer rejoins.
+    /// Must be called before [`NetworkManager::start`].
+    pub fn set_first_message(&mut self, message: String) {
+        self.inner.borrow_mut().first_message = Some(message);
+    }
+
+    fn send_first_message(&self) {
+        let first_message = self.inner.borrow().first_message.clone();
+        if let Some(first_message) = first_message {
+            self.send_message(&first_message)
+                .unwrap_or_else(|error| error!("failed to send first message: {:?}", error));
+        }
+    }
+
+    /// Sets a callback called with the metadata of the other peer, usually before the data channel opens.
+    /// It's never called if the other peer doesn't set any metadata.
+    pub fn set_on_peer_metadata(&mut self, on_peer_metadata: impl FnMut(String) + 'static) {
+        self.inner.borrow_mut().on_peer_metadata = Some(MessageCallback::new(on_peer_metadata));
+    }
+
+    pub(crate) fn on_peer_metadata(&self, metadata: String) {
+        match Callback::of(&self.inner, |inner| &inner.on_peer_metadata) {
+            Some(callback) => callback.call(metadata),
+            None => debug!("no callback set for peer metadata, ignoring it"),
+        }
+    }
+
+    /// Sets a callback called once the connection with the other peer is gone,
+    /// with the reason telling apart peer leaving on purpose from the connection failing.
+    /// It's called at most once for each time the session becomes ready,
+    /// with the reason that was detected first, e.g. peer leaving is usually signaled
+    /// before the connection closing as a result is noticed.
+    pub fn set_on_disconnect(&mut self, on_disconnect: impl FnMut(DisconnectReason) + 'static) {
+        self.inner.borrow_mut().on_disconnect = Some(DisconnectCallback::new(on_disconnect));
+    }
+
+    /// Sets a callback called once an established connection gets interrupted, e.g. by a network change,
+    /// for the application to snapshot its state or show that the peer is reconnecting.
+    /// It's followed by the callback set with [`NetworkManager::set_on_reconnected`]
+    /// if the connection recovers, on its own or after [`NetworkManager::restart_ice`].
+    ///
+    /// Data channel stays open while the transport reconnects, so messages sent meanwhile
+    /// are held by the browser and delivered exactly once, in order, after the connection recovers,
+    /// and `on_message_callback` isn't called again for messages delivered before the interruption.
+    /// If the connection doesn't recover and the other peer rejoins instead, the new data channel
+    /// starts empty, messages that were in flight on the old one aren't replayed.
+    pub fn set_on_reconnecting(&mut self, on_reconnecting: impl FnMut() + 'static) {
+        self.inner.borrow_mut().on_reconnecting = Some(NotifyCallback::new(on_reconnecting));
+    }
+
+    /// Sets a callback called once an interrupted connection recovers,
+    /// see [`NetworkManager::set_on_reconnecting`].
+    pub fn set_on_reconnected(&mut self, on_reconnected: impl FnMut() + 'static) {
+        self.inner.borrow_mut().on_reconnected = Some(NotifyCallback::new(on_reconnected));
+    }
+
+    /// Sets a callback called when the transport under a data channel fails, with whatever the browser
+    /// exposes of the `SCTP` error, instead of only logging it. Covers channels added with
+    /// [`NetworkManager::add_channel`] too, told apart by [`TransportError::label`].
+    ///
+    /// After a recoverable error `ICE` is restarted, see [`NetworkManager::restart_ice`].
+    /// After a fatal one the channel closes for good, so once the one opened in [`NetworkManager::start`]
+    /// closes, messages waiting in the outbound queue are dropped and the other peer is reported
+    /// as gone with [`DisconnectReason::ConnectionFailed`].
+    pub fn set_on_transport_error(
+        &mut self,
+        on_transport_error: impl FnMut(TransportError) + 'static,
+    ) {
+        self.inner.borrow_mut().on_transport_error =
+            Some(TransportErrorCallback::new(on_transport_error));
+    }
+
+    pub(crate) fn on_transport_error(&self, transport_error: TransportError) {
+        error!("data channel transport error: {:?}", transport_error);
+        self.record_error(format!(
+            "transport error on {}: {}",
+            transport_error.label, transport_error.message
+        ));
+        let on_transport_error = {
+            let mut inner = self.inner.borrow_mut();
+            let is_main_channel = inner
+                .data_channel
+                .as_ref()
+                .is_some_and(|data_channel| data_channel.label() == transport_error.label);
+            if transport_error.fatal && is_main_channel {
+                inner.transport_failed = true;
+            }
+            inner.on_transport_error.clone()
+        };
+        let fatal = transport_error.fatal;
+        if let Some(callback) = on_transport_error {
+            callback.call(transport_error);
+        }
+        if !fatal {
+            let network_manager = self.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(error) = network_manager.restart_ice().await {
+                    error!("failed to restart ICE after transport error: {:?}", error);
+                }
+            });
+        }
+    }
+
+    /// Cleans up the data channel opened in [`NetworkManager::start`] if it closed after a fatal transport error.
+    pub(crate) fn on_data_channel_closed(&self) {
+        {
+            let mut inner = self.inner.borrow_mut();
+            if !std::mem::take(&mut inner.transport_failed) {
+                return;
+            }
+            inner.data_channel = None;
+            inner.outbound_queue.clear();
+        }
+        self.on_disconnect(DisconnectReason::ConnectionFailed);
+    }
+
+    /// Keeps the session alive when the other peer goes away, e.g. because it reloaded its page,
+    /// waiting for it to rejoin the session with the same session id.
+    /// Instead of calling the disconnect callback, it calls the one set with
+    /// [`NetworkManager::set_on_peer_reconnecting`] once the peer is noticed to be gone,
+    /// either because it left, the connection failed, or it rejoined before that was noticed.
+    /// When it rejoins, the peer connection is replaced with a fresh one and negotiated again,
+    /// after which `on_open_callback` is called again, followed by [`NetworkManager::set_on_peer_reconnected`].
+    /// Disabled by default. Closing the network manager is reported as usual either way.
+    pub fn set_wait_for_peer_return(&mut self, enabled: bool) {
+        self.inner.borrow_mut().wait_for_peer_return = enabled;
+    }
+
+    /// Sets a callback called once the other peer is gone and awaited,
+    /// see [`NetworkManager::set_wait_for_peer_return`].
+    pub fn set_on_peer_reconnecting(&mut self, on_peer_reconnecting: impl FnMut() + 'static) {
+        self.inner.borrow_mut().on_peer_reconnecting =
+            Some(NotifyCallback::new(on_peer_reconnecting));
+    }
+
+    /// Sets a callback called once the connection with the returning peer is re-established,
+    /// see [`NetworkManager::set_wait_for_peer_return`].
+    /// Data channel is a new one, so messages in flight when the peer went away are lost.
+    pub fn set_on_peer_reconnected(&mut self, on_peer_reconnected: impl FnMut() + 'static) {
+        self.inner.borrow_mut().on_peer_reconnected =
+            Some(NotifyCallback::new(on_peer_reconnected));
+    }
+
+    fn on_peer_gone(&self) {
+        let on_peer_reconnecting = {
+            let mut inner = self.inner.borrow_mut();
+            if std::mem::replace(&mut inner.peer_returning, true) {
+                return;
+            }
+            inner.on_peer_reconnecting.clone()
+        };
+        info!("other peer is gone, waiting for it to return");
+        if let Some(callback) = on_peer_reconnecting {
+            callback.call();
+        }
+    }
+
+    fn on_peer_reconnected(&self) {
+        let on_peer_reconnected = {
+            let mut inner = self.inner.borrow_mut();
+            if !std::mem::take(&mut inner.peer_returning) {
+                return;
+            }
+            inner.on_peer_reconnected.clone()
+        };
+        if let Some(callback) = on_peer_reconnected {
+            callback.call();
+        }
+    }
+
+    /// Returns `true` if the session is ready again because the awaited peer returned,
+    /// so the peer connection has to be replaced before negotiating.
+    pub(crate) fn on_session_ready(&self) -> bool {
+        let peer_returned = {
+            let mut inner = self.inner.borrow_mut();
+            let was_ready = std::mem::replace(&mut inner.was_ready, true);
+            let migrating = std::mem::take(&mut inner.migrating);
+            inner.wait_for_peer_return && was_ready && !migrating
+        };
+        if peer_returned {
+            self.on_peer_gone();
+        }
+        peer_returned
+    }
+
+    pub(crate) fn on_ice_connection_state(&self, state: RtcIceConnectionState) {
+        let callback = {
+            let mut inner = self.inner.borrow_mut();
+            match inner.reconnect_tracker.on_ice_connection_state(state) {
+                Some(ReconnectEvent::Reconnecting) => inner.on_reconnecting.clone(),
+                Some(ReconnectEvent::Reconnected) => inner.on_reconnected.clone(),
+                None => None,
+            }
+        };
+        if let Some(callback) = callback {
+            callback.call();
+        }
+    }
+
+    pub(crate) fn on_disconnect(&self, reason: DisconnectReason) {
+        if reason != DisconnectReason::LocalClose && self.inner.borrow().wait_for_peer_return {
+            self.on_peer_gone();
+            return;
+        }
+        let on_disconnect = {
+            let mut inner = self.inner.borrow_mut();
+            if inner.disconnect_reported {
+                return;
+            }
+            inner.disconnect_reported = true;
+            inner.on_disconnect.clone()
+        };
+        match on_disconnect {
+            Some(callback) => callback.call(reason),
+            None => debug!("no callback set for disconnect, ignoring it"),
+        }
+    }
+
     /// Second part of the setup that begins the actual connection.
     /// Requires specifying a callbacks that are guaranteed to run
     /// when the connection opens and on each message received.
     pub fn start(
         &mut self,
-        on_open_callback: impl FnMut() + Clone + 'static,
+        mut on_open_callback: impl FnMut() + Clone + 'static,
         on_message_callback: impl FnMut(String) + Clone + 'static,
     ) -> Result<(), JsValue> {
-        let NetworkManagerInner {
-            websocket,
-            peer_connection,
-            session_id,
-            ..
-        } = self.inner.borrow().clone();
+        self.inner.borrow_mut().on_message = Some(MessageCallback::new(on_message_callback));
+        let network_manager = self.clone();
+        let on_open_callback = move || {
+            network_manager.send_first_message();
+            network_manager.on_peer_reconnected();
+            on_open_callback();
+        };
+        self.inner.borrow_mut().on_open = Some(NotifyCallback::new(on_open_callback));
+        let (websocket, peer_connection, session_id) = {
+            let inner = self.inner.borrow();
+            (
+                inner.websocket.clone(),
+                inner.peer_connection.clone(),
+                inner.session_id.clone(),
+            )
+        };
+        self.set_up_peer_connection(&peer_connection);
+        let join_message = match self.inner.borrow().match_criteria.clone() {
+            Some(criteria) => SignalMessage::FindMatch(criteria),
+            None => SignalMessage::SessionJoin(session_id),
+        };
+        set_websocket_on_open(&websocket, &join_message);
+        set_websocket_on_message(&websocket, peer_connection, self.clone());
+
+        Ok(())
+    }
+
+    /// Creates the data channel and sets the callbacks of the peer connection, which starts without them.
+    fn set_up_peer_connection(&self, peer_connection: &RtcPeerConnection) {
+        let (websocket, session_id, data_channel_config, on_open) = {
+            let inner = self.inner.borrow();
+            (
+                inner.websocket.clone(),
+                inner.session_id.clone(),
+                inner.data_channel_config.clone(),
+                inner.on_open.clone().expect("network manager is started"),
+            )
+        };
+        let on_open_callback = move || on_open.call();
+        let network_manager = self.clone();
+        let on_message_callback = move |message| network_manager.receive_message(message);
 
-        let data_channel = peer_connection.create_data_channel(&session_id.clone().into_inner());
+        let data_channel =
+            create_data_channel(peer_connection, session_id.as_str(), &data_channel_config);
         debug!(
             "data_channel created with label: {:?}",
             data_channel.label()
         );
 
         set_data_channel_on_open(&data_channel, on_open_callback.clone());
-        set_data_channel_on_error(&data_channel);
+        set_data_channel_on_error(&data_channel, self.clone());
+        set_data_channel_on_close(&data_channel, self.clone());
         set_data_channel_on_message(&data_channel, on_message_callback.clone());
+        set_data_channel_on_buffered_amount_low(&data_channel, self.clone());
 
         self.inner.borrow_mut().data_channel = Some(data_channel);
         set_peer_connection_on_data_channel(
-            &peer_connection,
+            peer_connection,
             self.clone(),
             on_open_callback,
             on_message_callback,
         );
 
-        set_peer_connection_on_ice_candidate(
-            &peer_connection,
-            websocket.clone(),
-            session_id.clone(),
-        );
-        set_peer_connection_on_ice_connection_state_change(&peer_connection);
-        set_peer_connection_on_ice_gathering_state_change(&peer_connection);
-        set_peer_connection_on_negotiation_needed(&peer_connection);
-        set_websocket_on_open(&websocket, session_id);
-        set_websocket_on_message(&websocket, peer_connection);
+        set_peer_connection_on_ice_candidate(peer_connection, websocket, self.clone());
+        set_peer_connection_on_ice_connection_state_change(peer_connection, self.clone());
+        set_peer_connection_on_ice_gathering_state_change(peer_connection, self.clone());
+        set_peer_connection_on_negotiation_needed(peer_connection, self.clone());
+    }
 
-        Ok(())
+    /// Replaces the peer connection with a fresh one with the same configuration,
+    /// as the other peer rejoined with a fresh one too, e.g. after reloading its page.
+    pub(crate) fn replace_peer_connection(&self) -> Result<RtcPeerConnection, JsValue> {
+        let (old_peer_connection, websocket) = {
+            let inner = self.inner.borrow();
+            (inner.peer_connection.clone(), inner.websocket.clone())
+        };
+        info!("other peer returned, replacing the peer connection");
+        let peer_connection =
+            RtcPeerConnection::new_with_configuration(&old_peer_connection.get_configuration())?;
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.peer_connection = peer_connection.clone();
+            inner.data_channel = None;
+            inner.added_channels.clear();
+            inner.held_ice_candidates.clear();
+            inner.first_candidate_at = None;
+            inner.ice_restart_pending = false;
+            inner.negotiation_needed = false;
+            inner.renegotiation_pending = false;
+            inner.reconnect_tracker = ReconnectTracker::default();
+            inner.fragmentation_reported = false;
+            inner.reassembler = Reassembler::default();
+            inner.transport_failed = false;
+        }
+        old_peer_connection.close();
+        self.set_up_peer_connection(&peer_connection);
+        set_websocket_on_message(&websocket, peer_connection.clone(), self.clone());
+        Ok(peer_connection)
+    }
+
+    /// Stops calling `on_message_callback`, e.g. during a heavy render,
+    /// holding received messages until [`NetworkManager::resume_receive`] is called.
+    /// At most [`MAX_PAUSED_MESSAGES`] messages are held, after that the oldest ones are dropped,
+    /// see [`NetworkManager::set_on_receive_overflow`].
+    pub fn pause_receive(&self) {
+        self.inner.borrow_mut().inbound_buffer.pause();
+    }
+
+    /// Delivers messages held since [`NetworkManager::pause_receive`] in order they arrived,
+    /// and goes back to calling `on_message_callback` as messages arrive.
+    /// Can be called from `on_message_callback`, held messages are then delivered after it returns.
+    pub fn resume_receive(&self) {
+        let message = {
+            let mut inner = self.inner.borrow_mut();
+            inner.inbound_buffer.resume();
+            if inner.inbound_buffer.is_delivering() {
+                return;
+            }
+            inner.inbound_buffer.pop()
+        };
+        if let Some(message) = message {
+            self.deliver_message(message);
+        }
+    }
+
+    /// Returns `true` if receiving is paused with [`NetworkManager::pause_receive`].
+    pub fn is_receive_paused(&self) -> bool {
+        self.inner.borrow().inbound_buffer.is_paused()
+    }
+
+    /// Number of received messages held while receiving is paused.
+    pub fn paused_message_count(&self) -> usize {
+        self.inner.borrow().inbound_buffer.len()
+    }
+
+    /// Drops received messages identical to any of the last `window` messages,
+    /// before they reach `on_message_callback`, for applications that can't handle duplicates.
+    /// Messages are compared by content, so applications sending the same message repeatedly
+    /// on purpose should make them unique, e.g. with a sequence number.
+    /// Window is capped at [`MAX_DUPLICATE_WINDOW`], see it for the memory cost.
+    /// Disabled by default, setting window to 0 disables it again.
+    pub fn set_duplicate_window(&self, window: usize) {
+        self.inner.borrow_mut().duplicate_filter.set_window(window);
+    }
+
+    /// Sets a callback called with each message dropped because too many messages
+    /// arrived while receiving was paused.
+    pub fn set_on_receive_overflow(&mut self, on_receive_overflow: impl FnMut(String) + 'static) {
+        self.inner.borrow_mut().on_receive_overflow =
+            Some(MessageCallback::new(on_receive_overflow));
+    }
+
+    /// Sets a callback called with each notice the signaling server operator sends to all users,
+    /// e.g. about upcoming maintenance, to be shown to the user.
+    pub fn set_on_server_notice(&mut self, on_server_notice: impl FnMut(String) + 'static) {
+        self.inner.borrow_mut().on_server_notice = Some(MessageCallback::new(on_server_notice));
+    }
+
+    pub(crate) fn on_server_notice(&self, notice: String) {
+        match Callback::of(&self.inner, |inner| &inner.on_server_notice) {
+            Some(callback) => callback.call(notice),
+            None => info!("signaling server notice: {}", notice),
+        }
+    }
+
+    /// Sets a callback called when the signaling server hints that the other peer connects
+    /// from the same address, so it's likely on the same local network, e.g. to prefer host candidates
+    /// with [`NetworkManager::set_ice_options`]. The server only sends the hint if enabled by its operator.
+    pub fn set_on_same_network_hint(&mut self, on_same_network_hint: impl FnMut() + 'static) {
+        self.inner.borrow_mut().on_same_network_hint =
+            Some(NotifyCallback::new(on_same_network_hint));
+    }
+
+    pub(crate) fn on_same_network_hint(&self) {
+        if let Some(callback) = Callback::of(&self.inner, |inner| &inner.on_same_network_hint) {
+            callback.call();
+        }
+    }
+
+    fn receive_message(&self, message: String) {
+        self.inner.borrow_mut().presence.on_message_received();
+        if is_heartbeat(&message) {
+            return;
+        }
+        if let Some(fragment) = decode_fragment(&message) {
+            let reassembled =
+                fragment.and_then(|fragment| self.inner.borrow_mut().reassembler.push(&fragment));
+            match reassembled {
+                Ok(Some(message)) => self.receive_message(message),
+                Ok(None) => {}
+                Err(error) => error!("invalid message fragment from peer: {:?}", error),
+            }
+            return;
+        }
+        if let Some(quality) = decode_report(&message) {
+            match quality {
+                Ok(quality) => self.receive_peer_quality(quality),
+                Err(error) => error!("invalid quality report from peer: {:?}", error),
+            }
+            return;
+        }
+        if let Some(probe) = decode_probe(&message) {
+            match probe {
+                Ok(probe) => self.receive_clock_probe(probe),
+                Err(error) => error!("invalid clock probe from peer: {:?}", error),
+            }
+            return;
+        }
+        // this is an ugly fix to the fact, that if you send empty string as message
+        // webrtc fails with a cryptic "The operation failed for an operation-specific reason"
+        // message
+        let message = match message.strip_prefix(APPLICATION_FRAME) {
+            Some(message) => message.to_string(),
+            None => {
+                error!("message without a fix-bug x prepended: {:?}", message);
+                return;
+            }
+        };
+        let dropped = {
+            let mut inner = self.inner.borrow_mut();
+            if inner.duplicate_filter.is_duplicate(&message) {
+                debug!("dropping duplicate message");
+                return;
+            }
+            if !inner.inbound_buffer.is_paused() && !inner.inbound_buffer.is_delivering() {
+                drop(inner);
+                self.deliver_message(message);
+                return;
+            }
+            match inner.inbound_buffer.push(message) {
+                Some(dropped) => dropped,
+                None => return,
+            }
+        };
+        let on_receive_overflow = self.inner.borrow().on_receive_overflow.clone();
+        match on_receive_overflow {
+            Some(callback) => callback.call(dropped),
+            None => debug!("no callback set for receive overflow, dropping message"),
+        }
+    }
+
+    /// Calls `on_message_callback` with the message, then with messages held meanwhile,
+    /// until receiving is paused or none are left.
+    fn deliver_message(&self, message: String) {
+        self.inner.borrow_mut().inbound_buffer.set_delivering(true);
+        let mut message = Some(message);
+        while let Some(next) = message {
+            if let Some(callback) = Callback::of(&self.inner, |inner| &inner.on_message) {
+                callback.call(next);
+            }
+            // pop one at a time, in case callback pauses receiving again
+            message = self.inner.borrow_mut().inbound_buffer.pop();
+        }
+        self.inner.borrow_mut().inbound_buffer.set_delivering(false);
+    }
+
+    /// Tries to establish a connection with each of the connection types from `policy` in order,
+    /// resolving with the first network manager whose data channel opens
+    /// and the connection type it was created with.
+    /// Attempts that don't open in time are closed before the next one is made.
+    ///
+    /// The other peer should use the same policy, as both peers need to
+    /// make an attempt in the same session at the same time for it to succeed.
+    ///
+    /// Dropping the returned future cancels the connection attempt in progress,
+    /// closing all of its resources.
+    ///
+    /// # Errors
+    /// This function errors if none of the connection types succeeds within policy's timeout,
+    /// or if creating any of the network managers fails.
+    pub async fn connect_with_fallback(
+        signaling_server_url: &str,
+        session_id: SessionId,
+        policy: ConnectionFallbackPolicy,
+        on_open_callback: impl FnMut() + Clone + 'static,
+        on_message_callback: impl FnMut(String) + Clone + 'static,
+    ) -> Result<(Self, ConnectionType), JsValue> {
+        let deadline = Date::now() + f64::from(policy.timeout_ms);
+        let attempts_count = policy.connection_types.len();
+        for (attempt, connection_type) in policy.connection_types.into_iter().enumerate() {
+            let mut network_manager = NetworkManager::new(
+                signaling_server_url,
+                session_id.clone(),
+                connection_type.clone(),
+            )?;
+
+            let mut resolve_opened = None;
+            let opened = Promise::new(&mut |resolve, _reject| resolve_opened = Some(resolve));
+            let resolve_opened = resolve_opened.expect("promise executor runs synchronously");
+            let mut on_open_callback = on_open_callback.clone();
+            let on_open = move || {
+                let _ = resolve_opened.call1(&JsValue::NULL, &JsValue::TRUE);
+                on_open_callback();
+            };
+            let mut attempt_guard = CloseOnDrop(Some(network_manager.clone()));
+            network_manager.start(on_open, on_message_callback.clone())?;
+
+            let remaining_ms = (deadline - Date::now()).max(0.0);
+            let attempt_timeout_ms = remaining_ms / (attempts_count - attempt) as f64;
+            let race = Promise::race(&Array::of2(
+                &opened,
+                &timeout_promise(attempt_timeout_ms as u32),
+            ));
+            if JsFuture::from(race).await?.is_truthy() {
+                info!("connection established with {:?}", connection_type);
+                attempt_guard.0 = None;
+                return Ok((network_manager, connection_type));
+            }
+            info!("connection with {:?} timed out", connection_type);
+        }
+        Err(JsValue::from_str(
+            "none of the connection types managed to establish a connection",
+        ))
+    }
+
+    /// Closes the data channel, peer connection and connection to the signaling server,
+    /// letting the signaling server know that the session is left.
+    /// Can be called at any point, including when connection is still being established,
+    /// to cancel the connection attempt.
+    /// Network manager can't be used after it's closed.
+    pub fn close(&self) {
+        let inner = self.inner.borrow();
+        if inner.websocket.ready_state() == WebSocket::OPEN {
+            let signal_message = SignalMessage::SessionLeave(inner.session_id.clone());
+            if let Ok(signal_message) = serialize_message(&signal_message) {
+                let _ = inner.websocket.send_with_str(&signal_message);
+            }
+        }
+        if let Some(data_channel) = inner.data_channel.as_ref() {
+            data_channel.close();
+        }
+        inner.peer_connection.close();
+        let _ = inner.websocket.close();
+        drop(inner);
+        let _ = self.stop_congestion_monitor();
+        let _ = self.stop_quality_reports();
+        let _ = self.stop_clock_sync();
+        let _ = self.stop_presence_heartbeats();
+        let _ = self.stop_ice_server_refresh();
+        self.on_disconnect(DisconnectReason::LocalClose);
     }
 
     fn datachannel(&self) -> Result<RtcDataChannel, JsValue> {
@@ -180,20 +1205,522 @@ impl NetworkManager {
             .clone())
     }
 
+    /// Returns the sub-protocol of the established data channel,
+    /// which is an empty string if none was negotiated.
+    ///
+    /// # Errors
+    /// This function errors if data channel is not yet set up.
+    pub fn data_channel_protocol(&self) -> Result<String, JsValue> {
+        get_data_channel_protocol(&self.datachannel()?)
+    }
+
+    /// Returns types of the candidates `ICE` selected for the connection,
+    /// e.g. to log whether it's direct or relayed through a `TURN` server,
+    /// or `None` if no candidate pair is selected yet.
+    /// Reflects the current selection, also after an `ICE` restart.
+    ///
+    /// # Errors
+    /// This function errors if reading connection stats fails.
+    pub async fn selected_candidate_pair(&self) -> Result<Option<SelectedCandidatePair>, JsValue> {
+        let peer_connection = self.inner.borrow().peer_connection.clone();
+        let selected_candidate_pair = get_selected_candidate_pair(&peer_connection).await?;
+        self.inner.borrow_mut().relayed = selected_candidate_pair
+            .as_ref()
+            .is_some_and(SelectedCandidatePair::is_relayed);
+        Ok(selected_candidate_pair)
+    }
+
+    /// Returns whether the connection goes through a `TURN` server rather than directly between peers,
+    /// e.g. to warn the user about higher latency. `false` until a candidate pair is selected.
+    /// Updated from connection stats whenever `ICE` connects, also after an `ICE` restart,
+    /// and whenever [`NetworkManager::selected_candidate_pair`] or [`NetworkManager::diagnostics`] is called.
+    pub fn is_relayed(&self) -> bool {
+        self.inner.borrow().relayed
+    }
+
+    pub(crate) fn refresh_relayed(&self) {
+        let network_manager = self.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(error) = network_manager.selected_candidate_pair().await {
+                error!("failed to read selected candidate pair: {:?}", error);
+            }
+        });
+    }
+
+    /// Fingerprint of this peer's `DTLS` certificate, read from the local `SDP`,
+    /// `None` until the local description is set. See [`crate::fingerprint`].
+    pub fn local_fingerprint(&self) -> Option<DtlsFingerprint> {
+        let peer_connection = self.inner.borrow().peer_connection.clone();
+        parse_fingerprint(&peer_connection.local_description()?.sdp())
+    }
+
+    /// Fingerprint of the other peer's `DTLS` certificate, read from the remote `SDP`,
+    /// `None` until the remote description is set. See [`crate::fingerprint`].
+    pub fn remote_fingerprint(&self) -> Option<DtlsFingerprint> {
+        let peer_connection = self.inner.borrow().peer_connection.clone();
+        parse_fingerprint(&peer_connection.remote_description()?.sdp())
+    }
+
+    /// Whether [`NetworkManager::remote_fingerprint`] matches the value the other peer shared
+    /// over another channel, see [`DtlsFingerprint::matches`]. `false` until the remote description is set.
+    pub fn verify_remote_fingerprint(&self, expected: &str) -> bool {
+        self.remote_fingerprint()
+            .is_some_and(|fingerprint| fingerprint.matches(expected))
+    }
+
+    /// Gathers the state of the connection into a single structure,
+    /// e.g. to attach it to a bug report when the connection doesn't get established.
+    /// Only reads the state, so it can be called at any point without disturbing the connection.
+    /// If reading connection stats fails, the rest of the state is still returned,
+    /// without the selected candidate pair.
+    pub async fn diagnostics(&self) -> Diagnostics {
+        let peer_connection = self.inner.borrow().peer_connection.clone();
+        let selected_candidate_pair = match get_selected_candidate_pair(&peer_connection).await {
+            Ok(selected_candidate_pair) => {
+                self.inner.borrow_mut().relayed = selected_candidate_pair
+                    .as_ref()
+                    .is_some_and(SelectedCandidatePair::is_relayed);
+                selected_candidate_pair
+            }
+            Err(error) => {
+                warn!(
+                    "failed to read connection stats for diagnostics: {:?}",
+                    error
+                );
+                None
+            }
+        };
+        let inner = self.inner.borrow();
+        Diagnostics {
+            signaling_state: websocket_state_name(&inner.websocket),
+            connection_state: js_enum_name(peer_connection.connection_state()),
+            ice_connection_state: js_enum_name(peer_connection.ice_connection_state()),
+            ice_gathering_state: js_enum_name(peer_connection.ice_gathering_state()),
+            selected_candidate_pair,
+            channels: inner.data_channel.iter().map(ChannelInfo::of).collect(),
+            queued_amount: inner.outbound_queue.queued_amount(),
+            paused_message_count: inner.inbound_buffer.len(),
+            last_error: inner.last_error.clone(),
+        }
+    }
+
+    /// Restarts `ICE` with fresh credentials, e.g. after the network changed or the connection failed,
+    /// coordinating with the other peer through the signaling server.
+    /// Only the host creates the restart offer, so if called on the other peer,
+    /// the request is passed to the host. Restart requested during a negotiation
+    /// starts once the negotiation is done.
+    ///
+    /// # Errors
+    /// This function errors if creating the offer or sending it to the signaling server fails.
+    pub async fn restart_ice(&self) -> Result<(), JsValue> {
+        let (is_host, session_id, websocket, peer_connection) = {
+            let inner = self.inner.borrow();
+            (
+                inner.is_host,
+                inner.session_id.clone(),
+                inner.websocket.clone(),
+                inner.peer_connection.clone(),
+            )
+        };
+        let signal_message = if !is_host {
+            info!("asking the host to restart ICE");
+            SignalMessage::IceRestart(session_id)
+        } else if peer_connection.signaling_state() != RtcSignalingState::Stable {
+            info!("negotiation in progress, ICE restart will follow it");
+            self.inner.borrow_mut().ice_restart_pending = true;
+            return Ok(());
+        } else {
+            info!("restarting ICE");
+            self.inner.borrow_mut().first_candidate_at = None;
+            let offer = create_ice_restart_offer(&peer_connection).await?;
+            SignalMessage::SdpOffer(session_id, offer)
+        };
+        let signal_message = serialize_message(&signal_message)?;
+        websocket.send_with_str(&signal_message)
+    }
+
+    /// Renegotiates the connection with the other peer through the signaling server,
+    /// e.g. right after [`NetworkManager::add_channel`], instead of waiting for the browser to ask.
+    /// Either peer can renegotiate, offers colliding with the other peer's follow the perfect negotiation
+    /// pattern, with the polite peer's renegotiation retried after answering.
+    /// Renegotiation requested during a negotiation starts once the negotiation is done.
+    ///
+    /// It's a no-op unless the browser reported that the connection needs renegotiating,
+    /// so it's safe to call whenever something might have changed.
+    ///
+    /// # Errors
+    /// This function errors if creating the offer or sending it to the signaling server fails.
+    pub async fn renegotiate(&self) -> Result<(), JsValue> {
+        let (negotiation_needed, session_id, websocket, peer_connection) = {
+            let inner = self.inner.borrow();
+            (
+                inner.negotiation_needed,
+                inner.session_id.clone(),
+                inner.websocket.clone(),
+                inner.peer_connection.clone(),
+            )
+        };
+        if !negotiation_needed {
+            debug!("connection doesn't need renegotiating");
+            return Ok(());
+        }
+        if peer_connection.signaling_state() != RtcSignalingState::Stable {
+            info!("negotiation in progress, renegotiation will follow it");
+            self.inner.borrow_mut().renegotiation_pending = true;
+            return Ok(());
+        }
+        info!("renegotiating the connection");
+        // cleared before the offer, so the browser asks again if something changes in the meantime
+        self.inner.borrow_mut().negotiation_needed = false;
+        let offer = create_sdp_offer(&peer_connection).await?;
+        let signal_message = SignalMessage::SdpOffer(session_id, offer);
+        let signal_message = serialize_message(&signal_message)?;
+        websocket.send_with_str(&signal_message)
+    }
+
+    /// Starts the `ICE` restart or renegotiation requested while the negotiation that just finished was in progress.
+    pub(crate) async fn on_negotiation_finished(&self) -> Result<(), JsValue> {
+        let (ice_restart_pending, renegotiation_pending) = {
+            let mut inner = self.inner.borrow_mut();
+            (
+                std::mem::take(&mut inner.ice_restart_pending),
+                std::mem::take(&mut inner.renegotiation_pending),
+            )
+        };
+        if ice_restart_pending {
+            self.restart_ice().await?;
+        } else if renegotiation_pending {
+            self.renegotiate().await?;
+        }
+        Ok(())
+    }
+
+    /// Checks the local candidate against [`IceOptions`], including the gathering timeout.
+    pub(crate) fn allows_local_candidate(&self, candidate: &str) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        let now = Date::now();
+        let first_candidate_at = *inner.first_candidate_at.get_or_insert(now);
+        let timed_out = inner
+            .ice_options
+            .gathering_timeout_ms
+            .is_some_and(|timeout_ms| now - first_candidate_at > f64::from(timeout_ms));
+        !timed_out && inner.ice_options.allows_candidate(candidate)
+    }
+
+    pub(crate) fn record_error(&self, error: String) {
+        self.inner.borrow_mut().last_error = Some(error);
+    }
+
     /// Send message to the other end of the connection.
     /// It might fail if the connection is not yet set up
     /// and thus should only be called after `on_open_callback` triggers.
     /// Otherwise it will result in an error.
+    ///
+    /// If the browser already buffers a lot of data for the data channel,
+    /// message is held in the crate's outbound queue until the buffer drains,
+    /// see [`NetworkManager::queued_amount`].
+    ///
+    /// Messages larger than [`NetworkManager::max_message_size`] are rejected right away.
     pub fn send_message(&self, message: &str) -> Result<(), JsValue> {
         debug!("server will try to send a message: {:?}", &message);
+        let data_channel = self.datachannel()?;
         // FIXME(tkarwowski): this is an ugly fix to the fact, that if you send empty string as message
         //  webrtc fails with a cryptic "The operation failed for an operation-specific reason"
         //  message
-        self.datachannel()?.send_with_str(&format!("x{}", message))
+        let message = format!("{}{}", APPLICATION_FRAME, message);
+        let max_fragment_length = match self.fragment_length(message.len()) {
+            Some(max_fragment_length) => max_fragment_length,
+            None => {
+                self.check_message_size(message.len())?;
+                return self
+                    .inner
+                    .borrow_mut()
+                    .outbound_queue
+                    .send_text(&data_channel, message);
+            }
+        };
+        let mut inner = self.inner.borrow_mut();
+        let id = inner.next_fragmented_id;
+        inner.next_fragmented_id = id.wrapping_add(1);
+        for fragment in split(&message, id, max_fragment_length)? {
+            inner.outbound_queue.send_text(&data_channel, fragment)?;
+        }
+        Ok(())
     }
 
     /// Same as [::], but allows to send byte array
     pub fn send_u8_array(&self, message: &[u8]) -> Result<(), JsValue> {
-        self.datachannel()?.send_with_u8_array(message)
+        let data_channel = self.datachannel()?;
+        self.check_message_size(message.len())?;
+        self.inner
+            .borrow_mut()
+            .outbound_queue
+            .send_binary(&data_channel, message.to_vec())
+    }
+
+    /// Maximum size in bytes of a single message, as negotiated by the peers' `SCTP` transports.
+    /// `None` until the connection is established, or if the size isn't limited.
+    /// Text messages carry one extra byte of framing, so they can be one byte shorter.
+    pub fn max_message_size(&self) -> Option<usize> {
+        get_max_message_size(&self.inner.borrow().peer_connection)
+    }
+
+    fn check_message_size(&self, length: usize) -> Result<(), JsValue> {
+        match self.max_message_size() {
+            Some(max_message_size) if length > max_message_size => {
+                Err(JsValue::from_str(&format!(
+                    "message is too large: {} bytes, maximum negotiated by the peers is {}",
+                    length, max_message_size
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of bytes the browser has buffered on the data channel, but not yet sent.
+    /// Those are out of the crate's reach and can't be cleared.
+    ///
+    /// # Errors
+    /// This function errors if data channel is not yet set up.
+    pub fn buffered_amount(&self) -> Result<u32, JsValue> {
+        Ok(self.datachannel()?.buffered_amount())
+    }
+
+    /// Number of bytes of messages held in the crate's outbound queue,
+    /// waiting for the browser's buffer to drain.
+    /// Unlike [`NetworkManager::buffered_amount`], those can still be dropped
+    /// with [`NetworkManager::clear_outbound_queue`].
+    pub fn queued_amount(&self) -> usize {
+        self.inner.borrow().outbound_queue.queued_amount()
+    }
+
+    /// Drops all messages from the crate's outbound queue, e.g. when they became stale,
+    /// and returns how many of them were dropped.
+    /// Data already handed over to the browser is still sent.
+    pub fn clear_outbound_queue(&self) -> usize {
+        self.inner.borrow_mut().outbound_queue.clear()
+    }
+
+    pub(crate) fn flush_outbound_queue(&self) -> Result<(), JsValue> {
+        let data_channel = self.datachannel()?;
+        self.inner.borrow_mut().outbound_queue.flush(&data_channel)
+    }
+}
+
+/// Closes the network manager when dropped unless it's taken out,
+/// so that a dropped connection attempt doesn't leak its resources.
+struct CloseOnDrop(Option<NetworkManager>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        if let Some(network_manager) = self.0.take() {
+            network_manager.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn test_closing_during_connection_attempt_releases_resources() {
+        let mut network_manager = NetworkManager::new(
+            "ws://0.0.0.0:9001/one-to-one",
+            SessionId::new("dummy-session-id".to_string()),
+            ConnectionType::Local,
+        )
+        .unwrap();
+        network_manager.start(|| {}, |_| {}).unwrap();
+
+        network_manager.close();
+
+        let inner = network_manager.inner.borrow();
+        assert_eq!(
+            inner.peer_connection.signaling_state(),
+            RtcSignalingState::Closed
+        );
+        assert!(matches!(
+            inner.websocket.ready_state(),
+            WebSocket::CLOSING | WebSocket::CLOSED
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_prewarm_enables_candidate_pool_and_refresh() {
+        let mut network_manager = NetworkManager::new(
+            "ws://0.0.0.0:9001/one-to-one",
+            SessionId::new("dummy-session-id".to_string()),
+            ConnectionType::Local,
+        )
+        .unwrap();
+
+        assert!(network_manager
+            .prewarm(0, || async { Ok(ConnectionType::Local) })
+            .is_err());
+        network_manager
+            .prewarm(60_000, || async { Ok(ConnectionType::Local) })
+            .unwrap();
+
+        let inner = network_manager.inner.borrow();
+        assert_eq!(inner.ice_options.candidate_pool_size, Some(1));
+        assert!(inner.ice_server_refresh_timer.is_some());
+        drop(inner);
+        network_manager.close();
+        assert!(network_manager
+            .inner
+            .borrow()
+            .ice_server_refresh_timer
+            .is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_incoming_channel_hook_sees_label_and_protocol() {
+        let mut network_manager = NetworkManager::new(
+            "ws://0.0.0.0:9001/one-to-one",
+            SessionId::new("dummy-session-id".to_string()),
+            ConnectionType::Local,
+        )
+        .unwrap();
+        network_manager.set_on_incoming_channel(|channel| {
+            channel.label == "game-state" && channel.protocol == "v1"
+        });
+        let peer_connection = network_manager.inner.borrow().peer_connection.clone();
+        let data_channel_config = DataChannelConfig {
+            protocol: Some("v1".to_string()),
+            priority: None,
+        };
+
+        let expected = create_data_channel(&peer_connection, "game-state", &data_channel_config);
+        let unexpected = create_data_channel(&peer_connection, "other", &data_channel_config);
+
+        assert!(network_manager.accepts_incoming_channel(&expected));
+        assert!(!network_manager.accepts_incoming_channel(&unexpected));
+        network_manager.close();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_diagnostics_of_closed_connection() {
+        let network_manager = NetworkManager::new(
+            "ws://0.0.0.0:9001/one-to-one",
+            SessionId::new("dummy-session-id".to_string()),
+            ConnectionType::Local,
+        )
+        .unwrap();
+        network_manager.record_error("session is full".to_string());
+
+        network_manager.close();
+        let diagnostics = network_manager.diagnostics().await;
+
+        assert_eq!(diagnostics.connection_state, "closed");
+        assert!(diagnostics.selected_candidate_pair.is_none());
+        assert!(!network_manager.is_relayed());
+        assert!(diagnostics.channels.is_empty());
+        assert_eq!(diagnostics.last_error.as_deref(), Some("session is full"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_returning_peer_gets_a_fresh_peer_connection() {
+        let mut network_manager = NetworkManager::new(
+            "ws://0.0.0.0:9001/one-to-one",
+            SessionId::new("dummy-session-id".to_string()),
+            ConnectionType::Local,
+        )
+        .unwrap();
+        let reconnecting = Rc::new(RefCell::new(0));
+        let disconnects = Rc::new(RefCell::new(0));
+        network_manager.set_wait_for_peer_return(true);
+        let reconnecting_clone = reconnecting.clone();
+        network_manager.set_on_peer_reconnecting(move || *reconnecting_clone.borrow_mut() += 1);
+        let disconnects_clone = disconnects.clone();
+        network_manager.set_on_disconnect(move |_| *disconnects_clone.borrow_mut() += 1);
+        network_manager.start(|| {}, |_| {}).unwrap();
+        let old_peer_connection = network_manager.inner.borrow().peer_connection.clone();
+
+        assert!(!network_manager.on_session_ready());
+        network_manager.on_disconnect(DisconnectReason::ConnectionFailed);
+        assert!(network_manager.on_session_ready());
+        let peer_connection = network_manager.replace_peer_connection().unwrap();
+
+        assert_eq!(*reconnecting.borrow(), 1);
+        assert_eq!(*disconnects.borrow(), 0);
+        assert_eq!(
+            old_peer_connection.signaling_state(),
+            RtcSignalingState::Closed
+        );
+        assert_eq!(peer_connection.signaling_state(), RtcSignalingState::Stable);
+        assert_eq!(network_manager.channels().len(), 1);
+        network_manager.close();
+        assert_eq!(*disconnects.borrow(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_app_role_follows_host_status_once_session_is_ready() {
+        let mut network_manager = NetworkManager::new(
+            "ws://0.0.0.0:9001/one-to-one",
+            SessionId::new("dummy-session-id".to_string()),
+            ConnectionType::Local,
+        )
+        .unwrap();
+        network_manager
+            .set_role_resolver(|is_host| if is_host { "player1" } else { "player2" }.to_string());
+
+        assert_eq!(network_manager.app_role(), None);
+        network_manager.on_session_ready();
+        network_manager.inner.borrow_mut().is_host = true;
+        assert_eq!(network_manager.app_role().as_deref(), Some("player1"));
+        network_manager.inner.borrow_mut().is_host = false;
+        assert_eq!(network_manager.app_role().as_deref(), Some("player2"));
+        network_manager.close();
+    }
+
+    #[wasm_bindgen_test]
+    fn test_ice_gathering_state_changes_are_passed_on_typed() {
+        let mut network_manager = NetworkManager::new(
+            "ws://0.0.0.0:9001/one-to-one",
+            SessionId::new("dummy-session-id".to_string()),
+            ConnectionType::Local,
+        )
+        .unwrap();
+        assert_eq!(
+            network_manager.ice_gathering_state(),
+            IceGatheringState::New
+        );
+        let states = Rc::new(RefCell::new(Vec::new()));
+        let states_clone = states.clone();
+        network_manager
+            .set_on_ice_gathering_state_change(move |state| states_clone.borrow_mut().push(state));
+
+        network_manager.on_ice_gathering_state(RtcIceGatheringState::Gathering);
+        network_manager.on_ice_gathering_state(RtcIceGatheringState::Complete);
+
+        assert_eq!(
+            *states.borrow(),
+            [IceGatheringState::Gathering, IceGatheringState::Complete]
+        );
+        network_manager.close();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_renegotiate_is_no_op_until_negotiation_is_needed() {
+        let mut network_manager = NetworkManager::new(
+            "ws://0.0.0.0:9001/one-to-one",
+            SessionId::new("dummy-session-id".to_string()),
+            ConnectionType::Local,
+        )
+        .unwrap();
+        network_manager.start(|| {}, |_| {}).unwrap();
+
+        // signaling websocket isn't open, so sending an offer would fail
+        network_manager.renegotiate().await.unwrap();
+        network_manager
+            .add_channel("game-state", &DataChannelConfig::default())
+            .unwrap();
+        assert_eq!(network_manager.channels().len(), 2);
+        network_manager.close();
     }
 }
@@ -0,0 +1,446 @@
+use std::rc::Rc;
+
+use js_sys::Date;
+use log::{debug, error, info};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{RtcDataChannelState, RtcIceGatheringState};
+
+use crate::callback::Callback;
+use crate::one_to_one::clock_sync::{encode_probe, Probe};
+use crate::one_to_one::peer_quality::encode_report;
+use crate::one_to_one::presence::{encode_heartbeat, PresenceTracker};
+use crate::one_to_one::{
+    BandwidthCallback, ClockOffset, CongestionCallback, CongestionLevel, CongestionThresholds,
+    IceGatheringCallback, IceGatheringState, IntervalTimer, NetworkManager, NotifyCallback,
+    PeerQualityCallback, MIN_CLOCK_SYNC_INTERVAL_MS, MIN_PRESENCE_INTERVAL_MS,
+    MIN_QUALITY_REPORT_INTERVAL_MS,
+};
+use crate::utils::{get_connection_quality, global_function, ConnectionQuality};
+
+impl NetworkManager {
+    /// Congestion level found by the last sample of the monitor started with
+    /// [`NetworkManager::start_congestion_monitor`], [`CongestionLevel::Low`] before the first one.
+    pub fn congestion_level(&self) -> CongestionLevel {
+        self.inner.borrow().congestion.level()
+    }
+
+    /// Returns how far gathering of local `ICE` candidates got, e.g. to show "gathering candidates"
+    /// before "connecting" in a progress indicator.
+    pub fn ice_gathering_state(&self) -> IceGatheringState {
+        self.inner
+            .borrow()
+            .peer_connection
+            .ice_gathering_state()
+            .into()
+    }
+
+    /// Sets a callback called with the new state each time [`NetworkManager::ice_gathering_state`] changes.
+    pub fn set_on_ice_gathering_state_change(
+        &mut self,
+        on_ice_gathering_state_change: impl FnMut(IceGatheringState) + 'static,
+    ) {
+        self.inner.borrow_mut().on_ice_gathering_state_change =
+            Some(IceGatheringCallback::new(on_ice_gathering_state_change));
+    }
+
+    pub(crate) fn on_ice_gathering_state(&self, state: RtcIceGatheringState) {
+        if let Some(callback) =
+            Callback::of(&self.inner, |inner| &inner.on_ice_gathering_state_change)
+        {
+            callback.call(state.into());
+        }
+    }
+
+    /// Sets a callback called with the new congestion level each time it changes,
+    /// e.g. to lower the send rate of an adaptive application.
+    pub fn set_on_congestion_change(
+        &mut self,
+        on_congestion_change: impl FnMut(CongestionLevel) + 'static,
+    ) {
+        self.inner.borrow_mut().on_congestion_change =
+            Some(CongestionCallback::new(on_congestion_change));
+    }
+
+    /// Sets a callback called on each sample of [`NetworkManager::start_congestion_monitor`]
+    /// with [`ConnectionQuality::available_outgoing_bitrate`], e.g. to adapt the amount of data sent
+    /// to the estimated bandwidth. Not called while the browser doesn't report the estimate.
+    pub fn set_on_bandwidth_estimate(&mut self, on_bandwidth_estimate: impl FnMut(f64) + 'static) {
+        self.inner.borrow_mut().on_bandwidth_estimate =
+            Some(BandwidthCallback::new(on_bandwidth_estimate));
+    }
+
+    /// Samples congestion of the connection every `interval_ms` milliseconds,
+    /// until [`NetworkManager::stop_congestion_monitor`] or [`NetworkManager::close`] is called.
+    /// Replaces previously started monitor.
+    ///
+    /// Each sample takes the bytes waiting to be sent, i.e. [`NetworkManager::queued_amount`]
+    /// plus [`NetworkManager::buffered_amount`], and the round trip time of the selected candidate pair.
+    /// The level is [`CongestionLevel::Medium`] or [`CongestionLevel::High`] once the waiting bytes
+    /// reach their thresholds, and is raised by one more level when the waiting bytes grew in each of
+    /// the latest [`CongestionThresholds::trend_samples`] samples, and by another one when the round trip time
+    /// exceeds the lowest one seen so far [`CongestionThresholds::round_trip_time_ratio`] times.
+    ///
+    /// # Errors
+    /// This function errors if the timer can't be set.
+    pub fn start_congestion_monitor(
+        &self,
+        interval_ms: u32,
+        thresholds: CongestionThresholds,
+    ) -> Result<(), JsValue> {
+        self.stop_congestion_monitor()?;
+        self.inner
+            .borrow_mut()
+            .congestion
+            .set_thresholds(thresholds);
+        let network_manager = self.clone();
+        let on_interval = Closure::wrap(Box::new(move || {
+            let network_manager = network_manager.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                network_manager
+                    .sample_congestion()
+                    .await
+                    .unwrap_or_else(|error| {
+                        log::error!("failed to sample congestion: {:?}", error)
+                    });
+            });
+        }) as Box<dyn FnMut()>);
+        let set_interval = global_function("setInterval")?;
+        let handle = set_interval.call2(
+            &JsValue::NULL,
+            on_interval.as_ref().unchecked_ref(),
+            &JsValue::from(interval_ms),
+        )?;
+        self.inner.borrow_mut().congestion_timer =
+            Some(IntervalTimer(Rc::new((handle, on_interval))));
+        Ok(())
+    }
+
+    /// Stops the monitor started with [`NetworkManager::start_congestion_monitor`], if any.
+    /// The last congestion level is kept.
+    ///
+    /// # Errors
+    /// This function errors if the timer can't be cleared.
+    pub fn stop_congestion_monitor(&self) -> Result<(), JsValue> {
+        let timer = self.inner.borrow_mut().congestion_timer.take();
+        if let Some(IntervalTimer(timer)) = timer {
+            global_function("clearInterval")?.call1(&JsValue::NULL, &timer.0)?;
+        }
+        Ok(())
+    }
+
+    async fn sample_congestion(&self) -> Result<(), JsValue> {
+        let peer_connection = self.inner.borrow().peer_connection.clone();
+        let quality = get_connection_quality(&peer_connection).await?;
+        let pending_bytes =
+            self.queued_amount() + self.buffered_amount().unwrap_or_default() as usize;
+        let (changed, on_congestion_change, on_bandwidth_estimate) = {
+            let mut inner = self.inner.borrow_mut();
+            let changed = inner
+                .congestion
+                .update(pending_bytes, quality.round_trip_time_ms);
+            (
+                changed,
+                inner.on_congestion_change.clone(),
+                inner.on_bandwidth_estimate.clone(),
+            )
+        };
+        if let (Some(level), Some(callback)) = (changed, on_congestion_change) {
+            callback.call(level);
+        }
+        if let (Some(bitrate), Some(callback)) =
+            (quality.available_outgoing_bitrate, on_bandwidth_estimate)
+        {
+            callback.call(bitrate);
+        }
+        Ok(())
+    }
+
+    /// Quality of the connection as measured on this side, see [`ConnectionQuality`].
+    ///
+    /// # Errors
+    /// This function errors if the stats can't be read.
+    pub async fn connection_quality(&self) -> Result<ConnectionQuality, JsValue> {
+        let peer_connection = self.inner.borrow().peer_connection.clone();
+        get_connection_quality(&peer_connection).await
+    }
+
+    /// Sets a callback called with each quality report sent by the other peer
+    /// with [`NetworkManager::start_quality_reports`], i.e. the quality of the connection as the peer sees it,
+    /// which can differ from [`NetworkManager::connection_quality`] on asymmetric links.
+    pub fn set_on_peer_quality(
+        &mut self,
+        on_peer_quality: impl FnMut(ConnectionQuality) + 'static,
+    ) {
+        self.inner.borrow_mut().on_peer_quality = Some(PeerQualityCallback::new(on_peer_quality));
+    }
+
+    /// Sends [`NetworkManager::connection_quality`] to the other peer every `interval_ms` milliseconds,
+    /// until [`NetworkManager::stop_quality_reports`] or [`NetworkManager::close`] is called.
+    /// Replaces previously started reports. Reports are skipped while the data channel isn't open.
+    ///
+    /// Each peer reports its own side, so both need to start the reports to show quality in both directions.
+    /// Reports share the data channel with application messages but never reach `on_message_callback`,
+    /// so the other peer needs a version of the crate that knows them.
+    ///
+    /// # Errors
+    /// This function errors if `interval_ms` is shorter than [`MIN_QUALITY_REPORT_INTERVAL_MS`]
+    /// or if the timer can't be set.
+    pub fn start_quality_reports(&self, interval_ms: u32) -> Result<(), JsValue> {
+        if interval_ms < MIN_QUALITY_REPORT_INTERVAL_MS {
+            return Err(JsValue::from_str(&format!(
+                "quality report interval is too short: {} ms, minimum is {}",
+                interval_ms, MIN_QUALITY_REPORT_INTERVAL_MS
+            )));
+        }
+        self.stop_quality_reports()?;
+        let network_manager = self.clone();
+        let on_interval = Closure::wrap(Box::new(move || {
+            let network_manager = network_manager.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                network_manager
+                    .send_quality_report()
+                    .await
+                    .unwrap_or_else(|error| error!("failed to send quality report: {:?}", error));
+            });
+        }) as Box<dyn FnMut()>);
+        let set_interval = global_function("setInterval")?;
+        let handle = set_interval.call2(
+            &JsValue::NULL,
+            on_interval.as_ref().unchecked_ref(),
+            &JsValue::from(interval_ms),
+        )?;
+        self.inner.borrow_mut().quality_report_timer =
+            Some(IntervalTimer(Rc::new((handle, on_interval))));
+        Ok(())
+    }
+
+    /// Stops the reports started with [`NetworkManager::start_quality_reports`], if any.
+    ///
+    /// # Errors
+    /// This function errors if the timer can't be cleared.
+    pub fn stop_quality_reports(&self) -> Result<(), JsValue> {
+        let timer = self.inner.borrow_mut().quality_report_timer.take();
+        if let Some(IntervalTimer(timer)) = timer {
+            global_function("clearInterval")?.call1(&JsValue::NULL, &timer.0)?;
+        }
+        Ok(())
+    }
+
+    async fn send_quality_report(&self) -> Result<(), JsValue> {
+        let data_channel = match self.inner.borrow().data_channel.clone() {
+            Some(data_channel) if data_channel.ready_state() == RtcDataChannelState::Open => {
+                data_channel
+            }
+            _ => return Ok(()),
+        };
+        let report = encode_report(&self.connection_quality().await?)?;
+        self.inner
+            .borrow_mut()
+            .outbound_queue
+            .send_text(&data_channel, report)
+    }
+
+    pub(super) fn receive_peer_quality(&self, quality: ConnectionQuality) {
+        let on_peer_quality = {
+            let mut inner = self.inner.borrow_mut();
+            let now = Date::now();
+            let too_soon = inner
+                .last_peer_quality_at
+                .is_some_and(|last_peer_quality_at| {
+                    now - last_peer_quality_at < f64::from(MIN_QUALITY_REPORT_INTERVAL_MS) / 2.0
+                });
+            if too_soon {
+                debug!("quality report arrived too soon after the previous one, dropping it");
+                return;
+            }
+            inner.last_peer_quality_at = Some(now);
+            inner.on_peer_quality.clone()
+        };
+        if let Some(callback) = on_peer_quality {
+            callback.call(quality);
+        }
+    }
+
+    /// Estimates the offset of the other peer's clock every `interval_ms` milliseconds,
+    /// starting right away, until [`NetworkManager::stop_clock_sync`] or [`NetworkManager::close`] is called,
+    /// see [`NetworkManager::peer_time_offset`]. Replaces previously started sync.
+    /// Probes are skipped while the data channel isn't open.
+    ///
+    /// Probes share the data channel with application messages but never reach `on_message_callback`,
+    /// so the other peer needs a version of the crate that knows them. It answers them without starting the sync.
+    ///
+    /// # Errors
+    /// This function errors if `interval_ms` is shorter than [`MIN_CLOCK_SYNC_INTERVAL_MS`]
+    /// or if the timer can't be set.
+    pub fn start_clock_sync(&self, interval_ms: u32) -> Result<(), JsValue> {
+        if interval_ms < MIN_CLOCK_SYNC_INTERVAL_MS {
+            return Err(JsValue::from_str(&format!(
+                "clock sync interval is too short: {} ms, minimum is {}",
+                interval_ms, MIN_CLOCK_SYNC_INTERVAL_MS
+            )));
+        }
+        self.stop_clock_sync()?;
+        let network_manager = self.clone();
+        let on_interval = Closure::wrap(Box::new(move || {
+            network_manager
+                .send_clock_request()
+                .unwrap_or_else(|error| error!("failed to send clock probe: {:?}", error));
+        }) as Box<dyn FnMut()>);
+        let set_interval = global_function("setInterval")?;
+        let handle = set_interval.call2(
+            &JsValue::NULL,
+            on_interval.as_ref().unchecked_ref(),
+            &JsValue::from(interval_ms),
+        )?;
+        self.inner.borrow_mut().clock_sync_timer =
+            Some(IntervalTimer(Rc::new((handle, on_interval))));
+        self.send_clock_request()
+    }
+
+    /// Stops the sync started with [`NetworkManager::start_clock_sync`], if any,
+    /// keeping the last estimate.
+    ///
+    /// # Errors
+    /// This function errors if the timer can't be cleared.
+    pub fn stop_clock_sync(&self) -> Result<(), JsValue> {
+        let timer = self.inner.borrow_mut().clock_sync_timer.take();
+        if let Some(IntervalTimer(timer)) = timer {
+            global_function("clearInterval")?.call1(&JsValue::NULL, &timer.0)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the estimated offset of the other peer's clock, e.g. to order events of both peers
+    /// or to schedule something at the same moment on both sides,
+    /// or `None` until [`NetworkManager::start_clock_sync`] gets the first answer.
+    /// Estimate is picked from the latest few probes, so it follows the clocks drifting apart.
+    pub fn peer_time_offset(&self) -> Option<ClockOffset> {
+        self.inner.borrow().clock_sync.offset()
+    }
+
+    fn send_clock_request(&self) -> Result<(), JsValue> {
+        self.send_clock_probe(&Probe {
+            origin: Date::now(),
+            receive: None,
+            transmit: None,
+        })
+    }
+
+    fn send_clock_probe(&self, probe: &Probe) -> Result<(), JsValue> {
+        let data_channel = match self.inner.borrow().data_channel.clone() {
+            Some(data_channel) if data_channel.ready_state() == RtcDataChannelState::Open => {
+                data_channel
+            }
+            _ => return Ok(()),
+        };
+        let probe = encode_probe(probe)?;
+        self.inner
+            .borrow_mut()
+            .outbound_queue
+            .send_text(&data_channel, probe)
+    }
+
+    pub(super) fn receive_clock_probe(&self, probe: Probe) {
+        let now = Date::now();
+        if probe.receive.is_some() {
+            self.inner.borrow_mut().clock_sync.add_response(&probe, now);
+            return;
+        }
+        let response = Probe {
+            receive: Some(now),
+            transmit: Some(Date::now()),
+            ..probe
+        };
+        self.send_clock_probe(&response)
+            .unwrap_or_else(|error| error!("failed to answer clock probe: {:?}", error));
+    }
+
+    /// Sends a presence heartbeat to the other peer every `interval_ms` milliseconds,
+    /// until [`NetworkManager::stop_presence_heartbeats`] or [`NetworkManager::close`] is called,
+    /// and calls the callback set with [`NetworkManager::set_on_peer_unresponsive`]
+    /// once more than `max_missed` intervals in a row pass without anything arriving from the peer.
+    /// Replaces previously started heartbeats. Intervals while the data channel isn't open don't count.
+    ///
+    /// Unlike the `ICE` connection state, this notices the other peer's application hanging
+    /// while its browser keeps the connection up. Both peers need to start the heartbeats,
+    /// with the same or shorter interval on the other side, and heartbeats never reach `on_message_callback`,
+    /// so the other peer needs a version of the crate that knows them.
+    ///
+    /// # Errors
+    /// This function errors if `interval_ms` is shorter than [`MIN_PRESENCE_INTERVAL_MS`]
+    /// or if the timer can't be set.
+    pub fn start_presence_heartbeats(
+        &self,
+        interval_ms: u32,
+        max_missed: u32,
+    ) -> Result<(), JsValue> {
+        if interval_ms < MIN_PRESENCE_INTERVAL_MS {
+            return Err(JsValue::from_str(&format!(
+                "presence heartbeat interval is too short: {} ms, minimum is {}",
+                interval_ms, MIN_PRESENCE_INTERVAL_MS
+            )));
+        }
+        self.stop_presence_heartbeats()?;
+        self.inner.borrow_mut().presence = PresenceTracker::new(max_missed);
+        let network_manager = self.clone();
+        let on_interval = Closure::wrap(Box::new(move || {
+            network_manager
+                .send_presence_heartbeat()
+                .unwrap_or_else(|error| error!("failed to send presence heartbeat: {:?}", error));
+        }) as Box<dyn FnMut()>);
+        let set_interval = global_function("setInterval")?;
+        let handle = set_interval.call2(
+            &JsValue::NULL,
+            on_interval.as_ref().unchecked_ref(),
+            &JsValue::from(interval_ms),
+        )?;
+        self.inner.borrow_mut().presence_timer =
+            Some(IntervalTimer(Rc::new((handle, on_interval))));
+        Ok(())
+    }
+
+    /// Stops the heartbeats started with [`NetworkManager::start_presence_heartbeats`], if any.
+    ///
+    /// # Errors
+    /// This function errors if the timer can't be cleared.
+    pub fn stop_presence_heartbeats(&self) -> Result<(), JsValue> {
+        let timer = self.inner.borrow_mut().presence_timer.take();
+        if let Some(IntervalTimer(timer)) = timer {
+            global_function("clearInterval")?.call1(&JsValue::NULL, &timer.0)?;
+        }
+        Ok(())
+    }
+
+    /// Sets a callback called when the other peer stops responding to presence heartbeats,
+    /// see [`NetworkManager::start_presence_heartbeats`]. It's called once per lapse,
+    /// again only after the peer was heard from in between.
+    pub fn set_on_peer_unresponsive(&mut self, on_peer_unresponsive: impl FnMut() + 'static) {
+        self.inner.borrow_mut().on_peer_unresponsive =
+            Some(NotifyCallback::new(on_peer_unresponsive));
+    }
+
+    fn send_presence_heartbeat(&self) -> Result<(), JsValue> {
+        let data_channel = match self.inner.borrow().data_channel.clone() {
+            Some(data_channel) if data_channel.ready_state() == RtcDataChannelState::Open => {
+                data_channel
+            }
+            _ => return Ok(()),
+        };
+        let on_peer_unresponsive = {
+            let mut inner = self.inner.borrow_mut();
+            inner
+                .outbound_queue
+                .send_text(&data_channel, encode_heartbeat())?;
+            if !inner.presence.on_interval() {
+                return Ok(());
+            }
+            inner.on_peer_unresponsive.clone()
+        };
+        info!("peer missed presence heartbeats");
+        if let Some(callback) = on_peer_unresponsive {
+            callback.call();
+        }
+        Ok(())
+    }
+}
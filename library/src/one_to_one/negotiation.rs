@@ -0,0 +1,46 @@
+use web_sys::RtcSignalingState;
+
+/// What to do with an offer from the other peer, following the perfect negotiation pattern,
+/// so renegotiation started by either peer is answered without the application's involvement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OfferAction {
+    Answer,
+    /// Both peers offered at once, polite one drops its own offer in favor of the other peer's.
+    RollbackAndAnswer,
+    /// Both peers offered at once, impolite one keeps its own offer, the other peer answers it.
+    Ignore,
+}
+
+/// Peer that isn't the host is the polite one.
+pub(crate) fn on_remote_offer(polite: bool, signaling_state: RtcSignalingState) -> OfferAction {
+    match signaling_state {
+        RtcSignalingState::HaveLocalOffer if polite => OfferAction::RollbackAndAnswer,
+        RtcSignalingState::HaveLocalOffer => OfferAction::Ignore,
+        _ => OfferAction::Answer,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_only_polite_peer_gives_way_on_collision() {
+        for polite in [true, false] {
+            assert_eq!(
+                on_remote_offer(polite, RtcSignalingState::Stable),
+                OfferAction::Answer
+            );
+        }
+        assert_eq!(
+            on_remote_offer(true, RtcSignalingState::HaveLocalOffer),
+            OfferAction::RollbackAndAnswer
+        );
+        assert_eq!(
+            on_remote_offer(false, RtcSignalingState::HaveLocalOffer),
+            OfferAction::Ignore
+        );
+    }
+}
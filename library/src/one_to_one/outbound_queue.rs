@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+
+use wasm_bindgen::JsValue;
+use web_sys::RtcDataChannel;
+
+/// Once browser buffers this many bytes on the data channel,
+/// new messages are held in [`OutboundQueue`] instead.
+pub(crate) const BUFFERED_AMOUNT_HIGH_THRESHOLD: u32 = 1024 * 1024;
+/// Browser fires `bufferedamountlow` once its buffer drains below this many bytes,
+/// which is when [`OutboundQueue`] is flushed.
+pub(crate) const BUFFERED_AMOUNT_LOW_THRESHOLD: u32 = 256 * 1024;
+
+#[derive(Debug, Clone)]
+enum OutboundMessage {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl OutboundMessage {
+    fn len(&self) -> usize {
+        match self {
+            OutboundMessage::Text(message) => message.len(),
+            OutboundMessage::Binary(message) => message.len(),
+        }
+    }
+
+    fn send(&self, data_channel: &RtcDataChannel) -> Result<(), JsValue> {
+        match self {
+            OutboundMessage::Text(message) => data_channel.send_with_str(message),
+            OutboundMessage::Binary(message) => data_channel.send_with_u8_array(message),
+        }
+    }
+}
+
+/// Messages held back by the crate while the browser's data channel buffer is full.
+///
+/// Unlike the browser's buffer, which can't be cleared once data is handed over to it,
+/// messages in this queue can still be dropped by the application.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OutboundQueue {
+    messages: VecDeque<OutboundMessage>,
+    queued_amount: usize,
+}
+
+impl OutboundQueue {
+    pub(crate) fn queued_amount(&self) -> usize {
+        self.queued_amount
+    }
+
+    /// Drops all queued messages and returns how many of them there were.
+    pub(crate) fn clear(&mut self) -> usize {
+        let dropped = self.messages.len();
+        self.messages.clear();
+        self.queued_amount = 0;
+        dropped
+    }
+
+    pub(crate) fn send_text(
+        &mut self,
+        data_channel: &RtcDataChannel,
+        message: String,
+    ) -> Result<(), JsValue> {
+        self.send(data_channel, OutboundMessage::Text(message))
+    }
+
+    pub(crate) fn send_binary(
+        &mut self,
+        data_channel: &RtcDataChannel,
+        message: Vec<u8>,
+    ) -> Result<(), JsValue> {
+        self.send(data_channel, OutboundMessage::Binary(message))
+    }
+
+    /// Sends the message right away, unless there are messages waiting
+    /// or browser's buffer is full, in which case message is queued to keep the order.
+    fn send(
+        &mut self,
+        data_channel: &RtcDataChannel,
+        message: OutboundMessage,
+    ) -> Result<(), JsValue> {
+        if self.messages.is_empty()
+            && data_channel.buffered_amount() < BUFFERED_AMOUNT_HIGH_THRESHOLD
+        {
+            return message.send(data_channel);
+        }
+        self.queued_amount += message.len();
+        self.messages.push_back(message);
+        Ok(())
+    }
+
+    /// Hands queued messages over to the browser until its buffer fills up again.
+    pub(crate) fn flush(&mut self, data_channel: &RtcDataChannel) -> Result<(), JsValue> {
+        while data_channel.buffered_amount() < BUFFERED_AMOUNT_HIGH_THRESHOLD {
+            let message = match self.messages.pop_front() {
+                Some(message) => message,
+                None => break,
+            };
+            self.queued_amount -= message.len();
+            message.send(data_channel)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use wasm_bindgen::JsValue;
+
+use crate::control::QUALITY_REPORT_FRAME;
+use crate::utils::ConnectionQuality;
+
+/// Shortest interval between quality reports, see [`crate::one_to_one::NetworkManager::start_quality_reports`].
+/// Reports from the other peer arriving more often than every half of it are dropped.
+pub const MIN_QUALITY_REPORT_INTERVAL_MS: u32 = 1000;
+
+/// Reports longer than that are dropped, real ones take well under a hundred bytes.
+const MAX_REPORT_LENGTH: usize = 256;
+
+pub(crate) fn encode_report(quality: &ConnectionQuality) -> Result<String, JsValue> {
+    let report = serde_json_wasm::to_string(quality)
+        .map_err(|error| JsValue::from_str(&error.to_string()))?;
+    Ok(format!("{}{}", QUALITY_REPORT_FRAME, report))
+}
+
+/// Returns `None` if the message isn't a quality report.
+pub(crate) fn decode_report(message: &str) -> Option<Result<ConnectionQuality, JsValue>> {
+    let report = message.strip_prefix(QUALITY_REPORT_FRAME)?;
+    if report.len() > MAX_REPORT_LENGTH {
+        return Some(Err(JsValue::from_str(&format!(
+            "quality report is too long: {} bytes, maximum is {}",
+            report.len(),
+            MAX_REPORT_LENGTH
+        ))));
+    }
+    let quality = serde_json_wasm::from_str::<ConnectionQuality>(report)
+        .map_err(|error| JsValue::from_str(&error.to_string()))
+        .and_then(|quality| {
+            let valid = quality
+                .round_trip_time_ms
+                .is_none_or(|round_trip_time| round_trip_time >= 0.0)
+                && quality.loss.is_none_or(|loss| (0.0..=1.0).contains(&loss))
+                && quality
+                    .available_outgoing_bitrate
+                    .is_none_or(|bitrate| bitrate >= 0.0);
+            if valid {
+                Ok(quality)
+            } else {
+                Err(JsValue::from_str("quality report is out of range"))
+            }
+        });
+    Some(quality)
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_report_round_trips_quality() {
+        let quality = ConnectionQuality {
+            round_trip_time_ms: Some(42.5),
+            loss: Some(0.25),
+            available_outgoing_bitrate: Some(300_000.0),
+        };
+
+        let report = encode_report(&quality).unwrap();
+
+        assert_eq!(decode_report(&report).unwrap().unwrap(), quality);
+        assert!(decode_report(&format!("x{}", &report[1..])).is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_oversized_or_out_of_range_reports_are_rejected() {
+        let oversized = format!("q{}", " ".repeat(MAX_REPORT_LENGTH + 1));
+        let out_of_range = r#"q{"round_trip_time_ms":null,"loss":2.0}"#;
+
+        assert!(decode_report(&oversized).unwrap().is_err());
+        assert!(decode_report(out_of_range).unwrap().is_err());
+    }
+}
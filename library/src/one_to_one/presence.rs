@@ -0,0 +1,80 @@
+use crate::control::HEARTBEAT_FRAME;
+
+/// Shortest interval between presence heartbeats, see [`crate::one_to_one::NetworkManager::start_presence_heartbeats`].
+pub const MIN_PRESENCE_INTERVAL_MS: u32 = 1000;
+
+pub(crate) fn encode_heartbeat() -> String {
+    HEARTBEAT_FRAME.to_string()
+}
+
+pub(crate) fn is_heartbeat(message: &str) -> bool {
+    message == HEARTBEAT_FRAME
+}
+
+/// Counts heartbeat intervals in which nothing arrived from the other peer,
+/// reporting the peer unresponsive once per lapse, after more than `max_missed` of them in a row.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct PresenceTracker {
+    max_missed: u32,
+    missed: u32,
+    heard_from_peer: bool,
+    unresponsive: bool,
+}
+
+impl PresenceTracker {
+    pub(crate) fn new(max_missed: u32) -> Self {
+        PresenceTracker {
+            max_missed,
+            ..PresenceTracker::default()
+        }
+    }
+
+    /// Any message counts, not only heartbeats, a peer sending data is alive.
+    pub(crate) fn on_message_received(&mut self) {
+        self.heard_from_peer = true;
+    }
+
+    /// Returns `true` if the peer just became unresponsive.
+    pub(crate) fn on_interval(&mut self) -> bool {
+        if std::mem::take(&mut self.heard_from_peer) {
+            self.missed = 0;
+            self.unresponsive = false;
+            return false;
+        }
+        self.missed = self.missed.saturating_add(1);
+        if self.missed > self.max_missed && !self.unresponsive {
+            self.unresponsive = true;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_peer_is_reported_once_per_lapse_after_missed_heartbeats() {
+        let mut tracker = PresenceTracker::new(2);
+
+        assert!(!tracker.on_interval());
+        assert!(!tracker.on_interval());
+        assert!(tracker.on_interval());
+        assert!(!tracker.on_interval());
+
+        tracker.on_message_received();
+        assert!(!tracker.on_interval());
+        assert!(!tracker.on_interval());
+        assert!(!tracker.on_interval());
+        assert!(tracker.on_interval());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_heartbeat_never_looks_like_application_message() {
+        assert!(is_heartbeat(&encode_heartbeat()));
+        assert!(!is_heartbeat("xh"));
+    }
+}
@@ -0,0 +1,81 @@
+use web_sys::RtcIceConnectionState;
+
+/// Transition reported by [`ReconnectTracker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReconnectEvent {
+    Reconnecting,
+    Reconnected,
+}
+
+/// Tells interruptions of an established connection apart from the initial connection,
+/// so that reconnect callbacks fire once per interruption.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ReconnectTracker {
+    connected_once: bool,
+    reconnecting: bool,
+}
+
+impl ReconnectTracker {
+    pub(crate) fn on_ice_connection_state(
+        &mut self,
+        state: RtcIceConnectionState,
+    ) -> Option<ReconnectEvent> {
+        match state {
+            RtcIceConnectionState::Connected | RtcIceConnectionState::Completed => {
+                self.connected_once = true;
+                std::mem::take(&mut self.reconnecting).then_some(ReconnectEvent::Reconnected)
+            }
+            RtcIceConnectionState::Disconnected | RtcIceConnectionState::Failed
+                if self.connected_once && !self.reconnecting =>
+            {
+                self.reconnecting = true;
+                Some(ReconnectEvent::Reconnecting)
+            }
+            RtcIceConnectionState::Closed => {
+                self.reconnecting = false;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_each_interruption_is_reported_once() {
+        let mut tracker = ReconnectTracker::default();
+        let events: Vec<_> = [
+            // initial connection, failing before it's established isn't a reconnect
+            RtcIceConnectionState::Checking,
+            RtcIceConnectionState::Failed,
+            RtcIceConnectionState::Checking,
+            RtcIceConnectionState::Connected,
+            // interrupted, failed and restarted
+            RtcIceConnectionState::Disconnected,
+            RtcIceConnectionState::Failed,
+            RtcIceConnectionState::Checking,
+            RtcIceConnectionState::Connected,
+            RtcIceConnectionState::Completed,
+            // interrupted and closed
+            RtcIceConnectionState::Disconnected,
+            RtcIceConnectionState::Closed,
+        ]
+        .into_iter()
+        .filter_map(|state| tracker.on_ice_connection_state(state))
+        .collect();
+
+        assert_eq!(
+            events,
+            [
+                ReconnectEvent::Reconnecting,
+                ReconnectEvent::Reconnected,
+                ReconnectEvent::Reconnecting,
+            ]
+        );
+    }
+}
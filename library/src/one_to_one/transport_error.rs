@@ -0,0 +1,89 @@
+use js_sys::Reflect;
+use wasm_bindgen::JsValue;
+
+/// Error of the transport under a data channel, see [`crate::one_to_one::NetworkManager::set_on_transport_error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportError {
+    /// Label of the data channel that reported the error.
+    pub label: String,
+    /// `errorDetail` of the browser's `RTCError`, e.g. `sctp-failure`, `None` if the browser doesn't expose it.
+    pub detail: Option<String>,
+    /// `SCTP` cause code of an `sctp-failure`, as defined in RFC 4960, e.g. 13 for a protocol violation.
+    pub sctp_cause_code: Option<u16>,
+    pub message: String,
+    /// Fatal errors close the channel for good, after recoverable ones reconnecting can help.
+    pub fatal: bool,
+}
+
+/// `SCTP` causes a new association can get past: stale cookie, out of resource
+/// and restart of an association with new addresses.
+const RECOVERABLE_SCTP_CAUSES: [u16; 3] = [3, 4, 11];
+
+impl TransportError {
+    /// Reads the `RTCError` of a data channel's `error` event, whatever of it the browser exposes.
+    pub(crate) fn from_event(label: String, event: &JsValue) -> Self {
+        let error = Reflect::get(event, &JsValue::from_str("error")).unwrap_or(JsValue::UNDEFINED);
+        let field = |name: &str| {
+            Reflect::get(&error, &JsValue::from_str(name))
+                .ok()
+                .filter(|value| !value.is_undefined() && !value.is_null())
+        };
+        let detail = field("errorDetail").and_then(|detail| detail.as_string());
+        let sctp_cause_code = field("sctpCauseCode")
+            .and_then(|code| code.as_f64())
+            .and_then(|code| u16::try_from(code as i64).ok());
+        let message = field("message")
+            .and_then(|message| message.as_string())
+            .unwrap_or_default();
+        Self::new(label, detail, sctp_cause_code, message)
+    }
+
+    fn new(
+        label: String,
+        detail: Option<String>,
+        sctp_cause_code: Option<u16>,
+        message: String,
+    ) -> Self {
+        let fatal = match detail.as_deref() {
+            // certificate of the other peer doesn't check out, reconnecting won't change that
+            Some("dtls-failure" | "fingerprint-failure") => true,
+            Some("sctp-failure") => {
+                !sctp_cause_code.is_some_and(|code| RECOVERABLE_SCTP_CAUSES.contains(&code))
+            }
+            _ => false,
+        };
+        TransportError {
+            label,
+            detail,
+            sctp_cause_code,
+            message,
+            fatal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    fn error(detail: Option<&str>, sctp_cause_code: Option<u16>) -> TransportError {
+        TransportError::new(
+            "channel".to_string(),
+            detail.map(str::to_string),
+            sctp_cause_code,
+            String::new(),
+        )
+    }
+
+    #[wasm_bindgen_test]
+    fn test_only_transient_sctp_causes_are_recoverable() {
+        assert!(!error(Some("sctp-failure"), Some(4)).fatal);
+        assert!(error(Some("sctp-failure"), Some(13)).fatal);
+        assert!(error(Some("sctp-failure"), None).fatal);
+        assert!(error(Some("dtls-failure"), None).fatal);
+        assert!(!error(Some("data-channel-failure"), None).fatal);
+        assert!(!error(None, None).fatal);
+    }
+}
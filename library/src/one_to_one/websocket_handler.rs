@@ -1,13 +1,14 @@
 use ::log::{debug, error, info};
 use wasm_bindgen::JsValue;
-use wasm_bindgen_futures::JsFuture;
 use wasm_peers_protocol::one_to_one::SignalMessage;
-use web_sys::{
-    RtcIceCandidate, RtcIceCandidateInit, RtcPeerConnection, RtcSdpType, RtcSessionDescriptionInit,
-    WebSocket,
-};
+use web_sys::{RtcPeerConnection, WebSocket};
 
-use crate::utils::{create_sdp_answer, create_sdp_offer, IceCandidate};
+use crate::one_to_one::negotiation::{on_remote_offer, OfferAction};
+use crate::one_to_one::{DisconnectReason, NetworkManager};
+use crate::utils::{
+    accepts_ice_candidates, add_ice_candidates, create_sdp_answer, create_sdp_offer,
+    parse_ice_candidate, rollback_local_description, serialize_message, set_sdp_answer,
+};
 
 /// Basically a state  spread across host, client and signaling server,
 /// handling each step in session and then `WebRTC` setup.
@@ -15,69 +16,139 @@ pub(crate) async fn handle_websocket_message(
     message: SignalMessage,
     peer_connection: RtcPeerConnection,
     websocket: WebSocket,
+    network_manager: NetworkManager,
 ) -> Result<(), JsValue> {
     match message {
-        SignalMessage::SessionJoin(_session_id) => {
-            error!("error, SessionStartOrJoin should only be sent by peers to signaling server");
+        SignalMessage::SessionJoin(_)
+        | SignalMessage::SessionLeave(_)
+        | SignalMessage::FindMatch(_) => {
+            error!("error, SessionJoin, SessionLeave and FindMatch should only be sent by peers to signaling server");
+        }
+        SignalMessage::Matched(session_id) => {
+            info!(
+                "peer was matched with another one in session {:?}",
+                session_id
+            );
+            network_manager.inner.borrow_mut().session_id = session_id;
         }
         SignalMessage::SessionReady(session_id, is_host) => {
             info!("peer received info that session is ready {:?}", session_id);
+            // peer might be rejoining the session, with a fresh peer connection if it reloaded its page
+            let peer_connection = if network_manager.on_session_ready() {
+                network_manager.replace_peer_connection()?
+            } else {
+                peer_connection
+            };
+            network_manager.inner.borrow_mut().disconnect_reported = false;
+            network_manager.inner.borrow_mut().is_host = is_host;
+            // channel opened in `start` is negotiated by the offer below or the answer to the peer's one
+            network_manager.inner.borrow_mut().negotiation_needed = false;
+            let metadata = network_manager.inner.borrow().metadata.clone();
+            if let Some(metadata) = metadata {
+                let signal_message = SignalMessage::PeerMetadata(session_id.clone(), metadata);
+                let signal_message = serialize_message(&signal_message)?;
+                websocket.send_with_str(&signal_message)?;
+            }
             if is_host {
                 let offer = create_sdp_offer(&peer_connection).await?;
                 let signal_message = SignalMessage::SdpOffer(session_id.clone(), offer);
-                let signal_message = serde_json_wasm::to_string(&signal_message)
-                    .expect("failed to serialize SignalMessage");
+                let signal_message = serialize_message(&signal_message)?;
                 websocket.send_with_str(&signal_message)?;
                 debug!("(is_host: {}) sent an offer successfully", is_host);
             }
         }
         SignalMessage::SdpOffer(session_id, offer) => {
-            let answer = create_sdp_answer(&peer_connection, offer)
-                .await
-                .expect("failed to create SDP answer");
+            // offer may renegotiate the established connection, e.g. to add a channel
+            let polite = !network_manager.inner.borrow().is_host;
+            match on_remote_offer(polite, peer_connection.signaling_state()) {
+                OfferAction::Answer => {}
+                OfferAction::RollbackAndAnswer => {
+                    info!("offers collided, dropping own offer in favor of the peer's");
+                    rollback_local_description(&peer_connection).await?;
+                    // dropped offer still needs negotiating once the peer's offer is answered
+                    let mut inner = network_manager.inner.borrow_mut();
+                    inner.negotiation_needed = true;
+                    inner.renegotiation_pending = true;
+                }
+                OfferAction::Ignore => {
+                    info!("offers collided, ignoring the peer's offer in favor of own one");
+                    return Ok(());
+                }
+            }
+            let answer = create_sdp_answer(&peer_connection, offer).await?;
             debug!("received an offer and created an answer: {}", answer);
             let signal_message = SignalMessage::SdpAnswer(session_id, answer);
-            let signal_message = serde_json_wasm::to_string(&signal_message)
-                .expect("failed to serialize SignalMessage");
-            websocket
-                .send_with_str(&signal_message)
-                .expect("failed to send SPD answer to signaling server");
+            let signal_message = serialize_message(&signal_message)?;
+            websocket.send_with_str(&signal_message)?;
+            add_held_ice_candidates(&network_manager, &peer_connection).await?;
+            network_manager.on_negotiation_finished().await?;
         }
         SignalMessage::SdpAnswer(session_id, answer) => {
-            let mut remote_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
-            remote_session_description.sdp(&answer);
-            JsFuture::from(peer_connection.set_remote_description(&remote_session_description))
-                .await
-                .expect("failed to set remote descripiton");
+            set_sdp_answer(&peer_connection, &answer).await?;
             debug!(
                 "received answer from peer and set remote description: {}, {:?}",
                 answer, session_id
             );
+            add_held_ice_candidates(&network_manager, &peer_connection).await?;
+            network_manager.on_negotiation_finished().await?;
+        }
+        SignalMessage::IceRestart(session_id) => {
+            if network_manager.inner.borrow().is_host {
+                info!("peer asked to restart ICE in session {:?}", session_id);
+                network_manager.restart_ice().await?;
+            } else {
+                error!("error, only the host should be asked to restart ICE");
+            }
         }
         SignalMessage::IceCandidate(_session_id, ice_candidate) => {
             debug!("peer received ice candidate: {}", &ice_candidate);
-            let ice_candidate = serde_json_wasm::from_str::<IceCandidate>(&ice_candidate)
-                .expect("failed to deserialize IceCandidate");
-
-            let mut rtc_candidate = RtcIceCandidateInit::new("");
-            rtc_candidate.candidate(&ice_candidate.candidate);
-            rtc_candidate.sdp_m_line_index(ice_candidate.sdp_m_line_index);
-            rtc_candidate.sdp_mid(ice_candidate.sdp_mid.as_deref());
-
-            let rtc_candidate =
-                RtcIceCandidate::new(&rtc_candidate).expect("failed to create new RtcIceCandidate");
-            JsFuture::from(
-                peer_connection.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&rtc_candidate)),
-            )
-            .await
-            .expect("failed to add ICE candidate");
-            debug!("added ice candidate {:?}", ice_candidate);
+            let ice_candidate = parse_ice_candidate(&ice_candidate)?;
+            if accepts_ice_candidates(&peer_connection) {
+                add_ice_candidates(&peer_connection, vec![ice_candidate]).await?;
+            } else {
+                debug!("holding ice candidate until remote description is set");
+                network_manager
+                    .inner
+                    .borrow_mut()
+                    .held_ice_candidates
+                    .push(ice_candidate);
+            }
+        }
+        SignalMessage::PeerLeft(session_id) => {
+            info!("other peer left session {:?}", session_id);
+            network_manager.on_disconnect(DisconnectReason::PeerLeft);
+        }
+        SignalMessage::PeerMetadata(_session_id, metadata) => {
+            debug!("peer received metadata of the other peer: {}", &metadata);
+            network_manager.on_peer_metadata(metadata);
+        }
+        SignalMessage::NegotiationTimeout(session_id) => {
+            error!("negotiation timed out in session {:?}", session_id);
+            network_manager.record_error("negotiation timed out".to_string());
+        }
+        SignalMessage::SameNetworkHint(session_id) => {
+            info!(
+                "other peer is likely on the same network in session {:?}",
+                session_id
+            );
+            network_manager.on_same_network_hint();
+        }
+        SignalMessage::ServerNotice(notice) => {
+            network_manager.on_server_notice(notice);
+        }
+        SignalMessage::Migrate(signaling_server_url) => {
+            info!(
+                "signaling server asked to migrate to {}",
+                signaling_server_url
+            );
+            network_manager.on_migrate(signaling_server_url).await?;
         }
         SignalMessage::Error(session_id, error) => {
             error!(
                 "signaling server returned error: session id: {:?}, error:{}",
                 session_id, error
             );
+            network_manager.record_error(error);
         }
     }
 
@@ -85,6 +156,16 @@ pub(crate) async fn handle_websocket_message(
 }
 
 // // TODO(tkarwowski): uncomment once mocks work
+
+async fn add_held_ice_candidates(
+    network_manager: &NetworkManager,
+    peer_connection: &RtcPeerConnection,
+) -> Result<(), JsValue> {
+    let held_ice_candidates =
+        std::mem::take(&mut network_manager.inner.borrow_mut().held_ice_candidates);
+    add_ice_candidates(peer_connection, held_ice_candidates).await
+}
+
 // #[cfg(test)]
 // mod test {
 //     use super::*;
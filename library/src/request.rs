@@ -0,0 +1,270 @@
+/*!
+Helper for request/response exchanges over a data channel.
+
+Data channel messages are fire-and-forget, so asking the other peer for something and waiting
+for its answer requires matching them up. [`RequestChannel::request`] tags an outgoing request
+with a correlation id and resolves once the response with the same id arrives, or fails after
+a timeout. The other peer answers automatically with the handler set by
+[`RequestChannel::set_on_request`], so both peers need to pass received messages
+through [`RequestChannel::handle_message`].
+
+Requests and responses are [helper messages](crate::control),
+other messages are passed through unchanged.
+
+# Example
+
+```no_run
+use wasm_peers::one_to_one::NetworkManager;
+use wasm_peers::request::RequestChannel;
+use wasm_peers::{ConnectionType, SessionId};
+
+let mut network_manager = NetworkManager::new(
+    "ws://0.0.0.0:9001/one-to-one",
+    SessionId::new("some-session-id".to_string()),
+    ConnectionType::Local,
+)
+.unwrap();
+let network_manager_clone = network_manager.clone();
+let requests = RequestChannel::new(move |message| network_manager_clone.send_message(message));
+requests.set_on_request(|question| format!("you asked: {}", question));
+let requests_clone = requests.clone();
+network_manager.set_on_disconnect(move |_reason| requests_clone.fail_pending());
+let requests_clone = requests.clone();
+let on_open = move || {
+    let requests = requests_clone.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        let answer = requests.request("current score?", 5000).await;
+        log::info!("peer answered: {:?}", answer);
+    });
+};
+let requests_clone = requests.clone();
+let on_message = move |message: String| {
+    if let Ok(Some(message)) = requests_clone.handle_message(&message) {
+        // handle regular message
+    }
+};
+network_manager.start(on_open, on_message).unwrap();
+```
+*/
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use js_sys::{Function, Promise};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::callback::Callback;
+use crate::control::{self, Kind};
+use crate::utils::global_function;
+
+type SendFunction = Rc<dyn Fn(&str) -> Result<(), JsValue>>;
+type RequestHandler = Callback<dyn FnMut(String) -> String>;
+
+struct RequestChannelInner {
+    send: SendFunction,
+    next_id: u64,
+    /// `resolve` and `reject` functions of the promise returned for each pending request.
+    pending: HashMap<u64, (Function, Function)>,
+    on_request: Option<RequestHandler>,
+}
+
+/// Sends requests and answers the ones sent by the other peer, using provided send function.
+///
+/// This class is a pointer to the underlying resource and can be cloned freely.
+#[derive(Clone)]
+pub struct RequestChannel {
+    inner: Rc<RefCell<RequestChannelInner>>,
+}
+
+impl RequestChannel {
+    /// Creates a channel sending its messages with given function,
+    /// e.g. [`crate::one_to_one::NetworkManager::send_message`].
+    pub fn new(send: impl Fn(&str) -> Result<(), JsValue> + 'static) -> Self {
+        RequestChannel {
+            inner: Rc::new(RefCell::new(RequestChannelInner {
+                send: Rc::new(send),
+                next_id: 0,
+                pending: HashMap::new(),
+                on_request: None,
+            })),
+        }
+    }
+
+    /// Sets the handler producing responses to requests of the other peer.
+    /// Requests received without a handler are answered with an empty response.
+    pub fn set_on_request(&self, on_request: impl FnMut(String) -> String + 'static) {
+        self.inner.borrow_mut().on_request = Some(RequestHandler::new(on_request));
+    }
+
+    /// Sends the request and waits for the response.
+    ///
+    /// # Errors
+    /// This function errors if sending fails, if no response arrives within `timeout_ms` milliseconds,
+    /// or if pending requests are failed with [`RequestChannel::fail_pending`].
+    pub async fn request(&self, body: &str, timeout_ms: u32) -> Result<String, JsValue> {
+        let id = {
+            let mut inner = self.inner.borrow_mut();
+            inner.next_id += 1;
+            inner.next_id
+        };
+        let mut callbacks = None;
+        let promise = Promise::new(&mut |resolve, reject| callbacks = Some((resolve, reject)));
+        let (resolve, reject) =
+            callbacks.ok_or_else(|| JsValue::from_str("promise executor wasn't called"))?;
+        self.inner
+            .borrow_mut()
+            .pending
+            .insert(id, (resolve, reject.clone()));
+
+        let on_timeout = reject.bind1(&JsValue::NULL, &JsValue::from_str("request timed out"));
+        let timeout = global_function("setTimeout").and_then(|set_timeout| {
+            set_timeout.call2(&JsValue::NULL, &on_timeout, &JsValue::from(timeout_ms))
+        });
+        let result = match &timeout {
+            Ok(_) => match self.send_request(id, body) {
+                Ok(()) => JsFuture::from(promise).await,
+                Err(error) => Err(error),
+            },
+            Err(error) => Err(error.clone()),
+        };
+        if let Ok(timeout) = timeout {
+            // answered requests don't keep the timer around until it fires
+            let _ = global_function("clearTimeout")
+                .and_then(|clear_timeout| clear_timeout.call1(&JsValue::NULL, &timeout));
+        }
+        self.inner.borrow_mut().pending.remove(&id);
+        result.map(|response| response.as_string().unwrap_or_default())
+    }
+
+    fn send_request(&self, id: u64, body: &str) -> Result<(), JsValue> {
+        let send = self.inner.borrow().send.clone();
+        send(&control::encode(Kind::Request, &format!("{}:{}", id, body)))
+    }
+
+    /// Handles requests and responses, returns other messages to be processed by the application.
+    ///
+    /// # Errors
+    /// This function errors if the message is a malformed request or response,
+    /// or if sending the response fails.
+    pub fn handle_message(&self, message: &str) -> Result<Option<String>, JsValue> {
+        match control::decode(message) {
+            Some((Kind::Request, request)) => {
+                let (id, body) = split_id(request)?;
+                let response = match Callback::of(&self.inner, |inner| &inner.on_request) {
+                    Some(on_request) => on_request.call(body.to_string()),
+                    None => String::new(),
+                };
+                let send = self.inner.borrow().send.clone();
+                send(&control::encode(
+                    Kind::Response,
+                    &format!("{}:{}", id, response),
+                ))?;
+                Ok(None)
+            }
+            Some((Kind::Response, response)) => {
+                let (id, body) = split_id(response)?;
+                let pending = self.inner.borrow_mut().pending.remove(&id);
+                match pending {
+                    Some((resolve, _reject)) => {
+                        resolve.call1(&JsValue::NULL, &JsValue::from_str(body))?;
+                    }
+                    None => log::info!("response to unknown or timed out request: {}", id),
+                }
+                Ok(None)
+            }
+            _ => Ok(Some(message.to_string())),
+        }
+    }
+
+    /// Number of requests still waiting for a response.
+    pub fn pending_count(&self) -> usize {
+        self.inner.borrow().pending.len()
+    }
+
+    /// Fails all pending requests, e.g. when the other peer leaves.
+    pub fn fail_pending(&self) {
+        let pending = std::mem::take(&mut self.inner.borrow_mut().pending);
+        for (_resolve, reject) in pending.into_values() {
+            let _ = reject.call1(&JsValue::NULL, &JsValue::from_str("peer went away"));
+        }
+    }
+}
+
+fn split_id(message: &str) -> Result<(u64, &str), JsValue> {
+    let invalid_message = || JsValue::from_str("malformed request or response");
+    let (id, body) = message.split_once(':').ok_or_else(invalid_message)?;
+    let id = id.parse().map_err(|_| invalid_message())?;
+    Ok((id, body))
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    fn recording_channel() -> (RequestChannel, Rc<RefCell<Vec<String>>>) {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let sent_clone = sent.clone();
+        let channel = RequestChannel::new(move |message| {
+            sent_clone.borrow_mut().push(message.to_string());
+            Ok(())
+        });
+        (channel, sent)
+    }
+
+    #[wasm_bindgen_test]
+    fn test_regular_messages_are_passed_through() {
+        let (channel, sent) = recording_channel();
+
+        assert_eq!(
+            channel.handle_message("hello").unwrap().as_deref(),
+            Some("hello")
+        );
+        assert!(sent.borrow().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_request_is_answered_with_same_id() {
+        let (channel, sent) = recording_channel();
+        channel.set_on_request(|body| body.to_uppercase());
+
+        let handled = channel.handle_message("\u{1}q7:ping").unwrap();
+
+        assert_eq!(handled, None);
+        assert_eq!(*sent.borrow(), vec!["\u{1}r7:PING".to_string()]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_request_resolves_with_response() {
+        let (requester, requester_sent) = recording_channel();
+        let (responder, responder_sent) = recording_channel();
+        responder.set_on_request(|body| format!("{} pong", body));
+
+        let request = requester.request("ping", 1000);
+        let requester_clone = requester.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let request = requester_sent.borrow()[0].clone();
+            responder.handle_message(&request).unwrap();
+            let response = responder_sent.borrow()[0].clone();
+            requester_clone.handle_message(&response).unwrap();
+        });
+
+        assert_eq!(request.await.unwrap(), "ping pong");
+        assert_eq!(requester.pending_count(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_pending_requests_fail_when_peer_goes_away() {
+        let (channel, _sent) = recording_channel();
+
+        let request = channel.request("ping", 1000);
+        let channel_clone = channel.clone();
+        wasm_bindgen_futures::spawn_local(async move { channel_clone.fail_pending() });
+
+        assert!(request.await.is_err());
+        assert_eq!(channel.pending_count(), 0);
+    }
+}
@@ -0,0 +1,371 @@
+/*!
+Byte stream over a data channel, for code written against sockets.
+
+[`ByteStream::write`] splits written bytes into messages of at most [`CHUNK_SIZE`] bytes
+and [`ByteStream::read`] hands them out in the order they were written, so message boundaries
+don't matter to either side, same as with `TCP`. Writing pauses while too much data waits to be sent,
+as reported by the function provided to [`ByteStream::new`], so a fast writer doesn't end up
+buffering everything in memory. Received bytes are buffered until read, the other peer isn't slowed down
+by a slow reader. [`ByteStream::close`] ends the stream, after which the other peer reads
+the remaining bytes and then `None`, like end of file. Both peers need to pass received messages
+through [`ByteStream::handle_message`].
+
+Each stream has an id, and messages of streams with other ids are passed through unchanged,
+so several streams can share one data channel by passing messages through each of them in turn.
+Streams are sent as [helper messages](crate::control) encoded in base64.
+
+# Delivery guarantees
+
+Each message carries a sequence number, so what the stream guarantees depends on the data channel:
+- reliable and ordered channel, which is the default: bytes are read exactly once and in order,
+  as long as the connection stays up,
+- unordered channel, or one with limited retransmits or lifetime: bytes read are never reordered
+  or duplicated, but as soon as a message is lost or arrives out of order, the stream fails
+  and [`ByteStream::read`] returns an error, instead of handing out the bytes around the gap.
+
+Either way, [`ByteStream::write`] resolving only means that the bytes were handed over
+to the send function. Bytes in flight when the connection drops are lost and never resent,
+so across a disconnection delivery is at most once, call [`ByteStream::reset`] then and start a new stream.
+
+# Example
+
+```no_run
+use wasm_peers::one_to_one::NetworkManager;
+use wasm_peers::stream::ByteStream;
+use wasm_peers::{ConnectionType, SessionId};
+
+let mut network_manager = NetworkManager::new(
+    "ws://0.0.0.0:9001/one-to-one",
+    SessionId::new("some-session-id".to_string()),
+    ConnectionType::Local,
+)
+.unwrap();
+let network_manager_clone = network_manager.clone();
+let network_manager_pending = network_manager.clone();
+let stream = ByteStream::new(
+    1,
+    move |message| network_manager_clone.send_message(message),
+    move || {
+        network_manager_pending.queued_amount()
+            + network_manager_pending.buffered_amount().unwrap_or(0) as usize
+    },
+);
+let stream_clone = stream.clone();
+network_manager.set_on_disconnect(move |_reason| stream_clone.reset("peer went away"));
+let stream_clone = stream.clone();
+let on_open = move || {
+    let stream = stream_clone.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        stream.write(b"hello").await.unwrap();
+        while let Ok(Some(data)) = stream.read(1024).await {
+            log::info!("read {} bytes", data.len());
+        }
+    });
+};
+let stream_clone = stream.clone();
+let on_message = move |message: String| {
+    if let Ok(Some(message)) = stream_clone.handle_message(&message) {
+        // handle regular message
+    }
+};
+network_manager.start(on_open, on_message).unwrap();
+```
+*/
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use js_sys::{Function, Promise};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::control::{self, Kind};
+use crate::file_transfer::{decode_base64, encode_base64};
+use crate::utils::timeout_promise;
+
+/// Maximum number of written bytes sent in a single message,
+/// small enough to stay below message size limits of all browsers.
+pub const CHUNK_SIZE: usize = 16 * 1024;
+/// Writing pauses while more than this many bytes wait to be sent.
+pub const MAX_PENDING_BYTES: usize = 1024 * 1024;
+/// How often writing checks whether the pending data drained.
+const PENDING_POLL_INTERVAL_MS: u32 = 20;
+
+type SendFunction = Rc<dyn Fn(&str) -> Result<(), JsValue>>;
+type PendingFunction = Rc<dyn Fn() -> usize>;
+
+struct ByteStreamInner {
+    id: u32,
+    send: SendFunction,
+    pending: PendingFunction,
+    next_sent: u64,
+    next_received: u64,
+    /// Received bytes that weren't read yet.
+    received: VecDeque<u8>,
+    closed: bool,
+    /// Other peer closed the stream.
+    ended: bool,
+    failure: Option<String>,
+    /// Resolves the promise a pending read waits on.
+    waiting_read: Option<Function>,
+}
+
+/// Reliable ordered stream of bytes to the other peer, using provided send function.
+///
+/// This class is a pointer to the underlying resource and can be cloned freely.
+#[derive(Clone)]
+pub struct ByteStream {
+    inner: Rc<RefCell<ByteStreamInner>>,
+}
+
+impl ByteStream {
+    /// Creates a stream with given id, which the other peer's stream has to use too,
+    /// sending its messages with given function, e.g. [`crate::one_to_one::NetworkManager::send_message`].
+    /// `pending` returns the number of bytes sent, but still waiting to be sent over the network,
+    /// e.g. sum of [`crate::one_to_one::NetworkManager::queued_amount`]
+    /// and [`crate::one_to_one::NetworkManager::buffered_amount`].
+    pub fn new(
+        id: u32,
+        send: impl Fn(&str) -> Result<(), JsValue> + 'static,
+        pending: impl Fn() -> usize + 'static,
+    ) -> Self {
+        ByteStream {
+            inner: Rc::new(RefCell::new(ByteStreamInner {
+                id,
+                send: Rc::new(send),
+                pending: Rc::new(pending),
+                next_sent: 0,
+                next_received: 0,
+                received: VecDeque::new(),
+                closed: false,
+                ended: false,
+                failure: None,
+                waiting_read: None,
+            })),
+        }
+    }
+
+    /// Writes all of the data, resolving once the last of it is handed over to the send function.
+    ///
+    /// # Errors
+    /// This function errors if sending fails, or if the stream was closed, reset or failed.
+    pub async fn write(&self, data: &[u8]) -> Result<(), JsValue> {
+        for chunk in data.chunks(CHUNK_SIZE) {
+            self.wait_for_pending().await?;
+            let (send, message) = {
+                let mut inner = self.inner.borrow_mut();
+                let message = control::encode(
+                    Kind::StreamData,
+                    &format!("{}:{}:{}", inner.id, inner.next_sent, encode_base64(chunk)),
+                );
+                inner.next_sent += 1;
+                (inner.send.clone(), message)
+            };
+            send(&message)?;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_pending(&self) -> Result<(), JsValue> {
+        loop {
+            let pending = {
+                let inner = self.inner.borrow();
+                if let Some(failure) = &inner.failure {
+                    return Err(JsValue::from_str(failure));
+                }
+                if inner.closed {
+                    return Err(JsValue::from_str("stream is closed"));
+                }
+                inner.pending.clone()
+            };
+            if pending() <= MAX_PENDING_BYTES {
+                return Ok(());
+            }
+            JsFuture::from(timeout_promise(PENDING_POLL_INTERVAL_MS)).await?;
+        }
+    }
+
+    /// Ends the stream, the other peer reads the bytes written so far and then `None`.
+    /// Bytes written by the other peer can still be read.
+    ///
+    /// # Errors
+    /// This function errors if sending fails.
+    pub fn close(&self) -> Result<(), JsValue> {
+        let (send, message) = {
+            let mut inner = self.inner.borrow_mut();
+            if inner.closed {
+                return Ok(());
+            }
+            inner.closed = true;
+            let message = control::encode(
+                Kind::StreamEnd,
+                &format!("{}:{}", inner.id, inner.next_sent),
+            );
+            (inner.send.clone(), message)
+        };
+        send(&message)
+    }
+
+    /// Waits until some bytes arrive and returns at most `max_length` of them,
+    /// or `None` once the other peer closed the stream and all bytes were read.
+    ///
+    /// # Errors
+    /// This function errors if the stream failed or was reset, see [`crate::stream`].
+    pub async fn read(&self, max_length: usize) -> Result<Option<Vec<u8>>, JsValue> {
+        loop {
+            let promise = {
+                let mut inner = self.inner.borrow_mut();
+                if let Some(failure) = &inner.failure {
+                    return Err(JsValue::from_str(failure));
+                }
+                if !inner.received.is_empty() {
+                    let length = max_length.min(inner.received.len());
+                    return Ok(Some(inner.received.drain(..length).collect()));
+                }
+                if inner.ended {
+                    return Ok(None);
+                }
+                let mut waiting_read = None;
+                let promise = Promise::new(&mut |resolve, _reject| waiting_read = Some(resolve));
+                inner.waiting_read = waiting_read;
+                promise
+            };
+            JsFuture::from(promise).await?;
+        }
+    }
+
+    /// Number of received bytes that weren't read yet.
+    pub fn readable_amount(&self) -> usize {
+        self.inner.borrow().received.len()
+    }
+
+    /// Handles messages of this stream, returns other messages to be processed by the application
+    /// or by other streams.
+    ///
+    /// # Errors
+    /// This function errors if the message of this stream is malformed or out of sequence,
+    /// in which case the stream fails.
+    pub fn handle_message(&self, message: &str) -> Result<Option<String>, JsValue> {
+        let (body, end) = match control::decode(message) {
+            Some((Kind::StreamData, body)) => (body, false),
+            Some((Kind::StreamEnd, body)) => (body, true),
+            _ => return Ok(Some(message.to_string())),
+        };
+        let mut parts = body.splitn(3, ':');
+        let id = parts.next().and_then(|id| id.parse::<u32>().ok());
+        if id != Some(self.inner.borrow().id) {
+            return Ok(Some(message.to_string()));
+        }
+        let result = self.receive(parts.next(), parts.next(), end);
+        if let Err(error) = &result {
+            self.fail(error.as_string().unwrap_or_default());
+        }
+        self.wake_read();
+        result.map(|()| None)
+    }
+
+    fn receive(
+        &self,
+        sequence: Option<&str>,
+        data: Option<&str>,
+        end: bool,
+    ) -> Result<(), JsValue> {
+        let invalid_message = || JsValue::from_str("malformed stream message");
+        let sequence = sequence
+            .and_then(|sequence| sequence.parse::<u64>().ok())
+            .ok_or_else(invalid_message)?;
+        let mut inner = self.inner.borrow_mut();
+        if inner.failure.is_some() || inner.ended {
+            return Ok(());
+        }
+        if sequence != inner.next_received {
+            return Err(JsValue::from_str(
+                "stream message lost or out of order, data channel must be reliable and ordered",
+            ));
+        }
+        if end {
+            inner.ended = true;
+        } else {
+            let data = data.and_then(decode_base64).ok_or_else(invalid_message)?;
+            inner.received.extend(data);
+            inner.next_received += 1;
+        }
+        Ok(())
+    }
+
+    /// Fails the stream, e.g. when the other peer leaves. Pending and later reads and writes
+    /// return an error with given reason, bytes not read yet are dropped.
+    pub fn reset(&self, reason: &str) {
+        self.fail(reason.to_string());
+        self.wake_read();
+    }
+
+    fn fail(&self, reason: String) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.failure.is_none() {
+            inner.failure = Some(reason);
+            inner.received.clear();
+        }
+    }
+
+    fn wake_read(&self) {
+        let waiting_read = self.inner.borrow_mut().waiting_read.take();
+        if let Some(waiting_read) = waiting_read {
+            let _ = waiting_read.call0(&JsValue::NULL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    fn connected_pair() -> (ByteStream, ByteStream) {
+        let receiver = ByteStream::new(1, |_| Ok(()), || 0);
+        let receiver_clone = receiver.clone();
+        let sender = ByteStream::new(
+            1,
+            move |message| receiver_clone.handle_message(message).map(|_| ()),
+            || 0,
+        );
+        (sender, receiver)
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_bytes_are_read_in_order_until_end_of_stream() {
+        let (sender, receiver) = connected_pair();
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE + 7).map(|i| (i % 251) as u8).collect();
+
+        sender.write(&data).await.unwrap();
+        sender.close().unwrap();
+        assert!(sender.write(b"late").await.is_err());
+
+        let mut read = Vec::new();
+        while let Some(chunk) = receiver.read(1000).await.unwrap() {
+            assert!(chunk.len() <= 1000);
+            read.extend(chunk);
+        }
+        assert_eq!(read, data);
+        assert_eq!(receiver.readable_amount(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_lost_message_fails_the_stream() {
+        let receiver = ByteStream::new(1, |_| Ok(()), || 0);
+
+        assert_eq!(
+            receiver
+                .handle_message("\u{1}s2:0:YWI=")
+                .unwrap()
+                .as_deref(),
+            Some("\u{1}s2:0:YWI=")
+        );
+        receiver.handle_message("\u{1}s1:0:YWI=").unwrap();
+        assert!(receiver.handle_message("\u{1}s1:2:YWI=").is_err());
+
+        assert!(receiver.read(10).await.is_err());
+    }
+}
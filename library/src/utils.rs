@@ -1,9 +1,27 @@
-use js_sys::{Array, Object, Reflect};
-use serde::{Deserialize, Serialize};
-use wasm_bindgen::JsValue;
+#[cfg(feature = "one-to-one")]
+use js_sys::Map;
+use js_sys::{Array, Function, Object, Promise, Reflect};
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+use log::{debug, info};
+use serde::{Deserialize, Serialize, Serializer};
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{RtcConfiguration, RtcPeerConnection, RtcSdpType, RtcSessionDescriptionInit};
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+use web_sys::{
+    BinaryType, RtcDataChannel, RtcDataChannelInit, RtcIceCandidate, RtcIceCandidateInit, WebSocket,
+};
+use web_sys::{
+    RtcConfiguration, RtcDataChannelState, RtcPeerConnection, RtcSdpType, RtcSessionDescriptionInit,
+};
+#[cfg(feature = "one-to-one")]
+use web_sys::{RtcIceTransportPolicy, RtcOfferOptions};
 
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+use crate::Error;
+
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct IceCandidate {
     pub candidate: String,
@@ -27,25 +45,327 @@ pub enum ConnectionType {
     },
 }
 
+/// Sequence of connection types that are tried one after another,
+/// until one of them manages to open a data channel.
+///
+/// Useful on restrictive networks, where e.g. STUN-only setup fails
+/// and a connection can only be made with TURN over TCP or TLS.
+#[derive(Debug, Clone)]
+pub struct ConnectionFallbackPolicy {
+    /// Connection types in order in which they will be tried.
+    pub connection_types: Vec<ConnectionType>,
+    /// Overall time in milliseconds for all attempts,
+    /// the remaining time is split evenly between the remaining attempts.
+    pub timeout_ms: u32,
+}
+
+/// Options of `ICE` candidate gathering, see [`crate::one_to_one::NetworkManager::set_ice_options`].
+///
+/// Transport policy and candidate pool size are part of `RTCConfiguration` and are enforced by the browser.
+/// The other options are best-effort, as browsers don't let pages change what they gather:
+/// they only filter candidates trickled to the other peer, and candidates the browser
+/// includes in `SDP` itself still get through.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IceOptions {
+    /// Only use candidates relayed through a `TURN` server, so peers never learn each other's addresses.
+    pub relay_only: bool,
+    /// Number of candidates gathered up front, before an offer is created. Browser's default if `None`.
+    pub candidate_pool_size: Option<u8>,
+    /// Candidate types, e.g. `host` or `srflx`, that aren't sent to the other peer.
+    pub ignored_candidate_types: Vec<String>,
+    /// Don't send IPv6 candidates to the other peer.
+    pub ipv4_only: bool,
+    /// Don't send candidates with mDNS (`.local`) addresses, which browsers use to hide local IP addresses.
+    pub ignore_mdns: bool,
+    /// Don't send candidates gathered later than this many milliseconds after the first one,
+    /// e.g. slow `TURN` candidates when the connection should use whatever was gathered quickly.
+    pub gathering_timeout_ms: Option<u32>,
+}
+
+impl IceOptions {
+    /// Returns `false` if the candidate should not be sent according to the filtering options.
+    /// `gathering_timeout_ms` isn't checked, as it depends on when the candidate was gathered.
+    #[cfg(feature = "one-to-one")]
+    pub(crate) fn allows_candidate(&self, candidate: &str) -> bool {
+        // e.g. `candidate:1 1 udp 2113937151 192.168.1.2 52345 typ host`
+        let address = candidate.split_whitespace().nth(4).unwrap_or_default();
+        let candidate_type = candidate_type(candidate).unwrap_or_default();
+        !(self
+            .ignored_candidate_types
+            .iter()
+            .any(|ignored| ignored == candidate_type)
+            || self.ipv4_only && address.contains(':')
+            || self.ignore_mdns && address.ends_with(".local"))
+    }
+}
+
+/// Returns type of the candidate, e.g. `host` or `srflx`, `None` if it isn't a valid candidate.
+pub(crate) fn candidate_type(candidate: &str) -> Option<&str> {
+    let mut fields = candidate.split_whitespace();
+    fields.find(|field| *field == "typ")?;
+    fields.next()
+}
+
+/// Applies the options that are part of `RTCConfiguration`, keeping the rest of the configuration.
+#[cfg(feature = "one-to-one")]
+pub(crate) fn apply_ice_options(
+    peer_connection: &RtcPeerConnection,
+    ice_options: &IceOptions,
+) -> Result<(), JsValue> {
+    let configuration = peer_connection.get_configuration();
+    configuration.set_ice_transport_policy(if ice_options.relay_only {
+        RtcIceTransportPolicy::Relay
+    } else {
+        RtcIceTransportPolicy::All
+    });
+    // web_sys doesn't expose `iceCandidatePoolSize`
+    if let Some(candidate_pool_size) = ice_options.candidate_pool_size {
+        Reflect::set(
+            &configuration,
+            &JsValue::from_str("iceCandidatePoolSize"),
+            &JsValue::from(candidate_pool_size),
+        )?;
+    }
+    peer_connection.set_configuration_with_configuration(&configuration)
+}
+
+/// Maximum length in bytes of the data channel sub-protocol,
+/// as limited by the 16-bit length field of `DATA_CHANNEL_OPEN` message (RFC 8832).
+const MAX_DATA_CHANNEL_PROTOCOL_LENGTH: usize = u16::MAX as usize;
+
+/// Options used when creating a `RtcDataChannel`.
+///
+/// Only the peer that creates the data channel decides on these,
+/// the other peer can read them from the channel it receives.
+#[derive(Debug, Clone, Default)]
+pub struct DataChannelConfig {
+    /// Sub-protocol name announced to the other peer, useful when communicating
+    /// with other `WebRTC` implementations that key their behavior on it.
+    pub protocol: Option<String>,
+    /// Scheduling hint for when several channels compete for bandwidth. It's only a hint,
+    /// browsers may ignore it, and messages on the channel are delivered the same either way.
+    pub priority: Option<DataChannelPriority>,
+}
+
+/// Allowed values of data channel `priority`, see [`DataChannelConfig::priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataChannelPriority {
+    VeryLow,
+    Low,
+    Medium,
+    High,
+}
+
+impl DataChannelPriority {
+    /// Name browsers use for the priority, e.g. `"very-low"`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DataChannelPriority::VeryLow => "very-low",
+            DataChannelPriority::Low => "low",
+            DataChannelPriority::Medium => "medium",
+            DataChannelPriority::High => "high",
+        }
+    }
+}
+
+impl std::str::FromStr for DataChannelPriority {
+    type Err = JsValue;
+
+    fn from_str(priority: &str) -> Result<Self, Self::Err> {
+        match priority {
+            "very-low" => Ok(DataChannelPriority::VeryLow),
+            "low" => Ok(DataChannelPriority::Low),
+            "medium" => Ok(DataChannelPriority::Medium),
+            "high" => Ok(DataChannelPriority::High),
+            _ => Err(JsValue::from_str(&format!(
+                "invalid data channel priority: {:?}, expected one of \"very-low\", \"low\", \"medium\" or \"high\"",
+                priority
+            ))),
+        }
+    }
+}
+
+impl DataChannelConfig {
+    pub(crate) fn validate(&self) -> Result<(), JsValue> {
+        if let Some(protocol) = &self.protocol {
+            if protocol.len() > MAX_DATA_CHANNEL_PROTOCOL_LENGTH {
+                return Err(JsValue::from_str(&format!(
+                    "data channel protocol is too long: {} bytes, maximum is {}",
+                    protocol.len(),
+                    MAX_DATA_CHANNEL_PROTOCOL_LENGTH
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+pub(crate) fn create_data_channel(
+    peer_connection: &RtcPeerConnection,
+    label: &str,
+    data_channel_config: &DataChannelConfig,
+) -> RtcDataChannel {
+    let data_channel_init = RtcDataChannelInit::new();
+    if let Some(protocol) = &data_channel_config.protocol {
+        data_channel_init.set_protocol(protocol);
+    }
+    if let Some(priority) = data_channel_config.priority {
+        // `web_sys` doesn't expose `priority` member, set it like browsers that support it expect
+        let _ = Reflect::set(
+            &data_channel_init,
+            &JsValue::from_str("priority"),
+            &JsValue::from_str(priority.as_str()),
+        );
+    }
+    peer_connection.create_data_channel_with_data_channel_dict(label, &data_channel_init)
+}
+
+/// Snapshot of a data channel's state, for debugging and UI.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChannelInfo {
+    /// Label the channel was created with.
+    pub label: String,
+    /// Sub-protocol the channel was created with, empty if none.
+    pub protocol: String,
+    /// Whether the channel is connecting, open, closing or closed.
+    #[serde(serialize_with = "serialize_js_enum")]
+    pub ready_state: RtcDataChannelState,
+    /// Whether messages are guaranteed to arrive in order.
+    pub ordered: bool,
+    /// Number of bytes the browser has buffered on the channel, but not yet sent.
+    pub buffered_amount: u32,
+}
+
+impl ChannelInfo {
+    #[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+    pub(crate) fn of(data_channel: &RtcDataChannel) -> Self {
+        ChannelInfo {
+            label: data_channel.label(),
+            protocol: get_data_channel_protocol(data_channel).unwrap_or_default(),
+            ready_state: data_channel.ready_state(),
+            // `web_sys` doesn't expose `ordered` attribute either, channels are ordered by default
+            ordered: Reflect::get(data_channel, &JsValue::from_str("ordered"))
+                .ok()
+                .and_then(|ordered| ordered.as_bool())
+                .unwrap_or(true),
+            buffered_amount: data_channel.buffered_amount(),
+        }
+    }
+}
+
+/// Serializes `web_sys` enums as the strings browser uses for them, e.g. `"open"`.
+fn serialize_js_enum<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Copy + Into<JsValue>,
+    S: Serializer,
+{
+    serializer.serialize_str(&js_enum_name(*value))
+}
+
+pub(crate) fn js_enum_name(value: impl Into<JsValue>) -> String {
+    value.into().as_string().unwrap_or_default()
+}
+
+/// Snapshot of everything known about a connection, to be logged or attached to a bug report
+/// when it doesn't work, see [`crate::one_to_one::NetworkManager::diagnostics`].
+/// Serializes to `JSON` like any other [`serde`] structure.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostics {
+    /// State of the connection to the signaling server: `connecting`, `open`, `closing` or `closed`.
+    pub signaling_state: String,
+    /// `connectionState` of the peer connection, e.g. `connected` or `failed`.
+    pub connection_state: String,
+    /// `iceConnectionState` of the peer connection, e.g. `checking` or `disconnected`.
+    pub ice_connection_state: String,
+    /// `iceGatheringState` of the peer connection: `new`, `gathering` or `complete`.
+    pub ice_gathering_state: String,
+    /// Candidates `ICE` selected, `None` if it didn't select any yet or reading connection stats failed.
+    pub selected_candidate_pair: Option<SelectedCandidatePair>,
+    /// Data channels of the connection, with the amount of data the browser buffers on each.
+    pub channels: Vec<ChannelInfo>,
+    /// Number of bytes held in the crate's outbound queue.
+    pub queued_amount: usize,
+    /// Number of received messages held while receiving is paused.
+    pub paused_message_count: usize,
+    /// Last error reported by the signaling server or hit while handling its messages.
+    pub last_error: Option<String>,
+}
+
+#[cfg(feature = "one-to-one")]
+pub(crate) fn websocket_state_name(websocket: &WebSocket) -> String {
+    match websocket.ready_state() {
+        WebSocket::CONNECTING => "connecting",
+        WebSocket::OPEN => "open",
+        WebSocket::CLOSING => "closing",
+        _ => "closed",
+    }
+    .to_string()
+}
+
+/// `web_sys` doesn't expose `protocol` attribute of `RtcDataChannel`, so it's read via reflection.
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+pub(crate) fn get_data_channel_protocol(data_channel: &RtcDataChannel) -> Result<String, JsValue> {
+    Ok(Reflect::get(data_channel, &JsValue::from_str("protocol"))?
+        .as_string()
+        .unwrap_or_default())
+}
+
+/// `web_sys` doesn't expose `sctp` attribute of `RtcPeerConnection`, so it's read via reflection.
+/// Returns `None` until the `SCTP` transport is negotiated, or if message size isn't limited.
+#[cfg(feature = "one-to-one")]
+pub(crate) fn get_max_message_size(peer_connection: &RtcPeerConnection) -> Option<usize> {
+    let sctp = Reflect::get(peer_connection, &JsValue::from_str("sctp"))
+        .ok()
+        .filter(|sctp| !sctp.is_null() && !sctp.is_undefined())?;
+    let max_message_size = Reflect::get(&sctp, &JsValue::from_str("maxMessageSize"))
+        .ok()?
+        .as_f64()?;
+    // unlimited size is reported as infinity
+    max_message_size
+        .is_finite()
+        .then_some(max_message_size as usize)
+}
+
 pub(crate) fn create_peer_connection(
     connection_type: &ConnectionType,
 ) -> Result<RtcPeerConnection, JsValue> {
     match connection_type {
         ConnectionType::Local => RtcPeerConnection::new(),
-        ConnectionType::Stun { urls } => {
-            let ice_servers = Array::new();
-            {
-                let server_entry = Object::new();
+        ConnectionType::Stun { .. } | ConnectionType::StunAndTurn { .. } => {
+            let ice_servers = ice_servers(connection_type)?;
+            let rtc_configuration = RtcConfiguration::new();
+            rtc_configuration.set_ice_servers(&ice_servers);
 
-                Reflect::set(&server_entry, &"urls".into(), &urls.into())?;
+            RtcPeerConnection::new_with_configuration(&rtc_configuration)
+        }
+    }
+}
 
-                ice_servers.push(&server_entry);
-            }
+/// Replaces `ICE` servers of the connection with the ones of given connection type,
+/// keeping the rest of the configuration, e.g. to renew expiring `TURN` credentials.
+#[cfg(feature = "one-to-one")]
+pub(crate) fn set_ice_servers(
+    peer_connection: &RtcPeerConnection,
+    connection_type: &ConnectionType,
+) -> Result<(), JsValue> {
+    let ice_servers = ice_servers(connection_type)?;
+    let configuration = peer_connection.get_configuration();
+    configuration.set_ice_servers(&ice_servers);
+    peer_connection.set_configuration_with_configuration(&configuration)
+}
 
-            let mut rtc_configuration = RtcConfiguration::new();
-            rtc_configuration.ice_servers(&ice_servers);
+fn ice_servers(connection_type: &ConnectionType) -> Result<Array, JsValue> {
+    let ice_servers = Array::new();
+    match connection_type {
+        ConnectionType::Local => {}
+        ConnectionType::Stun { urls } => {
+            let server_entry = Object::new();
 
-            RtcPeerConnection::new_with_configuration(&rtc_configuration)
+            Reflect::set(&server_entry, &"urls".into(), &urls.into())?;
+
+            ice_servers.push(&server_entry);
         }
         ConnectionType::StunAndTurn {
             stun_urls,
@@ -53,7 +373,6 @@ pub(crate) fn create_peer_connection(
             username,
             credential,
         } => {
-            let ice_servers = Array::new();
             {
                 let stun_server_entry = Object::new();
 
@@ -70,31 +389,267 @@ pub(crate) fn create_peer_connection(
 
                 ice_servers.push(&turn_server_entry);
             }
+        }
+    }
+    Ok(ice_servers)
+}
 
-            let mut rtc_configuration = RtcConfiguration::new();
-            rtc_configuration.ice_servers(&ice_servers);
+/// Writes a signaling message to send to the signaling server.
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+pub(crate) fn serialize_message<T: Serialize>(message: &T) -> Result<String, Error> {
+    serde_json_wasm::to_string(message).map_err(|error| Error::Serialization(error.to_string()))
+}
 
-            RtcPeerConnection::new_with_configuration(&rtc_configuration)
+/// Opens a websocket to the signaling server, offering the protocol version of this crate,
+/// see [`wasm_peers_protocol::websocket_protocol`].
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+pub(crate) fn open_websocket(signaling_server_url: &str) -> Result<WebSocket, JsValue> {
+    let websocket = WebSocket::new_with_str(
+        signaling_server_url,
+        &wasm_peers_protocol::websocket_protocol(),
+    )?;
+    websocket.set_binary_type(BinaryType::Arraybuffer);
+    Ok(websocket)
+}
+
+/// How long connecting with failover waits for each signaling server to accept the connection
+/// before trying the next one.
+pub const FAILOVER_ATTEMPT_TIMEOUT_MS: u32 = 5_000;
+
+/// Connects to the first of signaling servers that accepts the connection, trying them in order.
+/// Resolves with a `WebSocket` that is already open.
+/// Servers not accepting the connection within `attempt_timeout_ms` count as failed.
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+pub(crate) async fn open_websocket_with_failover(
+    signaling_server_urls: &[&str],
+    attempt_timeout_ms: u32,
+) -> Result<WebSocket, JsValue> {
+    for signaling_server_url in signaling_server_urls {
+        let websocket = match open_websocket(signaling_server_url) {
+            Ok(websocket) => websocket,
+            Err(error) => {
+                info!(
+                    "invalid signaling server url {}: {:?}",
+                    signaling_server_url, error
+                );
+                continue;
+            }
+        };
+
+        let mut on_open = None;
+        let mut on_error = None;
+        let opened = Promise::new(&mut |resolve, _reject| {
+            let resolve_clone = resolve.clone();
+            on_open = Some(Closure::once(move || {
+                let _ = resolve_clone.call1(&JsValue::NULL, &JsValue::TRUE);
+            }));
+            on_error = Some(Closure::once(move || {
+                let _ = resolve.call1(&JsValue::NULL, &JsValue::FALSE);
+            }));
+        });
+        let (on_open, on_error) = on_open
+            .zip(on_error)
+            .expect("promise executor runs synchronously");
+        websocket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        websocket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        let opened = Promise::race(&Array::of2(&opened, &timeout_promise(attempt_timeout_ms)));
+        let opened = JsFuture::from(opened).await?.is_truthy();
+        websocket.set_onopen(None);
+        websocket.set_onerror(None);
+
+        if opened {
+            info!("connected to signaling server {}", signaling_server_url);
+            return Ok(websocket);
         }
+        info!(
+            "failed to connect to signaling server {}",
+            signaling_server_url
+        );
+        // stop an attempt that timed out from connecting later
+        let _ = websocket.close();
     }
+    Err(JsValue::from_str(
+        "failed to connect to any of the signaling servers",
+    ))
+}
+
+/// Looks up a function on the global object, so that it works both in window and in workers.
+pub(crate) fn global_function(name: &str) -> Result<Function, JsValue> {
+    Reflect::get(&js_sys::global(), &JsValue::from_str(name))?.dyn_into::<Function>()
+}
+
+/// Returns a promise that resolves with `false` after given number of milliseconds.
+/// `setTimeout` is taken from the global object, so it works both in window and in workers.
+pub(crate) fn timeout_promise(timeout_ms: u32) -> Promise {
+    Promise::new(&mut |resolve, reject| {
+        let set_timeout = Reflect::get(&js_sys::global(), &JsValue::from_str("setTimeout"))
+            .and_then(|set_timeout| set_timeout.dyn_into::<Function>());
+        let result = set_timeout.and_then(|set_timeout| {
+            let resolve = resolve.bind1(&JsValue::NULL, &JsValue::FALSE);
+            set_timeout.call2(&JsValue::NULL, &resolve, &JsValue::from(timeout_ms))
+        });
+        if let Err(error) = result {
+            let _ = reject.call1(&JsValue::NULL, &error);
+        }
+    })
+}
+
+/// Candidates that `ICE` selected for the connection, read from `RtcPeerConnection::getStats`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SelectedCandidatePair {
+    /// Type of the local candidate: `host`, `srflx`, `prflx` or `relay`.
+    pub local_candidate_type: String,
+    /// Type of the remote candidate: `host`, `srflx`, `prflx` or `relay`.
+    pub remote_candidate_type: String,
+}
+
+impl SelectedCandidatePair {
+    /// Whether the traffic goes through a `TURN` server, on either side of the connection.
+    pub fn is_relayed(&self) -> bool {
+        self.local_candidate_type == "relay" || self.remote_candidate_type == "relay"
+    }
+}
+
+#[cfg(feature = "one-to-one")]
+fn stats_field(stats: &JsValue, field: &str) -> Option<JsValue> {
+    Reflect::get(stats, &JsValue::from_str(field))
+        .ok()
+        .filter(|value| !value.is_undefined())
+}
+
+/// Returns stats of the candidate pair selected by `ICE`, if any.
+#[cfg(feature = "one-to-one")]
+fn selected_pair_stats(report: &Map) -> Option<JsValue> {
+    let mut stats = Vec::new();
+    report.for_each(&mut |value, _key| stats.push(value));
+
+    let of_type = |stats_type: &str| {
+        stats
+            .iter()
+            .filter(|value| {
+                stats_field(value, "type").and_then(|value| value.as_string())
+                    == Some(stats_type.to_string())
+            })
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    // browsers report selected pair either on the transport or on the pair itself
+    let selected_pair = of_type("transport")
+        .iter()
+        .find_map(|transport| stats_field(transport, "selectedCandidatePairId"))
+        .map(|pair_id| report.get(&pair_id))
+        .or_else(|| {
+            of_type("candidate-pair").into_iter().find(|pair| {
+                stats_field(pair, "selected").and_then(|selected| selected.as_bool()) == Some(true)
+            })
+        });
+    selected_pair.filter(|pair| !pair.is_undefined())
+}
+
+/// Returns `None` if `ICE` didn't select a candidate pair yet.
+/// Stats are read on each call, so the result reflects `ICE` restarts.
+#[cfg(feature = "one-to-one")]
+pub(crate) async fn get_selected_candidate_pair(
+    peer_connection: &RtcPeerConnection,
+) -> Result<Option<SelectedCandidatePair>, JsValue> {
+    let report: Map = JsFuture::from(peer_connection.get_stats())
+        .await?
+        .unchecked_into();
+    let selected_pair = match selected_pair_stats(&report) {
+        Some(pair) => pair,
+        None => return Ok(None),
+    };
+
+    let candidate_type = |candidate_id_field: &str| {
+        stats_field(&selected_pair, candidate_id_field)
+            .map(|candidate_id| report.get(&candidate_id))
+            .and_then(|candidate| stats_field(&candidate, "candidateType"))
+            .and_then(|candidate_type| candidate_type.as_string())
+            .ok_or_else(|| {
+                JsValue::from_str(&format!(
+                    "stats are missing candidate for {}",
+                    candidate_id_field
+                ))
+            })
+    };
+    Ok(Some(SelectedCandidatePair {
+        local_candidate_type: candidate_type("localCandidateId")?,
+        remote_candidate_type: candidate_type("remoteCandidateId")?,
+    }))
+}
+
+/// Quality of the connection as measured by one of the peers, read from `RtcPeerConnection::getStats`,
+/// see [`crate::one_to_one::NetworkManager::connection_quality`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionQuality {
+    /// Latest round trip time in milliseconds measured on the selected candidate pair.
+    pub round_trip_time_ms: Option<f64>,
+    /// Fraction of connectivity checks on the selected candidate pair left without a response,
+    /// from 0 to 1. Data channels don't report lost packets, so it's the closest measure of loss there is.
+    pub loss: Option<f64>,
+    /// Bits per second the browser's congestion control estimates can be sent on the selected candidate pair.
+    pub available_outgoing_bitrate: Option<f64>,
+}
+
+/// Returns the quality measured on the selected candidate pair,
+/// with fields set to `None` if no pair is selected yet or the browser doesn't report them.
+#[cfg(feature = "one-to-one")]
+pub(crate) async fn get_connection_quality(
+    peer_connection: &RtcPeerConnection,
+) -> Result<ConnectionQuality, JsValue> {
+    let report: Map = JsFuture::from(peer_connection.get_stats())
+        .await?
+        .unchecked_into();
+    let selected_pair = match selected_pair_stats(&report) {
+        Some(pair) => pair,
+        None => return Ok(ConnectionQuality::default()),
+    };
+    let number = |field: &str| stats_field(&selected_pair, field).and_then(|value| value.as_f64());
+    // stats report round trip time in seconds
+    let round_trip_time_ms = number("currentRoundTripTime").map(|seconds| seconds * 1000.0);
+    let loss = number("requestsSent")
+        .zip(number("responsesReceived"))
+        .filter(|(requests, _responses)| *requests > 0.0)
+        .map(|(requests, responses)| (1.0 - responses / requests).clamp(0.0, 1.0));
+    Ok(ConnectionQuality {
+        round_trip_time_ms,
+        loss,
+        available_outgoing_bitrate: number("availableOutgoingBitrate"),
+    })
 }
 
 pub(crate) async fn create_sdp_offer(
     peer_connection: &RtcPeerConnection,
 ) -> Result<String, JsValue> {
-    let offer = JsFuture::from(peer_connection.create_offer())
-        .await
-        .map_err(|error| {
-            JsValue::from_str(&format!(
-                "failed to create an SDP offer: {}",
-                error.as_string().unwrap_or_default()
-            ))
-        })?;
+    set_local_offer(peer_connection, peer_connection.create_offer()).await
+}
+
+/// Same as [`create_sdp_offer`], but with fresh `ICE` credentials, which restarts `ICE` once applied.
+#[cfg(feature = "one-to-one")]
+pub(crate) async fn create_ice_restart_offer(
+    peer_connection: &RtcPeerConnection,
+) -> Result<String, JsValue> {
+    let options = RtcOfferOptions::new();
+    options.set_ice_restart(true);
+    let offer = peer_connection.create_offer_with_rtc_offer_options(&options);
+    set_local_offer(peer_connection, offer).await
+}
+
+async fn set_local_offer(
+    peer_connection: &RtcPeerConnection,
+    offer: Promise,
+) -> Result<String, JsValue> {
+    let offer = JsFuture::from(offer).await.map_err(|error| {
+        JsValue::from_str(&format!(
+            "failed to create an SDP offer: {}",
+            error.as_string().unwrap_or_default()
+        ))
+    })?;
     let offer = Reflect::get(&offer, &JsValue::from_str("sdp"))?
         .as_string()
         .expect("failed to create JS object for SDP offer");
-    let mut local_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
-    local_session_description.sdp(&offer);
+    let local_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
+    local_session_description.set_sdp(&offer);
     JsFuture::from(peer_connection.set_local_description(&local_session_description))
         .await
         .map_err(|error| {
@@ -107,12 +662,77 @@ pub(crate) async fn create_sdp_offer(
     Ok(offer)
 }
 
+/// Sets the answer received from the other peer as the remote description.
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+pub(crate) async fn set_sdp_answer(
+    peer_connection: &RtcPeerConnection,
+    answer: &str,
+) -> Result<(), JsValue> {
+    let remote_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
+    remote_session_description.set_sdp(answer);
+    JsFuture::from(peer_connection.set_remote_description(&remote_session_description))
+        .await
+        .map_err(|error| {
+            JsValue::from_str(&format!(
+                "failed to set remote description: {}",
+                error.as_string().unwrap_or_default()
+            ))
+        })?;
+    Ok(())
+}
+
+/// Drops the offer this peer made, e.g. when it collided with the other peer's offer.
+#[cfg(feature = "one-to-one")]
+pub(crate) async fn rollback_local_description(
+    peer_connection: &RtcPeerConnection,
+) -> Result<(), JsValue> {
+    let rollback = RtcSessionDescriptionInit::new(RtcSdpType::Rollback);
+    JsFuture::from(peer_connection.set_local_description(&rollback)).await?;
+    Ok(())
+}
+
+/// Parses `ICE` candidate in the form [`IceCandidate`] is sent through the signaling server.
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+pub(crate) fn parse_ice_candidate(ice_candidate: &str) -> Result<RtcIceCandidate, JsValue> {
+    let ice_candidate = serde_json_wasm::from_str::<IceCandidate>(ice_candidate)
+        .map_err(|error| JsValue::from_str(&format!("invalid ICE candidate: {}", error)))?;
+    let rtc_candidate = RtcIceCandidateInit::new("");
+    rtc_candidate.set_candidate(&ice_candidate.candidate);
+    rtc_candidate.set_sdp_m_line_index(ice_candidate.sdp_m_line_index);
+    rtc_candidate.set_sdp_mid(ice_candidate.sdp_mid.as_deref());
+    RtcIceCandidate::new(&rtc_candidate)
+}
+
+/// Returns `true` if candidates can be added to the connection.
+/// Browsers reject candidates received before the remote description is set,
+/// which happens when they overtake the `SDP` being processed, so those have to be held until then.
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+pub(crate) fn accepts_ice_candidates(peer_connection: &RtcPeerConnection) -> bool {
+    peer_connection.remote_description().is_some()
+}
+
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+pub(crate) async fn add_ice_candidates(
+    peer_connection: &RtcPeerConnection,
+    ice_candidates: Vec<RtcIceCandidate>,
+) -> Result<(), JsValue> {
+    for ice_candidate in ice_candidates {
+        JsFuture::from(
+            peer_connection.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&ice_candidate)),
+        )
+        .await?;
+        debug!("added ice candidate {:?}", ice_candidate.candidate());
+    }
+    Ok(())
+}
+
+#[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
 pub(crate) async fn create_sdp_answer(
     peer_connection: &RtcPeerConnection,
     offer: String,
 ) -> Result<String, JsValue> {
-    let mut remote_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
-    remote_session_description.sdp(&offer);
+    let remote_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
+    remote_session_description.set_sdp(&offer);
     JsFuture::from(peer_connection.set_remote_description(&remote_session_description)).await?;
 
     let answer = JsFuture::from(peer_connection.create_answer()).await?;
@@ -120,8 +740,8 @@ pub(crate) async fn create_sdp_answer(
         .as_string()
         .expect("failed to create JS object for SPD answer");
 
-    let mut local_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
-    local_session_description.sdp(&answer);
+    let local_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
+    local_session_description.set_sdp(&answer);
     JsFuture::from(peer_connection.set_local_description(&local_session_description)).await?;
 
     Ok(answer)
@@ -150,6 +770,47 @@ mod test {
         );
     }
 
+    #[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+    #[wasm_bindgen_test]
+    fn test_create_data_channel_sets_protocol() {
+        let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
+        let data_channel_config = DataChannelConfig {
+            protocol: Some("custom-protocol".to_string()),
+            priority: Some(DataChannelPriority::High),
+        };
+        let data_channel = create_data_channel(&peer_connection, "label", &data_channel_config);
+        assert_eq!(
+            get_data_channel_protocol(&data_channel).unwrap(),
+            "custom-protocol"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_too_long_data_channel_protocol_is_rejected() {
+        let data_channel_config = DataChannelConfig {
+            protocol: Some("x".repeat(MAX_DATA_CHANNEL_PROTOCOL_LENGTH + 1)),
+            priority: None,
+        };
+        assert!(data_channel_config.validate().is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_only_allowed_data_channel_priorities_are_parsed() {
+        for priority in [
+            DataChannelPriority::VeryLow,
+            DataChannelPriority::Low,
+            DataChannelPriority::Medium,
+            DataChannelPriority::High,
+        ] {
+            assert_eq!(
+                priority.as_str().parse::<DataChannelPriority>().unwrap(),
+                priority
+            );
+        }
+        assert!("urgent".parse::<DataChannelPriority>().is_err());
+        assert!("High".parse::<DataChannelPriority>().is_err());
+    }
+
     #[wasm_bindgen_test]
     async fn test_create_sdp_offer_is_successful() {
         let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
@@ -157,6 +818,7 @@ mod test {
         assert!(peer_connection.local_description().is_some());
     }
 
+    #[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
     #[wasm_bindgen_test]
     async fn test_create_sdp_answer_is_successful() {
         let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
@@ -165,4 +827,90 @@ mod test {
         assert!(peer_connection.local_description().is_some());
         assert!(peer_connection.remote_description().is_some());
     }
+
+    #[cfg(feature = "one-to-one")]
+    #[wasm_bindgen_test]
+    async fn test_unconnected_peer_connection_has_no_selected_candidate_pair() {
+        let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
+        assert_eq!(
+            get_selected_candidate_pair(&peer_connection).await.unwrap(),
+            None
+        );
+    }
+
+    #[cfg(feature = "one-to-one")]
+    #[wasm_bindgen_test]
+    fn test_unconnected_peer_connection_has_no_max_message_size() {
+        let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
+        assert_eq!(get_max_message_size(&peer_connection), None);
+    }
+
+    #[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+    #[wasm_bindgen_test]
+    fn test_new_peer_connection_holds_ice_candidates() {
+        let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
+        assert!(!accepts_ice_candidates(&peer_connection));
+    }
+
+    #[cfg(feature = "one-to-one")]
+    #[wasm_bindgen_test]
+    fn test_ice_options_filter_candidates() {
+        let ice_options = IceOptions {
+            ignored_candidate_types: vec!["srflx".to_string()],
+            ipv4_only: true,
+            ignore_mdns: true,
+            ..IceOptions::default()
+        };
+
+        for (candidate, allowed) in [
+            (
+                "candidate:1 1 udp 2113937151 192.168.1.2 52345 typ host",
+                true,
+            ),
+            (
+                "candidate:2 1 udp 1677729535 203.0.113.7 52345 typ srflx",
+                false,
+            ),
+            (
+                "candidate:3 1 udp 2113939711 2001:db8::1 52346 typ host",
+                false,
+            ),
+            (
+                "candidate:4 1 udp 2113937151 0b5b7a1e.local 52347 typ host",
+                false,
+            ),
+        ] {
+            assert_eq!(
+                ice_options.allows_candidate(candidate),
+                allowed,
+                "{}",
+                candidate
+            );
+        }
+    }
+
+    #[cfg(feature = "one-to-one")]
+    #[wasm_bindgen_test]
+    fn test_relay_only_sets_transport_policy() {
+        let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
+        let ice_options = IceOptions {
+            relay_only: true,
+            ..IceOptions::default()
+        };
+
+        apply_ice_options(&peer_connection, &ice_options).unwrap();
+
+        assert_eq!(
+            peer_connection
+                .get_configuration()
+                .get_ice_transport_policy(),
+            Some(RtcIceTransportPolicy::Relay)
+        );
+    }
+
+    #[cfg(any(feature = "one-to-one", feature = "one-to-many"))]
+    #[wasm_bindgen_test]
+    fn test_malformed_ice_candidate_is_an_error() {
+        assert!(parse_ice_candidate("not a candidate").is_err());
+    }
 }
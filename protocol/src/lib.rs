@@ -1,6 +1,55 @@
 /*!
 Helper crate that declares common types and structures shared between [wasm-peers](https://docs.rs/wasm-peers/latest/wasm_peers/)
 and [wasm-peers-signaling-server](https://docs.rs/wasm-peers-signaling-server/latest/wasm_peers_signaling_server/).
+
+# Wire format
+
+Signaling messages are adjacently tagged: each one is sent as a JSON object
+with the variant name under `"type"` and its fields, in declaration order, as an array under `"data"`.
+Variants with a single field carry it directly, without the array:
+
+```json
+{"type":"SessionJoin","data":"some-session-id"}
+{"type":"SdpOffer","data":["some-session-id",1,"v=0\r\n..."]}
+```
+
+`"type"` is always sent first, clients written in other languages should do the same
+since the `WASM` deserializer doesn't accept it after `"data"`.
+
+## Compact keys
+
+With the `compact-json` feature the keys are shortened to `"t"` and `"d"`,
+saving 7 bytes on every message, e.g. for deployments that pay for signaling traffic:
+
+```json
+{"t":"SessionJoin","d":"some-session-id"}
+```
+
+The two forms aren't compatible, clients and the signaling server must all be built
+either with or without the feature. Both `wasm-peers` and the signaling server
+have a feature of the same name enabling it.
+
+# Features
+
+Each topology's messages are behind a feature of the same name, `one-to-one`, `one-to-many`
+and `many-to-many`. All of them are enabled by default.
+Consumers that only need some of them, e.g. an alternative signaling server for a single topology,
+can disable default features, leaving [`SessionId`], [`UserId`] and [`PROTOCOL_VERSION`]
+with no dependencies other than `serde`.
+
+# Versioning
+
+Wire format changes only in releases incompatible according to semver, so clients and servers
+depending on compatible versions of this crate understand each other.
+Clients offer [`websocket_protocol`] as the websocket subprotocol, which names the compatible
+versions and whether keys are compact, and the signaling server rejects clients offering another one.
+Clients offering no subprotocol, e.g. ones written in other languages, aren't checked.
+
+## Migrating from 0.3
+
+Earlier versions used the default externally tagged representation,
+e.g. `{"SdpOffer":["some-session-id",1,"v=0\r\n..."]}`, so 0.3 clients and servers
+don't understand 0.4 ones and need to be updated together.
 */
 
 #![deny(missing_docs)]
@@ -11,10 +60,43 @@ use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "many-to-many")]
 pub mod many_to_many;
+#[cfg(feature = "one-to-many")]
 pub mod one_to_many;
+#[cfg(feature = "one-to-one")]
 pub mod one_to_one;
 
+/// Version of this crate, clients and servers depending on versions compatible according to semver
+/// use the same wire format.
+pub const PROTOCOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Prefix of the websocket subprotocols named by [`websocket_protocol`].
+pub const WEBSOCKET_PROTOCOL_PREFIX: &str = "wasm-peers.";
+
+/// Websocket subprotocol clients offer and the signaling server accepts, e.g. `wasm-peers.0.4`,
+/// naming the versions compatible with [`PROTOCOL_VERSION`] according to semver,
+/// with `.compact` appended with the `compact-json` feature.
+pub fn websocket_protocol() -> String {
+    let mut parts = PROTOCOL_VERSION.split('.');
+    let major = parts.next().unwrap_or_default();
+    let compatible = if major == "0" {
+        format!("0.{}", parts.next().unwrap_or_default())
+    } else {
+        major.to_string()
+    };
+    let keys = if cfg!(feature = "compact-json") {
+        ".compact"
+    } else {
+        ""
+    };
+    format!("{}{}{}", WEBSOCKET_PROTOCOL_PREFIX, compatible, keys)
+}
+
+/// Maximum length in bytes of the text of a `ServerNotice`.
+/// It's the same message in every topology, so its serialized form doesn't depend on the topology.
+pub const MAX_SERVER_NOTICE_LENGTH: usize = 1024;
+
 /// Unique identifier of signaling session that each user provides
 /// when communicating with the signaling server.
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
@@ -90,3 +172,105 @@ impl Deref for UserId {
 /// Unique identifier specifying which peer is host and will be creating an offer,
 /// and which will await it.
 pub type IsHost = bool;
+
+// wire format is the same for all topologies, so it's only tested with all of them enabled
+#[cfg(all(
+    test,
+    not(feature = "compact-json"),
+    feature = "one-to-one",
+    feature = "one-to-many",
+    feature = "many-to-many"
+))]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_single_field_message_is_not_wrapped_in_array() {
+        let message = one_to_one::SignalMessage::SessionJoin(SessionId::new("abc".to_string()));
+
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({"type": "SessionJoin", "data": "abc"})
+        );
+    }
+
+    #[test]
+    fn test_multi_field_message_shape() {
+        let message = one_to_many::SignalMessage::SdpOffer(
+            SessionId::new("abc".to_string()),
+            UserId::new(1),
+            "v=0".to_string(),
+        );
+
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"type":"SdpOffer","data":["abc",1,"v=0"]}"#
+        );
+    }
+
+    #[test]
+    fn test_message_round_trips() {
+        let json = r#"{"type":"SessionReady","data":["abc",7]}"#;
+
+        let message: many_to_many::SignalMessage = serde_json::from_str(json).unwrap();
+
+        assert!(matches!(
+            &message,
+            many_to_many::SignalMessage::SessionReady(session_id, user_id)
+                if session_id.as_str() == "abc" && user_id.into_inner() == 7
+        ));
+        assert_eq!(serde_json::to_string(&message).unwrap(), json);
+    }
+
+    #[test]
+    fn test_websocket_protocol_names_compatible_versions() {
+        let expected = if cfg!(feature = "compact-json") {
+            "wasm-peers.0.4.compact"
+        } else {
+            "wasm-peers.0.4"
+        };
+        assert_eq!(websocket_protocol(), expected);
+    }
+
+    #[test]
+    fn test_externally_tagged_message_is_rejected() {
+        let json = r#"{"SessionJoin":"abc"}"#;
+
+        assert!(serde_json::from_str::<one_to_one::SignalMessage>(json).is_err());
+    }
+}
+
+// run with `cargo test --features compact-json`
+#[cfg(all(
+    test,
+    feature = "compact-json",
+    feature = "one-to-one",
+    feature = "one-to-many",
+    feature = "many-to-many"
+))]
+mod compact_test {
+    use super::*;
+
+    #[test]
+    fn test_compact_message_round_trips() {
+        let json = r#"{"t":"SdpOffer","d":["abc",1,"v=0"]}"#;
+
+        let message: one_to_many::SignalMessage = serde_json::from_str(json).unwrap();
+
+        assert!(matches!(
+            &message,
+            one_to_many::SignalMessage::SdpOffer(session_id, user_id, sdp)
+                if session_id.as_str() == "abc" && user_id.into_inner() == 1 && sdp == "v=0"
+        ));
+        assert_eq!(serde_json::to_string(&message).unwrap(), json);
+    }
+
+    #[test]
+    fn test_verbose_message_is_rejected() {
+        let json = r#"{"type":"SessionJoin","data":"abc"}"#;
+
+        assert!(serde_json::from_str::<one_to_one::SignalMessage>(json).is_err());
+    }
+}
@@ -10,7 +10,10 @@ use crate::{SessionId, UserId};
 /// `Enum` consisting of two main categories are messages used to setup signaling session
 /// and messages used to setup `WebRTC` connection afterwards.
 /// Most of the include [`SessionId`] and [`UserId`] to uniquely identify each peer.
+/// Serialized as described in [wire format](crate#wire-format).
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(not(feature = "compact-json"), serde(tag = "type", content = "data"))]
+#[cfg_attr(feature = "compact-json", serde(tag = "t", content = "d"))]
 pub enum SignalMessage {
     /// Either client or server connecting to signaling session
     SessionJoin(SessionId),
@@ -27,6 +30,9 @@ pub enum SignalMessage {
     /// Proposed ICE Candidate of one user passed to the other user without modifications
     IceCandidate(SessionId, UserId, String),
 
+    /// Free-form notice from the server operator sent to every connection, e.g. about upcoming maintenance,
+    /// at most [`crate::MAX_SERVER_NOTICE_LENGTH`] bytes long
+    ServerNotice(String),
     /// Generic error containing detailed information about the cause
     Error(SessionId, String),
 }
@@ -7,10 +7,19 @@ use serde::{Deserialize, Serialize};
 
 use crate::{IsHost, SessionId, UserId};
 
+/// Maximum length in bytes of the data sent with [`SignalMessage::RelayTo`].
+pub const MAX_RELAY_LENGTH: usize = 16 * 1024;
+
+/// Maximum length in bytes of a topic, see [`SignalMessage::Subscribe`].
+pub const MAX_TOPIC_LENGTH: usize = 64;
+
 /// `Enum` consisting of two main categories are messages used to setup signaling session
 /// and messages used to setup `WebRTC` connection afterwards.
 /// Most of the include [`SessionId`] and [`UserId`] to uniquely identify each peer.
+/// Serialized as described in [wire format](crate#wire-format).
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(not(feature = "compact-json"), serde(tag = "type", content = "data"))]
+#[cfg_attr(feature = "compact-json", serde(tag = "t", content = "d"))]
 pub enum SignalMessage {
     /// Either client or server connecting to signaling session
     SessionJoin(SessionId, IsHost),
@@ -27,6 +36,73 @@ pub enum SignalMessage {
     /// Proposed ICE Candidate of one user passed to the other user without modifications
     IceCandidate(SessionId, UserId, String),
 
+    /// Report to users remaining in session that a user left it by disconnecting from signaling server
+    PeerLeft(SessionId, UserId),
+
+    /// Request of the session owner, initially the first user to join,
+    /// to hand over owner rights to another user in session.
+    /// It's the only way ownership changes, sessions whose owner left have no owner
+    TransferOwnership(SessionId, UserId),
+
+    /// Report back to all users in session who the new owner is
+    OwnershipChanged(SessionId, UserId),
+
+    /// Application data that the signaling server passes to the listed users,
+    /// skipping the ones that are not in session
+    RelayTo(SessionId, Vec<UserId>, Vec<u8>),
+
+    /// Application data relayed by the signaling server from the user with given id
+    Relayed(SessionId, UserId, Vec<u8>),
+
+    /// Request of a user in session to receive data published to the topic,
+    /// at most [`MAX_TOPIC_LENGTH`] bytes long
+    Subscribe(SessionId, String),
+
+    /// Request of a user to stop receiving data published to the topic
+    Unsubscribe(SessionId, String),
+
+    /// Application data that the signaling server passes to the users subscribed to the topic,
+    /// except for the sender, whether the sender is subscribed or not
+    Publish(SessionId, String, Vec<u8>),
+
+    /// Application data published to the topic by the user with given id
+    Published(SessionId, UserId, String, Vec<u8>),
+
+    /// Report to the sender that data it asked to relay or publish was dropped for some or all recipients,
+    /// because the session relayed more than the signaling server allows per second
+    BandwidthExceeded(SessionId),
+
+    /// Request of a user in session for its current state, e.g. when its view may be stale
+    QuerySession(SessionId),
+
+    /// Report back to the querying user the current state of the session
+    SessionStatus(SessionId, SessionInfo),
+
+    /// Request of a user in session to close it for everyone, unlike leaving it,
+    /// by default only the session owner can
+    CloseSession(SessionId),
+
+    /// Report to all users in session that it was closed, with the reason.
+    /// Users stay connected to the signaling server and can join other sessions
+    SessionClosed(SessionId, String),
+
+    /// Free-form notice from the server operator sent to every connection, e.g. about upcoming maintenance,
+    /// at most [`crate::MAX_SERVER_NOTICE_LENGTH`] bytes long
+    ServerNotice(String),
     /// Generic error containing detailed information about the cause
     Error(SessionId, String),
 }
+
+/// Current state of a session, sent in response to [`SignalMessage::QuerySession`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// Host of the session, always `None` in many-to-many sessions.
+    pub host: Option<UserId>,
+    /// User with owner rights, see [`SignalMessage::TransferOwnership`].
+    pub owner: Option<UserId>,
+    /// All users in session, including the host, ordered by id.
+    pub users: Vec<UserId>,
+    /// Whether there are enough users for connections to be made:
+    /// host and at least one client, or any two users in many-to-many sessions.
+    pub ready: bool,
+}
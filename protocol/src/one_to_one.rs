@@ -7,15 +7,32 @@ use serde::{Deserialize, Serialize};
 
 use crate::{IsHost, SessionId};
 
+/// Maximum length in bytes of the metadata a peer can send with [`SignalMessage::PeerMetadata`].
+pub const MAX_PEER_METADATA_LENGTH: usize = 4096;
+
 /// `Enum` consisting of two main categories are messages used to setup signaling session
 /// and messages used to setup `WebRTC` connection afterwards.
 /// All of the messages include [`SessionId`] which is enough to identify the other peer in the connection.
+/// Serialized as described in [wire format](crate#wire-format).
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(not(feature = "compact-json"), serde(tag = "type", content = "data"))]
+#[cfg_attr(feature = "compact-json", serde(tag = "t", content = "d"))]
 pub enum SignalMessage {
     /// Either client or server connecting to signaling session
     SessionJoin(SessionId),
     /// Report back to the users that both of them are in session
     SessionReady(SessionId, IsHost),
+    /// User leaving the session without closing the connection to signaling server,
+    /// e.g. when cancelling a connection attempt
+    SessionLeave(SessionId),
+    /// Report back to the user remaining in session that the other one left it with `SessionLeave`
+    PeerLeft(SessionId),
+    /// Request to be paired with another user looking for a match with the same criteria,
+    /// e.g. a game mode, instead of joining a session with a known id
+    FindMatch(String),
+    /// Report back to matched users the id of the session created for them,
+    /// it's followed by `SessionReady`
+    Matched(SessionId),
 
     /// `SDP` Offer that gets passed to the other user without modifications
     SdpOffer(SessionId, String),
@@ -23,7 +40,27 @@ pub enum SignalMessage {
     SdpAnswer(SessionId, String),
     /// Proposed ICE Candidate of one user passed to the other user without modifications
     IceCandidate(SessionId, String),
+    /// Application-defined metadata of one user (e.g. display name or app version)
+    /// passed to the other user without modifications, once session is ready
+    PeerMetadata(SessionId, String),
+    /// Request of one user to restart ICE, e.g. after the network changed, passed to the other user.
+    /// The host then starts the restart with a fresh `SdpOffer`, so both sides never create restart offers at once
+    IceRestart(SessionId),
+    /// Report back to both users that negotiation didn't finish in time after `SessionReady`,
+    /// i.e. no `SdpAnswer` was passed between them, so they can retry, e.g. by rejoining the session
+    NegotiationTimeout(SessionId),
+    /// Report to both users, right after `SessionReady`, that they connect to the signaling server
+    /// from the same address, so they're likely on the same local network
+    /// and host candidates are likely to connect, even where the router doesn't support hairpinning
+    SameNetworkHint(SessionId),
 
+    /// Free-form notice from the server operator sent to every connection, e.g. about upcoming maintenance,
+    /// at most [`crate::MAX_SERVER_NOTICE_LENGTH`] bytes long
+    ServerNotice(String),
+    /// Request from the server to continue signaling through another signaling server at given url,
+    /// e.g. before it shuts down. Users reconnect there and rejoin their session,
+    /// keeping the peer connection open meanwhile
+    Migrate(String),
     /// Generic error containing detailed information about the cause
     Error(SessionId, String),
 }
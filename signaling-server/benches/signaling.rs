@@ -0,0 +1,91 @@
+//! Throughput and latency of relaying one-to-one signaling messages,
+//! over the in-memory transport so that only the server's routing logic is measured.
+//!
+//! Run with `just benchmark`, or `cargo bench -p wasm-peers-signaling-server-axum`.
+
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::SessionId;
+use wasm_peers_signaling_server_axum::config::ServerConfig;
+use wasm_peers_signaling_server_axum::memory::{self, MemoryConnection};
+use wasm_peers_signaling_server_axum::status::ServerState;
+
+const SESSION_COUNTS: [usize; 3] = [1, 100, 10_000];
+
+/// Ready sessions, each with the sending and the receiving user.
+struct Sessions {
+    users: Vec<(SessionId, MemoryConnection, MemoryConnection)>,
+}
+
+impl Sessions {
+    async fn new(count: usize) -> Self {
+        let state = ServerState::default();
+        let config = Arc::new(ServerConfig::default());
+        let mut users = Vec::with_capacity(count);
+        for index in 0..count {
+            let session_id = SessionId::new(format!("session-{}", index));
+            let mut sender = memory::connect(&state, config.clone());
+            let mut recipient = memory::connect(&state, config.clone());
+            for user in [&mut sender, &mut recipient] {
+                user.send(&SignalMessage::SessionJoin(session_id.clone()))
+                    .unwrap();
+            }
+            for user in [&mut sender, &mut recipient] {
+                assert!(matches!(
+                    user.recv().await.unwrap(),
+                    Some(SignalMessage::SessionReady(..))
+                ));
+            }
+            users.push((session_id, sender, recipient));
+        }
+        Sessions { users }
+    }
+
+    /// Sends a candidate in each session, then waits until all of them arrive.
+    async fn relay_round(&mut self) {
+        for (session_id, sender, _recipient) in &self.users {
+            sender
+                .send(&SignalMessage::IceCandidate(
+                    session_id.clone(),
+                    "candidate:1 1 udp 2122260223 192.0.2.1 54321 typ host".to_string(),
+                ))
+                .unwrap();
+        }
+        for (_session_id, _sender, recipient) in &mut self.users {
+            recipient.recv().await.unwrap();
+        }
+    }
+}
+
+fn throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("relay_throughput");
+    for count in SESSION_COUNTS {
+        let mut sessions = runtime.block_on(Sessions::new(count));
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, _| {
+            b.iter(|| runtime.block_on(sessions.relay_round()));
+        });
+    }
+    group.finish();
+}
+
+fn latency(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("relay_latency");
+    for count in SESSION_COUNTS {
+        let mut single = runtime.block_on(Sessions::new(count));
+        // other sessions stay idle, only adding to the state the message is routed through
+        let _idle = single.users.split_off(1);
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, _| {
+            b.iter(|| runtime.block_on(single.relay_round()));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput, latency);
+criterion_main!(benches);
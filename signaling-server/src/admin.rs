@@ -0,0 +1,599 @@
+/*!
+Managing sessions and users of a running server from the application embedding it.
+
+Create a [`ServerState`], keep a clone of it and pass the other one to
+[`crate::router::create_router_with_state`]. Methods below then act on the sessions served by the router,
+leaving access policy, e.g. who can kick users, to the embedding application.
+Sessions of tenants are managed by tenant, `None` meaning the default namespace, see [`crate::tenant`].
+
+Enabling [`crate::config::ServerConfig::admin_token`] also serves `GET /sessions/<session id>`,
+returning [`ServerState::session_detail`] as `JSON`, for drilling into one problematic session.
+It takes the token in an `Authorization: Bearer <token>` header, same as [`crate::broadcast`],
+and the tenant in the query parameter named by [`crate::config::ServerConfig::tenant_parameter`].
+
+```no_run
+# async fn example() {
+use wasm_peers_signaling_server_axum::config::ServerConfig;
+use wasm_peers_signaling_server_axum::router::create_router_with_state;
+use wasm_peers_signaling_server_axum::status::ServerState;
+
+let state = ServerState::default();
+let router = create_router_with_state(ServerConfig::default(), state.clone());
+for session in state.list_sessions().await {
+    println!("{:?} has {} users", session.session_id, session.users.len());
+}
+# }
+```
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::ws::Message;
+use axum::http::{HeaderMap, StatusCode};
+use log::info;
+use serde::Serialize;
+use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::{SessionId, UserId};
+
+use crate::broadcast;
+use crate::config::{ServerConfig, Topology};
+use crate::matchmaking;
+use crate::one_to_one::Connections;
+use crate::session_log::SessionEventKind;
+use crate::status::ServerState;
+use crate::tenant;
+use crate::{one_to_many, one_to_one};
+
+/// Session as seen by [`ServerState::list_sessions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub topology: Topology,
+    /// Tenant the session belongs to, `None` for the default namespace.
+    pub tenant: Option<String>,
+    pub session_id: SessionId,
+    /// Users currently in the session, in no particular order.
+    pub users: Vec<UserId>,
+    /// Host of a one-to-many session, always `None` in other topologies.
+    pub host: Option<UserId>,
+    /// Owner of a one-to-many or many-to-many session, always `None` in one-to-one sessions.
+    pub owner: Option<UserId>,
+}
+
+/// Session as seen by [`ServerState::session_detail`]. Only counts and metadata, never payloads like `SDP`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionDetail {
+    pub topology: Topology,
+    pub session_id: SessionId,
+    /// Users currently in the session, in no particular order.
+    pub members: Vec<SessionMember>,
+    /// Milliseconds since the Unix epoch.
+    pub created_at: u64,
+    /// Milliseconds since the Unix epoch, `None` if no message was counted yet.
+    pub last_message_at: Option<u64>,
+    /// See [`SessionActivity::messages`].
+    pub messages: usize,
+    /// Negotiation of a one-to-one session, always `None` in other topologies.
+    pub negotiation: Option<NegotiationDetail>,
+    /// Number of `SDP` offers sent between users, in one-to-one sessions counting renegotiations too.
+    pub offers: usize,
+    /// Number of subscribers of each topic, always empty in one-to-one sessions.
+    pub topics: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionMember {
+    pub user_id: UserId,
+    pub roles: Vec<MemberRole>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberRole {
+    /// First slot of a one-to-one session.
+    First,
+    /// Second slot of a one-to-one session.
+    Second,
+    Host,
+    Owner,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NegotiationDetail {
+    pub state: NegotiationState,
+    pub renegotiations: usize,
+    /// `ICE` candidates held until the other user joins.
+    pub held_candidates: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NegotiationState {
+    WaitingForPeer,
+    /// Full session waits for a slot of [`crate::negotiation_limit`].
+    WaitingForSlot,
+    WaitingForOffer,
+    WaitingForAnswer,
+    Answered,
+}
+
+/// When a session was created and how many messages its users sent for it, kept for [`ServerState::session_detail`].
+#[derive(Debug, Clone, Copy)]
+pub struct SessionActivity {
+    pub created_at: SystemTime,
+    pub last_message_at: Option<SystemTime>,
+    /// Messages passed on to other users in the session, e.g. `SDP` offers and `ICE` candidates,
+    /// joining and leaving isn't counted.
+    pub messages: usize,
+}
+
+impl Default for SessionActivity {
+    fn default() -> Self {
+        SessionActivity {
+            created_at: SystemTime::now(),
+            last_message_at: None,
+            messages: 0,
+        }
+    }
+}
+
+impl SessionActivity {
+    pub(crate) fn record_message(&mut self) {
+        self.last_message_at = Some(SystemTime::now());
+        self.messages += 1;
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since_epoch| {
+        u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX)
+    })
+}
+
+impl ServerState {
+    /// Returns detail of the tenant's session, looking for it in one-to-one, one-to-many
+    /// and then many-to-many sessions, `None` if there's no such session.
+    pub async fn session_detail(
+        &self,
+        tenant: Option<&str>,
+        session_id: &SessionId,
+    ) -> Option<SessionDetail> {
+        let one_to_one_sessions = tenant::existing(
+            &self.tenants.one_to_one,
+            tenant,
+            &(self.one_to_one_sessions.clone(), self.waiting_users.clone()),
+        )
+        .map(|(sessions, _)| sessions);
+        if let Some(one_to_one_sessions) = one_to_one_sessions {
+            if let Some(detail) = one_to_one_detail(&one_to_one_sessions, session_id).await {
+                return Some(detail);
+            }
+        }
+        for topology in [Topology::OneToMany, Topology::ManyToMany] {
+            let sessions = match self.sessions(topology, tenant) {
+                Some(sessions) => sessions,
+                None => continue,
+            };
+            let sessions = sessions.read().await;
+            let session = match sessions.get(session_id) {
+                Some(session) => session,
+                None => continue,
+            };
+
+            let members = session
+                .users
+                .iter()
+                .map(|&user_id| SessionMember {
+                    user_id,
+                    roles: [
+                        (session.host, MemberRole::Host),
+                        (session.owner, MemberRole::Owner),
+                    ]
+                    .into_iter()
+                    .filter(|(role_user_id, _)| *role_user_id == Some(user_id))
+                    .map(|(_, role)| role)
+                    .collect(),
+                })
+                .collect();
+            return Some(SessionDetail {
+                topology,
+                session_id: session_id.clone(),
+                members,
+                created_at: unix_millis(session.activity.created_at),
+                last_message_at: session.activity.last_message_at.map(unix_millis),
+                messages: session.activity.messages,
+                negotiation: None,
+                offers: session.offers.values().sum(),
+                topics: session
+                    .subscriptions
+                    .iter()
+                    .map(|(topic, subscribers)| (topic.clone(), subscribers.len()))
+                    .collect(),
+            });
+        }
+        None
+    }
+
+    /// Returns every session of every topology and tenant.
+    pub async fn list_sessions(&self) -> Vec<SessionSummary> {
+        let mut summaries = Vec::new();
+        for (tenant, sessions, _) in self.one_to_one_namespaces() {
+            summaries.extend(sessions.read().await.iter().map(|(session_id, session)| {
+                SessionSummary {
+                    topology: Topology::OneToOne,
+                    tenant: tenant.clone(),
+                    session_id: session_id.clone(),
+                    users: session.first.into_iter().chain(session.second).collect(),
+                    host: None,
+                    owner: None,
+                }
+            }));
+        }
+        for topology in [Topology::OneToMany, Topology::ManyToMany] {
+            for (tenant, sessions) in self.one_to_many_namespaces(topology) {
+                summaries.extend(sessions.read().await.iter().map(|(session_id, session)| {
+                    SessionSummary {
+                        topology,
+                        tenant: tenant.clone(),
+                        session_id: session_id.clone(),
+                        users: session.users.iter().copied().collect(),
+                        host: session.host,
+                        owner: session.owner,
+                    }
+                }));
+            }
+        }
+        summaries
+    }
+
+    /// One-to-many or many-to-many sessions of the tenant, `None` if it has no open connections.
+    fn sessions(&self, topology: Topology, tenant: Option<&str>) -> Option<one_to_many::Sessions> {
+        match topology {
+            Topology::ManyToMany => tenant::existing(
+                &self.tenants.many_to_many,
+                tenant,
+                &self.many_to_many_sessions,
+            ),
+            _ => tenant::existing(
+                &self.tenants.one_to_many,
+                tenant,
+                &self.one_to_many_sessions,
+            ),
+        }
+    }
+
+    /// Removes the session, telling its users with an `Error` that it was closed.
+    /// Users stay connected and can join other sessions.
+    /// Returns `false` if there was no such session.
+    pub async fn close_session(
+        &self,
+        topology: Topology,
+        tenant: Option<&str>,
+        session_id: &SessionId,
+    ) -> bool {
+        let users: Vec<UserId> = match topology {
+            Topology::OneToOne => {
+                let sessions = match tenant::existing(
+                    &self.tenants.one_to_one,
+                    tenant,
+                    &(self.one_to_one_sessions.clone(), self.waiting_users.clone()),
+                ) {
+                    Some((sessions, _)) => sessions,
+                    None => return false,
+                };
+                let removed = sessions.write().await.remove(session_id);
+                match removed {
+                    Some(mut session) => {
+                        session.record(SessionEventKind::Closed, None, None);
+                        if let Some(log) = session.log {
+                            log.export();
+                        }
+                        session.first.into_iter().chain(session.second).collect()
+                    }
+                    None => return false,
+                }
+            }
+            Topology::OneToMany | Topology::ManyToMany => {
+                let sessions = match self.sessions(topology, tenant) {
+                    Some(sessions) => sessions,
+                    None => return false,
+                };
+                let removed = sessions.write().await.remove(session_id);
+                match removed {
+                    Some(session) => session.users.into_iter().collect(),
+                    None => return false,
+                }
+            }
+        };
+        info!("session {:?} closed by operator", session_id);
+        for user_id in users {
+            send_error(
+                &self.connections,
+                user_id,
+                session_id,
+                "session closed by server operator",
+            )
+            .await;
+        }
+        true
+    }
+
+    /// Removes the user from its sessions and from matchmaking, then closes its connection.
+    /// Returns `false` if there was no such connection.
+    pub async fn kick_user(&self, user_id: UserId) -> bool {
+        if !self.connections.read().await.contains_key(&user_id) {
+            return false;
+        }
+        info!("user {:?} kicked by operator", user_id);
+        let no_session = SessionId::new(String::new());
+        send_error(
+            &self.connections,
+            user_id,
+            &no_session,
+            "kicked by server operator",
+        )
+        .await;
+        if let Some(user_tx) = self.connections.read().await.get(&user_id) {
+            let _ = user_tx.send(Message::Close(None));
+        }
+        // the user's tenant isn't known, but it's only in sessions of one of them
+        for topology in [Topology::OneToMany, Topology::ManyToMany] {
+            for (_, sessions) in self.one_to_many_namespaces(topology) {
+                one_to_many::user_disconnected(user_id, &self.connections, &sessions).await;
+            }
+        }
+        for (_, sessions, waiting_users) in self.one_to_one_namespaces() {
+            matchmaking::stop_waiting(&waiting_users, user_id).await;
+            one_to_one::user_disconnected(user_id, &self.connections, &sessions).await;
+        }
+        true
+    }
+}
+
+async fn one_to_one_detail(
+    sessions: &one_to_one::Sessions,
+    session_id: &SessionId,
+) -> Option<SessionDetail> {
+    let sessions = sessions.read().await;
+    let session = sessions.get(session_id)?;
+    let members = [
+        (session.first, MemberRole::First),
+        (session.second, MemberRole::Second),
+    ]
+    .into_iter()
+    .filter_map(|(user_id, role)| {
+        user_id.map(|user_id| SessionMember {
+            user_id,
+            roles: vec![role],
+        })
+    })
+    .collect();
+    let state = if session.first.is_none() || session.second.is_none() {
+        NegotiationState::WaitingForPeer
+    } else if session.waiting_for_negotiation_slot {
+        NegotiationState::WaitingForSlot
+    } else if !session.offer_received {
+        NegotiationState::WaitingForOffer
+    } else if !session.answer_received {
+        NegotiationState::WaitingForAnswer
+    } else {
+        NegotiationState::Answered
+    };
+    Some(SessionDetail {
+        topology: Topology::OneToOne,
+        session_id: session_id.clone(),
+        members,
+        created_at: unix_millis(session.activity.created_at),
+        last_message_at: session.activity.last_message_at.map(unix_millis),
+        messages: session.activity.messages,
+        negotiation: Some(NegotiationDetail {
+            state,
+            renegotiations: session.renegotiations,
+            held_candidates: session.held_candidates.len(),
+        }),
+        offers: usize::from(session.offer_received) + session.renegotiations,
+        topics: BTreeMap::new(),
+    })
+}
+
+/// Responds with [`ServerState::session_detail`] as `JSON`.
+pub(crate) async fn fetch_session(
+    config: &ServerConfig,
+    state: &ServerState,
+    headers: &HeaderMap,
+    query: &HashMap<String, String>,
+    session_id: String,
+) -> (StatusCode, String) {
+    if !broadcast::is_authorized(config, headers) {
+        return (StatusCode::UNAUTHORIZED, "invalid admin token".to_string());
+    }
+    let tenant = tenant::tenant(config, query);
+    let detail = state
+        .session_detail(tenant.as_deref(), &SessionId::new(session_id))
+        .await;
+    match detail.map(|detail| serde_json::to_string(&detail)) {
+        Some(Ok(detail)) => (StatusCode::OK, detail),
+        Some(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        None => (StatusCode::NOT_FOUND, "no such session".to_string()),
+    }
+}
+
+/// `Error` serializes the same in every topology, so one message fits all users.
+async fn send_error(
+    connections: &Connections,
+    user_id: UserId,
+    session_id: &SessionId,
+    error: &str,
+) {
+    let response = SignalMessage::Error(session_id.clone(), error.to_string());
+    if let (Ok(response), Some(user_tx)) = (
+        serde_json::to_string(&response),
+        connections.read().await.get(&user_id),
+    ) {
+        let _ = user_tx.send(Message::Text(response));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::test_util::connect;
+    use crate::test_util::one_to_one::received_message;
+
+    fn session_id() -> SessionId {
+        SessionId::new("dummy-session-id".to_string())
+    }
+
+    async fn insert_one_to_many_session(state: &ServerState, users: &[UserId]) {
+        state.one_to_many_sessions.write().await.insert(
+            session_id(),
+            one_to_many::Session {
+                host: Some(users[0]),
+                users: users.iter().copied().collect(),
+                owner: Some(users[0]),
+                offers: Default::default(),
+                relay_budget: None,
+                subscriptions: Default::default(),
+                activity: Default::default(),
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_listed_with_their_users() {
+        let state = ServerState::default();
+        let (host, client) = (UserId::new(1), UserId::new(2));
+        insert_one_to_many_session(&state, &[host, client]).await;
+
+        let sessions = state.list_sessions().await;
+
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].topology, Topology::OneToMany);
+        assert_eq!(sessions[0].session_id, session_id());
+        assert_eq!(
+            sessions[0].users.iter().copied().collect::<HashSet<_>>(),
+            HashSet::from([host, client])
+        );
+        assert_eq!(sessions[0].host, Some(host));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_of_tenants_are_managed_by_tenant() {
+        let state = ServerState::default();
+        let (sessions, _tenant_guard) = tenant::namespace(
+            &state.tenants.one_to_many,
+            Some("a".to_string()),
+            &state.one_to_many_sessions,
+            1,
+        )
+        .unwrap();
+        let tenant_state = ServerState {
+            one_to_many_sessions: sessions,
+            ..ServerState::default()
+        };
+        insert_one_to_many_session(&tenant_state, &[UserId::new(1)]).await;
+
+        let sessions = state.list_sessions().await;
+
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].tenant.as_deref(), Some("a"));
+        assert!(state.session_detail(None, &session_id()).await.is_none());
+        assert!(state
+            .session_detail(Some("a"), &session_id())
+            .await
+            .is_some());
+        assert!(
+            state
+                .close_session(Topology::OneToMany, Some("a"), &session_id())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_closed_session_is_removed_and_users_notified() {
+        let state = ServerState::default();
+        let user_id = UserId::new(1);
+        let mut rx = connect(&state.connections, user_id).await;
+        insert_one_to_many_session(&state, &[user_id]).await;
+
+        assert!(
+            state
+                .close_session(Topology::OneToMany, None, &session_id())
+                .await
+        );
+
+        assert!(state.one_to_many_sessions.read().await.is_empty());
+        assert!(matches!(
+            received_message(&mut rx),
+            Some(SignalMessage::Error(error_session_id, _)) if error_session_id == session_id()
+        ));
+        assert!(
+            !state
+                .close_session(Topology::OneToMany, None, &session_id())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kicked_user_leaves_sessions_and_is_disconnected() {
+        let state = ServerState::default();
+        let (host, client) = (UserId::new(1), UserId::new(2));
+        let _host_rx = connect(&state.connections, host).await;
+        let mut client_rx = connect(&state.connections, client).await;
+        insert_one_to_many_session(&state, &[host, client]).await;
+
+        assert!(state.kick_user(client).await);
+
+        assert!(matches!(
+            received_message(&mut client_rx),
+            Some(SignalMessage::Error(..))
+        ));
+        assert!(matches!(client_rx.try_recv(), Ok(Message::Close(None))));
+        assert!(!state.connections.read().await.contains_key(&client));
+        let sessions = state.one_to_many_sessions.read().await;
+        assert_eq!(
+            sessions.get(&session_id()).unwrap().users,
+            HashSet::from([host])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_detail_shows_roles_and_counts_without_payloads() {
+        let state = ServerState::default();
+        let (host, client) = (UserId::new(1), UserId::new(2));
+        insert_one_to_many_session(&state, &[host, client]).await;
+        {
+            let mut sessions = state.one_to_many_sessions.write().await;
+            let session = sessions.get_mut(&session_id()).unwrap();
+            session.offers.insert((host, client), 2);
+            session
+                .subscriptions
+                .insert("chat".to_string(), HashSet::from([client]));
+            session.activity.record_message();
+        }
+
+        let detail = state.session_detail(None, &session_id()).await.unwrap();
+
+        assert_eq!(detail.topology, Topology::OneToMany);
+        let host_member = detail
+            .members
+            .iter()
+            .find(|member| member.user_id == host)
+            .unwrap();
+        assert_eq!(host_member.roles, [MemberRole::Host, MemberRole::Owner]);
+        assert_eq!(detail.offers, 2);
+        assert_eq!(detail.topics, BTreeMap::from([("chat".to_string(), 1)]));
+        assert_eq!(detail.messages, 1);
+        assert!(detail.last_message_at.is_some());
+        assert!(state
+            .session_detail(None, &SessionId::new("other".to_string()))
+            .await
+            .is_none());
+
+        let json = serde_json::to_value(&detail).unwrap();
+        assert_eq!(json["topology"], "one-to-many");
+        assert_eq!(json["negotiation"], serde_json::Value::Null);
+    }
+}
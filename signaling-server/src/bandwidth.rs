@@ -0,0 +1,65 @@
+use tokio::time::Instant;
+
+/// Token bucket limiting the number of bytes per second, allowing bursts of up to one second's worth.
+#[derive(Debug)]
+pub struct TokenBucket {
+    bytes_per_second: usize,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    pub fn new(bytes_per_second: usize) -> Self {
+        TokenBucket {
+            bytes_per_second,
+            tokens: bytes_per_second as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes `bytes` tokens from the bucket if there are enough of them.
+    /// Returns `false` and takes nothing otherwise.
+    pub fn try_consume(&mut self, bytes: usize) -> bool {
+        self.refill(Instant::now());
+        if self.tokens < bytes as f64 {
+            return false;
+        }
+        self.tokens -= bytes as f64;
+        true
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.bytes_per_second as f64)
+            .min(self.bytes_per_second as f64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_bucket_rejects_bytes_over_capacity() {
+        let mut bucket = TokenBucket::new(100);
+
+        assert!(bucket.try_consume(60));
+        assert!(!bucket.try_consume(60));
+        assert!(bucket.try_consume(40));
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let mut bucket = TokenBucket::new(100);
+        assert!(bucket.try_consume(100));
+
+        let later = bucket.last_refill + Duration::from_millis(500);
+        bucket.refill(later);
+
+        assert_eq!(bucket.tokens.round() as usize, 50);
+    }
+}
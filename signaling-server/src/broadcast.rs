@@ -0,0 +1,198 @@
+/*!
+Operator notices pushed to every connected user, e.g. about upcoming maintenance.
+
+Enabled by setting [`ServerConfig::admin_token`], which serves `POST /broadcast`.
+Request must carry the token in an `Authorization: Bearer <token>` header
+and the notice text as its body, which is sent to every connection, in every topology,
+as `ServerNotice`. Broadcasts are limited to one per [`ServerConfig::min_broadcast_interval`].
+
+```sh
+curl -X POST -H "Authorization: Bearer $ADMIN_TOKEN" -d "restarting in 5 minutes" http://localhost:9001/broadcast
+```
+*/
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::extract::ws::Message;
+use axum::http::{header, HeaderMap, StatusCode};
+use log::info;
+use tokio::sync::Mutex;
+use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::MAX_SERVER_NOTICE_LENGTH;
+
+use crate::config::ServerConfig;
+use crate::one_to_one::Connections;
+use crate::serialization::serialize_message;
+
+/// When the last notice was broadcast.
+pub(crate) type LastBroadcast = Arc<Mutex<Option<Instant>>>;
+
+/// Sends the notice to every connection, responding with the number of connections it was sent to.
+pub(crate) async fn broadcast(
+    connections: &Connections,
+    config: &ServerConfig,
+    last_broadcast: &LastBroadcast,
+    headers: &HeaderMap,
+    notice: String,
+) -> (StatusCode, String) {
+    if !is_authorized(config, headers) {
+        return (StatusCode::UNAUTHORIZED, "invalid admin token".to_string());
+    }
+    if notice.len() > MAX_SERVER_NOTICE_LENGTH {
+        let error = format!(
+            "notice is too long: {} bytes, maximum is {}",
+            notice.len(),
+            MAX_SERVER_NOTICE_LENGTH
+        );
+        return (StatusCode::PAYLOAD_TOO_LARGE, error);
+    }
+    {
+        let mut last_broadcast = last_broadcast.lock().await;
+        if last_broadcast
+            .is_some_and(|last_broadcast| last_broadcast.elapsed() < config.min_broadcast_interval)
+        {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                "notice was broadcast too recently".to_string(),
+            );
+        }
+        *last_broadcast = Some(Instant::now());
+    }
+
+    // ServerNotice serializes the same in every topology, so one message fits all connections
+    let response = match serialize_message(&SignalMessage::ServerNotice(notice)) {
+        Ok(response) => response,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    };
+    let connections_reader = connections.read().await;
+    let recipients = connections_reader
+        .values()
+        .filter(|user_tx| user_tx.send(Message::Text(response.clone())).is_ok())
+        .count();
+    info!("broadcast server notice to {} connections", recipients);
+    (
+        StatusCode::OK,
+        format!("sent to {} connections", recipients),
+    )
+}
+
+/// Returns whether the request carries [`ServerConfig::admin_token`], shared by all admin endpoints.
+pub(crate) fn is_authorized(config: &ServerConfig, headers: &HeaderMap) -> bool {
+    config
+        .admin_token
+        .as_deref()
+        .zip(bearer_token(headers))
+        .is_some_and(|(admin_token, token)| tokens_match(admin_token, token))
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Compares all bytes regardless of where the first difference is, so timing doesn't reveal the token.
+fn tokens_match(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |difference, (expected, actual)| {
+                difference | (expected ^ actual)
+            })
+            == 0
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc;
+    use wasm_peers_protocol::UserId;
+
+    use super::*;
+
+    fn config() -> ServerConfig {
+        ServerConfig {
+            admin_token: Some("secret".to_string()),
+            ..ServerConfig::default()
+        }
+    }
+
+    fn headers(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_notice_is_sent_to_every_connection() {
+        let connections = Connections::default();
+        let mut receivers = Vec::new();
+        for user_id in [UserId::new(1), UserId::new(2)] {
+            let (tx, rx) = mpsc::unbounded_channel();
+            connections.write().await.insert(user_id, tx);
+            receivers.push(rx);
+        }
+
+        let (status, _) = broadcast(
+            &connections,
+            &config(),
+            &LastBroadcast::default(),
+            &headers("secret"),
+            "restarting soon".to_string(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        for rx in &mut receivers {
+            let message = match rx.try_recv() {
+                Ok(Message::Text(message)) => message,
+                other => panic!("unexpected message: {:?}", other),
+            };
+            assert!(matches!(
+                serde_json::from_str(&message),
+                Ok(SignalMessage::ServerNotice(notice)) if notice == "restarting soon"
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wrong_token_is_rejected() {
+        let (status, _) = broadcast(
+            &Connections::default(),
+            &config(),
+            &LastBroadcast::default(),
+            &headers("guess"),
+            "notice".to_string(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_broadcast_is_rate_limited() {
+        let connections = Connections::default();
+        let last_broadcast = LastBroadcast::default();
+
+        let mut statuses = Vec::new();
+        for notice in ["first", "second"] {
+            let (status, _) = broadcast(
+                &connections,
+                &config(),
+                &last_broadcast,
+                &headers("secret"),
+                notice.to_string(),
+            )
+            .await;
+            statuses.push(status);
+        }
+
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS]);
+    }
+}
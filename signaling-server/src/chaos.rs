@@ -0,0 +1,83 @@
+/*!
+Artificial signaling latency, for testing how clients cope with a slow server.
+
+Only compiled with the `chaos` feature, and only active when [`ServerConfig::injected_latency`] is also set,
+so it can't be enabled by configuration alone in a production build.
+Each message a user sends is held for a latency drawn from the [`LatencyDistribution`]
+before the server handles it. Later messages of the same user wait behind it,
+so messages are still handled in the order they were sent.
+*/
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use crate::config::ServerConfig;
+
+/// Latency added to each message, see [`crate::chaos`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyDistribution {
+    /// Same latency for every message.
+    Fixed(Duration),
+    /// Latency drawn uniformly between `min` and `max`, inclusive.
+    Uniform { min: Duration, max: Duration },
+}
+
+impl LatencyDistribution {
+    pub(crate) fn is_valid(&self) -> bool {
+        match self {
+            LatencyDistribution::Fixed(_) => true,
+            LatencyDistribution::Uniform { min, max } => min <= max,
+        }
+    }
+
+    fn sample(&self) -> Duration {
+        match *self {
+            LatencyDistribution::Fixed(latency) => latency,
+            LatencyDistribution::Uniform { min, max } => {
+                let range = u64::try_from((max - min).as_micros()).unwrap_or(u64::MAX);
+                // not worth a dependency, every `RandomState` is seeded differently
+                let random = RandomState::new().build_hasher().finish();
+                min + Duration::from_micros(random % range.saturating_add(1))
+            }
+        }
+    }
+}
+
+/// Waits for the injected latency, if any.
+pub(crate) async fn delay(config: &ServerConfig) {
+    if let Some(distribution) = &config.injected_latency {
+        tokio::time::sleep(distribution.sample()).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_uniform_latency_stays_within_bounds() {
+        let min = Duration::from_millis(100);
+        let max = Duration::from_millis(200);
+        let distribution = LatencyDistribution::Uniform { min, max };
+
+        for _ in 0..1000 {
+            let latency = distribution.sample();
+            assert!(min <= latency && latency <= max, "{:?}", latency);
+        }
+        assert!(!LatencyDistribution::Uniform { min: max, max: min }.is_valid());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_messages_are_held_for_injected_latency() {
+        let config = ServerConfig {
+            injected_latency: Some(LatencyDistribution::Fixed(Duration::from_millis(250))),
+            ..ServerConfig::default()
+        };
+        let start = tokio::time::Instant::now();
+
+        delay(&config).await;
+
+        assert_eq!(start.elapsed(), Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,575 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use wasm_peers_protocol::one_to_many::MAX_RELAY_LENGTH;
+use wasm_peers_protocol::SessionId;
+
+#[cfg(feature = "chaos")]
+use crate::chaos::LatencyDistribution;
+use crate::lifecycle_log::LifecycleLogConfig;
+use crate::maintenance::Maintenance;
+use crate::negotiation_limit::NegotiationLimit;
+use crate::offerer::{FirstSlot, OffererStrategy};
+use crate::region;
+use crate::relay_authorizer::{AllowAll, RelayAuthorizer};
+use crate::relay_validation::RelayValidation;
+use crate::sdp_filter::SdpFilter;
+use crate::session_allowlist::SessionAllowlist;
+use crate::session_log::SessionLogConfig;
+use crate::transcript::Transcripts;
+
+/// Settings of the signaling server shared by all of its connections.
+///
+/// Settings can be overridden for each of the topologies separately,
+/// otherwise the top-level values apply, see [`ServerConfig::for_topology`].
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Maximum number of renegotiations, that is `SDP` offers sent after the first one,
+    /// allowed in a single session (or between a single pair of peers, for one-to-many and many-to-many).
+    /// Further offers are rejected with an error.
+    pub max_renegotiations: usize,
+    /// How often the server pings each connection to check that it's still alive.
+    pub heartbeat_interval: Duration,
+    /// How long the server waits for any message, including a pong, before it drops the connection.
+    /// Must be longer than [`ServerConfig::heartbeat_interval`].
+    pub heartbeat_timeout: Duration,
+    /// How long a connection can go without sending any signaling message before it's dropped,
+    /// e.g. one that never joins a session. Unlike [`ServerConfig::heartbeat_timeout`],
+    /// pongs don't count, so it also drops connections that are alive but unused.
+    /// Clients that stay connected to the server for the whole session only send signaling messages
+    /// while connecting, so it's long by default.
+    pub idle_timeout: Option<Duration>,
+    /// Number of malformed messages in a row, e.g. invalid `JSON`, after which the connection is dropped.
+    /// Any valid message resets the count, so occasional bad messages are tolerated.
+    pub max_consecutive_malformed_messages: usize,
+    /// Number of one-to-one messages about a session the user isn't in, e.g. sent before `SessionJoin`,
+    /// after which the connection is dropped. Each of them is answered with an error either way.
+    /// `None` by default, as these usually come from buggy clients rather than hostile ones.
+    pub max_messages_outside_session: Option<usize>,
+    /// How long one-to-one users have after `SessionReady` to pass an `SdpAnswer` between them,
+    /// before both are sent `NegotiationTimeout`. Negotiation isn't watched by default.
+    pub negotiation_timeout: Option<Duration>,
+    /// Send `SameNetworkHint` to both users of a one-to-one session joined by id when they connect
+    /// from the same address, so they're likely on the same local network, where host candidates
+    /// connect even if the router doesn't support hairpinning. It's logged either way.
+    /// Off by default, as it tells users about each other's network. Addresses are only known
+    /// for raw TCP connections and websockets served with connect info, see [`crate::lifecycle_log`],
+    /// and behind a reverse proxy all users seem to connect from the proxy.
+    pub same_network_hints: bool,
+    /// Limit on one-to-one sessions negotiating at once, see [`crate::negotiation_limit`].
+    /// [`crate::negotiation_limit::DEFAULT_MAX_NEGOTIATIONS`] by default, each negotiation holding its slot
+    /// for at most [`crate::negotiation_limit::DEFAULT_SLOT_TIMEOUT`].
+    /// It's shared by clones of the config like [`Self::maintenance`].
+    pub negotiation_limit: NegotiationLimit,
+    /// What happens to one-to-one `IceCandidate` messages sent while the other user isn't in the session,
+    /// e.g. because it's reconnecting. Candidates are buffered briefly by default.
+    pub early_ice_candidates: EarlyIceCandidates,
+    /// Pair one-to-one users sending `FindMatch` with the same criteria into new sessions.
+    pub matchmaking: bool,
+    /// How long a user waits for a match before it's told that none was found.
+    pub matchmaking_timeout: Duration,
+    /// Maximum number of users waiting for a match at the same time, further ones are rejected.
+    pub max_waiting_users: usize,
+    /// Maximum number of criteria a single user can wait for a match with at the same time.
+    pub max_waits_per_user: usize,
+    /// Maximum number of `FindMatch` messages a single connection can send each minute,
+    /// further ones are rejected until the minute is over.
+    pub max_match_requests_per_minute: usize,
+    /// How long users waiting for a match are only paired with users in the same region,
+    /// after which users in any region will do, see [`crate::region`].
+    /// Doesn't matter unless connections are tagged with regions.
+    pub matchmaking_region_affinity: Duration,
+    /// Region tag of connections that aren't tagged with [`ServerConfig::region_header`],
+    /// e.g. the region this server instance runs in, see [`crate::region`]. Untagged by default.
+    pub region: Option<String>,
+    /// Name of the websocket upgrade request header, e.g. `x-region`, carrying the region tag
+    /// of the connection, see [`crate::region`]. Clients can send any value,
+    /// so it's meant to be set by a proxy in front of the server. Disabled by default.
+    pub region_header: Option<String>,
+    /// Also accept one-to-one signaling over raw TCP on this address, see [`crate::tcp`].
+    pub tcp_address: Option<SocketAddr>,
+    /// Maximum number of bytes per second the server relays with `RelayTo` or `Publish` in a single session,
+    /// counted once for each recipient. Recipients over the limit don't get the message and the sender gets an error.
+    /// Signaling messages don't count towards the limit.
+    pub max_relay_bytes_per_second: usize,
+    /// Maximum size in bytes of a websocket message or frame a client can send,
+    /// the connection is closed if it sends a larger one.
+    pub max_frame_size: usize,
+    /// Maximum number of users in a single one-to-many or many-to-many session,
+    /// further users trying to join get an error. Unlimited by default.
+    pub max_session_size: Option<usize>,
+    /// Maximum number of topics a single user can be subscribed to with `Subscribe`, in each session.
+    pub max_topics_per_user: usize,
+    /// Maximum number of topics with subscribers in a single one-to-many or many-to-many session.
+    pub max_topics_per_session: usize,
+    /// Users of a one-to-many or many-to-many session allowed to close it for everyone with `CloseSession`.
+    /// Only the owner by default.
+    pub close_session_policy: CloseSessionPolicy,
+    /// Contract each message sent with `RelayTo` or `Publish` must satisfy, see [`crate::relay_validation`].
+    /// Only limits the size by default.
+    pub relay_validation: RelayValidation,
+    /// Decides whether each message sent with `RelayTo` or `Publish` is relayed, see [`crate::relay_authorizer`].
+    /// Allows everything by default.
+    pub relay_authorizer: Arc<dyn RelayAuthorizer>,
+    /// Picks which one-to-one user creates the offer, see [`crate::offerer`].
+    /// User in the first slot of the session offers by default.
+    pub offerer_strategy: Arc<dyn OffererStrategy>,
+    /// Strips parts of relayed `SDP` and `ICE` candidates, e.g. host candidates.
+    /// Nothing is stripped by default, see [`crate::sdp_filter`] before enabling it.
+    pub sdp_filter: SdpFilter,
+    /// Name of the websocket URL query parameter, e.g. `tenant`, whose value namespaces session ids,
+    /// so that users of different tenants never share a session, see [`crate::tenant`].
+    /// Clients can pick any tenant, so it's meant to be set by a proxy in front of the server.
+    /// Disabled by default.
+    pub tenant_parameter: Option<String>,
+    /// Maximum number of tenants with open connections in each topology,
+    /// connections of further tenants are rejected with `503 Service Unavailable`.
+    pub max_tenants: usize,
+    /// Only session ids users can create and join, others are rejected with an error,
+    /// see [`crate::session_allowlist`]. Any session id is allowed by default.
+    pub session_allowlist: Option<SessionAllowlist>,
+    /// Record events of each one-to-one session and export them when it's torn down,
+    /// see [`crate::session_log`]. Disabled by default, as logs contain user ids and timing.
+    pub session_log: Option<SessionLogConfig>,
+    /// Record when one-to-one users connect, join and disconnect, and when sessions are created and closed,
+    /// see [`crate::lifecycle_log`]. Disabled by default.
+    pub lifecycle_log: Option<LifecycleLogConfig>,
+    /// Token required by admin endpoints, e.g. `POST /broadcast`, see [`crate::broadcast`].
+    /// Admin endpoints aren't served unless it's set.
+    pub admin_token: Option<String>,
+    /// Minimum time between two notices broadcast by the operator.
+    pub min_broadcast_interval: Duration,
+    /// Rejects new sessions while enabled, see [`crate::maintenance`].
+    /// It's a shared flag, so clones of the config toggle it for the running server too.
+    pub maintenance: Maintenance,
+    /// One-to-one sessions flagged for a full signaling transcript, see [`crate::transcript`].
+    /// Shared by clones of the config, like [`Self::maintenance`].
+    pub transcripts: Transcripts,
+    /// How long a transcript is captured and kept after its session is flagged.
+    pub transcript_ttl: Duration,
+    /// Path the websocket routes of the topologies are served under, e.g. `/signaling`
+    /// serves one-to-one signaling at `/signaling/one-to-one`, for mounting the server alongside other services.
+    /// Every other path gets a 404 without attempting an upgrade, except for the status page
+    /// and admin endpoints, which stay at their own paths. Empty by default, serving websockets at the root.
+    pub websocket_path_prefix: String,
+    /// Serve an HTML page with connection and session counts at `/`.
+    /// Page never shows session or user ids, but it's still disabled by default.
+    pub status_page: bool,
+    /// Delay every message users send, for testing clients against a slow server, see [`crate::chaos`].
+    #[cfg(feature = "chaos")]
+    pub injected_latency: Option<LatencyDistribution>,
+    /// Overrides for one-to-one topology.
+    pub one_to_one: TopologyOverrides,
+    /// Overrides for one-to-many topology.
+    pub one_to_many: TopologyOverrides,
+    /// Overrides for many-to-many topology.
+    pub many_to_many: TopologyOverrides,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            max_renegotiations: 16,
+            // lenient enough for mobile networks, where connections stall for a while
+            heartbeat_interval: Duration::from_secs(30),
+            heartbeat_timeout: Duration::from_secs(90),
+            idle_timeout: Some(Duration::from_secs(60 * 60)),
+            max_consecutive_malformed_messages: 10,
+            max_messages_outside_session: None,
+            negotiation_timeout: None,
+            same_network_hints: false,
+            negotiation_limit: NegotiationLimit::default(),
+            early_ice_candidates: EarlyIceCandidates::Buffer {
+                max_age: Duration::from_secs(5),
+                max_count: 32,
+            },
+            matchmaking: false,
+            matchmaking_timeout: Duration::from_secs(60),
+            max_waiting_users: 10_000,
+            max_waits_per_user: 1,
+            max_match_requests_per_minute: 30,
+            matchmaking_region_affinity: Duration::from_secs(5),
+            region: None,
+            region_header: None,
+            tcp_address: None,
+            max_relay_bytes_per_second: 1024 * 1024,
+            max_frame_size: 1024 * 1024,
+            max_session_size: None,
+            max_topics_per_user: 16,
+            max_topics_per_session: 256,
+            close_session_policy: CloseSessionPolicy::Owner,
+            relay_validation: RelayValidation::default(),
+            relay_authorizer: Arc::new(AllowAll),
+            offerer_strategy: Arc::new(FirstSlot),
+            sdp_filter: SdpFilter::default(),
+            tenant_parameter: None,
+            max_tenants: 1000,
+            session_allowlist: None,
+            session_log: None,
+            lifecycle_log: None,
+            admin_token: None,
+            min_broadcast_interval: Duration::from_secs(10),
+            maintenance: Maintenance::default(),
+            transcripts: Transcripts::default(),
+            transcript_ttl: Duration::from_secs(300),
+            websocket_path_prefix: String::new(),
+            status_page: false,
+            #[cfg(feature = "chaos")]
+            injected_latency: None,
+            one_to_one: TopologyOverrides::default(),
+            one_to_many: TopologyOverrides::default(),
+            many_to_many: TopologyOverrides::default(),
+        }
+    }
+}
+
+/// Network topologies served by the signaling server, each on its own route.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Topology {
+    /// Two equal peers.
+    OneToOne,
+    /// Single host and any number of clients.
+    OneToMany,
+    /// Any number of equal peers, each connected to every other one.
+    ManyToMany,
+}
+
+/// Handling of [`ServerConfig::early_ice_candidates`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EarlyIceCandidates {
+    /// Hold the candidates and pass them on when the other user joins, unless they're older than `max_age`.
+    /// At most `max_count` candidates are held per session, further ones are dropped.
+    Buffer { max_age: Duration, max_count: usize },
+    /// Drop the candidates silently.
+    Drop,
+    /// Send an error to the user who sent the candidate.
+    Reject,
+}
+
+/// Handling of [`ServerConfig::close_session_policy`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CloseSessionPolicy {
+    /// Only the session owner, see `TransferOwnership`.
+    Owner,
+    /// Any user in the session.
+    AnyMember,
+    /// Nobody, sessions are only closed when their last user leaves or by an operator, see [`crate::admin`].
+    Nobody,
+}
+
+/// Settings that can differ between topologies.
+/// Fields left as `None` inherit the value from [`ServerConfig`].
+#[derive(Debug, Clone, Default)]
+pub struct TopologyOverrides {
+    /// Overrides [`ServerConfig::max_renegotiations`].
+    pub max_renegotiations: Option<usize>,
+    /// Overrides [`ServerConfig::max_frame_size`].
+    pub max_frame_size: Option<usize>,
+    /// Overrides [`ServerConfig::max_relay_bytes_per_second`].
+    pub max_relay_bytes_per_second: Option<usize>,
+    /// Overrides [`ServerConfig::max_match_requests_per_minute`].
+    pub max_match_requests_per_minute: Option<usize>,
+    /// Overrides [`ServerConfig::max_session_size`], e.g. a strict cap for many-to-many meshes.
+    /// A topology can only set its own limit, not lift a global one.
+    pub max_session_size: Option<usize>,
+}
+
+/// All problems found by [`ServerConfig::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigErrors {
+    /// Description of each problem, in the order settings are checked.
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.problems.join("; "))
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+impl ServerConfig {
+    /// Checks that the settings are consistent, reporting all problems at once.
+    pub fn validate(&self) -> Result<(), ConfigErrors> {
+        let mut problems = Vec::new();
+        if self.heartbeat_interval.is_zero() {
+            problems.push("heartbeat interval must not be zero".to_string());
+        }
+        if self.heartbeat_timeout <= self.heartbeat_interval {
+            problems.push(format!(
+                "heartbeat timeout ({:?}) must be longer than heartbeat interval ({:?})",
+                self.heartbeat_timeout, self.heartbeat_interval
+            ));
+        }
+        if self.idle_timeout.is_some_and(|timeout| timeout.is_zero()) {
+            problems.push("idle timeout must not be zero".to_string());
+        }
+        if self.max_consecutive_malformed_messages == 0 {
+            problems.push(
+                "maximum number of consecutive malformed messages must not be zero".to_string(),
+            );
+        }
+        if self.max_messages_outside_session == Some(0) {
+            problems
+                .push("maximum number of messages outside session must not be zero".to_string());
+        }
+        if self
+            .negotiation_timeout
+            .is_some_and(|timeout| timeout.is_zero())
+        {
+            problems.push("negotiation timeout must not be zero".to_string());
+        }
+        if self.negotiation_limit.max_negotiations() == Some(0) {
+            problems.push("maximum number of concurrent negotiations must not be zero".to_string());
+        }
+        if self
+            .negotiation_limit
+            .slot_timeout()
+            .is_some_and(|timeout| timeout.is_zero())
+        {
+            problems.push("negotiation slot timeout must not be zero".to_string());
+        }
+        if let EarlyIceCandidates::Buffer { max_age, max_count } = self.early_ice_candidates {
+            if max_age.is_zero() || max_count == 0 {
+                problems.push(
+                    "buffering early ICE candidates requires non-zero maximum age and count"
+                        .to_string(),
+                );
+            }
+        }
+        if self.tenant_parameter.is_some() && self.max_tenants == 0 {
+            problems.push("maximum number of tenants must not be zero".to_string());
+        }
+        if self.transcript_ttl.is_zero() {
+            problems.push("transcript TTL must not be zero".to_string());
+        }
+        if self.matchmaking && self.matchmaking_timeout.is_zero() {
+            problems.push("matchmaking timeout must not be zero".to_string());
+        }
+        if self
+            .region
+            .as_ref()
+            .is_some_and(|region| !region::is_valid(region))
+        {
+            problems.push(format!(
+                "region must be 1 to {} ASCII letters, digits, '-', '_' or '.'",
+                region::MAX_REGION_LENGTH
+            ));
+        }
+        if self.region_header.as_ref().is_some_and(|region_header| {
+            axum::http::HeaderName::from_bytes(region_header.as_bytes()).is_err()
+        }) {
+            problems.push("region header must be a valid header name".to_string());
+        }
+        if self.max_topics_per_user == 0 || self.max_topics_per_session == 0 {
+            problems.push(
+                "maximum numbers of topics per user and session must not be zero".to_string(),
+            );
+        }
+        if self.relay_validation.max_length == 0
+            || self.relay_validation.max_length > MAX_RELAY_LENGTH
+        {
+            problems.push(format!(
+                "relay validation maximum length must be between 1 and {}",
+                MAX_RELAY_LENGTH
+            ));
+        }
+        if self.relay_validation.required_field.is_some() && !self.relay_validation.require_json {
+            problems.push("relay validation required field requires JSON validation".to_string());
+        }
+        if !self.websocket_path_prefix.is_empty()
+            && (!self.websocket_path_prefix.starts_with('/')
+                || self.websocket_path_prefix.ends_with('/')
+                || self.websocket_path_prefix.contains([':', '*']))
+        {
+            problems.push(format!(
+                "websocket path prefix ({:?}) must start with '/', must not end with it and must not contain ':' or '*'",
+                self.websocket_path_prefix
+            ));
+        }
+        if self
+            .session_log
+            .as_ref()
+            .is_some_and(|session_log| session_log.max_events == 0)
+        {
+            problems.push("session log must keep at least one event".to_string());
+        }
+        if self
+            .admin_token
+            .as_ref()
+            .is_some_and(|admin_token| admin_token.is_empty())
+        {
+            problems.push("admin token must not be empty".to_string());
+        }
+        problems.extend(self.limit_problems());
+        // overridden limits are checked too, without repeating problems of the inherited ones
+        for topology in [
+            Topology::OneToOne,
+            Topology::OneToMany,
+            Topology::ManyToMany,
+        ] {
+            for problem in self.for_topology(topology).limit_problems() {
+                if !problems.contains(&problem) {
+                    problems.push(format!("{:?} override: {}", topology, problem));
+                }
+            }
+        }
+        #[cfg(feature = "chaos")]
+        if self
+            .injected_latency
+            .is_some_and(|distribution| !distribution.is_valid())
+        {
+            problems.push("injected latency minimum must not exceed its maximum".to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigErrors { problems })
+        }
+    }
+
+    /// Problems of the settings [`TopologyOverrides`] can override.
+    fn limit_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.max_relay_bytes_per_second < MAX_RELAY_LENGTH {
+            problems.push(format!(
+                "relay bandwidth ({} bytes per second) must allow at least one message of maximum length ({} bytes)",
+                self.max_relay_bytes_per_second, MAX_RELAY_LENGTH
+            ));
+        }
+        if self.max_frame_size == 0 {
+            problems.push("maximum frame size must not be zero".to_string());
+        }
+        if self.max_session_size == Some(0) {
+            problems.push("maximum session size must not be zero".to_string());
+        }
+        problems
+    }
+
+    /// Returns `false` if [`ServerConfig::session_allowlist`] is set and doesn't allow the session id.
+    pub fn allows_session(&self, session_id: &SessionId) -> bool {
+        self.session_allowlist
+            .as_ref()
+            .is_none_or(|allowlist| allowlist.allows(session_id))
+    }
+
+    /// Returns the config with overrides for given topology applied.
+    pub fn for_topology(&self, topology: Topology) -> ServerConfig {
+        let overrides = match topology {
+            Topology::OneToOne => &self.one_to_one,
+            Topology::OneToMany => &self.one_to_many,
+            Topology::ManyToMany => &self.many_to_many,
+        };
+        ServerConfig {
+            max_renegotiations: overrides
+                .max_renegotiations
+                .unwrap_or(self.max_renegotiations),
+            max_frame_size: overrides.max_frame_size.unwrap_or(self.max_frame_size),
+            max_relay_bytes_per_second: overrides
+                .max_relay_bytes_per_second
+                .unwrap_or(self.max_relay_bytes_per_second),
+            max_match_requests_per_minute: overrides
+                .max_match_requests_per_minute
+                .unwrap_or(self.max_match_requests_per_minute),
+            max_session_size: overrides.max_session_size.or(self.max_session_size),
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert!(ServerConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_heartbeat_timeout_not_exceeding_interval_is_invalid() {
+        let config = ServerConfig {
+            heartbeat_interval: Duration::from_secs(10),
+            heartbeat_timeout: Duration::from_secs(10),
+            ..ServerConfig::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation_reports_every_problem() {
+        let config = ServerConfig {
+            heartbeat_interval: Duration::ZERO,
+            heartbeat_timeout: Duration::ZERO,
+            max_relay_bytes_per_second: 0,
+            ..ServerConfig::default()
+        };
+
+        assert_eq!(config.validate().unwrap_err().problems.len(), 3);
+    }
+
+    #[test]
+    fn test_topology_without_overrides_inherits_global_config() {
+        let config = ServerConfig {
+            max_renegotiations: 3,
+            ..ServerConfig::default()
+        };
+
+        assert_eq!(
+            config.for_topology(Topology::OneToOne).max_renegotiations,
+            3
+        );
+    }
+
+    #[test]
+    fn test_topology_overrides_apply_only_to_their_topology() {
+        let config = ServerConfig {
+            max_renegotiations: 3,
+            many_to_many: TopologyOverrides {
+                max_renegotiations: Some(1),
+                max_session_size: Some(8),
+                ..TopologyOverrides::default()
+            },
+            ..ServerConfig::default()
+        };
+
+        assert_eq!(
+            config.for_topology(Topology::ManyToMany).max_renegotiations,
+            1
+        );
+        assert_eq!(
+            config.for_topology(Topology::OneToMany).max_renegotiations,
+            3
+        );
+        assert_eq!(
+            config.for_topology(Topology::ManyToMany).max_session_size,
+            Some(8)
+        );
+        assert_eq!(
+            config.for_topology(Topology::OneToMany).max_session_size,
+            None
+        );
+    }
+
+    #[test]
+    fn test_invalid_topology_override_is_reported() {
+        let config = ServerConfig {
+            one_to_many: TopologyOverrides {
+                max_frame_size: Some(0),
+                ..TopologyOverrides::default()
+            },
+            ..ServerConfig::default()
+        };
+
+        assert_eq!(
+            config.validate().unwrap_err().problems,
+            vec!["OneToMany override: maximum frame size must not be zero".to_string()]
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+
+use axum::extract::ws::Message;
+use serde::de::DeserializeOwned;
+
+/// Error for a message that isn't a valid signaling message, e.g. invalid `JSON`,
+/// as opposed to a valid message that can't be handled in the current state of the session.
+#[derive(Debug)]
+pub(crate) struct MalformedMessage(String);
+
+impl fmt::Display for MalformedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed message: {}", self.0)
+    }
+}
+
+impl std::error::Error for MalformedMessage {}
+
+/// Error for a valid message about a session the user isn't in, e.g. sent before joining it.
+/// These come from buggy clients more often than hostile ones, so they're counted separately.
+#[derive(Debug)]
+pub(crate) struct NotInSession;
+
+impl fmt::Display for NotInSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("message for a session the user isn't in")
+    }
+}
+
+impl std::error::Error for NotInSession {}
+
+/// Reads a signaling message of any of the topologies.
+pub(crate) fn parse_message<T: DeserializeOwned>(msg: &Message) -> Result<T, MalformedMessage> {
+    let msg = msg
+        .to_text()
+        .map_err(|_err| MalformedMessage("websocket message is not text".to_string()))?;
+    serde_json::from_str(msg).map_err(|err| MalformedMessage(err.to_string()))
+}
+
+/// Counts malformed messages sent by a connection in a row, so that a client
+/// sending nothing but garbage gets dropped, while an occasional bad message is tolerated.
+/// Optionally also drops clients that keep sending messages for sessions they aren't in.
+pub(crate) struct ErrorBudget {
+    max_consecutive: usize,
+    consecutive: usize,
+    max_outside_session: Option<usize>,
+    outside_session: usize,
+}
+
+impl ErrorBudget {
+    pub(crate) fn new(max_consecutive: usize) -> Self {
+        ErrorBudget {
+            max_consecutive,
+            consecutive: 0,
+            max_outside_session: None,
+            outside_session: 0,
+        }
+    }
+
+    /// Also drops the connection after this many [`NotInSession`] errors in total, if set.
+    pub(crate) fn with_max_outside_session(mut self, max_outside_session: Option<usize>) -> Self {
+        self.max_outside_session = max_outside_session;
+        self
+    }
+
+    /// Records the outcome of handling a message, returns why the connection should be dropped
+    /// once the budget is used up. Valid messages reset the count of malformed ones,
+    /// errors other than [`MalformedMessage`] and [`NotInSession`] don't affect the budget.
+    pub(crate) fn exhausted_by(&mut self, result: &anyhow::Result<()>) -> Option<&'static str> {
+        match result {
+            Err(err) if err.is::<MalformedMessage>() => self.consecutive += 1,
+            Err(err) if err.is::<NotInSession>() => self.outside_session += 1,
+            Err(_) => {}
+            Ok(()) => self.consecutive = 0,
+        }
+        if self.consecutive >= self.max_consecutive {
+            Some("too many malformed messages")
+        } else if self
+            .max_outside_session
+            .is_some_and(|max_outside_session| self.outside_session >= max_outside_session)
+        {
+            Some("too many messages for sessions the user isn't in")
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    fn malformed() -> anyhow::Result<()> {
+        Err(MalformedMessage("garbage".to_string()).into())
+    }
+
+    #[test]
+    fn test_budget_is_exhausted_by_consecutive_malformed_messages_only() {
+        let mut budget = ErrorBudget::new(2);
+
+        assert!(budget.exhausted_by(&malformed()).is_none());
+        assert!(budget.exhausted_by(&Ok(())).is_none());
+        assert!(budget.exhausted_by(&malformed()).is_none());
+        assert!(budget
+            .exhausted_by(&Err(anyhow!("no such session")))
+            .is_none());
+        assert!(budget.exhausted_by(&Err(NotInSession.into())).is_none());
+        assert!(budget.exhausted_by(&malformed()).is_some());
+    }
+
+    #[test]
+    fn test_budget_is_exhausted_by_messages_outside_session_only_if_limited() {
+        let mut budget = ErrorBudget::new(2).with_max_outside_session(Some(2));
+
+        assert!(budget.exhausted_by(&Err(NotInSession.into())).is_none());
+        assert!(budget.exhausted_by(&Ok(())).is_none());
+        assert_eq!(
+            budget.exhausted_by(&Err(NotInSession.into())),
+            Some("too many messages for sessions the user isn't in")
+        );
+    }
+
+    #[test]
+    fn test_parse_message_rejects_invalid_json() {
+        let result = parse_message::<serde_json::Value>(&Message::Text("{".to_string()));
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use axum::extract::ws::Message;
+use futures_util::{Stream, StreamExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Periodically sends a ping to the connection, so that the client responds with a pong
+/// and dead connections can be detected even when there is no signaling going on.
+/// The task has to be aborted once the connection is closed.
+pub(crate) fn spawn_pings(
+    tx: mpsc::UnboundedSender<Message>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        // first tick completes immediately
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if tx.send(Message::Ping(Vec::new())).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Returns true for pings and pongs, which only keep the connection alive
+/// and aren't handled as signaling messages.
+pub(crate) fn is_heartbeat(msg: &Message) -> bool {
+    matches!(msg, Message::Ping(_) | Message::Pong(_))
+}
+
+/// Which of the timeouts dropped the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Timeout {
+    /// No message at all, not even a pong, within [`crate::config::ServerConfig::heartbeat_timeout`].
+    Heartbeat,
+    /// No signaling message within [`crate::config::ServerConfig::idle_timeout`].
+    Idle,
+}
+
+/// Tracks when the connection last sent a signaling message, heartbeats don't count.
+pub(crate) struct IdleTimer {
+    timeout: Option<Duration>,
+    last_message: Instant,
+}
+
+impl IdleTimer {
+    /// Timer that never expires if `timeout` is `None`.
+    pub(crate) fn new(timeout: Option<Duration>) -> Self {
+        IdleTimer {
+            timeout,
+            last_message: Instant::now(),
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.last_message = Instant::now();
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        self.timeout
+            .map(|timeout| timeout.saturating_sub(self.last_message.elapsed()))
+    }
+}
+
+/// Waits for the next message on the connection, until whichever of the timeouts comes first.
+pub(crate) async fn next_message<S: Stream + Unpin>(
+    stream: &mut S,
+    heartbeat_timeout: Option<Duration>,
+    idle_timer: &IdleTimer,
+) -> Result<Option<S::Item>, Timeout> {
+    let idle_remaining = idle_timer.remaining();
+    let wait = match (heartbeat_timeout, idle_remaining) {
+        (Some(heartbeat_timeout), Some(idle_remaining)) => {
+            Some(heartbeat_timeout.min(idle_remaining))
+        }
+        (heartbeat_timeout, idle_remaining) => heartbeat_timeout.or(idle_remaining),
+    };
+    match wait {
+        Some(wait) => tokio::time::timeout(wait, stream.next())
+            .await
+            .map_err(|_| {
+                if idle_remaining == Some(wait) {
+                    Timeout::Idle
+                } else {
+                    Timeout::Heartbeat
+                }
+            }),
+        None => Ok(stream.next().await),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_idle_timeout_ignores_heartbeats() {
+        let pongs = futures_util::stream::iter((0..).map(|_| Message::Pong(Vec::new()))).then(
+            |pong| async {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                pong
+            },
+        );
+        let mut pongs = Box::pin(pongs);
+        let idle_timer = IdleTimer::new(Some(Duration::from_millis(30)));
+
+        let timeout = loop {
+            match next_message(&mut pongs, Some(Duration::from_millis(20)), &idle_timer).await {
+                Ok(Some(msg)) => assert!(is_heartbeat(&msg)),
+                Ok(None) => panic!("stream ended"),
+                Err(timeout) => break timeout,
+            }
+        };
+
+        assert_eq!(timeout, Timeout::Idle);
+    }
+
+    #[tokio::test]
+    async fn test_silent_connection_hits_heartbeat_timeout_first() {
+        let mut silent = futures_util::stream::pending::<Message>();
+        let idle_timer = IdleTimer::new(Some(Duration::from_secs(60)));
+
+        let result = next_message(&mut silent, Some(Duration::from_millis(10)), &idle_timer).await;
+
+        assert_eq!(result.err(), Some(Timeout::Heartbeat));
+    }
+}
@@ -1,2 +1,33 @@
+pub mod admin;
+mod bandwidth;
+pub mod broadcast;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod config;
+mod error_budget;
+mod heartbeat;
+pub mod lifecycle_log;
+pub mod maintenance;
+pub mod many_to_many;
+pub mod matchmaking;
+pub mod memory;
+pub mod negotiation_limit;
+pub mod offerer;
+pub mod one_to_many;
 pub mod one_to_one;
-pub mod router;
\ No newline at end of file
+pub mod region;
+pub mod relay_authorizer;
+pub mod relay_validation;
+pub mod router;
+pub mod sdp_filter;
+mod serialization;
+pub mod session_allowlist;
+pub mod session_log;
+pub mod status;
+pub mod tcp;
+pub mod tenant;
+#[cfg(test)]
+mod test_util;
+pub mod transcript;
+#[cfg(unix)]
+pub mod unix_socket;
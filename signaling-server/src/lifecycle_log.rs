@@ -0,0 +1,152 @@
+/*!
+Structured record of connection and session lifecycle, for operators who need an audit trail.
+
+Enabled by setting [`crate::config::ServerConfig::lifecycle_log`], which passes a [`LifecycleRecord`]
+to the configured [`LifecycleLogSink`] when a one-to-one user connects, joins a session or disconnects,
+and when a session is created or closed because its last user left.
+Records have fixed fields with stable names, independent of the debug logs, fields that don't apply
+to an event are `null`. Unlike [`crate::session_log`], records are passed on as they happen,
+so [`crate::session_log::JsonLinesFile`] can store both logs.
+
+Remote IP addresses are left out unless enabled. They are known for raw TCP connections
+and for websockets if the server is served with connect info, e.g.
+`router.into_make_service_with_connect_info::<SocketAddr>()`.
+*/
+
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::error;
+use serde::Serialize;
+use wasm_peers_protocol::{SessionId, UserId};
+
+use crate::config::ServerConfig;
+use crate::session_log::JsonLinesFile;
+
+/// Settings of [`crate::config::ServerConfig::lifecycle_log`].
+#[derive(Debug, Clone)]
+pub struct LifecycleLogConfig {
+    /// Receives each record.
+    pub sink: Arc<dyn LifecycleLogSink>,
+    /// Record remote IP address of each connection.
+    pub include_remote_ip: bool,
+}
+
+/// Destination of lifecycle records, see [`crate::lifecycle_log`].
+pub trait LifecycleLogSink: fmt::Debug + Send + Sync {
+    /// Stores the record. Called on the async runtime in the order events happen,
+    /// so it must not block for long, appending to a local file is fine.
+    fn write(&self, record: &LifecycleRecord);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    Connected,
+    Joined,
+    Disconnected,
+    SessionCreated,
+    SessionClosed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LifecycleRecord {
+    pub event: LifecycleEvent,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub user_id: Option<UserId>,
+    pub session_id: Option<SessionId>,
+    /// Left out unless [`LifecycleLogConfig::include_remote_ip`] is set.
+    pub remote_ip: Option<IpAddr>,
+}
+
+/// Passes the record to the sink, if lifecycle logs are enabled.
+pub(crate) fn record(
+    config: &ServerConfig,
+    event: LifecycleEvent,
+    user_id: Option<UserId>,
+    session_id: Option<&SessionId>,
+    remote_ip: Option<IpAddr>,
+) {
+    let lifecycle_log = match &config.lifecycle_log {
+        Some(lifecycle_log) => lifecycle_log,
+        None => return,
+    };
+    lifecycle_log.sink.write(&LifecycleRecord {
+        event,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| {
+                u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX)
+            }),
+        user_id,
+        session_id: session_id.cloned(),
+        remote_ip: remote_ip.filter(|_| lifecycle_log.include_remote_ip),
+    });
+}
+
+impl LifecycleLogSink for JsonLinesFile {
+    fn write(&self, record: &LifecycleRecord) {
+        if let Err(err) = self.append(record) {
+            error!("failed to write lifecycle record: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_records_have_fixed_fields_and_redact_remote_ip() {
+        let path =
+            std::env::temp_dir().join(format!("wasm-peers-lifecycle-{}.jsonl", std::process::id()));
+        let config = ServerConfig {
+            lifecycle_log: Some(LifecycleLogConfig {
+                sink: Arc::new(JsonLinesFile {
+                    path: path.clone(),
+                    max_bytes: 1024,
+                }),
+                include_remote_ip: false,
+            }),
+            ..ServerConfig::default()
+        };
+
+        let remote_ip = Some(IpAddr::from([192, 0, 2, 1]));
+        record(
+            &config,
+            LifecycleEvent::Connected,
+            Some(UserId::new(1)),
+            None,
+            remote_ip,
+        );
+        record(
+            &config,
+            LifecycleEvent::Joined,
+            Some(UserId::new(1)),
+            Some(&SessionId::new("dummy-session-id".to_string())),
+            None,
+        );
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        for record in &records {
+            let mut fields: Vec<_> = record.as_object().unwrap().keys().collect();
+            fields.sort();
+            assert_eq!(
+                fields,
+                ["event", "remote_ip", "session_id", "timestamp", "user_id"]
+            );
+            assert!(record["remote_ip"].is_null());
+        }
+        assert_eq!(records[0]["event"], "connected");
+        assert_eq!(records[1]["session_id"], "dummy-session-id");
+    }
+}
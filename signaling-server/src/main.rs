@@ -2,13 +2,25 @@ use std::net::SocketAddr;
 
 use wasm_peers_signaling_server_axum::router::create_router;
 
+/// Listens on the address given as the first argument, `127.0.0.1:9001` by default,
+/// or on a Unix domain socket if it's given as `unix:<path>`.
 #[tokio::main]
 async fn main() {
     let app = create_router();
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:9001".to_string());
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 9001));
+    #[cfg(unix)]
+    if let Some(path) = address.strip_prefix("unix:") {
+        wasm_peers_signaling_server_axum::unix_socket::serve(app, path.as_ref())
+            .await
+            .unwrap();
+        return;
+    }
+    let addr: SocketAddr = address.parse().expect("invalid listen address");
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
@@ -0,0 +1,102 @@
+/*!
+Maintenance mode, for draining the server before a planned shutdown.
+
+While [`ServerConfig::maintenance`] is enabled, users can't create new sessions, neither by joining
+a session id that doesn't exist yet nor through matchmaking, and get an `Error` with [`MAINTENANCE_ERROR`].
+Existing sessions keep working, including users rejoining or joining them, so they can finish naturally.
+
+Enabling [`ServerConfig::admin_token`] serves `POST /maintenance`, which takes `on` or `off` as its body
+and the token in an `Authorization: Bearer <token>` header, same as [`crate::broadcast`].
+Applications embedding the server can keep a clone of [`ServerConfig::maintenance`] and toggle it directly.
+
+```sh
+curl -X POST -H "Authorization: Bearer $ADMIN_TOKEN" -d "on" http://localhost:9001/maintenance
+```
+*/
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use axum::http::{HeaderMap, StatusCode};
+use log::info;
+
+use crate::broadcast;
+use crate::config::ServerConfig;
+
+/// Error sent to users trying to create a session during maintenance.
+pub const MAINTENANCE_ERROR: &str = "server is in maintenance, no new sessions are accepted";
+
+/// Maintenance flag shared by all clones, disabled by default.
+#[derive(Debug, Clone, Default)]
+pub struct Maintenance(Arc<AtomicBool>);
+
+impl Maintenance {
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Takes effect for joins handled from now on, existing sessions aren't affected either way.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed);
+        info!(
+            "maintenance mode {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+    }
+}
+
+/// Turns maintenance on or off as requested by the body, responding with the new state.
+pub(crate) fn toggle(
+    config: &ServerConfig,
+    headers: &HeaderMap,
+    body: &str,
+) -> (StatusCode, String) {
+    if !broadcast::is_authorized(config, headers) {
+        return (StatusCode::UNAUTHORIZED, "invalid admin token".to_string());
+    }
+    let enabled = match body.trim() {
+        "on" => true,
+        "off" => false,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "body must be `on` or `off`".to_string(),
+            )
+        }
+    };
+    config.maintenance.set_enabled(enabled);
+    (StatusCode::OK, body.trim().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::header;
+
+    use super::*;
+    use crate::config::Topology;
+
+    #[test]
+    fn test_toggle_requires_token_and_valid_body() {
+        let config = ServerConfig {
+            admin_token: Some("secret".to_string()),
+            ..ServerConfig::default()
+        };
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(toggle(&config, &headers, "on").0, StatusCode::UNAUTHORIZED);
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert_eq!(
+            toggle(&config, &headers, "maybe").0,
+            StatusCode::BAD_REQUEST
+        );
+        assert!(!config.maintenance.is_enabled());
+
+        assert_eq!(toggle(&config, &headers, "on\n").0, StatusCode::OK);
+        assert!(config
+            .for_topology(Topology::OneToMany)
+            .maintenance
+            .is_enabled());
+        assert_eq!(toggle(&config, &headers, "off").0, StatusCode::OK);
+        assert!(!config.maintenance.is_enabled());
+    }
+}
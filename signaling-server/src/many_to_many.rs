@@ -0,0 +1,18 @@
+use std::sync::Arc;
+
+use axum::extract::ws::WebSocket;
+
+use crate::config::ServerConfig;
+use crate::one_to_many::{handle_connection, Sessions};
+use crate::one_to_one::Connections;
+
+/// Many-to-many peers use the same signaling messages as one-to-many ones,
+/// but there is no host and every user already in session negotiates with each new one.
+pub async fn user_connected(
+    ws: WebSocket,
+    connections: Connections,
+    sessions: Sessions,
+    config: Arc<ServerConfig>,
+) {
+    handle_connection(ws, connections, sessions, config, true).await;
+}
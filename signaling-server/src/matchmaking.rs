@@ -0,0 +1,672 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use axum::extract::ws::Message;
+use log::{error, info};
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::{SessionId, UserId};
+
+use crate::config::ServerConfig;
+use crate::lifecycle_log::{self, LifecycleEvent};
+use crate::maintenance::MAINTENANCE_ERROR;
+use crate::one_to_one::{self, Connections, Session, Sessions};
+use crate::serialization::serialize_message;
+use crate::session_log::{SessionEventKind, SessionLog};
+
+/// Users waiting for a match for each of the criteria and regions, see [`crate::region`].
+/// There is never more than one per key, as the next user with the same criteria in the same region
+/// is paired with it right away.
+pub type WaitingUsers = Arc<RwLock<HashMap<WaitKey, WaitingUser>>>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WaitKey {
+    pub criteria: String,
+    pub region: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WaitingUser {
+    pub user_id: UserId,
+    pub since: Instant,
+}
+
+/// Error sent to a connection sending `FindMatch` more often than [`ServerConfig::max_match_requests_per_minute`].
+pub const MATCH_REQUESTS_LIMIT_ERROR: &str = "too many match requests";
+
+/// Counts `FindMatch` messages of a connection, see [`ServerConfig::max_match_requests_per_minute`].
+pub(crate) struct MatchRequestLimit {
+    max_requests: usize,
+    minute_start: Instant,
+    requests: usize,
+}
+
+impl MatchRequestLimit {
+    pub(crate) fn new(max_requests: usize) -> Self {
+        MatchRequestLimit {
+            max_requests,
+            minute_start: Instant::now(),
+            requests: 0,
+        }
+    }
+
+    /// Counts a request, returns `false` if the limit of the current minute is already reached.
+    pub(crate) fn allow(&mut self) -> bool {
+        if self.minute_start.elapsed() >= Duration::from_secs(60) {
+            self.minute_start = Instant::now();
+            self.requests = 0;
+        }
+        if self.requests >= self.max_requests {
+            return false;
+        }
+        self.requests += 1;
+        true
+    }
+}
+
+/// Pairs the user with the one waiting for the same criteria in a freshly created session,
+/// or makes it wait for the next one, for at most [`ServerConfig::matchmaking_timeout`].
+/// Users in the same region are preferred, see [`crate::region`].
+pub(crate) async fn find_match(
+    waiting_users: &WaitingUsers,
+    sessions: &Sessions,
+    connections: &Connections,
+    config: &ServerConfig,
+    user_id: UserId,
+    region: Option<&str>,
+    criteria: String,
+) -> anyhow::Result<()> {
+    if !config.matchmaking {
+        return send_error(connections, user_id, "matchmaking is disabled").await;
+    }
+    if config.maintenance.is_enabled() {
+        return send_error(connections, user_id, MAINTENANCE_ERROR).await;
+    }
+
+    let key = WaitKey {
+        criteria,
+        region: region.map(str::to_string),
+    };
+    let waiting_user_id = {
+        let mut waiting_users_writer = waiting_users.write().await;
+        let same_region = match waiting_users_writer.remove(&key) {
+            Some(waiting_user) if waiting_user.user_id != user_id => Some(waiting_user.user_id),
+            // the user itself waits for the same criteria, it waits anew
+            _ => None,
+        };
+        let partner = same_region
+            .or_else(|| take_other_region(&mut waiting_users_writer, &key, user_id, config, false));
+        match partner {
+            Some(waiting_user_id) => {
+                stop_waiting_paired(&mut waiting_users_writer, [waiting_user_id, user_id]);
+                waiting_user_id
+            }
+            None => {
+                let user_waits = waiting_users_writer
+                    .values()
+                    .filter(|waiting_user| waiting_user.user_id == user_id)
+                    .count();
+                let error = if waiting_users_writer.len() >= config.max_waiting_users {
+                    Some("matchmaking queue is full")
+                } else if user_waits >= config.max_waits_per_user {
+                    Some("waiting for too many matches at once")
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    drop(waiting_users_writer);
+                    info!("user {:?} can't wait for a match: {}", user_id, error);
+                    return send_error(connections, user_id, error).await;
+                }
+                info!("user {:?} waits for a match: {:?}", user_id, key);
+                let since = Instant::now();
+                waiting_users_writer.insert(key.clone(), WaitingUser { user_id, since });
+                if key.region.is_some() {
+                    spawn_region_affinity_timeout(
+                        waiting_users.clone(),
+                        sessions.clone(),
+                        connections.clone(),
+                        config,
+                        key.clone(),
+                        since,
+                    );
+                }
+                spawn_timeout(
+                    waiting_users.clone(),
+                    connections.clone(),
+                    config,
+                    WaitingUser { user_id, since },
+                    key,
+                );
+                return Ok(());
+            }
+        }
+    };
+    pair(sessions, connections, config, waiting_user_id, user_id).await
+}
+
+/// Removes and returns the user waiting longest with the same criteria in another region,
+/// if either of the two users waited for [`ServerConfig::matchmaking_region_affinity`] already.
+fn take_other_region(
+    waiting_users: &mut HashMap<WaitKey, WaitingUser>,
+    key: &WaitKey,
+    user_id: UserId,
+    config: &ServerConfig,
+    user_waited: bool,
+) -> Option<UserId> {
+    let other_key = waiting_users
+        .iter()
+        .filter(|(other_key, waiting_user)| {
+            other_key.criteria == key.criteria
+                && other_key.region != key.region
+                && waiting_user.user_id != user_id
+                && (user_waited
+                    || waiting_user.since.elapsed() >= config.matchmaking_region_affinity)
+        })
+        .min_by_key(|(_, waiting_user)| waiting_user.since)
+        .map(|(other_key, _)| other_key.clone())?;
+    waiting_users
+        .remove(&other_key)
+        .map(|waiting_user| waiting_user.user_id)
+}
+
+async fn pair(
+    sessions: &Sessions,
+    connections: &Connections,
+    config: &ServerConfig,
+    waiting_user_id: UserId,
+    user_id: UserId,
+) -> anyhow::Result<()> {
+    let session_id = SessionId::new(uuid::Uuid::new_v4().to_string());
+    info!(
+        "matched users {:?} and {:?} in session {:?}",
+        waiting_user_id, user_id, session_id
+    );
+    let mut session = Session {
+        log: config
+            .session_log
+            .clone()
+            .map(|log_config| SessionLog::new(session_id.clone(), log_config)),
+        ..Session::new(Some(waiting_user_id), Some(user_id))
+    };
+    for joined in [waiting_user_id, user_id] {
+        session.record(SessionEventKind::Joined, Some(joined), None);
+    }
+    sessions.write().await.insert(session_id.clone(), session);
+    lifecycle_log::record(
+        config,
+        LifecycleEvent::SessionCreated,
+        None,
+        Some(&session_id),
+        None,
+    );
+    for joined in [waiting_user_id, user_id] {
+        lifecycle_log::record(
+            config,
+            LifecycleEvent::Joined,
+            Some(joined),
+            Some(&session_id),
+            None,
+        );
+    }
+    for recipient_id in [waiting_user_id, user_id] {
+        send(
+            connections,
+            recipient_id,
+            &SignalMessage::Matched(session_id.clone()),
+        )
+        .await?;
+    }
+    one_to_one::admit(sessions, connections, config, session_id, user_id).await
+}
+
+/// Removes the other waits of users that were just paired, as with [`ServerConfig::max_waits_per_user`]
+/// over one they may still wait for other criteria.
+fn stop_waiting_paired(waiting_users: &mut HashMap<WaitKey, WaitingUser>, users: [UserId; 2]) {
+    waiting_users.retain(|_, waiting_user| !users.contains(&waiting_user.user_id));
+}
+
+/// Stops the user from waiting for a match, e.g. when it disconnects.
+pub(crate) async fn stop_waiting(waiting_users: &WaitingUsers, user_id: UserId) {
+    waiting_users
+        .write()
+        .await
+        .retain(|_, waiting_user| waiting_user.user_id != user_id);
+}
+
+fn spawn_timeout(
+    waiting_users: WaitingUsers,
+    connections: Connections,
+    config: &ServerConfig,
+    waiting_user: WaitingUser,
+    key: WaitKey,
+) {
+    let timeout = config.matchmaking_timeout;
+    let WaitingUser { user_id, since } = waiting_user;
+    tokio::task::spawn(async move {
+        tokio::time::sleep(timeout).await;
+        let timed_out = {
+            let mut waiting_users = waiting_users.write().await;
+            // the user may have waited anew since, then it's timed out by the later timer
+            let timed_out = waiting_users.get(&key).is_some_and(|waiting_user| {
+                waiting_user.user_id == user_id && waiting_user.since == since
+            });
+            if timed_out {
+                waiting_users.remove(&key);
+            }
+            timed_out
+        };
+        if timed_out {
+            info!("user {:?} didn't find a match in time", user_id);
+            let _ = send_error(&connections, user_id, "no match found in time").await;
+        }
+    });
+}
+
+/// Once the user waited for [`ServerConfig::matchmaking_region_affinity`],
+/// pairs it with a user waiting in another region, if there is one.
+fn spawn_region_affinity_timeout(
+    waiting_users: WaitingUsers,
+    sessions: Sessions,
+    connections: Connections,
+    config: &ServerConfig,
+    key: WaitKey,
+    since: Instant,
+) {
+    let config = config.clone();
+    tokio::task::spawn(async move {
+        tokio::time::sleep(config.matchmaking_region_affinity).await;
+        let users = {
+            let mut waiting_users = waiting_users.write().await;
+            match waiting_users.get(&key) {
+                Some(waiting_user) if waiting_user.since == since => {
+                    let user_id = waiting_user.user_id;
+                    let partner_id =
+                        take_other_region(&mut waiting_users, &key, user_id, &config, true);
+                    if let Some(partner_id) = partner_id {
+                        stop_waiting_paired(&mut waiting_users, [partner_id, user_id]);
+                    }
+                    partner_id.map(|partner_id| (partner_id, user_id))
+                }
+                _ => None,
+            }
+        };
+        if let Some((partner_id, user_id)) = users {
+            info!(
+                "pairing user {:?} with a user in another region: {:?}",
+                user_id, key
+            );
+            if let Err(err) = pair(&sessions, &connections, &config, partner_id, user_id).await {
+                error!("failed to pair users: {}", err);
+            }
+        }
+    });
+}
+
+/// Matchmaking errors aren't related to any session, so session id is left empty.
+pub(crate) async fn send_error(
+    connections: &Connections,
+    user_id: UserId,
+    error: &str,
+) -> anyhow::Result<()> {
+    let response = SignalMessage::Error(SessionId::new(String::new()), error.to_string());
+    send(connections, user_id, &response).await
+}
+
+async fn send(
+    connections: &Connections,
+    recipient_id: UserId,
+    response: &SignalMessage,
+) -> anyhow::Result<()> {
+    let response = serialize_message(response)?;
+    let connections_reader = connections.read().await;
+    let recipient_tx = connections_reader
+        .get(&recipient_id)
+        .ok_or_else(|| anyhow!("no sender for given recipient_id"))?;
+    recipient_tx.send(Message::Text(response))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_util::connect;
+    use crate::test_util::one_to_one::received_message;
+
+    fn config() -> ServerConfig {
+        ServerConfig {
+            matchmaking: true,
+            ..ServerConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_users_with_same_criteria_are_matched() {
+        let (waiting_users, sessions, connections) = Default::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+
+        for user_id in [first, second] {
+            find_match(
+                &waiting_users,
+                &sessions,
+                &connections,
+                &config(),
+                user_id,
+                None,
+                "ranked".to_string(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let session_id = match received_message(&mut first_rx) {
+            Some(SignalMessage::Matched(session_id)) => session_id,
+            other => panic!("unexpected message: {:?}", other),
+        };
+        assert!(matches!(
+            received_message(&mut first_rx),
+            Some(SignalMessage::SessionReady(_, true))
+        ));
+        assert!(matches!(
+            received_message(&mut second_rx),
+            Some(SignalMessage::Matched(matched_session_id)) if matched_session_id == session_id
+        ));
+        assert!(matches!(
+            received_message(&mut second_rx),
+            Some(SignalMessage::SessionReady(_, false))
+        ));
+        let sessions = sessions.read().await;
+        let session = sessions.get(&session_id).unwrap();
+        assert_eq!((session.first, session.second), (Some(first), Some(second)));
+        assert!(waiting_users.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_users_with_different_criteria_keep_waiting() {
+        let (waiting_users, sessions, connections) = Default::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let _second_rx = connect(&connections, second).await;
+
+        for (user_id, criteria) in [(first, "ranked"), (second, "casual")] {
+            find_match(
+                &waiting_users,
+                &sessions,
+                &connections,
+                &config(),
+                user_id,
+                None,
+                criteria.to_string(),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert!(received_message(&mut first_rx).is_none());
+        assert_eq!(waiting_users.read().await.len(), 2);
+        assert!(sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_waiting_user_times_out() {
+        let (waiting_users, sessions, connections) = Default::default();
+        let user_id = UserId::new(1);
+        let mut rx = connect(&connections, user_id).await;
+        let config = ServerConfig {
+            matchmaking_timeout: Duration::from_millis(10),
+            ..config()
+        };
+
+        find_match(
+            &waiting_users,
+            &sessions,
+            &connections,
+            &config,
+            user_id,
+            None,
+            "ranked".to_string(),
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(matches!(
+            received_message(&mut rx),
+            Some(SignalMessage::Error(..))
+        ));
+        assert!(waiting_users.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_user_waiting_anew_isnt_timed_out_by_earlier_wait() {
+        let (waiting_users, sessions, connections) = Default::default();
+        let user_id = UserId::new(1);
+        let mut rx = connect(&connections, user_id).await;
+        let config = ServerConfig {
+            matchmaking_timeout: Duration::from_millis(100),
+            ..config()
+        };
+        let wait = || {
+            find_match(
+                &waiting_users,
+                &sessions,
+                &connections,
+                &config,
+                user_id,
+                None,
+                "ranked".to_string(),
+            )
+        };
+
+        wait().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        wait().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        assert!(received_message(&mut rx).is_none());
+        assert_eq!(waiting_users.read().await.len(), 1);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(matches!(
+            received_message(&mut rx),
+            Some(SignalMessage::Error(_, error)) if error == "no match found in time"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_rejects_waiting_user() {
+        let (waiting_users, sessions, connections) = Default::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        let config = ServerConfig {
+            max_waiting_users: 1,
+            ..config()
+        };
+
+        for (user_id, criteria) in [(first, "ranked"), (second, "casual")] {
+            find_match(
+                &waiting_users,
+                &sessions,
+                &connections,
+                &config,
+                user_id,
+                None,
+                criteria.to_string(),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert!(matches!(
+            received_message(&mut second_rx),
+            Some(SignalMessage::Error(..))
+        ));
+        assert_eq!(waiting_users.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_user_cannot_wait_with_more_criteria_than_allowed() {
+        let (waiting_users, sessions, connections) = Default::default();
+        let user_id = UserId::new(1);
+        let mut rx = connect(&connections, user_id).await;
+
+        for criteria in ["ranked", "casual"] {
+            find_match(
+                &waiting_users,
+                &sessions,
+                &connections,
+                &config(),
+                user_id,
+                None,
+                criteria.to_string(),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert!(matches!(
+            received_message(&mut rx),
+            Some(SignalMessage::Error(..))
+        ));
+        assert_eq!(waiting_users.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_paired_user_stops_waiting_for_other_criteria() {
+        let (waiting_users, sessions, connections) = Default::default();
+        let (first, second, third) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let _first_rx = connect(&connections, first).await;
+        let _second_rx = connect(&connections, second).await;
+        let mut third_rx = connect(&connections, third).await;
+        let config = ServerConfig {
+            max_waits_per_user: 2,
+            ..config()
+        };
+
+        for (user_id, criteria) in [(first, "ranked"), (first, "casual"), (second, "ranked")] {
+            find_match(
+                &waiting_users,
+                &sessions,
+                &connections,
+                &config,
+                user_id,
+                None,
+                criteria.to_string(),
+            )
+            .await
+            .unwrap();
+        }
+        assert!(waiting_users.read().await.is_empty());
+
+        find_match(
+            &waiting_users,
+            &sessions,
+            &connections,
+            &config,
+            third,
+            None,
+            "casual".to_string(),
+        )
+        .await
+        .unwrap();
+
+        assert!(received_message(&mut third_rx).is_none());
+        assert_eq!(sessions.read().await.len(), 1);
+        let waiting = waiting_users.read().await;
+        assert_eq!(waiting.len(), 1);
+        assert_eq!(waiting.values().next().unwrap().user_id, third);
+    }
+
+    #[test]
+    fn test_match_requests_over_limit_arent_allowed() {
+        let mut request_limit = MatchRequestLimit::new(2);
+
+        assert!(request_limit.allow());
+        assert!(request_limit.allow());
+        assert!(!request_limit.allow());
+
+        request_limit.minute_start -= Duration::from_secs(60);
+        assert!(request_limit.allow());
+    }
+
+    #[tokio::test]
+    async fn test_users_in_same_region_are_preferred() {
+        let (waiting_users, sessions, connections) = Default::default();
+        let config = ServerConfig {
+            matchmaking_region_affinity: Duration::from_millis(50),
+            ..config()
+        };
+        let users = [UserId::new(1), UserId::new(2), UserId::new(3)];
+        let mut receivers = Vec::new();
+        for user_id in users {
+            receivers.push(connect(&connections, user_id).await);
+        }
+
+        for (user_id, region) in users.into_iter().zip(["eu", "us", "eu"]) {
+            find_match(
+                &waiting_users,
+                &sessions,
+                &connections,
+                &config,
+                user_id,
+                Some(region),
+                "ranked".to_string(),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert!(matches!(
+            received_message(&mut receivers[0]),
+            Some(SignalMessage::Matched(_))
+        ));
+        assert!(received_message(&mut receivers[1]).is_none());
+        let waiting = waiting_users.read().await;
+        assert_eq!(waiting.len(), 1);
+        assert_eq!(waiting.values().next().unwrap().user_id, users[1]);
+    }
+
+    #[tokio::test]
+    async fn test_users_in_other_regions_are_paired_after_affinity_timeout() {
+        let (waiting_users, sessions, connections) = Default::default();
+        let config = ServerConfig {
+            matchmaking_region_affinity: Duration::from_millis(10),
+            ..config()
+        };
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+
+        for (user_id, region) in [(first, "eu"), (second, "us")] {
+            find_match(
+                &waiting_users,
+                &sessions,
+                &connections,
+                &config,
+                user_id,
+                Some(region),
+                "ranked".to_string(),
+            )
+            .await
+            .unwrap();
+        }
+        assert!(received_message(&mut first_rx).is_none());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        for rx in [&mut first_rx, &mut second_rx] {
+            assert!(matches!(
+                received_message(rx),
+                Some(SignalMessage::Matched(_))
+            ));
+        }
+        assert!(waiting_users.read().await.is_empty());
+        assert_eq!(sessions.read().await.len(), 1);
+    }
+}
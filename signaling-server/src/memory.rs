@@ -0,0 +1,273 @@
+/*!
+In-memory transport for one-to-one signaling, without any network in between.
+
+Connections share sessions with the ones connected over websockets and TCP.
+Useful for peers living in the embedding application itself and for measuring
+the server's routing logic on its own, see the `signaling` benchmark.
+There are no pings, connection is considered alive until [`MemoryConnection`] is dropped.
+*/
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use axum::extract::ws::Message;
+use futures_util::{sink, StreamExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use wasm_peers_protocol::one_to_one::SignalMessage;
+
+use crate::config::ServerConfig;
+use crate::one_to_one::{serve_user, ConnectionOptions};
+use crate::region;
+use crate::status::ServerState;
+
+/// Connection of a single one-to-one user, created with [`connect`].
+pub struct MemoryConnection {
+    incoming: mpsc::UnboundedSender<Message>,
+    outgoing: mpsc::UnboundedReceiver<Message>,
+}
+
+impl MemoryConnection {
+    /// Sends the message to the server.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the server dropped the connection.
+    pub fn send(&self, message: &SignalMessage) -> anyhow::Result<()> {
+        let message = serde_json::to_string(message)?;
+        self.incoming
+            .send(Message::Text(message))
+            .map_err(|_| anyhow!("connection was dropped by the server"))
+    }
+
+    /// Waits for the next message from the server, `None` once the server dropped the connection.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the server sent something other than a signaling message.
+    pub async fn recv(&mut self) -> anyhow::Result<Option<SignalMessage>> {
+        loop {
+            match self.outgoing.recv().await {
+                Some(Message::Text(message)) => return Ok(Some(serde_json::from_str(&message)?)),
+                Some(Message::Close(_)) | None => return Ok(None),
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+/// Connects a new one-to-one user to the server with given state, see [`crate::admin`].
+/// Must be called within a tokio runtime.
+pub fn connect(state: &ServerState, config: Arc<ServerConfig>) -> MemoryConnection {
+    let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+    let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+    let incoming = UnboundedReceiverStream::new(incoming_rx).map(Ok::<_, Infallible>);
+    let outgoing = Box::pin(sink::unfold(
+        outgoing_tx,
+        |outgoing_tx, message: Message| async move {
+            outgoing_tx
+                .send(message)
+                .map_err(|_| anyhow!("connection was dropped"))?;
+            Ok::<_, anyhow::Error>(outgoing_tx)
+        },
+    ));
+    let tracked = region::track(&state.region_counts, config.region.as_deref());
+    let user = serve_user(
+        outgoing,
+        incoming,
+        state.connections.clone(),
+        state.one_to_one_sessions.clone(),
+        state.waiting_users.clone(),
+        config.clone(),
+        ConnectionOptions {
+            region: config.region.clone(),
+            heartbeat: false,
+            remote_ip: None,
+        },
+    );
+    tokio::task::spawn(async move {
+        user.await;
+        drop(tracked);
+    });
+    MemoryConnection {
+        incoming: incoming_tx,
+        outgoing: outgoing_rx,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use wasm_peers_protocol::SessionId;
+
+    use super::*;
+    use crate::config::EarlyIceCandidates;
+
+    #[tokio::test]
+    async fn test_memory_connections_are_relayed_to_each_other() {
+        let state = ServerState::default();
+        let config = Arc::new(ServerConfig::default());
+        let session_id = SessionId::new("dummy-session-id".to_string());
+        let mut first = connect(&state, config.clone());
+        let mut second = connect(&state, config);
+
+        for connection in [&first, &second] {
+            connection
+                .send(&SignalMessage::SessionJoin(session_id.clone()))
+                .unwrap();
+        }
+        for connection in [&mut first, &mut second] {
+            assert!(matches!(
+                connection.recv().await.unwrap(),
+                Some(SignalMessage::SessionReady(..))
+            ));
+        }
+        first
+            .send(&SignalMessage::IceCandidate(
+                session_id,
+                "candidate".to_string(),
+            ))
+            .unwrap();
+
+        assert!(matches!(
+            second.recv().await.unwrap(),
+            Some(SignalMessage::IceCandidate(_, candidate)) if candidate == "candidate"
+        ));
+    }
+
+    fn session_id() -> SessionId {
+        SessionId::new("dummy-session-id".to_string())
+    }
+
+    async fn join(connection: &mut MemoryConnection) {
+        connection
+            .send(&SignalMessage::SessionJoin(session_id()))
+            .unwrap();
+    }
+
+    async fn expect_session_ready(connection: &mut MemoryConnection) {
+        assert!(matches!(
+            connection.recv().await.unwrap(),
+            Some(SignalMessage::SessionReady(..))
+        ));
+    }
+
+    /// Lets the server handle everything sent so far, without letting time pass.
+    async fn wait_for_users_in_session(state: &ServerState, users: usize) {
+        loop {
+            let sessions = state.one_to_one_sessions.read().await;
+            let session = &sessions[&session_id()];
+            if session.first.iter().chain(&session.second).count() == users {
+                return;
+            }
+            drop(sessions);
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// Both users join, then the second one drops and its replacement connects, without joining yet.
+    async fn drop_second_user(
+        state: &ServerState,
+        config: &Arc<ServerConfig>,
+    ) -> (MemoryConnection, MemoryConnection) {
+        let mut first = connect(state, config.clone());
+        let mut second = connect(state, config.clone());
+        join(&mut first).await;
+        join(&mut second).await;
+        expect_session_ready(&mut first).await;
+        expect_session_ready(&mut second).await;
+        drop(second);
+        wait_for_users_in_session(state, 1).await;
+        (first, connect(state, config.clone()))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dropped_user_rejoining_makes_session_ready_again() {
+        let state = ServerState::default();
+        let config = Arc::new(ServerConfig::default());
+
+        let (mut first, mut returning) = drop_second_user(&state, &config).await;
+        // nothing is sent to the remaining user while the other one is gone
+        assert!(tokio::time::timeout(Duration::from_secs(60), first.recv())
+            .await
+            .is_err());
+        join(&mut returning).await;
+
+        expect_session_ready(&mut first).await;
+        expect_session_ready(&mut returning).await;
+        wait_for_users_in_session(&state, 2).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_held_candidate_reaches_user_rejoining_within_max_age() {
+        let max_age = Duration::from_secs(5);
+        for (away_for, delivered) in [
+            (max_age - Duration::from_millis(1), true),
+            (max_age + Duration::from_millis(1), false),
+        ] {
+            let state = ServerState::default();
+            let config = Arc::new(ServerConfig {
+                early_ice_candidates: EarlyIceCandidates::Buffer {
+                    max_age,
+                    max_count: 32,
+                },
+                ..ServerConfig::default()
+            });
+
+            let (first, mut returning) = drop_second_user(&state, &config).await;
+            first
+                .send(&SignalMessage::IceCandidate(
+                    session_id(),
+                    "candidate".to_string(),
+                ))
+                .unwrap();
+            while state.one_to_one_sessions.read().await[&session_id()]
+                .held_candidates
+                .is_empty()
+            {
+                tokio::task::yield_now().await;
+            }
+            tokio::time::advance(away_for).await;
+            join(&mut returning).await;
+
+            expect_session_ready(&mut returning).await;
+            let next = tokio::time::timeout(Duration::from_millis(1), returning.recv()).await;
+            assert_eq!(
+                matches!(next, Ok(Ok(Some(SignalMessage::IceCandidate(..))))),
+                delivered,
+                "away for {:?}",
+                away_for
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_negotiation_timeout_restarts_when_user_rejoins() {
+        let negotiation_timeout = Duration::from_secs(1);
+        let state = ServerState::default();
+        let config = Arc::new(ServerConfig {
+            negotiation_timeout: Some(negotiation_timeout),
+            ..ServerConfig::default()
+        });
+
+        let (mut first, mut returning) = drop_second_user(&state, &config).await;
+        tokio::time::advance(negotiation_timeout / 2).await;
+        join(&mut returning).await;
+        expect_session_ready(&mut first).await;
+        let rejoined_at = tokio::time::Instant::now();
+
+        // timeout of the negotiation before the user dropped doesn't fire
+        assert!(
+            tokio::time::timeout(negotiation_timeout - Duration::from_millis(1), first.recv())
+                .await
+                .is_err()
+        );
+        assert!(matches!(
+            first.recv().await.unwrap(),
+            Some(SignalMessage::NegotiationTimeout(_))
+        ));
+        assert_eq!(rejoined_at.elapsed(), negotiation_timeout);
+    }
+}
@@ -0,0 +1,197 @@
+/*!
+Server-wide limit on one-to-one sessions negotiating at once, pacing join storms.
+
+A session is negotiating from the moment its users are told it's ready until an `SdpAnswer`
+is passed between them, one of them leaves, or [`ServerConfig::negotiation_timeout`] passes.
+Once [`NegotiationLimit::new`]'s maximum is reached, sessions that get full wait
+for a negotiation to finish before their users get `SessionReady`, in the order they got full.
+Sessions created by matchmaking wait the same, after their users get `Matched`.
+Numbers of sessions negotiating and waiting to negotiate are shown on the status page.
+
+At most [`DEFAULT_MAX_NEGOTIATIONS`] sessions negotiate at once by default, far more than a server
+usually sees, but enough to bound a burst. So that users who never answer can't hold the slots forever,
+a negotiation frees its slot after [`NegotiationLimit::slot_timeout`] even if it didn't finish,
+whether or not [`ServerConfig::negotiation_timeout`] is set. Only the slot is freed,
+users are told about the stalled negotiation by the negotiation timeout alone.
+
+[`ServerConfig::negotiation_timeout`]: crate::config::ServerConfig::negotiation_timeout
+*/
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Sessions negotiating at once by default, see [`crate::negotiation_limit`].
+pub const DEFAULT_MAX_NEGOTIATIONS: usize = 1000;
+
+/// How long a negotiation holds its slot by default, see [`NegotiationLimit::slot_timeout`].
+pub const DEFAULT_SLOT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Limit set by [`crate::config::ServerConfig::negotiation_limit`], shared by all clones.
+/// [`DEFAULT_MAX_NEGOTIATIONS`] by default, the number of negotiating sessions is counted either way.
+#[derive(Debug, Clone)]
+pub struct NegotiationLimit(Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    max_negotiations: Option<usize>,
+    slot_timeout: Option<Duration>,
+    /// `None` if unlimited.
+    slots: Option<Arc<Semaphore>>,
+    negotiating: AtomicUsize,
+    /// Sessions waiting for a slot.
+    waiting: AtomicUsize,
+}
+
+impl Default for NegotiationLimit {
+    fn default() -> Self {
+        NegotiationLimit::new(DEFAULT_MAX_NEGOTIATIONS)
+    }
+}
+
+/// Counts a session waiting for a slot, including when the wait is given up because a user left.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        Waiting(waiting)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Held by a negotiating session, frees its slot when dropped.
+#[derive(Debug)]
+pub struct NegotiationPermit {
+    limit: NegotiationLimit,
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for NegotiationPermit {
+    fn drop(&mut self) {
+        self.limit.0.negotiating.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl NegotiationLimit {
+    /// Allows at most `max_negotiations` sessions to negotiate at once, must not be zero.
+    /// Negotiations hold their slot for at most [`DEFAULT_SLOT_TIMEOUT`].
+    pub fn new(max_negotiations: usize) -> Self {
+        NegotiationLimit::with_slot_timeout(max_negotiations, DEFAULT_SLOT_TIMEOUT)
+    }
+
+    /// Same as [`NegotiationLimit::new`], but negotiations hold their slot for at most `slot_timeout`,
+    /// which must not be zero.
+    pub fn with_slot_timeout(max_negotiations: usize, slot_timeout: Duration) -> Self {
+        NegotiationLimit(Arc::new(Inner {
+            max_negotiations: Some(max_negotiations),
+            slot_timeout: Some(slot_timeout),
+            slots: Some(Arc::new(Semaphore::new(max_negotiations))),
+            negotiating: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+        }))
+    }
+
+    /// Lets any number of sessions negotiate at once.
+    pub fn unlimited() -> Self {
+        NegotiationLimit(Arc::new(Inner::default()))
+    }
+
+    /// Returns `None` if unlimited.
+    pub fn max_negotiations(&self) -> Option<usize> {
+        self.0.max_negotiations
+    }
+
+    /// How long a negotiation holds its slot before it's freed for waiting sessions,
+    /// even if no answer was passed yet. Returns `None` if unlimited.
+    pub fn slot_timeout(&self) -> Option<Duration> {
+        self.0.slot_timeout
+    }
+
+    /// Number of sessions negotiating right now.
+    pub fn negotiating(&self) -> usize {
+        self.0.negotiating.load(Ordering::Relaxed)
+    }
+
+    /// Number of full sessions waiting for a slot before their users get `SessionReady`.
+    pub fn waiting(&self) -> usize {
+        self.0.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Returns `None` if all slots are taken.
+    pub(crate) fn try_acquire(&self) -> Option<NegotiationPermit> {
+        let slot = match &self.0.slots {
+            Some(slots) => Some(slots.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        Some(self.permit(slot))
+    }
+
+    /// Waits for a free slot, slots are handed out in the order they were waited for.
+    pub(crate) async fn acquire(&self) -> NegotiationPermit {
+        let slot = match &self.0.slots {
+            Some(slots) => {
+                let _waiting = Waiting::new(&self.0.waiting);
+                Some(
+                    slots
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("negotiation slots are never closed"),
+                )
+            }
+            None => None,
+        };
+        self.permit(slot)
+    }
+
+    fn permit(&self, slot: Option<OwnedSemaphorePermit>) -> NegotiationPermit {
+        self.0.negotiating.fetch_add(1, Ordering::Relaxed);
+        NegotiationPermit {
+            limit: self.clone(),
+            _slot: slot,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_slots_are_freed_when_permits_drop() {
+        let limit = NegotiationLimit::new(1);
+
+        let first = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+        assert_eq!(limit.negotiating(), 1);
+
+        drop(first);
+        let _second = limit.acquire().await;
+        assert_eq!(limit.negotiating(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_waiting_for_a_slot_are_counted() {
+        let limit = NegotiationLimit::new(1);
+        let first = limit.try_acquire().unwrap();
+
+        let waiting_limit = limit.clone();
+        let waiting = tokio::spawn(async move { waiting_limit.acquire().await });
+        tokio::task::yield_now().await;
+        assert_eq!(limit.waiting(), 1);
+
+        drop(first);
+        let _second = waiting.await.unwrap();
+        assert_eq!(limit.waiting(), 0);
+        assert_eq!(limit.negotiating(), 1);
+        assert_eq!(NegotiationLimit::unlimited().max_negotiations(), None);
+    }
+}
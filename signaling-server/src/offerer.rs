@@ -0,0 +1,97 @@
+/*!
+Strategy picking which one-to-one user creates the offer, told to both with `SessionReady`.
+
+The offerer often seeds the initial state or bears more of the negotiation cost,
+so applications may want it to be e.g. the user who joined first or last.
+By default it's the user in the session's first slot, which is the first to join
+unless that user left and rejoined.
+
+# Glare
+
+Clients only send offers when `SessionReady` made them the offerer, and the other peer
+asks the offerer for ICE restarts with `IceRestart` instead of sending an offer itself.
+Whichever user the strategy picks, there is exactly one offerer per negotiation, so offers never collide.
+Strategies should be deterministic for given users, as the role is picked again
+each time the session gets ready, e.g. after a user rejoins.
+*/
+
+use std::fmt;
+
+use wasm_peers_protocol::{SessionId, UserId};
+
+/// Custom logic picking the offerer, see [`crate::offerer`].
+pub trait OffererStrategy: fmt::Debug + Send + Sync {
+    /// Returns the offerer of the session in which `first` and `second` are occupying the slots,
+    /// and `joined` is the one whose join just made the session ready.
+    /// Returning any other user makes `first` the offerer.
+    fn offerer(
+        &self,
+        session_id: &SessionId,
+        first: UserId,
+        second: UserId,
+        joined: UserId,
+    ) -> UserId;
+}
+
+/// Default strategy, user in the first slot offers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstSlot;
+
+impl OffererStrategy for FirstSlot {
+    fn offerer(
+        &self,
+        _session_id: &SessionId,
+        first: UserId,
+        _second: UserId,
+        _joined: UserId,
+    ) -> UserId {
+        first
+    }
+}
+
+/// User who was in the session before the other one joined offers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstJoiner;
+
+impl OffererStrategy for FirstJoiner {
+    fn offerer(
+        &self,
+        _session_id: &SessionId,
+        first: UserId,
+        second: UserId,
+        joined: UserId,
+    ) -> UserId {
+        if joined == first {
+            second
+        } else {
+            first
+        }
+    }
+}
+
+/// User whose join made the session ready offers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LastJoiner;
+
+impl OffererStrategy for LastJoiner {
+    fn offerer(
+        &self,
+        _session_id: &SessionId,
+        _first: UserId,
+        _second: UserId,
+        joined: UserId,
+    ) -> UserId {
+        joined
+    }
+}
+
+/// Returns whether `first` offers, according to the strategy.
+pub(crate) fn first_offers(
+    strategy: &dyn OffererStrategy,
+    session_id: &SessionId,
+    first: UserId,
+    second: UserId,
+    joined: UserId,
+) -> bool {
+    strategy.offerer(session_id, first, second, joined) != second
+}
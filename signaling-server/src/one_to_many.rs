@@ -0,0 +1,1553 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use tokio::sync::{mpsc, RwLock};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use wasm_peers_protocol::one_to_many::{SessionInfo, SignalMessage, MAX_TOPIC_LENGTH};
+use wasm_peers_protocol::{SessionId, UserId};
+
+use crate::admin::SessionActivity;
+use crate::bandwidth::TokenBucket;
+use crate::config::{CloseSessionPolicy, ServerConfig};
+use crate::error_budget::{parse_message, ErrorBudget};
+use crate::heartbeat::{self, IdleTimer, Timeout};
+use crate::maintenance::MAINTENANCE_ERROR;
+use crate::one_to_one::{Connections, NEXT_USER_ID};
+use crate::relay_authorizer::RelayDecision;
+use crate::serialization::{internal_error_response, serialize_message};
+
+/// Error sent to a user trying to join a session that already has [`ServerConfig::max_session_size`] users.
+pub const SESSION_FULL_ERROR: &str = "session is full";
+
+#[derive(Default)]
+pub struct Session {
+    /// Peer that every other user connects to, always `None` in many-to-many sessions.
+    pub host: Option<UserId>,
+    pub users: HashSet<UserId>,
+    /// User with owner rights, initially the first one to join.
+    /// Ownership only passes to the user the owner transfers it to,
+    /// if the owner leaves without transferring it the session has no owner.
+    pub owner: Option<UserId>,
+    /// Number of `SDP` offers send from one user to another.
+    pub offers: HashMap<(UserId, UserId), usize>,
+    /// Limits bytes relayed with `RelayTo` and `Publish`, created on first use.
+    pub relay_budget: Option<TokenBucket>,
+    /// Users subscribed to each topic, topics without subscribers are removed.
+    pub subscriptions: HashMap<String, HashSet<UserId>>,
+    pub activity: SessionActivity,
+}
+
+/// Users data is relayed to.
+#[derive(Debug)]
+enum Recipients {
+    /// Listed users in session, sent with `RelayTo`.
+    Listed(Vec<UserId>),
+    /// Users subscribed to the topic, sent with `Publish`.
+    Topic(String),
+}
+
+pub type Sessions = Arc<RwLock<HashMap<SessionId, Session>>>;
+
+pub async fn user_connected(
+    ws: WebSocket,
+    connections: Connections,
+    sessions: Sessions,
+    config: Arc<ServerConfig>,
+) {
+    handle_connection(ws, connections, sessions, config, false).await;
+}
+
+/// Connection loop shared by one-to-many and many-to-many topologies,
+/// which only differ in who gets notified that a new user joined.
+///
+/// Messages of a connection are handled one at a time and each recipient has a single FIFO channel,
+/// so messages relayed from one user to another arrive in the order they were sent,
+/// even when many users relay to the same recipient at once.
+pub(crate) async fn handle_connection(
+    ws: WebSocket,
+    connections: Connections,
+    sessions: Sessions,
+    config: Arc<ServerConfig>,
+    is_mesh: bool,
+) {
+    let user_id = UserId::new(NEXT_USER_ID.fetch_add(1, Ordering::Relaxed));
+    info!("new user connected: {:?}", user_id);
+
+    let (mut user_ws_tx, mut user_ws_rx) = ws.split();
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut rx = UnboundedReceiverStream::new(rx);
+
+    tokio::task::spawn(async move {
+        while let Some(message) = rx.next().await {
+            user_ws_tx
+                .send(message)
+                .await
+                .unwrap_or_else(|e| error!("websocket send error: {}", e));
+        }
+    });
+
+    let pings = heartbeat::spawn_pings(tx.clone(), config.heartbeat_interval);
+    connections.write().await.insert(user_id, tx);
+    let mut error_budget = ErrorBudget::new(config.max_consecutive_malformed_messages);
+    let mut idle_timer = IdleTimer::new(config.idle_timeout);
+
+    loop {
+        let result = match heartbeat::next_message(
+            &mut user_ws_rx,
+            Some(config.heartbeat_timeout),
+            &idle_timer,
+        )
+        .await
+        {
+            Ok(Some(result)) => result,
+            Ok(None) => break,
+            Err(Timeout::Heartbeat) => {
+                info!("heartbeat timeout, dropping user: {:?}", user_id);
+                break;
+            }
+            Err(Timeout::Idle) => {
+                info!("idle timeout, dropping user: {:?}", user_id);
+                let response = SignalMessage::Error(
+                    SessionId::new(String::new()),
+                    "connection idle for too long".to_string(),
+                );
+                let _ = send(&connections, user_id, &response).await;
+                break;
+            }
+        };
+        let msg = match result {
+            Ok(msg) => msg,
+            Err(err) => {
+                error!("websocket error (user_id={:?}): {}", user_id, err);
+                break;
+            }
+        };
+        if heartbeat::is_heartbeat(&msg) {
+            continue;
+        }
+        idle_timer.reset();
+        #[cfg(feature = "chaos")]
+        crate::chaos::delay(&config).await;
+
+        let result = user_message(user_id, msg, &connections, &sessions, &config, is_mesh).await;
+        if let Err(err) = &result {
+            error!("user_message error (user_id={:?}): {}", user_id, err);
+        }
+        if let Some(response) = internal_error_response(&result, SignalMessage::Error) {
+            if let Some(user_tx) = connections.read().await.get(&user_id) {
+                let _ = user_tx.send(response);
+            }
+        }
+        if let Some(reason) = error_budget.exhausted_by(&result) {
+            info!("{}, dropping user: {:?}", reason, user_id);
+            let response = SignalMessage::Error(SessionId::new(String::new()), reason.to_string());
+            let _ = send(&connections, user_id, &response).await;
+            break;
+        }
+    }
+
+    pings.abort();
+    info!("user disconnected: {:?}", user_id);
+    user_disconnected(user_id, &connections, &sessions).await;
+}
+
+async fn user_message(
+    user_id: UserId,
+    msg: Message,
+    connections: &Connections,
+    sessions: &Sessions,
+    config: &ServerConfig,
+    is_mesh: bool,
+) -> anyhow::Result<()> {
+    let request: SignalMessage = parse_message(&msg)?;
+    info!("message received from user {:?}: {:?}", user_id, request);
+    if let Some(session_id) = relayed_session_id(&request) {
+        if let Some(session) = sessions.write().await.get_mut(session_id) {
+            if session.users.contains(&user_id) {
+                session.activity.record_message();
+            }
+        }
+    }
+    match request {
+        SignalMessage::SessionJoin(session_id, is_host) => {
+            let rejection = if !config.allows_session(&session_id) {
+                Some("session not allowed")
+            } else if config.maintenance.is_enabled()
+                && !sessions.read().await.contains_key(&session_id)
+            {
+                Some(MAINTENANCE_ERROR)
+            } else {
+                None
+            };
+            if let Some(rejection) = rejection {
+                info!("user {:?} can't join session {:?}", user_id, session_id);
+                let response = SignalMessage::Error(session_id, rejection.to_string());
+                return send(connections, user_id, &response).await;
+            }
+            session_join(
+                sessions,
+                connections,
+                user_id,
+                session_id,
+                is_host && !is_mesh,
+                config.max_session_size,
+            )
+            .await?;
+        }
+        // pass offer to the recipient, only replacing the user id and applying the configured filter
+        SignalMessage::SdpOffer(session_id, recipient_id, offer) => {
+            {
+                let mut sessions = sessions.write().await;
+                let session = sessions
+                    .get_mut(&session_id)
+                    .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
+                if !session.users.contains(&user_id) {
+                    return Err(anyhow!(
+                        "sender {:?} is not in session: {:?}",
+                        user_id,
+                        session_id
+                    ));
+                }
+                let offers = session.offers.entry((user_id, recipient_id)).or_default();
+                if *offers > config.max_renegotiations {
+                    info!(
+                        "too many renegotiations, rejecting the offer: {:?}",
+                        session_id
+                    );
+                    let response =
+                        SignalMessage::Error(session_id, "too many renegotiations".to_string());
+                    return send(connections, user_id, &response).await;
+                }
+                *offers += 1;
+            }
+            let offer = config.sdp_filter.filter_sdp(&offer);
+            let response = SignalMessage::SdpOffer(session_id.clone(), user_id, offer);
+            relay(
+                sessions,
+                connections,
+                &session_id,
+                user_id,
+                recipient_id,
+                &response,
+            )
+            .await?;
+        }
+        // pass answer to the recipient, only replacing the user id and applying the configured filter
+        SignalMessage::SdpAnswer(session_id, recipient_id, answer) => {
+            let answer = config.sdp_filter.filter_sdp(&answer);
+            let response = SignalMessage::SdpAnswer(session_id.clone(), user_id, answer);
+            relay(
+                sessions,
+                connections,
+                &session_id,
+                user_id,
+                recipient_id,
+                &response,
+            )
+            .await?;
+        }
+        SignalMessage::IceCandidate(session_id, recipient_id, candidate) => {
+            if !config.sdp_filter.allows_candidate(&candidate) {
+                info!("dropping filtered ICE candidate: {:?}", session_id);
+                return Ok(());
+            }
+            let response = SignalMessage::IceCandidate(session_id.clone(), user_id, candidate);
+            relay(
+                sessions,
+                connections,
+                &session_id,
+                user_id,
+                recipient_id,
+                &response,
+            )
+            .await?;
+        }
+        SignalMessage::RelayTo(session_id, recipient_ids, data) => {
+            relay_to(
+                sessions,
+                connections,
+                config,
+                user_id,
+                session_id,
+                Recipients::Listed(recipient_ids),
+                data,
+            )
+            .await?;
+        }
+        SignalMessage::Subscribe(session_id, topic) => {
+            subscribe(sessions, connections, config, user_id, session_id, topic).await?;
+        }
+        SignalMessage::Unsubscribe(session_id, topic) => {
+            if let Some(session) = sessions.write().await.get_mut(&session_id) {
+                unsubscribe(session, user_id, Some(&topic));
+            }
+        }
+        SignalMessage::Publish(session_id, topic, data) => {
+            relay_to(
+                sessions,
+                connections,
+                config,
+                user_id,
+                session_id,
+                Recipients::Topic(topic),
+                data,
+            )
+            .await?;
+        }
+        SignalMessage::TransferOwnership(session_id, new_owner) => {
+            transfer_ownership(sessions, connections, user_id, session_id, new_owner).await?;
+        }
+        SignalMessage::QuerySession(session_id) => {
+            query_session(sessions, connections, user_id, session_id, is_mesh).await?;
+        }
+        SignalMessage::CloseSession(session_id) => {
+            close_session(sessions, connections, config, user_id, session_id).await?;
+        }
+        other => {
+            error!("received unexpected signal message: {:?}", other);
+        }
+    }
+    Ok(())
+}
+
+/// Session of a message passed on to other users, counted in the session's activity.
+fn relayed_session_id(request: &SignalMessage) -> Option<&SessionId> {
+    match request {
+        SignalMessage::SdpOffer(session_id, ..)
+        | SignalMessage::SdpAnswer(session_id, ..)
+        | SignalMessage::IceCandidate(session_id, ..)
+        | SignalMessage::RelayTo(session_id, ..)
+        | SignalMessage::Publish(session_id, ..) => Some(session_id),
+        _ => None,
+    }
+}
+
+async fn session_join(
+    sessions: &Sessions,
+    connections: &Connections,
+    user_id: UserId,
+    session_id: SessionId,
+    is_host: bool,
+    max_session_size: Option<usize>,
+) -> anyhow::Result<()> {
+    let mut sessions = sessions.write().await;
+    let session = sessions.entry(session_id.clone()).or_default();
+    if !session.users.contains(&user_id)
+        && max_session_size.is_some_and(|max_session_size| session.users.len() >= max_session_size)
+    {
+        info!(
+            "user {:?} can't join full session {:?}",
+            user_id, session_id
+        );
+        let response = SignalMessage::Error(session_id, SESSION_FULL_ERROR.to_string());
+        return send(connections, user_id, &response).await;
+    }
+    if is_host {
+        if session.host.is_some() {
+            return Err(anyhow!("session already has a host: {:?}", &session_id));
+        }
+        session.host = Some(user_id);
+    }
+
+    // host is the one creating offers, so only host needs to know that someone joined,
+    // in many-to-many every user already in session creates an offer for the new one
+    let notifications = match session.host {
+        Some(host_id) if host_id == user_id => session
+            .users
+            .iter()
+            .map(|&client_id| (host_id, client_id))
+            .collect(),
+        Some(host_id) => vec![(host_id, user_id)],
+        None => session
+            .users
+            .iter()
+            .map(|&peer_id| (peer_id, user_id))
+            .collect::<Vec<_>>(),
+    };
+    // only the user creating the session becomes the owner, ownership isn't claimed by later joiners
+    if session.users.is_empty() {
+        session.owner = Some(user_id);
+    }
+    session.users.insert(user_id);
+
+    for (recipient_id, peer_id) in notifications {
+        let response = SignalMessage::SessionReady(session_id.clone(), peer_id);
+        if let Err(err) = send(connections, recipient_id, &response).await {
+            warn!(
+                "failed to notify {:?} about {:?}: {}",
+                recipient_id, peer_id, err
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn transfer_ownership(
+    sessions: &Sessions,
+    connections: &Connections,
+    user_id: UserId,
+    session_id: SessionId,
+    new_owner: UserId,
+) -> anyhow::Result<()> {
+    let mut sessions = sessions.write().await;
+    let session = sessions
+        .get_mut(&session_id)
+        .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
+    let error = if session.owner != Some(user_id) {
+        Some("only session owner can transfer ownership")
+    } else if !session.users.contains(&new_owner) {
+        Some("new owner is not in session")
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        let response = SignalMessage::Error(session_id, error.to_string());
+        return send(connections, user_id, &response).await;
+    }
+
+    info!(
+        "ownership of session {:?} transferred from {:?} to {:?}",
+        session_id, user_id, new_owner
+    );
+    session.owner = Some(new_owner);
+    let response = SignalMessage::OwnershipChanged(session_id, new_owner);
+    send_to_all(connections, session.users.iter().copied(), &response).await;
+    Ok(())
+}
+
+/// Removes the session if the user is allowed to close it, see [`ServerConfig::close_session_policy`],
+/// telling all its users, who stay connected and can join other sessions.
+async fn close_session(
+    sessions: &Sessions,
+    connections: &Connections,
+    config: &ServerConfig,
+    user_id: UserId,
+    session_id: SessionId,
+) -> anyhow::Result<()> {
+    let mut sessions = sessions.write().await;
+    let session = sessions
+        .get(&session_id)
+        .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
+    let allowed = session.users.contains(&user_id)
+        && match config.close_session_policy {
+            CloseSessionPolicy::Owner => session.owner == Some(user_id),
+            CloseSessionPolicy::AnyMember => true,
+            CloseSessionPolicy::Nobody => false,
+        };
+    if !allowed {
+        let response =
+            SignalMessage::Error(session_id, "not allowed to close the session".to_string());
+        return send(connections, user_id, &response).await;
+    }
+
+    info!("session {:?} closed by user {:?}", session_id, user_id);
+    let session = sessions
+        .remove(&session_id)
+        .expect("session was just found");
+    drop(sessions);
+    let reason = if session.owner == Some(user_id) {
+        "closed by session owner"
+    } else {
+        "closed by user in session"
+    };
+    let response = SignalMessage::SessionClosed(session_id, reason.to_string());
+    send_to_all(connections, session.users, &response).await;
+    Ok(())
+}
+
+/// Only users in session can query it, so that user ids aren't revealed to outsiders.
+async fn query_session(
+    sessions: &Sessions,
+    connections: &Connections,
+    user_id: UserId,
+    session_id: SessionId,
+    is_mesh: bool,
+) -> anyhow::Result<()> {
+    let info = sessions
+        .read()
+        .await
+        .get(&session_id)
+        .filter(|session| session.users.contains(&user_id))
+        .map(|session| {
+            let mut users: Vec<_> = session.users.iter().copied().collect();
+            users.sort_by_key(|user_id| user_id.into_inner());
+            let ready = if is_mesh {
+                users.len() >= 2
+            } else {
+                session.host.is_some() && users.len() >= 2
+            };
+            SessionInfo {
+                host: session.host,
+                owner: session.owner,
+                users,
+                ready,
+            }
+        });
+    let response = match info {
+        Some(info) => SignalMessage::SessionStatus(session_id, info),
+        None => SignalMessage::Error(session_id, "not in session".to_string()),
+    };
+    send(connections, user_id, &response).await
+}
+
+/// Subscribes a user in session to the topic, within limits of topics per user and per session.
+async fn subscribe(
+    sessions: &Sessions,
+    connections: &Connections,
+    config: &ServerConfig,
+    user_id: UserId,
+    session_id: SessionId,
+    topic: String,
+) -> anyhow::Result<()> {
+    let error = {
+        let mut sessions = sessions.write().await;
+        let session = sessions
+            .get_mut(&session_id)
+            .filter(|session| session.users.contains(&user_id))
+            .ok_or_else(|| anyhow!("user {:?} not in session: {:?}", user_id, session_id))?;
+        let user_topics = session
+            .subscriptions
+            .values()
+            .filter(|subscribers| subscribers.contains(&user_id))
+            .count();
+        if topic.len() > MAX_TOPIC_LENGTH {
+            Some(format!(
+                "topic is too long: {} bytes, maximum is {}",
+                topic.len(),
+                MAX_TOPIC_LENGTH
+            ))
+        } else if session
+            .subscriptions
+            .get(&topic)
+            .is_some_and(|subscribers| subscribers.contains(&user_id))
+        {
+            None
+        } else if user_topics >= config.max_topics_per_user {
+            Some("too many topics subscribed".to_string())
+        } else if !session.subscriptions.contains_key(&topic)
+            && session.subscriptions.len() >= config.max_topics_per_session
+        {
+            Some("too many topics in session".to_string())
+        } else {
+            session
+                .subscriptions
+                .entry(topic)
+                .or_default()
+                .insert(user_id);
+            None
+        }
+    };
+    match error {
+        Some(error) => {
+            info!(
+                "user {:?} can't subscribe in session {:?}",
+                user_id, session_id
+            );
+            send(
+                connections,
+                user_id,
+                &SignalMessage::Error(session_id, error),
+            )
+            .await
+        }
+        None => Ok(()),
+    }
+}
+
+/// Unsubscribes the user from the topic, or from every topic if `None`.
+fn unsubscribe(session: &mut Session, user_id: UserId, topic: Option<&str>) {
+    session
+        .subscriptions
+        .retain(|subscribed_topic, subscribers| {
+            if topic.is_none_or(|topic| topic == subscribed_topic) {
+                subscribers.remove(&user_id);
+            }
+            !subscribers.is_empty()
+        });
+}
+
+/// Passes data to those of the recipients that are in session, skipping the others.
+async fn relay_to(
+    sessions: &Sessions,
+    connections: &Connections,
+    config: &ServerConfig,
+    user_id: UserId,
+    session_id: SessionId,
+    recipients: Recipients,
+    data: Vec<u8>,
+) -> anyhow::Result<()> {
+    if let Err(error) = config.relay_validation.check(&data) {
+        info!("invalid relay payload in session: {:?}", session_id);
+        return send(
+            connections,
+            user_id,
+            &SignalMessage::Error(session_id, error),
+        )
+        .await;
+    }
+    let (mut recipients, topic) = {
+        let mut sessions_writer = sessions.write().await;
+        let session = sessions_writer
+            .get_mut(&session_id)
+            .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
+        if !session.users.contains(&user_id) {
+            return Err(anyhow!(
+                "sender {:?} is not in session: {:?}",
+                user_id,
+                session_id
+            ));
+        }
+        let (recipient_ids, topic) = match recipients {
+            Recipients::Listed(recipient_ids) => (recipient_ids, None),
+            Recipients::Topic(topic) => {
+                let mut subscribers: Vec<_> = session
+                    .subscriptions
+                    .get(&topic)
+                    .map(|subscribers| subscribers.iter().copied().collect())
+                    .unwrap_or_default();
+                subscribers.sort_by_key(|user_id| user_id.into_inner());
+                (subscribers, Some(topic))
+            }
+        };
+        let mut recipients = Vec::new();
+        for recipient_id in recipient_ids {
+            if !session.users.contains(&recipient_id) {
+                info!(
+                    "skipping relay recipient not in session: {:?}",
+                    recipient_id
+                );
+            } else if recipient_id != user_id && !recipients.contains(&recipient_id) {
+                recipients.push(recipient_id);
+            }
+        }
+        (recipients, topic)
+    };
+
+    // authorizer is user code, so the sessions lock isn't held while it runs
+    if let RelayDecision::Deny(reason) =
+        config
+            .relay_authorizer
+            .authorize(user_id, &session_id, &recipients, &data)
+    {
+        info!("relay denied in session: {:?}", session_id);
+        let error = reason.unwrap_or_else(|| "relay not allowed".to_string());
+        return send(
+            connections,
+            user_id,
+            &SignalMessage::Error(session_id, error),
+        )
+        .await;
+    }
+    // each recipient is charged separately, so a large fan-out reaches as many of them
+    // as the budget allows instead of being dropped as a whole
+    let within_budget = {
+        let mut sessions_writer = sessions.write().await;
+        let session = sessions_writer
+            .get_mut(&session_id)
+            .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
+        let budget = session
+            .relay_budget
+            .get_or_insert_with(|| TokenBucket::new(config.max_relay_bytes_per_second));
+        recipients
+            .iter()
+            .take_while(|_| budget.try_consume(data.len()))
+            .count()
+    };
+    let bandwidth_exceeded = within_budget < recipients.len();
+    recipients.truncate(within_budget);
+
+    if !recipients.is_empty() {
+        let response = match topic {
+            Some(topic) => SignalMessage::Published(session_id.clone(), user_id, topic, data),
+            None => SignalMessage::Relayed(session_id.clone(), user_id, data),
+        };
+        for recipient_id in recipients {
+            if let Err(error) = send(connections, recipient_id, &response).await {
+                warn!(
+                    "failed to relay message to {:?} in session {:?}: {}",
+                    recipient_id, session_id, error
+                );
+            }
+        }
+    }
+    if bandwidth_exceeded {
+        info!("relay bandwidth exceeded in session: {:?}", session_id);
+        send(
+            connections,
+            user_id,
+            &SignalMessage::BandwidthExceeded(session_id),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Passes the message from the sender to the recipient, if both of them are in session.
+async fn relay(
+    sessions: &Sessions,
+    connections: &Connections,
+    session_id: &SessionId,
+    sender_id: UserId,
+    recipient_id: UserId,
+    response: &SignalMessage,
+) -> anyhow::Result<()> {
+    let (sender_is_member, recipient_is_member) = {
+        let sessions_reader = sessions.read().await;
+        let users = &sessions_reader
+            .get(session_id)
+            .ok_or_else(|| anyhow!("no such session: {:?}", session_id))?
+            .users;
+        (users.contains(&sender_id), users.contains(&recipient_id))
+    };
+    if !sender_is_member {
+        return Err(anyhow!(
+            "sender {:?} is not in session: {:?}",
+            sender_id,
+            session_id
+        ));
+    }
+    if !recipient_is_member {
+        return Err(anyhow!(
+            "recipient {:?} is not in session: {:?}",
+            recipient_id,
+            session_id
+        ));
+    }
+    send(connections, recipient_id, response).await
+}
+
+async fn send(
+    connections: &Connections,
+    recipient_id: UserId,
+    response: &SignalMessage,
+) -> anyhow::Result<()> {
+    let response = serialize_message(response)?;
+    let connections_reader = connections.read().await;
+    let recipient_tx = connections_reader
+        .get(&recipient_id)
+        .ok_or_else(|| anyhow!("no sender for given recipient_id"))?;
+    recipient_tx.send(Message::Text(response))?;
+    Ok(())
+}
+
+/// Sends the message to each of the recipients, failing to reach one doesn't stop the others.
+async fn send_to_all(
+    connections: &Connections,
+    recipients: impl IntoIterator<Item = UserId>,
+    response: &SignalMessage,
+) {
+    for recipient_id in recipients {
+        if let Err(err) = send(connections, recipient_id, response).await {
+            warn!("failed to send to {:?}: {}", recipient_id, err);
+        }
+    }
+}
+
+pub(crate) async fn user_disconnected(
+    user_id: UserId,
+    connections: &Connections,
+    sessions: &Sessions,
+) {
+    let mut sessions_writer = sessions.write().await;
+    let mut left = Vec::new();
+    for (session_id, session) in sessions_writer.iter_mut() {
+        if session.users.contains(&user_id) {
+            left.push(session_id.clone());
+        }
+        if session.host == Some(user_id) {
+            session.host = None;
+        }
+        if session.owner == Some(user_id) {
+            session.owner = None;
+        }
+        session.users.remove(&user_id);
+        unsubscribe(session, user_id, None);
+        session.offers.retain(|(sender_id, recipient_id), _| {
+            *sender_id != user_id && *recipient_id != user_id
+        });
+    }
+    // remove sessions that are empty
+    sessions_writer.retain(|_, session| !session.users.is_empty());
+    let notifications: Vec<_> = left
+        .into_iter()
+        .filter_map(|session_id| {
+            let users = sessions_writer.get(&session_id)?.users.clone();
+            Some((SignalMessage::PeerLeft(session_id, user_id), users))
+        })
+        .collect();
+    drop(sessions_writer);
+    connections.write().await.remove(&user_id);
+    for (response, users) in notifications {
+        send_to_all(connections, users, &response).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_peers_protocol::one_to_many::MAX_RELAY_LENGTH;
+
+    use super::*;
+    use crate::relay_authorizer::RelayAuthorizer;
+    use crate::session_allowlist::SessionAllowlist;
+    use crate::test_util::connect;
+    use crate::test_util::one_to_many::received_message;
+
+    fn session_id() -> SessionId {
+        SessionId::new("dummy-session-id".to_string())
+    }
+
+    fn received_session_ready(rx: &mut mpsc::UnboundedReceiver<Message>) -> Option<UserId> {
+        match rx.try_recv() {
+            Ok(Message::Text(message)) => match serde_json::from_str(&message).unwrap() {
+                SignalMessage::SessionReady(_, peer_id) => Some(peer_id),
+                other => panic!("unexpected message: {:?}", other),
+            },
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_host_is_notified_about_clients_joining_before_and_after_it() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (early_client, host, late_client) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let mut early_client_rx = connect(&connections, early_client).await;
+        let mut host_rx = connect(&connections, host).await;
+        let mut late_client_rx = connect(&connections, late_client).await;
+
+        session_join(
+            &sessions,
+            &connections,
+            early_client,
+            session_id(),
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+        session_join(&sessions, &connections, host, session_id(), true, None)
+            .await
+            .unwrap();
+        session_join(
+            &sessions,
+            &connections,
+            late_client,
+            session_id(),
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(received_session_ready(&mut host_rx), Some(early_client));
+        assert_eq!(received_session_ready(&mut host_rx), Some(late_client));
+        assert_eq!(received_session_ready(&mut early_client_rx), None);
+        assert_eq!(received_session_ready(&mut late_client_rx), None);
+    }
+
+    #[tokio::test]
+    async fn test_mesh_users_already_in_session_are_notified_about_new_user() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second, third) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        let mut third_rx = connect(&connections, third).await;
+
+        for user_id in [first, second, third] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(received_session_ready(&mut first_rx), Some(second));
+        assert_eq!(received_session_ready(&mut first_rx), Some(third));
+        assert_eq!(received_session_ready(&mut second_rx), Some(third));
+        assert_eq!(received_session_ready(&mut third_rx), None);
+    }
+
+    #[tokio::test]
+    async fn test_full_session_rejects_new_users_only() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second, third) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let mut first_rx = connect(&connections, first).await;
+        let _second_rx = connect(&connections, second).await;
+        let mut third_rx = connect(&connections, third).await;
+
+        for user_id in [first, second, third, first] {
+            session_join(
+                &sessions,
+                &connections,
+                user_id,
+                session_id(),
+                false,
+                Some(2),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert!(matches!(
+            received_message(&mut third_rx),
+            Some(SignalMessage::Error(_, error)) if error == SESSION_FULL_ERROR
+        ));
+        assert!(!matches!(
+            received_message(&mut first_rx),
+            Some(SignalMessage::Error(..))
+        ));
+        assert_eq!(sessions.read().await[&session_id()].users.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_only_sessions_with_allowed_prefix_can_be_joined() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let user_id = UserId::new(1);
+        let mut rx = connect(&connections, user_id).await;
+        let config = ServerConfig {
+            session_allowlist: Some(SessionAllowlist {
+                ids: Vec::new(),
+                prefixes: vec!["room-".to_string()],
+            }),
+            ..ServerConfig::default()
+        };
+
+        for session_id in ["room-1", "other"] {
+            let request = SignalMessage::SessionJoin(SessionId::new(session_id.to_string()), false);
+            let msg = Message::Text(serde_json::to_string(&request).unwrap());
+            user_message(user_id, msg, &connections, &sessions, &config, false)
+                .await
+                .unwrap();
+        }
+
+        assert!(matches!(
+            received_message(&mut rx),
+            Some(SignalMessage::Error(session_id, error))
+                if session_id.as_str() == "other" && error == "session not allowed"
+        ));
+        let sessions = sessions.read().await;
+        assert!(sessions.contains_key(&SessionId::new("room-1".to_string())));
+        assert_eq!(sessions.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_relays_to_one_recipient_keep_each_senders_order() {
+        const CANDIDATES: usize = 500;
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let host = UserId::new(1);
+        let clients = [UserId::new(2), UserId::new(3), UserId::new(4)];
+        let mut host_rx = connect(&connections, host).await;
+        session_join(&sessions, &connections, host, session_id(), true, None)
+            .await
+            .unwrap();
+        for client in clients {
+            let _client_rx = connect(&connections, client).await;
+            session_join(&sessions, &connections, client, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+        while received_message(&mut host_rx).is_some() {}
+
+        // each client's messages are handled one at a time, as in its connection loop
+        let senders = clients.map(|client| {
+            let connections = connections.clone();
+            let sessions = sessions.clone();
+            tokio::task::spawn(async move {
+                for index in 0..CANDIDATES {
+                    let request = SignalMessage::IceCandidate(
+                        session_id(),
+                        host,
+                        format!(
+                            "candidate:{} 1 udp 2113937151 192.168.1.2 52345 typ host",
+                            index
+                        ),
+                    );
+                    let msg = Message::Text(serde_json::to_string(&request).unwrap());
+                    let config = ServerConfig::default();
+                    user_message(client, msg, &connections, &sessions, &config, false)
+                        .await
+                        .unwrap();
+                }
+            })
+        });
+        for sender in senders {
+            sender.await.unwrap();
+        }
+
+        let mut next_index = HashMap::new();
+        while let Some(message) = received_message(&mut host_rx) {
+            match message {
+                SignalMessage::IceCandidate(_, sender_id, candidate) => {
+                    let index = next_index.entry(sender_id).or_insert(0);
+                    assert!(
+                        candidate.starts_with(&format!("candidate:{} ", index)),
+                        "candidate {} of {:?} arrived out of order: {}",
+                        index,
+                        sender_id,
+                        candidate
+                    );
+                    *index += 1;
+                }
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+        for client in clients {
+            assert_eq!(next_index.get(&client), Some(&CANDIDATES));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_owner_can_transfer_ownership_to_other_member() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        for user_id in [first, second] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+        while received_message(&mut first_rx).is_some() {}
+
+        transfer_ownership(&sessions, &connections, first, session_id(), second)
+            .await
+            .unwrap();
+
+        for rx in [&mut first_rx, &mut second_rx] {
+            assert!(matches!(
+                received_message(rx),
+                Some(SignalMessage::OwnershipChanged(_, owner)) if owner == second
+            ));
+        }
+        let sessions = sessions.read().await;
+        assert_eq!(sessions.get(&session_id()).unwrap().owner, Some(second));
+    }
+
+    #[tokio::test]
+    async fn test_only_owner_closes_session_for_everyone_by_default() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let config = ServerConfig::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        for user_id in [first, second] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+        while received_message(&mut first_rx).is_some() {}
+
+        close_session(&sessions, &connections, &config, second, session_id())
+            .await
+            .unwrap();
+        assert!(matches!(
+            received_message(&mut second_rx),
+            Some(SignalMessage::Error(..))
+        ));
+        assert!(sessions.read().await.contains_key(&session_id()));
+
+        close_session(&sessions, &connections, &config, first, session_id())
+            .await
+            .unwrap();
+        for rx in [&mut first_rx, &mut second_rx] {
+            assert!(matches!(
+                received_message(rx),
+                Some(SignalMessage::SessionClosed(closed_session_id, _)) if closed_session_id == session_id()
+            ));
+        }
+        assert!(sessions.read().await.is_empty());
+        assert!(connections.read().await.contains_key(&second));
+    }
+
+    #[tokio::test]
+    async fn test_ownership_isnt_claimed_after_owner_leaves() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (owner, member, joiner) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        for user_id in [owner, member, joiner] {
+            let _rx = connect(&connections, user_id).await;
+        }
+        for user_id in [owner, member] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+
+        user_disconnected(owner, &connections, &sessions).await;
+        session_join(&sessions, &connections, joiner, session_id(), false, None)
+            .await
+            .unwrap();
+
+        let sessions = sessions.read().await;
+        assert_eq!(sessions.get(&session_id()).unwrap().owner, None);
+    }
+
+    #[tokio::test]
+    async fn test_remaining_members_are_told_about_user_leaving() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (leaving, remaining) = (UserId::new(1), UserId::new(2));
+        let _leaving_rx = connect(&connections, leaving).await;
+        let mut remaining_rx = connect(&connections, remaining).await;
+        for user_id in [leaving, remaining] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+        while received_message(&mut remaining_rx).is_some() {}
+
+        user_disconnected(leaving, &connections, &sessions).await;
+
+        assert!(matches!(
+            received_message(&mut remaining_rx),
+            Some(SignalMessage::PeerLeft(_, user_id)) if user_id == leaving
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_non_owner_cannot_transfer_ownership() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        for user_id in [first, second] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+        while received_message(&mut first_rx).is_some() {}
+
+        transfer_ownership(&sessions, &connections, second, session_id(), second)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            received_message(&mut second_rx),
+            Some(SignalMessage::Error(..))
+        ));
+        assert!(received_message(&mut first_rx).is_none());
+        let sessions = sessions.read().await;
+        assert_eq!(sessions.get(&session_id()).unwrap().owner, Some(first));
+    }
+
+    #[tokio::test]
+    async fn test_relay_to_reaches_only_listed_members() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (sender, listed, unlisted, outsider) = (
+            UserId::new(1),
+            UserId::new(2),
+            UserId::new(3),
+            UserId::new(4),
+        );
+        let mut listed_rx = connect(&connections, listed).await;
+        let mut unlisted_rx = connect(&connections, unlisted).await;
+        let mut outsider_rx = connect(&connections, outsider).await;
+        let _sender_rx = connect(&connections, sender).await;
+        for user_id in [sender, listed, unlisted] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+        while received_message(&mut listed_rx).is_some() {}
+
+        relay_to(
+            &sessions,
+            &connections,
+            &ServerConfig::default(),
+            sender,
+            session_id(),
+            Recipients::Listed(vec![listed, outsider, listed]),
+            vec![1, 2, 3],
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            received_message(&mut listed_rx),
+            Some(SignalMessage::Relayed(_, from, data)) if from == sender && data == vec![1, 2, 3]
+        ));
+        assert!(received_message(&mut listed_rx).is_none());
+        assert!(received_message(&mut unlisted_rx).is_none());
+        assert!(received_message(&mut outsider_rx).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_offer_from_non_member_is_rejected() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (host, outsider) = (UserId::new(1), UserId::new(2));
+        let mut host_rx = connect(&connections, host).await;
+        let _outsider_rx = connect(&connections, outsider).await;
+        session_join(&sessions, &connections, host, session_id(), true, None)
+            .await
+            .unwrap();
+
+        let config = ServerConfig::default();
+        for request in [
+            SignalMessage::SdpOffer(session_id(), host, "offer".to_string()),
+            SignalMessage::SdpAnswer(session_id(), host, "answer".to_string()),
+        ] {
+            let msg = Message::Text(serde_json::to_string(&request).unwrap());
+            assert!(
+                user_message(outsider, msg, &connections, &sessions, &config, false)
+                    .await
+                    .is_err()
+            );
+        }
+
+        assert!(received_message(&mut host_rx).is_none());
+        assert!(sessions
+            .read()
+            .await
+            .get(&session_id())
+            .unwrap()
+            .offers
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_relay_reaches_recipients_after_failed_one() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (sender, gone, recipient) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let _sender_rx = connect(&connections, sender).await;
+        let _gone_rx = connect(&connections, gone).await;
+        let mut recipient_rx = connect(&connections, recipient).await;
+        for user_id in [sender, gone, recipient] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+        while received_message(&mut recipient_rx).is_some() {}
+        connections.write().await.remove(&gone);
+
+        relay_to(
+            &sessions,
+            &connections,
+            &ServerConfig::default(),
+            sender,
+            session_id(),
+            Recipients::Listed(vec![gone, recipient]),
+            vec![1],
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            received_message(&mut recipient_rx),
+            Some(SignalMessage::Relayed(_, from, _)) if from == sender
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_published_data_reaches_only_subscribers_within_topic_limits() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let config = ServerConfig {
+            max_topics_per_user: 1,
+            ..ServerConfig::default()
+        };
+        let (publisher, subscriber, other) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let _publisher_rx = connect(&connections, publisher).await;
+        let mut subscriber_rx = connect(&connections, subscriber).await;
+        let mut other_rx = connect(&connections, other).await;
+        for user_id in [publisher, subscriber, other] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+        while received_message(&mut subscriber_rx).is_some() {}
+        while received_message(&mut other_rx).is_some() {}
+
+        for topic in ["scores", "chat"] {
+            subscribe(
+                &sessions,
+                &connections,
+                &config,
+                subscriber,
+                session_id(),
+                topic.to_string(),
+            )
+            .await
+            .unwrap();
+        }
+        assert!(matches!(
+            received_message(&mut subscriber_rx),
+            Some(SignalMessage::Error(_, error)) if error == "too many topics subscribed"
+        ));
+        for topic in ["scores", "chat"] {
+            relay_to(
+                &sessions,
+                &connections,
+                &config,
+                publisher,
+                session_id(),
+                Recipients::Topic(topic.to_string()),
+                vec![1],
+            )
+            .await
+            .unwrap();
+        }
+
+        assert!(matches!(
+            received_message(&mut subscriber_rx),
+            Some(SignalMessage::Published(_, from, topic, data))
+                if from == publisher && topic == "scores" && data == vec![1]
+        ));
+        assert!(received_message(&mut subscriber_rx).is_none());
+        assert!(received_message(&mut other_rx).is_none());
+
+        user_disconnected(subscriber, &connections, &sessions).await;
+        assert!(sessions.read().await[&session_id()]
+            .subscriptions
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_last_user_leaving_removes_session() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (host, client) = (UserId::new(1), UserId::new(2));
+        let _host_rx = connect(&connections, host).await;
+        let _client_rx = connect(&connections, client).await;
+        session_join(&sessions, &connections, host, session_id(), true, None)
+            .await
+            .unwrap();
+        session_join(&sessions, &connections, client, session_id(), false, None)
+            .await
+            .unwrap();
+
+        user_disconnected(host, &connections, &sessions).await;
+        assert_eq!(sessions.read().await.get(&session_id()).unwrap().host, None);
+        user_disconnected(client, &connections, &sessions).await;
+
+        assert!(sessions.read().await.is_empty());
+        assert!(connections.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_relay_over_bandwidth_limit_is_dropped() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (sender, first, second) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let mut sender_rx = connect(&connections, sender).await;
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        for user_id in [sender, first, second] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+        for rx in [&mut sender_rx, &mut first_rx, &mut second_rx] {
+            while received_message(rx).is_some() {}
+        }
+        let config = ServerConfig {
+            max_relay_bytes_per_second: 100,
+            ..ServerConfig::default()
+        };
+
+        for _ in 0..2 {
+            relay_to(
+                &sessions,
+                &connections,
+                &config,
+                sender,
+                session_id(),
+                Recipients::Listed(vec![first, second]),
+                vec![0; 30],
+            )
+            .await
+            .unwrap();
+        }
+
+        // the second relay only had budget left for its first recipient
+        for _ in 0..2 {
+            assert!(matches!(
+                received_message(&mut first_rx),
+                Some(SignalMessage::Relayed(..))
+            ));
+        }
+        assert!(received_message(&mut first_rx).is_none());
+        assert!(matches!(
+            received_message(&mut second_rx),
+            Some(SignalMessage::Relayed(..))
+        ));
+        assert!(received_message(&mut second_rx).is_none());
+        assert!(matches!(
+            received_message(&mut sender_rx),
+            Some(SignalMessage::BandwidthExceeded(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_large_fan_out_reaches_recipients_within_budget() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let sender = UserId::new(0);
+        let mut sender_rx = connect(&connections, sender).await;
+        session_join(&sessions, &connections, sender, session_id(), false, None)
+            .await
+            .unwrap();
+        let mut recipients = Vec::new();
+        for id in 1..=100 {
+            let recipient = UserId::new(id);
+            let recipient_rx = connect(&connections, recipient).await;
+            session_join(
+                &sessions,
+                &connections,
+                recipient,
+                session_id(),
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+            recipients.push((recipient, recipient_rx));
+        }
+        while received_message(&mut sender_rx).is_some() {}
+        for (_, recipient_rx) in &mut recipients {
+            while received_message(recipient_rx).is_some() {}
+        }
+        let config = ServerConfig::default();
+        let within_budget = config.max_relay_bytes_per_second / MAX_RELAY_LENGTH;
+
+        relay_to(
+            &sessions,
+            &connections,
+            &config,
+            sender,
+            session_id(),
+            Recipients::Listed(recipients.iter().map(|(user_id, _)| *user_id).collect()),
+            vec![0; MAX_RELAY_LENGTH],
+        )
+        .await
+        .unwrap();
+
+        assert!(within_budget > 0 && within_budget < recipients.len());
+        for (index, (_, recipient_rx)) in recipients.iter_mut().enumerate() {
+            let received = received_message(recipient_rx);
+            if index < within_budget {
+                assert!(matches!(received, Some(SignalMessage::Relayed(..))));
+            } else {
+                assert!(received.is_none());
+            }
+        }
+        assert!(matches!(
+            received_message(&mut sender_rx),
+            Some(SignalMessage::BandwidthExceeded(_))
+        ));
+    }
+
+    #[derive(Debug)]
+    struct DenyLarge;
+
+    impl RelayAuthorizer for DenyLarge {
+        fn authorize(
+            &self,
+            _sender: UserId,
+            _session_id: &SessionId,
+            _recipients: &[UserId],
+            data: &[u8],
+        ) -> RelayDecision {
+            if data.len() > 2 {
+                RelayDecision::Deny(Some("message too large".to_string()))
+            } else {
+                RelayDecision::Allow
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relay_denied_by_authorizer_is_dropped() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (sender, recipient) = (UserId::new(1), UserId::new(2));
+        let mut sender_rx = connect(&connections, sender).await;
+        let mut recipient_rx = connect(&connections, recipient).await;
+        for user_id in [sender, recipient] {
+            session_join(&sessions, &connections, user_id, session_id(), false, None)
+                .await
+                .unwrap();
+        }
+        while received_message(&mut sender_rx).is_some() {}
+        while received_message(&mut recipient_rx).is_some() {}
+        let config = ServerConfig {
+            relay_authorizer: Arc::new(DenyLarge),
+            ..ServerConfig::default()
+        };
+
+        for data in [vec![1, 2, 3], vec![1]] {
+            relay_to(
+                &sessions,
+                &connections,
+                &config,
+                sender,
+                session_id(),
+                Recipients::Listed(vec![recipient]),
+                data,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert!(matches!(
+            received_message(&mut sender_rx),
+            Some(SignalMessage::Error(_, reason)) if reason == "message too large"
+        ));
+        assert!(matches!(
+            received_message(&mut recipient_rx),
+            Some(SignalMessage::Relayed(_, _, data)) if data == vec![1]
+        ));
+        assert!(received_message(&mut recipient_rx).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_query_session_reports_members_to_member_only() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (host, client, outsider) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let mut host_rx = connect(&connections, host).await;
+        let mut client_rx = connect(&connections, client).await;
+        let mut outsider_rx = connect(&connections, outsider).await;
+        session_join(&sessions, &connections, client, session_id(), false, None)
+            .await
+            .unwrap();
+        session_join(&sessions, &connections, host, session_id(), true, None)
+            .await
+            .unwrap();
+        while received_message(&mut host_rx).is_some() {}
+
+        query_session(&sessions, &connections, client, session_id(), false)
+            .await
+            .unwrap();
+        query_session(&sessions, &connections, outsider, session_id(), false)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            received_message(&mut client_rx),
+            Some(SignalMessage::SessionStatus(_, info)) if info == SessionInfo {
+                host: Some(host),
+                owner: Some(client),
+                users: vec![host, client],
+                ready: true,
+            }
+        ));
+        assert!(matches!(
+            received_message(&mut outsider_rx),
+            Some(SignalMessage::Error(..))
+        ));
+    }
+}
@@ -1,64 +1,300 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::IpAddr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use anyhow::anyhow;
 use axum::extract::ws::{Message, WebSocket};
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
 use log::{error, info};
 use tokio::sync::{mpsc, RwLock};
+use tokio::time::Instant;
 use tokio_stream::wrappers::UnboundedReceiverStream;
-use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::one_to_one::{SignalMessage, MAX_PEER_METADATA_LENGTH};
 use wasm_peers_protocol::{SessionId, UserId};
 
+use crate::admin::SessionActivity;
+use crate::config::{EarlyIceCandidates, ServerConfig};
+use crate::error_budget::{parse_message, ErrorBudget, NotInSession};
+use crate::heartbeat::{self, IdleTimer, Timeout};
+use crate::lifecycle_log::{self, LifecycleEvent};
+use crate::maintenance::MAINTENANCE_ERROR;
+use crate::matchmaking::{self, MatchRequestLimit, WaitingUsers, MATCH_REQUESTS_LIMIT_ERROR};
+use crate::negotiation_limit::NegotiationPermit;
+use crate::offerer;
+use crate::serialization::{internal_error_response, serialize_message};
+use crate::session_log::{SessionEventKind, SessionLog};
+
+/// Error sent in response to a message about a session the user isn't in, e.g. sent before joining it.
+pub const NOT_IN_SESSION_ERROR: &str = "not in session";
+
+/// Error sent to a user whose message couldn't be handled because of a fault of the server,
+/// e.g. a response that failed to serialize, so it isn't left waiting for a response that never comes.
+/// Sent in every topology.
+pub const INTERNAL_ERROR: &str = "internal server error";
+
 pub struct Session {
     pub first: Option<UserId>,
     pub second: Option<UserId>,
     pub offer_received: bool,
+    pub renegotiations: usize,
+    /// When both users were last told that the session is ready,
+    /// identifies the negotiation watched for [`ServerConfig::negotiation_timeout`].
+    pub ready_at: Option<Instant>,
+    pub answer_received: bool,
+    /// `ICE` candidates sent while the other user wasn't in the session,
+    /// see [`ServerConfig::early_ice_candidates`].
+    pub held_candidates: Vec<HeldCandidate>,
+    /// Events of the session, if enabled with [`ServerConfig::session_log`].
+    pub log: Option<SessionLog>,
+    /// Slot taken while the session negotiates, see [`crate::negotiation_limit`].
+    pub negotiation_permit: Option<NegotiationPermit>,
+    /// Whether the full session waits for a negotiation slot before its users get `SessionReady`.
+    pub waiting_for_negotiation_slot: bool,
+    pub activity: SessionActivity,
+    /// Addresses the users of the slots connect from, if known, see [`ServerConfig::same_network_hints`].
+    pub first_remote_ip: Option<IpAddr>,
+    pub second_remote_ip: Option<IpAddr>,
+}
+
+impl Session {
+    /// Session with given users in its slots, which didn't negotiate yet.
+    pub fn new(first: Option<UserId>, second: Option<UserId>) -> Self {
+        Session {
+            first,
+            second,
+            offer_received: false,
+            renegotiations: 0,
+            ready_at: None,
+            answer_received: false,
+            held_candidates: Vec::new(),
+            log: None,
+            negotiation_permit: None,
+            waiting_for_negotiation_slot: false,
+            activity: SessionActivity::default(),
+            first_remote_ip: None,
+            second_remote_ip: None,
+        }
+    }
+
+    /// Records the event in the session's log, if logs are enabled, see [`crate::session_log`].
+    pub(crate) fn record(
+        &mut self,
+        kind: SessionEventKind,
+        user_id: Option<UserId>,
+        payload: Option<&str>,
+    ) {
+        if let Some(log) = &mut self.log {
+            log.record(kind, user_id, payload);
+        }
+    }
+}
+
+/// Candidate held until the other user joins the session.
+pub struct HeldCandidate {
+    pub sender: UserId,
+    pub received_at: Instant,
+    pub candidate: String,
 }
 
 pub type Connections = Arc<RwLock<HashMap<UserId, mpsc::UnboundedSender<Message>>>>;
 pub type Sessions = Arc<RwLock<HashMap<SessionId, Session>>>;
 
-static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
+pub(crate) static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
 
-pub async fn user_connected(ws: WebSocket, connections: Connections, sessions: Sessions) {
-    let user_id = UserId::new(NEXT_USER_ID.fetch_add(1, Ordering::Relaxed));
-    info!("new user connected: {:?}", user_id);
+pub async fn user_connected(
+    ws: WebSocket,
+    connections: Connections,
+    sessions: Sessions,
+    waiting_users: WaitingUsers,
+    config: Arc<ServerConfig>,
+    region: Option<String>,
+    remote_ip: Option<IpAddr>,
+) {
+    let (user_ws_tx, user_ws_rx) = ws.split();
+    serve_user(
+        user_ws_tx,
+        user_ws_rx,
+        connections,
+        sessions,
+        waiting_users,
+        config,
+        ConnectionOptions {
+            region,
+            heartbeat: true,
+            remote_ip,
+        },
+    )
+    .await;
+}
+
+/// Properties of a connection served with [`serve_user`] that depend on its transport.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionOptions {
+    /// Region tag of the connection, see [`crate::region`].
+    pub(crate) region: Option<String>,
+    /// Transports without pings and pongs should disable it.
+    pub(crate) heartbeat: bool,
+    /// Address the connection comes from, if the transport knows it, see [`crate::lifecycle_log`].
+    pub(crate) remote_ip: Option<IpAddr>,
+}
+
+/// Connection served with [`serve_user`], with the state kept between its messages.
+struct Connection {
+    options: ConnectionOptions,
+    match_request_limit: MatchRequestLimit,
+}
+
+impl Connection {
+    fn new(options: ConnectionOptions, config: &ServerConfig) -> Self {
+        Connection {
+            options,
+            match_request_limit: MatchRequestLimit::new(config.max_match_requests_per_minute),
+        }
+    }
+}
 
-    let (mut user_ws_tx, mut user_ws_rx) = ws.split();
+/// Connection loop independent of the transport, so that it can be shared
+/// by websocket and raw TCP connections.
+///
+/// Messages of a connection are handled one at a time and each recipient has a single FIFO channel,
+/// so messages relayed from one user to another, e.g. `ICE` candidates, arrive in the order they were sent.
+pub(crate) async fn serve_user<Tx, Rx, E>(
+    mut user_tx: Tx,
+    mut user_rx: Rx,
+    connections: Connections,
+    sessions: Sessions,
+    waiting_users: WaitingUsers,
+    config: Arc<ServerConfig>,
+    options: ConnectionOptions,
+) where
+    Tx: Sink<Message> + Unpin + Send + 'static,
+    Tx::Error: Display,
+    Rx: Stream<Item = Result<Message, E>> + Unpin,
+    E: Display,
+{
+    let ConnectionOptions {
+        heartbeat,
+        remote_ip,
+        ..
+    } = options;
+    let user_id = UserId::new(NEXT_USER_ID.fetch_add(1, Ordering::Relaxed));
+    info!(
+        "new user connected: {:?}, region: {:?}",
+        user_id, options.region
+    );
+    let mut connection = Connection::new(options, &config);
+    lifecycle_log::record(
+        &config,
+        LifecycleEvent::Connected,
+        Some(user_id),
+        None,
+        remote_ip,
+    );
 
     let (tx, rx) = mpsc::unbounded_channel();
     let mut rx = UnboundedReceiverStream::new(rx);
 
     tokio::task::spawn(async move {
         while let Some(message) = rx.next().await {
-            user_ws_tx
+            user_tx
                 .send(message)
                 .await
                 .unwrap_or_else(|e| error!("websocket send error: {}", e));
         }
     });
 
-    connections.write().await.insert(user_id, tx);
+    let pings = heartbeat.then(|| heartbeat::spawn_pings(tx.clone(), config.heartbeat_interval));
+    connections.write().await.insert(user_id, tx.clone());
+    let mut error_budget = ErrorBudget::new(config.max_consecutive_malformed_messages)
+        .with_max_outside_session(config.max_messages_outside_session);
+    let mut idle_timer = IdleTimer::new(config.idle_timeout);
+    let heartbeat_timeout = heartbeat.then_some(config.heartbeat_timeout);
 
-    while let Some(result) = user_ws_rx.next().await {
-        let msg = match result {
-            Ok(msg) => msg,
-            Err(err) => {
+    loop {
+        let next = match heartbeat::next_message(&mut user_rx, heartbeat_timeout, &idle_timer).await
+        {
+            Ok(next) => next,
+            Err(Timeout::Heartbeat) => {
+                info!("heartbeat timeout, dropping user: {:?}", user_id);
+                break;
+            }
+            Err(Timeout::Idle) => {
+                info!("idle timeout, dropping user: {:?}", user_id);
+                let response = SignalMessage::Error(
+                    SessionId::new(String::new()),
+                    "connection idle for too long".to_string(),
+                );
+                if let Ok(response) = serialize_message(&response) {
+                    let _ = tx.send(Message::Text(response));
+                }
+                break;
+            }
+        };
+        let msg = match next {
+            Some(Ok(msg)) => msg,
+            Some(Err(err)) => {
                 eprintln!("websocket error (user_id={:?}): {}", user_id, err);
                 break;
             }
+            None => break,
         };
+        if heartbeat::is_heartbeat(&msg) {
+            continue;
+        }
+        idle_timer.reset();
+        #[cfg(feature = "chaos")]
+        crate::chaos::delay(&config).await;
 
-        if let Err(err) = user_message(user_id, msg, &connections, &sessions).await {
-            error!("user_message error: {}", err);
+        let result = user_message(
+            user_id,
+            msg,
+            &connections,
+            &sessions,
+            &waiting_users,
+            &config,
+            &mut connection,
+        )
+        .await;
+        if let Err(err) = &result {
+            error!("user_message error (user_id={:?}): {}", user_id, err);
+        }
+        if let Some(response) = internal_error_response(&result, SignalMessage::Error) {
+            let _ = tx.send(response);
+        }
+        if let Some(reason) = error_budget.exhausted_by(&result) {
+            info!("{}, dropping user: {:?}", reason, user_id);
+            let response = SignalMessage::Error(SessionId::new(String::new()), reason.to_string());
+            if let Ok(response) = serialize_message(&response) {
+                let _ = tx.send(Message::Text(response));
+            }
+            break;
         }
     }
 
+    if let Some(pings) = pings {
+        pings.abort();
+    }
     eprintln!("user disconnected: {:?}", user_id);
-    user_disconnected(user_id, &connections, &sessions).await;
+    matchmaking::stop_waiting(&waiting_users, user_id).await;
+    for closed_session_id in user_disconnected(user_id, &connections, &sessions).await {
+        lifecycle_log::record(
+            &config,
+            LifecycleEvent::SessionClosed,
+            None,
+            Some(&closed_session_id),
+            None,
+        );
+    }
+    lifecycle_log::record(
+        &config,
+        LifecycleEvent::Disconnected,
+        Some(user_id),
+        None,
+        remote_ip,
+    );
 }
 
 async fn user_message(
@@ -66,34 +302,117 @@ async fn user_message(
     msg: Message,
     connections: &Connections,
     sessions: &Sessions,
+    waiting_users: &WaitingUsers,
+    config: &ServerConfig,
+    connection: &mut Connection,
 ) -> anyhow::Result<()> {
-    let msg = msg
-        .to_text()
-        .map_err(|_err| anyhow!("websocket message is not text"))?;
-    let request = serde_json::from_str::<SignalMessage>(msg)?;
+    let request: SignalMessage = parse_message(&msg)?;
     info!("message received from user {:?}: {:?}", user_id, request);
+    if config.session_log.is_some() {
+        record_relayed(sessions, user_id, &request).await;
+    }
+    config
+        .transcripts
+        .capture(config.transcript_ttl, user_id, &request);
+    if let Some(session_id) = relayed_session_id(&request) {
+        if !record_message(sessions, user_id, session_id).await {
+            info!(
+                "user {:?} sent a message for session it isn't in: {:?}",
+                user_id, session_id
+            );
+            let response =
+                SignalMessage::Error(session_id.clone(), NOT_IN_SESSION_ERROR.to_string());
+            let response = serialize_message(&response)?;
+            let connections_reader = connections.read().await;
+            let user_tx = connections_reader
+                .get(&user_id)
+                .ok_or_else(|| anyhow!("no sender for given user_id"))?;
+            user_tx.send(Message::Text(response))?;
+            return Err(NotInSession.into());
+        }
+    }
     match request {
         SignalMessage::SessionJoin(session_id) => {
-            session_join(sessions, connections, user_id, session_id).await?;
+            session_join(
+                sessions,
+                connections,
+                config,
+                user_id,
+                session_id,
+                connection.options.remote_ip,
+            )
+            .await?;
+        }
+        SignalMessage::FindMatch(criteria) => {
+            if !connection.match_request_limit.allow() {
+                info!("user {:?} sends too many match requests", user_id);
+                matchmaking::send_error(connections, user_id, MATCH_REQUESTS_LIMIT_ERROR).await?;
+                return Ok(());
+            }
+            matchmaking::find_match(
+                waiting_users,
+                sessions,
+                connections,
+                config,
+                user_id,
+                connection.options.region.as_deref(),
+                criteria,
+            )
+            .await?;
+        }
+        SignalMessage::SessionLeave(session_id) => {
+            let peer_id = match leave_session(user_id, &session_id, sessions).await {
+                Some(peer_id) => peer_id,
+                None => {
+                    info!(
+                        "ignoring user {:?} leaving session {:?} it isn't in",
+                        user_id, session_id
+                    );
+                    return Ok(());
+                }
+            };
+            info!("user {:?} left session {:?}", user_id, session_id);
+            match peer_id {
+                // let the remaining user know that the other one left on purpose
+                Some(peer_id) => {
+                    let response = SignalMessage::PeerLeft(session_id);
+                    let response = serialize_message(&response)?;
+                    let connections_reader = connections.read().await;
+                    let peer_tx = connections_reader
+                        .get(&peer_id)
+                        .ok_or_else(|| anyhow!("no sender for given peer_id"))?;
+                    peer_tx.send(Message::Text(response))?;
+                }
+                None => lifecycle_log::record(
+                    config,
+                    LifecycleEvent::SessionClosed,
+                    None,
+                    Some(&session_id),
+                    None,
+                ),
+            }
         }
-        // pass offer to the other user in session without changing anything
+        // pass offer to the other user in session, only applying the configured filter
         SignalMessage::SdpOffer(session_id, offer) => {
-            sdp_offer(sessions, connections, user_id, session_id, offer).await?;
+            sdp_offer(sessions, connections, config, user_id, session_id, offer).await?;
         }
-        // pass answer to the other user in session without changing anything
+        // pass answer to the other user in session, only applying the configured filter
         SignalMessage::SdpAnswer(session_id, answer) => {
-            let sessions = sessions.read().await;
+            let mut sessions = sessions.write().await;
             let session = sessions
-                .get(&session_id)
+                .get_mut(&session_id)
                 .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
+            session.answer_received = true;
+            session.negotiation_permit = None;
             let recipient_id = if Some(user_id) == session.first {
                 session.second
             } else {
                 session.first
             }
             .ok_or_else(|| anyhow!("missing second user in session: {:?}", &session_id))?;
-            let response = SignalMessage::SdpAnswer(session_id, answer);
-            let response = serde_json::to_string(&response)?;
+            let response =
+                SignalMessage::SdpAnswer(session_id, config.sdp_filter.filter_sdp(&answer));
+            let response = serialize_message(&response)?;
             let connections_reader = connections.read().await;
             let recipient_tx = connections_reader
                 .get(&recipient_id)
@@ -102,74 +421,486 @@ async fn user_message(
             recipient_tx.send(Message::Text(response))?;
         }
         SignalMessage::IceCandidate(session_id, candidate) => {
-            let sessions = sessions.read().await;
-            let session = sessions
-                .get(&session_id)
-                .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
-            let recipient_id = if Some(user_id) == session.first {
-                session.second
+            ice_candidate(
+                sessions,
+                connections,
+                config,
+                user_id,
+                session_id,
+                candidate,
+            )
+            .await?;
+        }
+        SignalMessage::PeerMetadata(session_id, metadata) => {
+            peer_metadata(sessions, connections, user_id, session_id, metadata).await?;
+        }
+        // pass the request to the other user, the host then restarts ICE with a new offer
+        SignalMessage::IceRestart(session_id) => {
+            ice_restart(sessions, connections, user_id, session_id).await?;
+        }
+        other => {
+            error!("received unexpected signal message: {:?}", other);
+        }
+    }
+    Ok(())
+}
+
+/// Session of a message that only users in the session can send.
+fn relayed_session_id(request: &SignalMessage) -> Option<&SessionId> {
+    match request {
+        SignalMessage::SdpOffer(session_id, _)
+        | SignalMessage::SdpAnswer(session_id, _)
+        | SignalMessage::IceCandidate(session_id, _)
+        | SignalMessage::PeerMetadata(session_id, _)
+        | SignalMessage::IceRestart(session_id) => Some(session_id),
+        _ => None,
+    }
+}
+
+/// Counts the message in the session's activity, returns `false` if the user isn't in the session.
+async fn record_message(sessions: &Sessions, user_id: UserId, session_id: &SessionId) -> bool {
+    match sessions.write().await.get_mut(session_id) {
+        Some(session) if session.first == Some(user_id) || session.second == Some(user_id) => {
+            session.activity.record_message();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Records messages passed between the users in the session's log,
+/// others are recorded where they change the session.
+async fn record_relayed(sessions: &Sessions, user_id: UserId, request: &SignalMessage) {
+    let (session_id, kind, payload) = match request {
+        SignalMessage::SdpOffer(session_id, offer) => {
+            (session_id, SessionEventKind::SdpOffer, Some(offer))
+        }
+        SignalMessage::SdpAnswer(session_id, answer) => {
+            (session_id, SessionEventKind::SdpAnswer, Some(answer))
+        }
+        SignalMessage::IceCandidate(session_id, candidate) => {
+            (session_id, SessionEventKind::IceCandidate, Some(candidate))
+        }
+        SignalMessage::IceRestart(session_id) => (session_id, SessionEventKind::IceRestart, None),
+        _ => return,
+    };
+    if let Some(session) = sessions.write().await.get_mut(session_id) {
+        session.record(kind, Some(user_id), payload.map(String::as_str));
+    }
+}
+
+async fn ice_candidate(
+    sessions: &Sessions,
+    connections: &Connections,
+    config: &ServerConfig,
+    user_id: UserId,
+    session_id: SessionId,
+    candidate: String,
+) -> anyhow::Result<()> {
+    if !config.sdp_filter.allows_candidate(&candidate) {
+        info!("dropping filtered ICE candidate: {:?}", session_id);
+        return Ok(());
+    }
+    let mut sessions = sessions.write().await;
+    let session = sessions
+        .get_mut(&session_id)
+        .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
+    let recipient_id = if Some(user_id) == session.first {
+        session.second
+    } else {
+        session.first
+    };
+    let recipient_id = match (recipient_id, config.early_ice_candidates) {
+        (Some(recipient_id), _) => recipient_id,
+        (None, EarlyIceCandidates::Buffer { max_age, max_count }) => {
+            session
+                .held_candidates
+                .retain(|held| held.received_at.elapsed() <= max_age);
+            if session.held_candidates.len() < max_count {
+                info!(
+                    "holding ICE candidate until session is ready: {:?}",
+                    session_id
+                );
+                session.held_candidates.push(HeldCandidate {
+                    sender: user_id,
+                    received_at: Instant::now(),
+                    candidate,
+                });
             } else {
-                session.first
+                info!("too many ICE candidates held, dropping: {:?}", session_id);
             }
-            .ok_or_else(|| anyhow!("missing second user in session: {:?}", &session_id))?;
-            let response = SignalMessage::IceCandidate(session_id, candidate);
-            let response = serde_json::to_string(&response)?;
+            return Ok(());
+        }
+        (None, EarlyIceCandidates::Drop) => {
+            info!(
+                "dropping ICE candidate, session isn't ready: {:?}",
+                session_id
+            );
+            return Ok(());
+        }
+        (None, EarlyIceCandidates::Reject) => {
+            info!(
+                "rejecting ICE candidate, session isn't ready: {:?}",
+                session_id
+            );
+            let response = SignalMessage::Error(session_id, "session isn't ready".to_string());
+            let response = serialize_message(&response)?;
             let connections_reader = connections.read().await;
-            let recipient_tx = connections_reader
-                .get(&recipient_id)
-                .ok_or_else(|| anyhow!("no sender for given recipient_id"))?;
+            let user_tx = connections_reader
+                .get(&user_id)
+                .ok_or_else(|| anyhow!("no sender for given user_id"))?;
+            user_tx.send(Message::Text(response))?;
+            return Ok(());
+        }
+    };
+    let response = SignalMessage::IceCandidate(session_id, candidate);
+    let response = serialize_message(&response)?;
+    let connections_reader = connections.read().await;
+    let recipient_tx = connections_reader
+        .get(&recipient_id)
+        .ok_or_else(|| anyhow!("no sender for given recipient_id"))?;
 
-            recipient_tx.send(Message::Text(response))?;
+    recipient_tx.send(Message::Text(response))?;
+    Ok(())
+}
+
+/// Passes held candidates that aren't too old to the user who just made the session ready.
+fn release_held_candidates(
+    session: &mut Session,
+    recipient_tx: &mpsc::UnboundedSender<Message>,
+    config: &ServerConfig,
+    session_id: &SessionId,
+    recipient_id: UserId,
+) -> anyhow::Result<()> {
+    let max_age = match config.early_ice_candidates {
+        EarlyIceCandidates::Buffer { max_age, .. } => max_age,
+        EarlyIceCandidates::Drop | EarlyIceCandidates::Reject => return Ok(()),
+    };
+    for held in std::mem::take(&mut session.held_candidates) {
+        // candidates of a user who left since belong to a connection that's gone
+        let sender_in_session =
+            session.first == Some(held.sender) || session.second == Some(held.sender);
+        if held.sender == recipient_id || !sender_in_session || held.received_at.elapsed() > max_age
+        {
+            continue;
         }
-        other => {
-            error!("received unexpected signal message: {:?}", other);
+        let response = SignalMessage::IceCandidate(session_id.clone(), held.candidate);
+        recipient_tx.send(Message::Text(serialize_message(&response)?))?;
+    }
+    Ok(())
+}
+
+async fn peer_metadata(
+    sessions: &Sessions,
+    connections: &Connections,
+    user_id: UserId,
+    session_id: SessionId,
+    metadata: String,
+) -> anyhow::Result<()> {
+    let (response, recipient_id) = if metadata.len() > MAX_PEER_METADATA_LENGTH {
+        let error = format!(
+            "peer metadata is too long: {} bytes, maximum is {}",
+            metadata.len(),
+            MAX_PEER_METADATA_LENGTH
+        );
+        (SignalMessage::Error(session_id, error), user_id)
+    } else {
+        let sessions = sessions.read().await;
+        let session = sessions
+            .get(&session_id)
+            .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
+        let recipient_id = if Some(user_id) == session.first {
+            session.second
+        } else {
+            session.first
         }
+        .ok_or_else(|| anyhow!("missing second user in session: {:?}", &session_id))?;
+        (
+            SignalMessage::PeerMetadata(session_id, metadata),
+            recipient_id,
+        )
+    };
+    let response = serialize_message(&response)?;
+    let connections_reader = connections.read().await;
+    let recipient_tx = connections_reader
+        .get(&recipient_id)
+        .ok_or_else(|| anyhow!("no sender for given recipient_id"))?;
+
+    recipient_tx.send(Message::Text(response))?;
+    Ok(())
+}
+
+async fn ice_restart(
+    sessions: &Sessions,
+    connections: &Connections,
+    user_id: UserId,
+    session_id: SessionId,
+) -> anyhow::Result<()> {
+    let sessions = sessions.read().await;
+    let session = sessions
+        .get(&session_id)
+        .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
+    let recipient_id = if Some(user_id) == session.first {
+        session.second
+    } else {
+        session.first
     }
+    .ok_or_else(|| anyhow!("missing second user in session: {:?}", &session_id))?;
+    let response = SignalMessage::IceRestart(session_id);
+    let response = serialize_message(&response)?;
+    let connections_reader = connections.read().await;
+    let recipient_tx = connections_reader
+        .get(&recipient_id)
+        .ok_or_else(|| anyhow!("no sender for given recipient_id"))?;
+
+    recipient_tx.send(Message::Text(response))?;
     Ok(())
 }
 
 async fn session_join(
     sessions: &Sessions,
     connections: &Connections,
+    config: &ServerConfig,
     user_id: UserId,
     session_id: SessionId,
+    remote_ip: Option<IpAddr>,
 ) -> anyhow::Result<()> {
-    match sessions.write().await.entry(session_id.clone()) {
+    let rejection = if !config.allows_session(&session_id) {
+        Some("session not allowed")
+    } else if config.maintenance.is_enabled() && !sessions.read().await.contains_key(&session_id) {
+        Some(MAINTENANCE_ERROR)
+    } else {
+        None
+    };
+    if let Some(rejection) = rejection {
+        info!("user {:?} can't join session {:?}", user_id, session_id);
+        let response = SignalMessage::Error(session_id, rejection.to_string());
+        let response = serialize_message(&response)?;
+        let connections_reader = connections.read().await;
+        let user_tx = connections_reader
+            .get(&user_id)
+            .ok_or_else(|| anyhow!("no sender for given user_id"))?;
+        user_tx.send(Message::Text(response))?;
+        return Ok(());
+    }
+    let full = match sessions.write().await.entry(session_id.clone()) {
         // on first user in session - create session object and store connecting user id
         Entry::Vacant(entry) => {
-            entry.insert(Session {
-                first: Some(user_id),
-                second: None,
-                offer_received: false,
+            let log = config
+                .session_log
+                .clone()
+                .map(|log_config| SessionLog::new(session_id.clone(), log_config));
+            let session = entry.insert(Session {
+                log,
+                first_remote_ip: remote_ip,
+                ..Session::new(Some(user_id), None)
             });
+            session.record(SessionEventKind::Joined, Some(user_id), None);
+            lifecycle_log::record(
+                config,
+                LifecycleEvent::SessionCreated,
+                None,
+                Some(&session_id),
+                None,
+            );
+            false
         }
-        // on second user - add him to existing session and notify users that session is ready
+        // on second user - add him to the free slot of existing session
+        // (first one might be free if its user left and is now rejoining)
+        // and notify users that session is ready
         Entry::Occupied(mut entry) => {
-            entry.get_mut().second = Some(user_id);
-            let first_response = SignalMessage::SessionReady(session_id.clone(), true);
-            let first_response = serde_json::to_string(&first_response)?;
-            let second_response = SignalMessage::SessionReady(session_id, false);
-            let second_response = serde_json::to_string(&second_response)?;
-
-            let connections_reader = connections.read().await;
-            if let Some(first_id) = entry.get().first {
-                let first_tx = connections_reader
-                    .get(&first_id)
-                    .ok_or_else(|| anyhow!("no sender for given id"))?;
-                first_tx.send(Message::Text(first_response))?;
-                let second_tx = connections_reader
-                    .get(&user_id)
-                    .ok_or_else(|| anyhow!("no sender for given id"))?;
-                second_tx.send(Message::Text(second_response))?;
+            let session = entry.get_mut();
+            if session.first.is_none() {
+                session.first = Some(user_id);
+                session.first_remote_ip = remote_ip;
+            } else if session.second.is_none() {
+                session.second = Some(user_id);
+                session.second_remote_ip = remote_ip;
+            } else {
+                return Err(anyhow!("session is already full: {:?}", &session_id));
             }
+            session.record(SessionEventKind::Joined, Some(user_id), None);
+            // rejoining user starts a new negotiation
+            session.offer_received = false;
+            session.answer_received = false;
+            // session waiting for a slot already gets ready once it has one
+            session.first.is_some()
+                && session.second.is_some()
+                && !session.waiting_for_negotiation_slot
+        }
+    };
+    lifecycle_log::record(
+        config,
+        LifecycleEvent::Joined,
+        Some(user_id),
+        Some(&session_id),
+        None,
+    );
+    if full {
+        admit(sessions, connections, config, session_id, user_id).await?;
+    }
+    Ok(())
+}
+
+/// Tells both users of the full session that it's ready, as soon as a negotiation slot is free,
+/// see [`crate::negotiation_limit`]. `joined` is the user whose join made the session full.
+pub(crate) async fn admit(
+    sessions: &Sessions,
+    connections: &Connections,
+    config: &ServerConfig,
+    session_id: SessionId,
+    joined: UserId,
+) -> anyhow::Result<()> {
+    if let Some(permit) = config.negotiation_limit.try_acquire() {
+        return session_ready(sessions, connections, config, session_id, joined, permit).await;
+    }
+    info!(
+        "too many negotiations, session waits for a slot: {:?}",
+        session_id
+    );
+    if let Some(session) = sessions.write().await.get_mut(&session_id) {
+        session.waiting_for_negotiation_slot = true;
+    }
+    let sessions = sessions.clone();
+    let connections = connections.clone();
+    let config = config.clone();
+    tokio::task::spawn(async move {
+        let permit = config.negotiation_limit.acquire().await;
+        session_ready(&sessions, &connections, &config, session_id, joined, permit)
+            .await
+            .unwrap_or_else(|err| error!("failed to make session ready: {}", err));
+    });
+    Ok(())
+}
+
+/// Does nothing if one of the users left in the meantime.
+async fn session_ready(
+    sessions: &Sessions,
+    connections: &Connections,
+    config: &ServerConfig,
+    session_id: SessionId,
+    user_id: UserId,
+    permit: NegotiationPermit,
+) -> anyhow::Result<()> {
+    let mut sessions_writer = sessions.write().await;
+    let session = match sessions_writer.get_mut(&session_id) {
+        Some(session) => session,
+        None => return Ok(()),
+    };
+    session.waiting_for_negotiation_slot = false;
+    let (first_id, second_id) = match (session.first, session.second) {
+        (Some(first_id), Some(second_id)) => (first_id, second_id),
+        _ => return Ok(()),
+    };
+    let connections_reader = connections.read().await;
+    let first_offers = offerer::first_offers(
+        config.offerer_strategy.as_ref(),
+        &session_id,
+        first_id,
+        second_id,
+        user_id,
+    );
+    let first_response = SignalMessage::SessionReady(session_id.clone(), first_offers);
+    let first_response = serialize_message(&first_response)?;
+    let second_response = SignalMessage::SessionReady(session_id.clone(), !first_offers);
+    let second_response = serialize_message(&second_response)?;
+    let first_tx = connections_reader
+        .get(&first_id)
+        .ok_or_else(|| anyhow!("no sender for given id"))?;
+    first_tx.send(Message::Text(first_response))?;
+    let second_tx = connections_reader
+        .get(&second_id)
+        .ok_or_else(|| anyhow!("no sender for given id"))?;
+    second_tx.send(Message::Text(second_response))?;
+    if session.first_remote_ip.is_some() && session.first_remote_ip == session.second_remote_ip {
+        info!(
+            "users of session {:?} connect from the same address, likely on the same network",
+            session_id
+        );
+        if config.same_network_hints {
+            let hint = serialize_message(&SignalMessage::SameNetworkHint(session_id.clone()))?;
+            first_tx.send(Message::Text(hint.clone()))?;
+            second_tx.send(Message::Text(hint))?;
         }
     }
+    let joined_tx = if user_id == first_id {
+        first_tx
+    } else {
+        second_tx
+    };
+    release_held_candidates(session, joined_tx, config, &session_id, user_id)?;
+    session.record(SessionEventKind::SessionReady, None, None);
+    session.negotiation_permit = Some(permit);
+    let ready_at = Instant::now();
+    session.ready_at = Some(ready_at);
+    watch_negotiation(sessions, connections, config, session_id, ready_at);
     Ok(())
 }
 
+/// Lets both users know if no answer was passed between them within [`ServerConfig::negotiation_timeout`]
+/// after the session got ready at `ready_at`, and frees its negotiation slot
+/// after [`crate::negotiation_limit::NegotiationLimit::slot_timeout`] either way.
+/// Does nothing if the session got ready again in the meantime.
+pub(crate) fn watch_negotiation(
+    sessions: &Sessions,
+    connections: &Connections,
+    config: &ServerConfig,
+    session_id: SessionId,
+    ready_at: Instant,
+) {
+    if let Some(slot_timeout) = config.negotiation_limit.slot_timeout() {
+        let sessions = sessions.clone();
+        let session_id = session_id.clone();
+        tokio::task::spawn(async move {
+            tokio::time::sleep(slot_timeout).await;
+            if let Some(session) = sessions.write().await.get_mut(&session_id) {
+                if session.ready_at == Some(ready_at) && session.negotiation_permit.is_some() {
+                    info!(
+                        "negotiation held its slot for too long, freeing it: {:?}",
+                        session_id
+                    );
+                    session.negotiation_permit = None;
+                }
+            }
+        });
+    }
+    let timeout = match config.negotiation_timeout {
+        Some(timeout) => timeout,
+        None => return,
+    };
+    let sessions = sessions.clone();
+    let connections = connections.clone();
+    tokio::task::spawn(async move {
+        tokio::time::sleep(timeout).await;
+        let stalled_users = match sessions.write().await.get_mut(&session_id) {
+            Some(session) if session.ready_at == Some(ready_at) && !session.answer_received => {
+                // stalled negotiation doesn't hold its slot any longer
+                session.negotiation_permit = None;
+                [session.first, session.second]
+            }
+            _ => return,
+        };
+        info!("negotiation timed out in session: {:?}", session_id);
+        let response = SignalMessage::NegotiationTimeout(session_id);
+        let response = match serialize_message(&response) {
+            Ok(response) => response,
+            Err(err) => return error!("{}", err),
+        };
+        let connections_reader = connections.read().await;
+        for user_tx in stalled_users
+            .iter()
+            .flatten()
+            .filter_map(|user_id| connections_reader.get(user_id))
+        {
+            let _ = user_tx.send(Message::Text(response.clone()));
+        }
+    });
+}
+
 async fn sdp_offer(
     sessions: &Sessions,
     connections: &Connections,
+    config: &ServerConfig,
     user_id: UserId,
     session_id: SessionId,
     offer: String,
@@ -179,8 +910,23 @@ async fn sdp_offer(
         .get_mut(&session_id)
         .ok_or_else(|| anyhow!("no such session: {:?}", &session_id))?;
     if session.offer_received {
+        if session.renegotiations >= config.max_renegotiations {
+            info!(
+                "too many renegotiations, rejecting the offer: {:?}",
+                session_id
+            );
+            let response = SignalMessage::Error(session_id, "too many renegotiations".to_string());
+            let response = serialize_message(&response)?;
+            let connections_reader = connections.read().await;
+            let sender_tx = connections_reader
+                .get(&user_id)
+                .ok_or_else(|| anyhow!("no sender for given user_id"))?;
+            sender_tx.send(Message::Text(response))?;
+            return Ok(());
+        }
+        session.renegotiations += 1;
         info!(
-            "offer already sent by the the peer, ignoring the second offer: {:?}",
+            "offer already sent by the peer, renegotiating: {:?}",
             session_id
         );
     } else {
@@ -193,8 +939,8 @@ async fn sdp_offer(
         session.first
     }
     .ok_or_else(|| anyhow!("missing second user in session: {:?}", &session_id))?;
-    let response = SignalMessage::SdpOffer(session_id, offer);
-    let response = serde_json::to_string(&response)?;
+    let response = SignalMessage::SdpOffer(session_id, config.sdp_filter.filter_sdp(&offer));
+    let response = serialize_message(&response)?;
     let connections_reader = connections.read().await;
     let recipient_tx = connections_reader
         .get(&recipient_id)
@@ -204,26 +950,1106 @@ async fn sdp_offer(
     Ok(())
 }
 
-async fn user_disconnected(user_id: UserId, connections: &Connections, sessions: &Sessions) {
-    let mut session_to_delete = None;
-    for (session_id, session) in sessions.write().await.iter_mut() {
-        if session.first == Some(user_id) {
-            session.first = None;
-            if session.first.is_none() && session.second.is_none() {
-                session_to_delete = Some(session_id.clone());
+/// Returns ids of the sessions removed because the user was the last one in them.
+pub(crate) async fn user_disconnected(
+    user_id: UserId,
+    connections: &Connections,
+    sessions: &Sessions,
+) -> Vec<SessionId> {
+    let joined: Vec<_> = sessions
+        .read()
+        .await
+        .iter()
+        .filter(|(_, session)| session.first == Some(user_id) || session.second == Some(user_id))
+        .map(|(session_id, _)| session_id.clone())
+        .collect();
+    let mut closed_session_ids = Vec::new();
+    for session_id in joined {
+        if let Some(None) = leave_session(user_id, &session_id, sessions).await {
+            closed_session_ids.push(session_id);
+        }
+    }
+    connections.write().await.remove(&user_id);
+    closed_session_ids
+}
+
+/// Removes the user from the session, `None` if it isn't in it.
+/// Otherwise returns the other user in the session, `None` if the session was removed
+/// because the user was the last one in it.
+async fn leave_session(
+    user_id: UserId,
+    session_id: &SessionId,
+    sessions: &Sessions,
+) -> Option<Option<UserId>> {
+    let mut sessions_writer = sessions.write().await;
+    let session = sessions_writer.get_mut(session_id)?;
+    let peer_id = if session.first == Some(user_id) {
+        session.first = None;
+        session.second
+    } else if session.second == Some(user_id) {
+        session.second = None;
+        session.first
+    } else {
+        return None;
+    };
+    session.record(SessionEventKind::Left, Some(user_id), None);
+    session.negotiation_permit = None;
+    if peer_id.is_none() {
+        // removing the session tears down its log
+        let session = sessions_writer.remove(session_id);
+        drop(sessions_writer);
+        if let Some(log) = session.and_then(|session| session.log) {
+            log.export();
+        }
+    }
+    Some(peer_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::negotiation_limit::NegotiationLimit;
+    use crate::offerer::{FirstJoiner, FirstSlot, LastJoiner, OffererStrategy};
+    use crate::session_allowlist::SessionAllowlist;
+    use crate::session_log::{SessionLogConfig, SessionLogSink};
+    use crate::test_util::connect;
+
+    fn session_id() -> SessionId {
+        SessionId::new("dummy-session-id".to_string())
+    }
+
+    async fn insert_session(sessions: &Sessions, first: Option<UserId>, second: Option<UserId>) {
+        sessions
+            .write()
+            .await
+            .insert(session_id(), Session::new(first, second));
+    }
+
+    #[tokio::test]
+    async fn test_first_user_leaving_keeps_session_with_second() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        user_disconnected(first, &connections, &sessions).await;
+
+        let sessions = sessions.read().await;
+        let session = sessions.get(&session_id()).expect("session was removed");
+        assert_eq!(session.first, None);
+        assert_eq!(session.second, Some(second));
+        assert!(!connections.read().await.contains_key(&first));
+        assert!(connections.read().await.contains_key(&second));
+        assert!(second_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_second_user_leaving_keeps_session_with_first() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let _second_rx = connect(&connections, second).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        user_disconnected(second, &connections, &sessions).await;
+
+        let sessions = sessions.read().await;
+        let session = sessions.get(&session_id()).expect("session was removed");
+        assert_eq!(session.first, Some(first));
+        assert_eq!(session.second, None);
+        assert!(connections.read().await.contains_key(&first));
+        assert!(!connections.read().await.contains_key(&second));
+        assert!(first_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_both_users_leaving_removes_session() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_rx = connect(&connections, first).await;
+        let _second_rx = connect(&connections, second).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        user_disconnected(first, &connections, &sessions).await;
+        assert!(sessions.read().await.contains_key(&session_id()));
+        user_disconnected(second, &connections, &sessions).await;
+
+        assert!(sessions.read().await.is_empty());
+        assert!(connections.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_only_user_leaving_removes_session() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let first = UserId::new(1);
+        let _first_rx = connect(&connections, first).await;
+        insert_session(&sessions, Some(first), None).await;
+
+        user_disconnected(first, &connections, &sessions).await;
+
+        assert!(sessions.read().await.is_empty());
+        assert!(connections.read().await.is_empty());
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordedLogs(std::sync::Mutex<Vec<SessionLog>>);
+
+    impl SessionLogSink for RecordedLogs {
+        fn export(&self, log: &SessionLog) {
+            self.0.lock().unwrap().push(log.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_log_is_exported_when_last_user_leaves() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_rx = connect(&connections, first).await;
+        let _second_rx = connect(&connections, second).await;
+        let sink = Arc::new(RecordedLogs::default());
+        let config = ServerConfig {
+            session_log: Some(SessionLogConfig {
+                sink: sink.clone(),
+                max_events: 16,
+                include_user_ids: true,
+                include_payloads: false,
+            }),
+            ..ServerConfig::default()
+        };
+
+        for user_id in [first, second] {
+            session_join(
+                &sessions,
+                &connections,
+                &config,
+                user_id,
+                session_id(),
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        for user_id in [first, second] {
+            user_disconnected(user_id, &connections, &sessions).await;
+        }
+        // export runs on a blocking thread
+        while sink.0.lock().unwrap().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+
+        let logs = sink.0.lock().unwrap();
+        let events: Vec<_> = logs[0]
+            .events
+            .iter()
+            .map(|event| (event.kind, event.user_id))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (SessionEventKind::Joined, Some(first)),
+                (SessionEventKind::Joined, Some(second)),
+                (SessionEventKind::SessionReady, None),
+                (SessionEventKind::Left, Some(first)),
+                (SessionEventKind::Left, Some(second)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_user_outside_of_session_leaving_keeps_sessions_intact() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second, outsider) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let _outsider_rx = connect(&connections, outsider).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        user_disconnected(outsider, &connections, &sessions).await;
+
+        let sessions = sessions.read().await;
+        let session = sessions.get(&session_id()).expect("session was removed");
+        assert_eq!(session.first, Some(first));
+        assert_eq!(session.second, Some(second));
+        assert!(connections.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_session_leave_frees_slot_but_keeps_connection() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        let message = serde_json::to_string(&SignalMessage::SessionLeave(session_id())).unwrap();
+        user_message(
+            first,
+            Message::Text(message),
+            &connections,
+            &sessions,
+            &WaitingUsers::default(),
+            &ServerConfig::default(),
+            &mut Connection::new(ConnectionOptions::default(), &ServerConfig::default()),
+        )
+        .await
+        .unwrap();
+
+        let sessions = sessions.read().await;
+        let session = sessions.get(&session_id()).expect("session was removed");
+        assert_eq!(session.first, None);
+        assert_eq!(session.second, Some(second));
+        assert!(connections.read().await.contains_key(&first));
+        match second_rx.try_recv() {
+            Ok(Message::Text(message)) => assert!(matches!(
+                serde_json::from_str(&message).unwrap(),
+                SignalMessage::PeerLeft(_)
+            )),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_leaving_other_session_is_ignored() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        let other_session_id = SessionId::new("other-session-id".to_string());
+        let message =
+            serde_json::to_string(&SignalMessage::SessionLeave(other_session_id)).unwrap();
+        user_message(
+            first,
+            Message::Text(message),
+            &connections,
+            &sessions,
+            &WaitingUsers::default(),
+            &ServerConfig::default(),
+            &mut Connection::new(ConnectionOptions::default(), &ServerConfig::default()),
+        )
+        .await
+        .unwrap();
+
+        let sessions = sessions.read().await;
+        let session = sessions.get(&session_id()).expect("session was removed");
+        assert_eq!(session.first, Some(first));
+        assert!(second_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_leaving_one_of_two_sessions_keeps_the_other() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (user_id, first_peer, second_peer) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let _rx = connect(&connections, user_id).await;
+        let mut first_peer_rx = connect(&connections, first_peer).await;
+        let mut second_peer_rx = connect(&connections, second_peer).await;
+        let other_session_id = SessionId::new("other-session-id".to_string());
+        insert_session(&sessions, Some(user_id), Some(first_peer)).await;
+        sessions.write().await.insert(
+            other_session_id.clone(),
+            Session::new(Some(user_id), Some(second_peer)),
+        );
+
+        let message =
+            serde_json::to_string(&SignalMessage::SessionLeave(other_session_id.clone())).unwrap();
+        user_message(
+            user_id,
+            Message::Text(message),
+            &connections,
+            &sessions,
+            &WaitingUsers::default(),
+            &ServerConfig::default(),
+            &mut Connection::new(ConnectionOptions::default(), &ServerConfig::default()),
+        )
+        .await
+        .unwrap();
+
+        let sessions = sessions.read().await;
+        let kept = &sessions[&session_id()];
+        assert_eq!((kept.first, kept.second), (Some(user_id), Some(first_peer)));
+        let left = &sessions[&other_session_id];
+        assert_eq!((left.first, left.second), (None, Some(second_peer)));
+        assert!(first_peer_rx.try_recv().is_err());
+        match second_peer_rx.try_recv() {
+            Ok(Message::Text(message)) => assert!(matches!(
+                serde_json::from_str(&message).unwrap(),
+                SignalMessage::PeerLeft(left_session_id) if left_session_id == other_session_id
+            )),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_offers_over_renegotiation_limit_are_rejected() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let config = ServerConfig {
+            max_renegotiations: 1,
+            ..ServerConfig::default()
+        };
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        for _ in 0..3 {
+            sdp_offer(
+                &sessions,
+                &connections,
+                &config,
+                first,
+                session_id(),
+                "offer".to_string(),
+            )
+            .await
+            .unwrap();
+        }
+
+        for _ in 0..2 {
+            assert!(matches!(
+                second_rx.try_recv(),
+                Ok(Message::Text(message)) if message.contains("SdpOffer")
+            ));
+        }
+        assert!(second_rx.try_recv().is_err());
+        assert!(matches!(
+            first_rx.try_recv(),
+            Ok(Message::Text(message)) if message.contains("too many renegotiations")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_user_rejoining_free_first_slot_gets_session_ready() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (rejoining, second) = (UserId::new(3), UserId::new(2));
+        let mut rejoining_rx = connect(&connections, rejoining).await;
+        let mut second_rx = connect(&connections, second).await;
+        insert_session(&sessions, None, Some(second)).await;
+
+        session_join(
+            &sessions,
+            &connections,
+            &ServerConfig::default(),
+            rejoining,
+            session_id(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let session = sessions.read().await;
+        let session = session.get(&session_id()).unwrap();
+        assert_eq!(session.first, Some(rejoining));
+        assert_eq!(session.second, Some(second));
+        assert!(matches!(
+            rejoining_rx.try_recv(),
+            Ok(Message::Text(message)) if message.contains("SessionReady")
+        ));
+        assert!(matches!(
+            second_rx.try_recv(),
+            Ok(Message::Text(message)) if message.contains("SessionReady")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_offerer_strategy_picks_session_ready_roles() {
+        let (rejoining, second) = (UserId::new(3), UserId::new(2));
+        for (strategy, rejoining_offers) in [
+            (Arc::new(FirstSlot) as Arc<dyn OffererStrategy>, true),
+            (Arc::new(FirstJoiner), false),
+            (Arc::new(LastJoiner), true),
+        ] {
+            let connections = Connections::default();
+            let sessions = Sessions::default();
+            let mut rejoining_rx = connect(&connections, rejoining).await;
+            let mut second_rx = connect(&connections, second).await;
+            insert_session(&sessions, None, Some(second)).await;
+            let config = ServerConfig {
+                offerer_strategy: strategy.clone(),
+                ..ServerConfig::default()
+            };
+
+            session_join(
+                &sessions,
+                &connections,
+                &config,
+                rejoining,
+                session_id(),
+                None,
+            )
+            .await
+            .unwrap();
+
+            for (rx, offers) in [
+                (&mut rejoining_rx, rejoining_offers),
+                (&mut second_rx, !rejoining_offers),
+            ] {
+                let expected =
+                    serde_json::to_string(&SignalMessage::SessionReady(session_id(), offers))
+                        .unwrap();
+                assert!(
+                    matches!(rx.try_recv(), Ok(Message::Text(message)) if message == expected),
+                    "{:?}",
+                    strategy
+                );
             }
-            break;
-        } else if session.second == Some(user_id) {
-            session.second = None;
-            if session.first.is_none() && session.second.is_none() {
-                session_to_delete = Some(session_id.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_joining_full_session_fails() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second, third) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let _third_rx = connect(&connections, third).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        assert!(session_join(
+            &sessions,
+            &connections,
+            &ServerConfig::default(),
+            third,
+            session_id(),
+            None
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_users_from_same_address_get_same_network_hint() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        let config = ServerConfig {
+            same_network_hints: true,
+            ..ServerConfig::default()
+        };
+        let remote_ip = Some(IpAddr::from([198, 51, 100, 7]));
+
+        for user_id in [first, second] {
+            session_join(
+                &sessions,
+                &connections,
+                &config,
+                user_id,
+                session_id(),
+                remote_ip,
+            )
+            .await
+            .unwrap();
+        }
+
+        for rx in [&mut first_rx, &mut second_rx] {
+            assert!(matches!(
+                rx.try_recv(),
+                Ok(Message::Text(message)) if message.contains("SessionReady")
+            ));
+            assert!(matches!(
+                rx.try_recv(),
+                Ok(Message::Text(message)) if message.contains("SameNetworkHint")
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_joining_session_outside_allowlist_is_rejected() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let user_id = UserId::new(1);
+        let mut rx = connect(&connections, user_id).await;
+        let config = ServerConfig {
+            session_allowlist: Some(SessionAllowlist {
+                ids: vec!["main-room".to_string()],
+                prefixes: Vec::new(),
+            }),
+            ..ServerConfig::default()
+        };
+
+        session_join(
+            &sessions,
+            &connections,
+            &config,
+            user_id,
+            session_id(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(Message::Text(message)) if message.contains("session not allowed")
+        ));
+        assert!(sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_rejects_new_sessions_but_not_existing_ones() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        let config = ServerConfig::default();
+        config.maintenance.set_enabled(true);
+        let new_session_id = SessionId::new("new-session-id".to_string());
+
+        session_join(
+            &sessions,
+            &connections,
+            &config,
+            second,
+            new_session_id,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            second_rx.try_recv(),
+            Ok(Message::Text(message)) if message.contains(MAINTENANCE_ERROR)
+        ));
+        assert!(sessions.read().await.is_empty());
+
+        insert_session(&sessions, Some(first), None).await;
+        session_join(&sessions, &connections, &config, second, session_id(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            sessions.read().await.get(&session_id()).unwrap().second,
+            Some(second)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_leaving_one_session_keeps_other_sessions_intact() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, other) = (UserId::new(1), UserId::new(2));
+        let other_session_id = SessionId::new("other-session-id".to_string());
+        let _first_rx = connect(&connections, first).await;
+        let _other_rx = connect(&connections, other).await;
+        insert_session(&sessions, Some(first), None).await;
+        sessions
+            .write()
+            .await
+            .insert(other_session_id.clone(), Session::new(Some(other), None));
+
+        user_disconnected(first, &connections, &sessions).await;
+
+        let sessions = sessions.read().await;
+        assert!(!sessions.contains_key(&session_id()));
+        assert_eq!(
+            sessions.get(&other_session_id).map(|session| session.first),
+            Some(Some(other))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_early_ice_candidate_is_passed_on_when_peer_rejoins() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, rejoining) = (UserId::new(1), UserId::new(2));
+        let _first_rx = connect(&connections, first).await;
+        let mut rejoining_rx = connect(&connections, rejoining).await;
+        insert_session(&sessions, Some(first), None).await;
+        let config = ServerConfig::default();
+
+        ice_candidate(
+            &sessions,
+            &connections,
+            &config,
+            first,
+            session_id(),
+            "candidate".to_string(),
+        )
+        .await
+        .unwrap();
+        session_join(
+            &sessions,
+            &connections,
+            &config,
+            rejoining,
+            session_id(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            next_response(&mut rejoining_rx).await,
+            SignalMessage::SessionReady(..)
+        ));
+        assert!(matches!(
+            next_response(&mut rejoining_rx).await,
+            SignalMessage::IceCandidate(_, candidate) if candidate == "candidate"
+        ));
+        let sessions = sessions.read().await;
+        assert!(sessions
+            .get(&session_id())
+            .unwrap()
+            .held_candidates
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_early_ice_candidate_is_rejected_when_configured() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let user_id = UserId::new(1);
+        let mut rx = connect(&connections, user_id).await;
+        insert_session(&sessions, Some(user_id), None).await;
+        let config = ServerConfig {
+            early_ice_candidates: EarlyIceCandidates::Reject,
+            ..ServerConfig::default()
+        };
+
+        ice_candidate(
+            &sessions,
+            &connections,
+            &config,
+            user_id,
+            session_id(),
+            "candidate".to_string(),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            next_response(&mut rx).await,
+            SignalMessage::Error(_, error) if error == "session isn't ready"
+        ));
+        let sessions = sessions.read().await;
+        assert!(sessions
+            .get(&session_id())
+            .unwrap()
+            .held_candidates
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_peer_metadata_is_passed_to_the_other_user() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        peer_metadata(
+            &sessions,
+            &connections,
+            first,
+            session_id(),
+            "alice".to_string(),
+        )
+        .await
+        .unwrap();
+
+        match second_rx.try_recv() {
+            Ok(Message::Text(message)) => assert!(matches!(
+                serde_json::from_str(&message).unwrap(),
+                SignalMessage::PeerMetadata(_, metadata) if metadata == "alice"
+            )),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ice_restart_request_is_passed_to_the_host() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let _second_rx = connect(&connections, second).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        ice_restart(&sessions, &connections, second, session_id())
+            .await
+            .unwrap();
+
+        match first_rx.try_recv() {
+            Ok(Message::Text(message)) => assert!(matches!(
+                serde_json::from_str(&message).unwrap(),
+                SignalMessage::IceRestart(restart_session_id) if restart_session_id == session_id()
+            )),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_peer_metadata_is_rejected() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        insert_session(&sessions, Some(first), Some(second)).await;
+
+        let metadata = "x".repeat(MAX_PEER_METADATA_LENGTH + 1);
+        peer_metadata(&sessions, &connections, first, session_id(), metadata)
+            .await
+            .unwrap();
+
+        match first_rx.try_recv() {
+            Ok(Message::Text(message)) => assert!(matches!(
+                serde_json::from_str(&message).unwrap(),
+                SignalMessage::Error(..)
+            )),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        assert!(second_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_user_sending_only_malformed_messages_is_dropped() {
+        let config = ServerConfig {
+            max_consecutive_malformed_messages: 3,
+            ..ServerConfig::default()
+        };
+        let garbage = (0..3).map(|_| Ok::<_, String>(Message::Text("{".to_string())));
+        let incoming = futures_util::stream::iter(garbage).chain(futures_util::stream::pending());
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+        let outgoing = Box::pin(futures_util::sink::unfold(
+            outgoing_tx,
+            |outgoing_tx, message: Message| async move {
+                outgoing_tx.send(message).map_err(|err| err.to_string())?;
+                Ok::<_, String>(outgoing_tx)
+            },
+        ));
+
+        tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            serve_user(
+                outgoing,
+                Box::pin(incoming),
+                Connections::default(),
+                Sessions::default(),
+                WaitingUsers::default(),
+                Arc::new(config),
+                ConnectionOptions {
+                    region: None,
+                    heartbeat: false,
+                    remote_ip: None,
+                },
+            ),
+        )
+        .await
+        .expect("user wasn't dropped");
+
+        match outgoing_rx.recv().await {
+            Some(Message::Text(message)) => assert!(matches!(
+                serde_json::from_str(&message).unwrap(),
+                SignalMessage::Error(_, error) if error == "too many malformed messages"
+            )),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_user_signaling_before_joining_is_answered_and_dropped_over_limit() {
+        let config = ServerConfig {
+            max_messages_outside_session: Some(2),
+            ..ServerConfig::default()
+        };
+        let candidate = SignalMessage::IceCandidate(session_id(), "candidate".to_string());
+        let candidate = serde_json::to_string(&candidate).unwrap();
+        let early = (0..2).map(|_| Ok::<_, String>(Message::Text(candidate.clone())));
+        let incoming = futures_util::stream::iter(early).chain(futures_util::stream::pending());
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+        let outgoing = Box::pin(futures_util::sink::unfold(
+            outgoing_tx,
+            |outgoing_tx, message: Message| async move {
+                outgoing_tx.send(message).map_err(|err| err.to_string())?;
+                Ok::<_, String>(outgoing_tx)
+            },
+        ));
+
+        tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            serve_user(
+                outgoing,
+                Box::pin(incoming),
+                Connections::default(),
+                Sessions::default(),
+                WaitingUsers::default(),
+                Arc::new(config),
+                ConnectionOptions {
+                    region: None,
+                    heartbeat: false,
+                    remote_ip: None,
+                },
+            ),
+        )
+        .await
+        .expect("user wasn't dropped");
+
+        let mut errors = Vec::new();
+        while let Some(Message::Text(message)) = outgoing_rx.recv().await {
+            match serde_json::from_str(&message).unwrap() {
+                SignalMessage::Error(_, error) => errors.push(error),
+                other => panic!("unexpected message: {:?}", other),
             }
-            break;
         }
+        assert_eq!(
+            errors,
+            [
+                NOT_IN_SESSION_ERROR,
+                NOT_IN_SESSION_ERROR,
+                "too many messages for sessions the user isn't in"
+            ]
+        );
+    }
+
+    type Incoming = mpsc::UnboundedSender<Result<Message, String>>;
+
+    fn spawn_user(
+        connections: &Connections,
+        sessions: &Sessions,
+    ) -> (Incoming, mpsc::UnboundedReceiver<Message>) {
+        let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let outgoing = Box::pin(futures_util::sink::unfold(
+            outgoing_tx,
+            |outgoing_tx, message: Message| async move {
+                outgoing_tx.send(message).map_err(|err| err.to_string())?;
+                Ok::<_, String>(outgoing_tx)
+            },
+        ));
+        tokio::task::spawn(serve_user(
+            outgoing,
+            UnboundedReceiverStream::new(incoming_rx),
+            connections.clone(),
+            sessions.clone(),
+            WaitingUsers::default(),
+            Arc::new(ServerConfig::default()),
+            ConnectionOptions {
+                region: None,
+                heartbeat: false,
+                remote_ip: None,
+            },
+        ));
+        (incoming_tx, outgoing_rx)
     }
-    // remove session if it's empty
-    if let Some(session_id) = session_to_delete {
-        sessions.write().await.remove(&session_id);
+
+    fn send_request(incoming: &Incoming, request: &SignalMessage) {
+        let request = serde_json::to_string(request).unwrap();
+        incoming.send(Ok(Message::Text(request))).unwrap();
+    }
+
+    async fn next_response(outgoing: &mut mpsc::UnboundedReceiver<Message>) -> SignalMessage {
+        match outgoing.recv().await {
+            Some(Message::Text(message)) => serde_json::from_str(&message).unwrap(),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_flood_of_ice_candidates_is_relayed_in_order() {
+        const CANDIDATES: usize = 1000;
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first_in, mut first_out) = spawn_user(&connections, &sessions);
+        let (second_in, mut second_out) = spawn_user(&connections, &sessions);
+        send_request(&first_in, &SignalMessage::SessionJoin(session_id()));
+        // make sure first user joins first
+        while sessions.read().await.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        send_request(&second_in, &SignalMessage::SessionJoin(session_id()));
+        for outgoing in [&mut first_out, &mut second_out] {
+            assert!(matches!(
+                next_response(outgoing).await,
+                SignalMessage::SessionReady(..)
+            ));
+        }
+
+        for index in 0..CANDIDATES {
+            let candidate = format!(
+                "candidate:{} 1 udp 2113937151 192.168.1.2 52345 typ host",
+                index
+            );
+            send_request(
+                &first_in,
+                &SignalMessage::IceCandidate(session_id(), candidate),
+            );
+        }
+
+        for index in 0..CANDIDATES {
+            match next_response(&mut second_out).await {
+                SignalMessage::IceCandidate(_, candidate) => assert!(
+                    candidate.starts_with(&format!("candidate:{} ", index)),
+                    "candidate {} arrived out of order: {}",
+                    index,
+                    candidate
+                ),
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+    }
+
+    async fn join_both_with_negotiation_timeout(
+        sessions: &Sessions,
+        connections: &Connections,
+        first: UserId,
+        second: UserId,
+    ) {
+        let config = ServerConfig {
+            negotiation_timeout: Some(std::time::Duration::from_millis(10)),
+            ..ServerConfig::default()
+        };
+        for user_id in [first, second] {
+            session_join(sessions, connections, &config, user_id, session_id(), None)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_negotiation_is_reported_to_both_users() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+
+        join_both_with_negotiation_timeout(&sessions, &connections, first, second).await;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        for rx in [&mut first_rx, &mut second_rx] {
+            assert!(matches!(
+                rx.try_recv(),
+                Ok(Message::Text(message)) if message.contains("SessionReady")
+            ));
+            assert!(matches!(
+                rx.try_recv(),
+                Ok(Message::Text(message)) if message.contains("NegotiationTimeout")
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_negotiation_with_answer_is_not_reported() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let _second_rx = connect(&connections, second).await;
+
+        join_both_with_negotiation_timeout(&sessions, &connections, first, second).await;
+        let answer = SignalMessage::SdpAnswer(session_id(), "answer".to_string());
+        user_message(
+            second,
+            Message::Text(serde_json::to_string(&answer).unwrap()),
+            &connections,
+            &sessions,
+            &WaitingUsers::default(),
+            &ServerConfig::default(),
+            &mut Connection::new(ConnectionOptions::default(), &ServerConfig::default()),
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        assert!(matches!(
+            first_rx.try_recv(),
+            Ok(Message::Text(message)) if message.contains("SessionReady")
+        ));
+        assert!(matches!(
+            first_rx.try_recv(),
+            Ok(Message::Text(message)) if message.contains("SdpAnswer")
+        ));
+        assert!(first_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stalled_negotiation_frees_its_slot() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let users = [1, 2, 3, 4].map(UserId::new);
+        let mut receivers = Vec::new();
+        for user_id in users {
+            receivers.push(connect(&connections, user_id).await);
+        }
+        let config = ServerConfig {
+            negotiation_limit: NegotiationLimit::with_slot_timeout(
+                1,
+                std::time::Duration::from_millis(10),
+            ),
+            ..ServerConfig::default()
+        };
+        let other_session_id = SessionId::new("other-session-id".to_string());
+
+        for (user_id, session_id) in users.into_iter().zip([
+            session_id(),
+            session_id(),
+            other_session_id.clone(),
+            other_session_id,
+        ]) {
+            session_join(&sessions, &connections, &config, user_id, session_id, None)
+                .await
+                .unwrap();
+        }
+        assert!(receivers[2].try_recv().is_err());
+
+        // first session never answers, but the second one gets its slot anyway
+        for rx in &mut receivers[2..] {
+            assert!(matches!(
+                rx.recv().await,
+                Some(Message::Text(message)) if message.contains("SessionReady")
+            ));
+        }
+        assert_eq!(config.negotiation_limit.negotiating(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_full_session_waits_for_negotiation_slot() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_rx = connect(&connections, first).await;
+        let mut second_rx = connect(&connections, second).await;
+        let config = ServerConfig {
+            negotiation_limit: NegotiationLimit::new(1),
+            ..ServerConfig::default()
+        };
+        let other_negotiation = config.negotiation_limit.try_acquire().unwrap();
+
+        for user_id in [first, second] {
+            session_join(
+                &sessions,
+                &connections,
+                &config,
+                user_id,
+                session_id(),
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        tokio::task::yield_now().await;
+        assert!(first_rx.try_recv().is_err());
+        assert!(second_rx.try_recv().is_err());
+
+        drop(other_negotiation);
+        for rx in [&mut first_rx, &mut second_rx] {
+            assert!(matches!(
+                rx.recv().await,
+                Some(Message::Text(message)) if message.contains("SessionReady")
+            ));
+        }
+        assert_eq!(config.negotiation_limit.negotiating(), 1);
     }
-    connections.write().await.remove(&user_id);
 }
@@ -0,0 +1,137 @@
+/*!
+Region tags of connections, for deployments spread over multiple regions or zones.
+
+Each websocket connection is tagged with the value of the [`ServerConfig::region_header`] header,
+e.g. set by a geo-aware load balancer, or with [`ServerConfig::region`] if there is no such header
+or its value isn't a valid region. Connections over TCP and [`crate::memory`] only use the latter.
+
+Matchmaking prefers pairing users in the same region: a user waiting for a match is only paired
+with users in another region after waiting for [`ServerConfig::matchmaking_region_affinity`].
+Users joining sessions with explicit session ids are paired regardless, the region is only logged
+and counted. Number of connections in each region is shown on the status page.
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use axum::http::HeaderMap;
+
+use crate::config::ServerConfig;
+
+/// Longest region tag accepted, longer header values are ignored.
+pub const MAX_REGION_LENGTH: usize = 64;
+
+/// Number of open connections in each region, untagged connections aren't counted.
+pub type RegionCounts = Arc<Mutex<HashMap<String, usize>>>;
+
+/// Returns whether the region tag is short and made of ASCII letters, digits, `-`, `_` or `.`,
+/// so that it's safe to log and show on the status page.
+pub(crate) fn is_valid(region: &str) -> bool {
+    !region.is_empty()
+        && region.len() <= MAX_REGION_LENGTH
+        && region
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Returns the region of a websocket connection with given upgrade request headers.
+pub(crate) fn connection_region(config: &ServerConfig, headers: &HeaderMap) -> Option<String> {
+    config
+        .region_header
+        .as_ref()
+        .and_then(|region_header| headers.get(region_header.as_str()))
+        .and_then(|region| region.to_str().ok())
+        .filter(|region| is_valid(region))
+        .map(str::to_string)
+        .or_else(|| config.region.clone())
+}
+
+/// Counts the connection in its region until dropped.
+#[derive(Debug)]
+pub(crate) struct TrackedConnection {
+    counts: RegionCounts,
+    region: String,
+}
+
+pub(crate) fn track(counts: &RegionCounts, region: Option<&str>) -> Option<TrackedConnection> {
+    let region = region?.to_string();
+    *counts
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry(region.clone())
+        .or_default() += 1;
+    Some(TrackedConnection {
+        counts: counts.clone(),
+        region,
+    })
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        let mut counts = self
+            .counts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(count) = counts.get_mut(&self.region) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.region);
+            }
+        }
+    }
+}
+
+/// Returns the counts sorted by region.
+pub(crate) fn snapshot(counts: &RegionCounts) -> BTreeMap<String, usize> {
+    counts
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .iter()
+        .map(|(region, count)| (region.clone(), *count))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_header_region_is_preferred_over_configured_one_unless_invalid() {
+        let config = ServerConfig {
+            region: Some("eu-west".to_string()),
+            region_header: Some("x-region".to_string()),
+            ..ServerConfig::default()
+        };
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(
+            connection_region(&config, &headers).as_deref(),
+            Some("eu-west")
+        );
+        headers.insert("x-region", HeaderValue::from_static("us-east"));
+        assert_eq!(
+            connection_region(&config, &headers).as_deref(),
+            Some("us-east")
+        );
+        headers.insert("x-region", HeaderValue::from_static("<script>"));
+        assert_eq!(
+            connection_region(&config, &headers).as_deref(),
+            Some("eu-west")
+        );
+    }
+
+    #[test]
+    fn test_connections_are_counted_until_dropped() {
+        let counts = RegionCounts::default();
+
+        let first = track(&counts, Some("eu-west"));
+        let second = track(&counts, Some("eu-west"));
+        assert!(track(&counts, None).is_none());
+        assert_eq!(snapshot(&counts).get("eu-west"), Some(&2));
+
+        drop((first, second));
+        assert!(snapshot(&counts).is_empty());
+    }
+}
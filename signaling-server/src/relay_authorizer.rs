@@ -0,0 +1,51 @@
+/*!
+Hook deciding whether data sent with `RelayTo` is passed on to its recipients.
+
+Lets embedders enforce their own rules, e.g. business rules or limits per kind of message,
+without forking the server. It's consulted after the server's own checks,
+so it only sees messages from session members, addressed to other members,
+and before [`crate::config::ServerConfig::max_relay_bytes_per_second`] is applied,
+so denied messages don't count towards the limit.
+Signaling messages needed to establish connections are never passed to it.
+*/
+
+use std::fmt;
+
+use wasm_peers_protocol::{SessionId, UserId};
+
+/// Outcome of [`RelayAuthorizer::authorize`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayDecision {
+    /// Relay the message to all its recipients.
+    Allow,
+    /// Drop the message and send an error to the sender, with given reason if any.
+    Deny(Option<String>),
+}
+
+/// Custom logic consulted for every relayed message, see [`crate::relay_authorizer`].
+pub trait RelayAuthorizer: fmt::Debug + Send + Sync {
+    /// Decides whether `data` from `sender` may be relayed to `recipients` in the session.
+    fn authorize(
+        &self,
+        sender: UserId,
+        session_id: &SessionId,
+        recipients: &[UserId],
+        data: &[u8],
+    ) -> RelayDecision;
+}
+
+/// Default authorizer, allowing every message.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllowAll;
+
+impl RelayAuthorizer for AllowAll {
+    fn authorize(
+        &self,
+        _sender: UserId,
+        _session_id: &SessionId,
+        _recipients: &[UserId],
+        _data: &[u8],
+    ) -> RelayDecision {
+        RelayDecision::Allow
+    }
+}
@@ -0,0 +1,105 @@
+/*!
+Contract that data sent with `RelayTo` must satisfy, so the relay can't be used as a general data pipe.
+
+Checked before [`crate::relay_authorizer`], on every relayed message. Messages breaking the contract
+are dropped and the sender gets an `Error` starting with [`INVALID_RELAY_PAYLOAD`].
+Only the size is checked by default, against the protocol's [`MAX_RELAY_LENGTH`].
+Applications relaying `JSON` can also require each message to be a `JSON` object with a given field:
+
+```
+use wasm_peers_signaling_server_axum::config::ServerConfig;
+use wasm_peers_signaling_server_axum::relay_validation::RelayValidation;
+
+let config = ServerConfig {
+    relay_validation: RelayValidation {
+        max_length: 1024,
+        require_json: true,
+        required_field: Some("type".to_string()),
+    },
+    ..ServerConfig::default()
+};
+assert!(config.validate().is_ok());
+```
+*/
+
+use wasm_peers_protocol::one_to_many::MAX_RELAY_LENGTH;
+
+/// Errors about messages breaking the contract start with this.
+pub const INVALID_RELAY_PAYLOAD: &str = "invalid relay payload";
+
+/// Contract set by [`crate::config::ServerConfig::relay_validation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayValidation {
+    /// Longest message relayed, at most [`MAX_RELAY_LENGTH`].
+    pub max_length: usize,
+    /// Relay only messages that are valid `JSON`.
+    pub require_json: bool,
+    /// Relay only `JSON` objects with this top-level field, e.g. `type`. Requires `require_json`.
+    pub required_field: Option<String>,
+}
+
+impl Default for RelayValidation {
+    fn default() -> Self {
+        RelayValidation {
+            max_length: MAX_RELAY_LENGTH,
+            require_json: false,
+            required_field: None,
+        }
+    }
+}
+
+impl RelayValidation {
+    /// Returns the error to send to the sender if the data breaks the contract.
+    pub(crate) fn check(&self, data: &[u8]) -> Result<(), String> {
+        if data.len() > self.max_length {
+            return Err(format!(
+                "{}: {} bytes, maximum is {}",
+                INVALID_RELAY_PAYLOAD,
+                data.len(),
+                self.max_length
+            ));
+        }
+        if !self.require_json {
+            return Ok(());
+        }
+        let value: serde_json::Value = serde_json::from_slice(data)
+            .map_err(|_| format!("{}: not valid JSON", INVALID_RELAY_PAYLOAD))?;
+        if let Some(required_field) = &self.required_field {
+            if value.get(required_field).is_none() {
+                return Err(format!(
+                    "{}: missing field {:?}",
+                    INVALID_RELAY_PAYLOAD, required_field
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_contract_only_limits_size() {
+        let validation = RelayValidation::default();
+
+        assert!(validation.check(&[0xff; MAX_RELAY_LENGTH]).is_ok());
+        assert!(validation.check(&[0; MAX_RELAY_LENGTH + 1]).is_err());
+    }
+
+    #[test]
+    fn test_json_contract_requires_object_with_field() {
+        let validation = RelayValidation {
+            require_json: true,
+            required_field: Some("type".to_string()),
+            ..RelayValidation::default()
+        };
+
+        assert!(validation.check(br#"{"type":"move","x":1}"#).is_ok());
+        for data in [&b"not json"[..], br#"{"x":1}"#, b"[1,2]"] {
+            let error = validation.check(data).unwrap_err();
+            assert!(error.starts_with(INVALID_RELAY_PAYLOAD), "{}", error);
+        }
+    }
+}
@@ -1,20 +1,516 @@
-use axum::{extract::ws::WebSocketUpgrade, response::Response, routing::get, Extension, Router};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
-use crate::one_to_one::{user_connected, Connections, Sessions};
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{ConnectInfo, Path, Query};
+use axum::http::header::SEC_WEBSOCKET_PROTOCOL;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Html;
+use axum::routing::{get, post};
+use axum::{Extension, Router};
+use log::{error, warn};
+use tokio::net::TcpListener;
 
-async fn handler(
-    ws: WebSocketUpgrade,
-    Extension(connections): Extension<Connections>,
-    Extension(sessions): Extension<Sessions>,
-) -> Response {
-    ws.on_upgrade(move |socket| user_connected(socket, connections, sessions))
-}
+use crate::admin;
+use crate::broadcast::{self, LastBroadcast};
+use crate::config::{ServerConfig, Topology};
+use crate::maintenance;
+use crate::region::{self, RegionCounts};
+use crate::status::{render_status_page, ServerState};
+use crate::tcp;
+use crate::tenant;
+use crate::transcript;
+use crate::{many_to_many, one_to_many, one_to_one};
 
 pub fn create_router() -> Router {
-    let connections = Connections::default();
-    let sessions = Sessions::default();
-    Router::new()
-        .route("/one_to_one", get(handler))
-        .layer(Extension(connections))
-        .layer(Extension(sessions))
+    create_router_with_config(ServerConfig::default())
+}
+
+/// If [`ServerConfig::tcp_address`] is set, it starts accepting TCP connections right away
+/// and must be called within a tokio runtime.
+///
+/// # Panics
+///
+/// Panics if the config is invalid, see [`ServerConfig::validate`],
+/// or if binding to the TCP address fails.
+pub fn create_router_with_config(config: ServerConfig) -> Router {
+    create_router_with_state(config, ServerState::default())
+}
+
+/// Same as [`create_router_with_config`], but serves given state,
+/// so that a clone of it can manage the sessions, see [`crate::admin`].
+///
+/// # Panics
+///
+/// Panics if the config is invalid, see [`ServerConfig::validate`],
+/// or if binding to the TCP address fails.
+pub fn create_router_with_state(config: ServerConfig, state: ServerState) -> Router {
+    if let Err(err) = config.validate() {
+        panic!("invalid server config: {}", err);
+    }
+    let ServerState {
+        connections,
+        one_to_one_sessions,
+        waiting_users,
+        one_to_many_sessions,
+        many_to_many_sessions,
+        region_counts,
+        ..
+    } = state.clone();
+
+    let one_to_one_config = Arc::new(config.for_topology(Topology::OneToOne));
+    if let Some(tcp_address) = config.tcp_address {
+        let listener = std::net::TcpListener::bind(tcp_address)
+            .and_then(|listener| {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)
+            })
+            .unwrap_or_else(|err| panic!("failed to bind to {}: {}", tcp_address, err));
+        let tcp_server = tcp::serve(
+            listener,
+            connections.clone(),
+            one_to_one_sessions.clone(),
+            waiting_users.clone(),
+            one_to_one_config.clone(),
+            region_counts.clone(),
+        );
+        tokio::task::spawn(async move {
+            if let Err(err) = tcp_server.await {
+                error!("TCP server error: {}", err);
+            }
+        });
+    }
+    let one_to_one_namespaces = state.tenants.one_to_one.clone();
+    let one_to_one_region_counts = region_counts.clone();
+    let one_to_one_handler = move |ws: WebSocketUpgrade,
+                                   Query(query): Query<HashMap<String, String>>,
+                                   headers: HeaderMap,
+                                   connect_info: Option<ConnectInfo<SocketAddr>>,
+                                   Extension(connections)| async move {
+        check_protocol(&headers)?;
+        let ws = ws
+            .protocols([wasm_peers_protocol::websocket_protocol()])
+            .max_message_size(one_to_one_config.max_frame_size)
+            .max_frame_size(one_to_one_config.max_frame_size);
+        let region = region::connection_region(&one_to_one_config, &headers);
+        let remote_ip = connect_info.map(|ConnectInfo(address)| address.ip());
+        let tenant = tenant::tenant(&one_to_one_config, &query);
+        let ((sessions, waiting_users), tenant_guard) = tenant::namespace(
+            &one_to_one_namespaces,
+            tenant,
+            &(one_to_one_sessions, waiting_users),
+            one_to_one_config.max_tenants,
+        )
+        .map_err(too_many_tenants)?;
+        Ok::<_, (StatusCode, &str)>(ws.on_upgrade(move |socket| async move {
+            let _tenant_guard = tenant_guard;
+            tracked(
+                one_to_one_region_counts,
+                region.clone(),
+                one_to_one::user_connected(
+                    socket,
+                    connections,
+                    sessions,
+                    waiting_users,
+                    one_to_one_config,
+                    region,
+                    remote_ip,
+                ),
+            )
+            .await;
+        }))
+    };
+    let one_to_many_config = Arc::new(config.for_topology(Topology::OneToMany));
+    let one_to_many_namespaces = state.tenants.one_to_many.clone();
+    let one_to_many_region_counts = region_counts.clone();
+    let one_to_many_handler = move |ws: WebSocketUpgrade,
+                                    Query(query): Query<HashMap<String, String>>,
+                                    headers: HeaderMap,
+                                    Extension(connections)| async move {
+        check_protocol(&headers)?;
+        let ws = ws
+            .protocols([wasm_peers_protocol::websocket_protocol()])
+            .max_message_size(one_to_many_config.max_frame_size)
+            .max_frame_size(one_to_many_config.max_frame_size);
+        let region = region::connection_region(&one_to_many_config, &headers);
+        let tenant = tenant::tenant(&one_to_many_config, &query);
+        let (sessions, tenant_guard) = tenant::namespace(
+            &one_to_many_namespaces,
+            tenant,
+            &one_to_many_sessions,
+            one_to_many_config.max_tenants,
+        )
+        .map_err(too_many_tenants)?;
+        Ok::<_, (StatusCode, &str)>(ws.on_upgrade(move |socket| async move {
+            let _tenant_guard = tenant_guard;
+            tracked(
+                one_to_many_region_counts,
+                region,
+                one_to_many::user_connected(socket, connections, sessions, one_to_many_config),
+            )
+            .await;
+        }))
+    };
+    let many_to_many_config = Arc::new(config.for_topology(Topology::ManyToMany));
+    let many_to_many_namespaces = state.tenants.many_to_many.clone();
+    let many_to_many_handler = move |ws: WebSocketUpgrade,
+                                     Query(query): Query<HashMap<String, String>>,
+                                     headers: HeaderMap,
+                                     Extension(connections)| async move {
+        check_protocol(&headers)?;
+        let ws = ws
+            .protocols([wasm_peers_protocol::websocket_protocol()])
+            .max_message_size(many_to_many_config.max_frame_size)
+            .max_frame_size(many_to_many_config.max_frame_size);
+        let region = region::connection_region(&many_to_many_config, &headers);
+        let tenant = tenant::tenant(&many_to_many_config, &query);
+        let (sessions, tenant_guard) = tenant::namespace(
+            &many_to_many_namespaces,
+            tenant,
+            &many_to_many_sessions,
+            many_to_many_config.max_tenants,
+        )
+        .map_err(too_many_tenants)?;
+        Ok::<_, (StatusCode, &str)>(ws.on_upgrade(move |socket| async move {
+            let _tenant_guard = tenant_guard;
+            tracked(
+                region_counts,
+                region,
+                many_to_many::user_connected(socket, connections, sessions, many_to_many_config),
+            )
+            .await;
+        }))
+    };
+
+    let admin_state = state.clone();
+    let prefix = &config.websocket_path_prefix;
+    let mut router = Router::new()
+        // kept for compatibility with clients using the original route
+        .route(
+            &format!("{}/one_to_one", prefix),
+            get(one_to_one_handler.clone()),
+        )
+        .route(&format!("{}/one-to-one", prefix), get(one_to_one_handler))
+        .route(&format!("{}/one-to-many", prefix), get(one_to_many_handler))
+        .route(
+            &format!("{}/many-to-many", prefix),
+            get(many_to_many_handler),
+        );
+    if config.status_page {
+        let negotiation_limit = config.negotiation_limit.clone();
+        let status_handler =
+            move || async move { Html(render_status_page(&state.stats(&negotiation_limit).await)) };
+        router = router.route("/", get(status_handler));
+    }
+    if config.admin_token.is_some() {
+        let config = Arc::new(config);
+        let maintenance_config = config.clone();
+        let flag_config = config.clone();
+        let transcript_config = config.clone();
+        let session_config = config.clone();
+        let last_broadcast = LastBroadcast::default();
+        let broadcast_handler =
+            move |headers: HeaderMap, Extension(connections), notice: String| async move {
+                broadcast::broadcast(&connections, &config, &last_broadcast, &headers, notice).await
+            };
+        let maintenance_handler = move |headers: HeaderMap, body: String| async move {
+            maintenance::toggle(&maintenance_config, &headers, &body)
+        };
+        let flag_handler = move |headers: HeaderMap, Path(session_id): Path<String>| async move {
+            transcript::flag(&flag_config, &headers, session_id)
+        };
+        let transcript_handler = move |headers: HeaderMap, Path(session_id): Path<String>| async move {
+            transcript::fetch(&transcript_config, &headers, session_id)
+        };
+        let session_handler = move |headers: HeaderMap,
+                                    Query(query): Query<HashMap<String, String>>,
+                                    Path(session_id): Path<String>| async move {
+            admin::fetch_session(&session_config, &admin_state, &headers, &query, session_id).await
+        };
+        router = router
+            .route("/broadcast", post(broadcast_handler))
+            .route("/maintenance", post(maintenance_handler))
+            .route(
+                "/transcript/:session_id",
+                post(flag_handler).get(transcript_handler),
+            )
+            .route("/sessions/:session_id", get(session_handler));
+    }
+    router.layer(Extension(connections))
+}
+
+/// Checks the websocket subprotocols offered by the client, rejecting clients using
+/// an incompatible version of the protocol, see [`wasm_peers_protocol::websocket_protocol`].
+/// Clients offering no wasm-peers subprotocol aren't checked.
+fn check_protocol(headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+    let protocol = wasm_peers_protocol::websocket_protocol();
+    let offered: Vec<&str> = headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|offered| offered.starts_with(wasm_peers_protocol::WEBSOCKET_PROTOCOL_PREFIX))
+        .collect();
+    if !offered.is_empty() && !offered.contains(&protocol.as_str()) {
+        warn!(
+            "rejected client offering protocols {:?}, server uses {}",
+            offered, protocol
+        );
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "incompatible wasm-peers protocol version",
+        ));
+    }
+    Ok(())
+}
+
+fn too_many_tenants(_: tenant::TooManyTenants) -> (StatusCode, &'static str) {
+    warn!("rejected connection of a new tenant, too many tenants are connected");
+    (StatusCode::SERVICE_UNAVAILABLE, "too many tenants")
+}
+
+/// Serves the user, counting the connection in its region meanwhile, see [`crate::region`].
+async fn tracked(
+    region_counts: RegionCounts,
+    region: Option<String>,
+    user: impl Future<Output = ()>,
+) {
+    let _tracked = region::track(&region_counts, region.as_deref());
+    user.await;
+}
+
+#[cfg(test)]
+mod test {
+    use axum::body::Body;
+    use axum::http::{Method, Request, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn request(method: Method, uri: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn status(method: Method, uri: &str) -> StatusCode {
+        create_router()
+            .oneshot(request(method, uri))
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_topology_routes_require_websocket_upgrade() {
+        for uri in [
+            "/one_to_one",
+            "/one-to-one",
+            "/one-to-many",
+            "/many-to-many",
+        ] {
+            assert_eq!(status(Method::GET, uri).await, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn test_incompatible_protocol_version_is_rejected() {
+        let headers = |protocols: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(SEC_WEBSOCKET_PROTOCOL, protocols.parse().unwrap());
+            headers
+        };
+        let protocol = wasm_peers_protocol::websocket_protocol();
+
+        assert!(check_protocol(&HeaderMap::new()).is_ok());
+        assert!(check_protocol(&headers("chat")).is_ok());
+        assert!(check_protocol(&headers(&protocol)).is_ok());
+        assert!(check_protocol(&headers(&format!("wasm-peers.0.3, {}", protocol))).is_ok());
+        assert_eq!(
+            check_protocol(&headers("wasm-peers.0.3")).unwrap_err().0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_topology_routes_reject_other_methods() {
+        assert_eq!(
+            status(Method::POST, "/one-to-one").await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_route_is_not_found() {
+        assert_eq!(status(Method::GET, "/unknown").await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_routes_are_only_served_under_prefix() {
+        let router = create_router_with_config(ServerConfig {
+            websocket_path_prefix: "/signaling".to_string(),
+            ..ServerConfig::default()
+        });
+
+        for (uri, expected) in [
+            ("/signaling/one-to-one", StatusCode::BAD_REQUEST),
+            ("/signaling/many-to-many", StatusCode::BAD_REQUEST),
+            ("/one-to-one", StatusCode::NOT_FOUND),
+            ("/signaling", StatusCode::NOT_FOUND),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(request(Method::GET, uri))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_page_is_disabled_by_default() {
+        assert_eq!(status(Method::GET, "/").await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_is_disabled_without_admin_token() {
+        assert_eq!(
+            status(Method::POST, "/broadcast").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oversized_broadcast_is_rejected() {
+        let router = create_router_with_config(ServerConfig {
+            admin_token: Some("secret".to_string()),
+            ..ServerConfig::default()
+        });
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/broadcast")
+            .header("Authorization", "Bearer secret")
+            .body(Body::from(
+                "x".repeat(wasm_peers_protocol::MAX_SERVER_NOTICE_LENGTH + 1),
+            ))
+            .unwrap();
+
+        let response = router.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_status_page_shows_counts() {
+        let router = create_router_with_config(ServerConfig {
+            status_page: true,
+            ..ServerConfig::default()
+        });
+
+        let response = router.oneshot(request(Method::GET, "/")).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("<tr><td>connections</td><td>0</td></tr>"));
+    }
+
+    #[tokio::test]
+    async fn test_session_detail_requires_token_and_existing_session() {
+        let router = create_router_with_config(ServerConfig {
+            admin_token: Some("secret".to_string()),
+            ..ServerConfig::default()
+        });
+        let session_request = |token: &str| {
+            Request::builder()
+                .uri("/sessions/unknown-session-id")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(session_request("wrong"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = router.oneshot(session_request("secret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn admin_request(method: Method, uri: &str, token: &str, body: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_requires_token_and_valid_body() {
+        let config = ServerConfig {
+            admin_token: Some("secret".to_string()),
+            ..ServerConfig::default()
+        };
+        let maintenance = config.maintenance.clone();
+        let router = create_router_with_config(config);
+
+        for (token, body, expected) in [
+            ("wrong", "on", StatusCode::UNAUTHORIZED),
+            ("secret", "maybe", StatusCode::BAD_REQUEST),
+            ("secret", "on", StatusCode::OK),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(admin_request(Method::POST, "/maintenance", token, body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", body);
+        }
+        assert!(maintenance.is_enabled());
+    }
+
+    #[tokio::test]
+    async fn test_transcript_is_served_once_flagged_with_token() {
+        let router = create_router_with_config(ServerConfig {
+            admin_token: Some("secret".to_string()),
+            ..ServerConfig::default()
+        });
+        let uri = "/transcript/dummy-session-id";
+        let send = |method: Method, token: &str| {
+            router
+                .clone()
+                .oneshot(admin_request(method, uri, token, ""))
+        };
+
+        assert_eq!(
+            send(Method::POST, "wrong").await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(Method::GET, "secret").await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            send(Method::POST, "secret").await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(Method::GET, "wrong").await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let response = send(Method::GET, "secret").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let transcript: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(transcript["session_id"], "dummy-session-id");
+        assert_eq!(transcript["messages"], serde_json::json!([]));
+    }
 }
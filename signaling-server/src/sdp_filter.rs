@@ -0,0 +1,118 @@
+/*!
+Stripping parts of `SDP` and `ICE` candidates relayed by the server.
+
+Lets operators enforce a privacy policy regardless of client behavior,
+e.g. stripping host candidates so that peers never learn each other's internal addresses.
+Aggressive stripping can easily break connectivity, e.g. stripping host and server reflexive
+candidates leaves peers only with TURN, so it's intended for deliberate relay-only setups.
+*/
+
+/// Rules applied to `SDP` offers, answers and `ICE` candidates relayed by the server.
+/// Default filter lets everything through unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct SdpFilter {
+    /// Candidate types, e.g. `host` or `srflx`, whose `a=candidate` lines are removed
+    /// from `SDP` and whose trickled `ICE` candidates are dropped.
+    pub strip_candidate_types: Vec<String>,
+    /// Prefixes of `SDP` lines to remove, e.g. `a=extmap`.
+    pub strip_line_prefixes: Vec<String>,
+}
+
+impl SdpFilter {
+    /// Returns `true` if the filter doesn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.strip_candidate_types.is_empty() && self.strip_line_prefixes.is_empty()
+    }
+
+    /// Removes lines matching the rules, keeping the rest of `SDP` intact.
+    pub fn filter_sdp(&self, sdp: &str) -> String {
+        if self.is_empty() {
+            return sdp.to_string();
+        }
+        sdp.split_inclusive('\n')
+            .filter(|line| self.allows_line(line.trim_end_matches(['\r', '\n'])))
+            .collect()
+    }
+
+    /// Returns `false` if the trickled `ICE` candidate is of one of the stripped types.
+    pub fn allows_candidate(&self, candidate: &str) -> bool {
+        match candidate_type(candidate) {
+            Some(candidate_type) => !self
+                .strip_candidate_types
+                .iter()
+                .any(|stripped| stripped == candidate_type),
+            None => true,
+        }
+    }
+
+    fn allows_line(&self, line: &str) -> bool {
+        if self
+            .strip_line_prefixes
+            .iter()
+            .any(|prefix| line.starts_with(prefix.as_str()))
+        {
+            return false;
+        }
+        !line.starts_with("a=candidate:") || self.allows_candidate(line)
+    }
+}
+
+/// Type follows the `typ` keyword, e.g. `candidate:1 1 udp 2113937151 192.168.1.2 52345 typ host`.
+/// Candidates sent by the library are wrapped in JSON, so anything following the type is ignored.
+fn candidate_type(candidate: &str) -> Option<&str> {
+    let mut tokens = candidate.split_whitespace();
+    tokens.find(|token| *token == "typ")?;
+    let candidate_type = tokens
+        .next()?
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .next()?;
+    Some(candidate_type)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn strip_host() -> SdpFilter {
+        SdpFilter {
+            strip_candidate_types: vec!["host".to_string()],
+            ..SdpFilter::default()
+        }
+    }
+
+    #[test]
+    fn test_host_candidate_lines_are_stripped() {
+        let sdp = "v=0\r\n\
+            a=candidate:1 1 udp 2113937151 192.168.1.2 52345 typ host generation 0\r\n\
+            a=candidate:2 1 udp 1677729535 203.0.113.7 52345 typ srflx raddr 192.168.1.2 rport 52345\r\n\
+            a=mid:0\r\n";
+
+        assert_eq!(
+            strip_host().filter_sdp(sdp),
+            "v=0\r\n\
+            a=candidate:2 1 udp 1677729535 203.0.113.7 52345 typ srflx raddr 192.168.1.2 rport 52345\r\n\
+            a=mid:0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_trickled_candidate_wrapped_in_json_is_recognized() {
+        let candidate = r#"{"candidate":"candidate:1 1 udp 2113937151 192.168.1.2 52345 typ host","sdp_mid":"0"}"#;
+
+        assert!(!strip_host().allows_candidate(candidate));
+        assert!(SdpFilter::default().allows_candidate(candidate));
+    }
+
+    #[test]
+    fn test_lines_with_stripped_prefix_are_removed() {
+        let filter = SdpFilter {
+            strip_line_prefixes: vec!["a=extmap".to_string()],
+            ..SdpFilter::default()
+        };
+
+        assert_eq!(
+            filter.filter_sdp("v=0\r\na=extmap-allow-mixed\r\nt=0 0\r\n"),
+            "v=0\r\nt=0 0\r\n"
+        );
+    }
+}
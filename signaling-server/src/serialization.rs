@@ -0,0 +1,80 @@
+use std::fmt;
+
+use axum::extract::ws::Message;
+use serde::Serialize;
+use wasm_peers_protocol::SessionId;
+
+use crate::one_to_one::INTERNAL_ERROR;
+
+/// Error for an outgoing message that couldn't be serialized. Well-formed messages always serialize,
+/// so it's a fault of the server rather than of the user, who gets [`INTERNAL_ERROR`] in response.
+#[derive(Debug)]
+pub(crate) struct SerializationFailed(String);
+
+impl fmt::Display for SerializationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to serialize outgoing message: {}", self.0)
+    }
+}
+
+impl std::error::Error for SerializationFailed {}
+
+/// Writes a signaling message of any of the topologies, keeping the message in the error for context.
+pub(crate) fn serialize_message<T: Serialize + fmt::Debug>(
+    message: &T,
+) -> Result<String, SerializationFailed> {
+    serde_json::to_string(message)
+        .map_err(|err| SerializationFailed(format!("{}, message: {:?}", err, message)))
+}
+
+/// Response telling the user that handling its message failed with [`SerializationFailed`], if it did,
+/// built with `error_message`, the `Error` variant of the topology's signaling message.
+pub(crate) fn internal_error_response<T: Serialize + fmt::Debug>(
+    result: &anyhow::Result<()>,
+    error_message: impl FnOnce(SessionId, String) -> T,
+) -> Option<Message> {
+    match result {
+        Err(err) if err.is::<SerializationFailed>() => {
+            let response = error_message(SessionId::new(String::new()), INTERNAL_ERROR.to_string());
+            serialize_message(&response).ok().map(Message::Text)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wasm_peers_protocol::one_to_many::SignalMessage;
+
+    use super::*;
+    use crate::error_budget::NotInSession;
+
+    #[derive(Debug)]
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("unserializable"))
+        }
+    }
+
+    #[test]
+    fn test_failed_serialization_is_answered_with_internal_error() {
+        let result: anyhow::Result<()> = serialize_message(&Unserializable)
+            .map(|_| ())
+            .map_err(Into::into);
+
+        let err = result.as_ref().unwrap_err();
+        assert!(err.to_string().contains("Unserializable"));
+        let response = match internal_error_response(&result, SignalMessage::Error) {
+            Some(Message::Text(response)) => response,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        assert!(matches!(
+            serde_json::from_str(&response).unwrap(),
+            SignalMessage::Error(_, error) if error == INTERNAL_ERROR
+        ));
+        assert!(internal_error_response(&Err(NotInSession.into()), SignalMessage::Error).is_none());
+        assert!(internal_error_response(&Ok(()), SignalMessage::Error).is_none());
+    }
+}
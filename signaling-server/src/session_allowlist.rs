@@ -0,0 +1,60 @@
+/*!
+Restricting which session ids users can create and join.
+
+Turns the server into a fixed-room signaling service, e.g. for a known set of rooms,
+where joining any other session is rejected with a `session not allowed` error.
+Sessions created by matchmaking get random ids and aren't restricted,
+so matchmaking should stay disabled if no other sessions are meant to exist.
+*/
+
+use wasm_peers_protocol::SessionId;
+
+/// Session ids allowed by [`crate::config::ServerConfig::session_allowlist`].
+/// Empty allowlist doesn't allow any session.
+#[derive(Debug, Clone, Default)]
+pub struct SessionAllowlist {
+    /// Session ids allowed exactly as they are.
+    pub ids: Vec<String>,
+    /// Prefixes of allowed session ids, e.g. `lobby-` allows `lobby-1` and `lobby-2`.
+    pub prefixes: Vec<String>,
+}
+
+impl SessionAllowlist {
+    /// Returns `true` if the session id is one of the allowed ids or starts with one of the prefixes.
+    pub fn allows(&self, session_id: &SessionId) -> bool {
+        let session_id = session_id.as_str();
+        self.ids.iter().any(|id| id == session_id)
+            || self
+                .prefixes
+                .iter()
+                .any(|prefix| session_id.starts_with(prefix.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allowlist_matches_exact_ids_and_prefixes() {
+        let allowlist = SessionAllowlist {
+            ids: vec!["main-room".to_string()],
+            prefixes: vec!["lobby-".to_string()],
+        };
+
+        for (session_id, allowed) in [
+            ("main-room", true),
+            ("main-room-2", false),
+            ("lobby-1", true),
+            ("lobby", false),
+            ("other", false),
+        ] {
+            assert_eq!(
+                allowlist.allows(&SessionId::new(session_id.to_string())),
+                allowed,
+                "{}",
+                session_id
+            );
+        }
+    }
+}
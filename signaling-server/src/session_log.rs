@@ -0,0 +1,225 @@
+/*!
+Per-session log of signaling events, exported when the session is torn down.
+
+Enabled by setting [`crate::config::ServerConfig::session_log`], which records what happened in
+each one-to-one session, e.g. when users joined and how many offers and candidates were exchanged,
+for diagnosing connections that didn't get established. A session is torn down, and its log passed to
+the configured [`SessionLogSink`], once its last user leaves or it's closed with
+[`crate::status::ServerState::close_session`].
+
+Logs are kept in memory only until then and hold at most [`SessionLogConfig::max_events`] events,
+dropping the oldest ones. User ids and payloads, i.e. `SDP` and `ICE` candidates
+which contain IP addresses, are left out unless enabled.
+*/
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use log::error;
+use serde::Serialize;
+use wasm_peers_protocol::{SessionId, UserId};
+
+/// Settings of [`crate::config::ServerConfig::session_log`].
+#[derive(Debug, Clone)]
+pub struct SessionLogConfig {
+    /// Receives the log of each torn down session.
+    pub sink: Arc<dyn SessionLogSink>,
+    /// Maximum number of events kept per session, older ones are dropped.
+    pub max_events: usize,
+    /// Record which user caused each event.
+    pub include_user_ids: bool,
+    /// Record `SDP` and `ICE` candidates, not only that they were sent.
+    pub include_payloads: bool,
+}
+
+/// Destination of exported session logs, see [`crate::session_log`].
+pub trait SessionLogSink: fmt::Debug + Send + Sync {
+    /// Stores the log of a torn down session. Called outside of the async runtime's worker threads,
+    /// so it can block, e.g. on file or network IO.
+    fn export(&self, log: &SessionLog);
+}
+
+/// What happened in a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SessionEventKind {
+    Joined,
+    SessionReady,
+    SdpOffer,
+    SdpAnswer,
+    IceCandidate,
+    IceRestart,
+    Left,
+    /// Session was closed by the server operator.
+    Closed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionEvent {
+    /// Milliseconds since the session was created.
+    pub at_ms: u64,
+    pub kind: SessionEventKind,
+    /// Left out unless [`SessionLogConfig::include_user_ids`] is set.
+    pub user_id: Option<UserId>,
+    /// Left out unless [`SessionLogConfig::include_payloads`] is set.
+    pub payload: Option<String>,
+}
+
+/// Events of a single session.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionLog {
+    pub session_id: SessionId,
+    /// Seconds since the Unix epoch when the session was created.
+    pub created_at: u64,
+    pub events: VecDeque<SessionEvent>,
+    /// Number of the oldest events dropped because of [`SessionLogConfig::max_events`].
+    pub dropped_events: usize,
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    config: Option<SessionLogConfig>,
+}
+
+impl SessionLog {
+    pub(crate) fn new(session_id: SessionId, config: SessionLogConfig) -> Self {
+        SessionLog {
+            session_id,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs()),
+            events: VecDeque::new(),
+            dropped_events: 0,
+            started: Instant::now(),
+            config: Some(config),
+        }
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        kind: SessionEventKind,
+        user_id: Option<UserId>,
+        payload: Option<&str>,
+    ) {
+        let config = match &self.config {
+            Some(config) => config,
+            None => return,
+        };
+        if self.events.len() >= config.max_events {
+            self.events.pop_front();
+            self.dropped_events += 1;
+        }
+        self.events.push_back(SessionEvent {
+            at_ms: u64::try_from(self.started.elapsed().as_millis()).unwrap_or(u64::MAX),
+            kind,
+            user_id: user_id.filter(|_| config.include_user_ids),
+            payload: payload
+                .filter(|_| config.include_payloads)
+                .map(str::to_string),
+        });
+    }
+
+    /// Passes the log to the sink on a blocking thread, must be called within a tokio runtime.
+    pub(crate) fn export(mut self) {
+        if let Some(config) = self.config.take() {
+            tokio::task::spawn_blocking(move || config.sink.export(&self));
+        }
+    }
+}
+
+/// Appends each log as a line of `JSON` to a file, until the file reaches `max_bytes`,
+/// after which further logs are dropped, so that retention stays bounded until the file is rotated.
+/// It's a sink for [`crate::lifecycle_log`] records as well.
+#[derive(Debug, Clone)]
+pub struct JsonLinesFile {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+}
+
+impl JsonLinesFile {
+    /// Drops the value, logging an error, if the file is full.
+    pub(crate) fn append(&self, value: &impl Serialize) -> anyhow::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        if file.metadata()?.len() >= self.max_bytes {
+            error!("log file {:?} is full, dropping a record", self.path);
+            return Ok(());
+        }
+        let mut line = serde_json::to_string(value)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl SessionLogSink for JsonLinesFile {
+    fn export(&self, log: &SessionLog) {
+        if let Err(err) = self.append(log) {
+            error!("failed to export session log: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Recorded(Mutex<Vec<SessionLog>>);
+
+    impl SessionLogSink for Recorded {
+        fn export(&self, log: &SessionLog) {
+            self.0.lock().unwrap().push(log.clone());
+        }
+    }
+
+    fn config(sink: Arc<Recorded>) -> SessionLogConfig {
+        SessionLogConfig {
+            sink,
+            max_events: 2,
+            include_user_ids: false,
+            include_payloads: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_log_keeps_latest_events_and_redacts_them() {
+        let sink = Arc::new(Recorded::default());
+        let mut log = SessionLog::new(
+            SessionId::new("dummy-session-id".to_string()),
+            config(sink.clone()),
+        );
+
+        for kind in [
+            SessionEventKind::Joined,
+            SessionEventKind::SdpOffer,
+            SessionEventKind::IceCandidate,
+        ] {
+            log.record(kind, Some(UserId::new(1)), Some("candidate"));
+        }
+        log.export();
+        // export runs on a blocking thread
+        while sink.0.lock().unwrap().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+
+        let exported = sink.0.lock().unwrap();
+        let kinds: Vec<_> = exported[0].events.iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            [SessionEventKind::SdpOffer, SessionEventKind::IceCandidate]
+        );
+        assert_eq!(exported[0].dropped_events, 1);
+        assert!(exported[0]
+            .events
+            .iter()
+            .all(|event| event.user_id.is_none() && event.payload.is_none()));
+    }
+}
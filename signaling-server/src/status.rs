@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+
+use crate::config::Topology;
+use crate::matchmaking::WaitingUsers;
+use crate::negotiation_limit::NegotiationLimit;
+use crate::one_to_one::Connections;
+use crate::region::{self, RegionCounts};
+use crate::tenant::{self, Tenants};
+use crate::{one_to_many, one_to_one};
+
+/// Aggregate counts describing the current load of the server.
+/// Deliberately contains no session or user ids, so it's safe to show publicly.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ServerStats {
+    pub connections: usize,
+    /// Connections in each region, see [`crate::region`].
+    pub connections_by_region: BTreeMap<String, usize>,
+    pub one_to_one_sessions: usize,
+    /// One-to-one sessions negotiating right now, see [`crate::negotiation_limit`].
+    pub negotiating_sessions: usize,
+    /// One-to-one sessions waiting to negotiate, see [`crate::negotiation_limit`].
+    pub sessions_waiting_to_negotiate: usize,
+    pub waiting_users: usize,
+    pub one_to_many_sessions: usize,
+    pub many_to_many_sessions: usize,
+    /// Tenants with open connections, summed over topologies, see [`crate::tenant`].
+    pub tenants: usize,
+}
+
+/// Shared state of the server that the stats are computed from.
+///
+/// It's a handle to the underlying state and can be cloned freely,
+/// see [`crate::admin`] for managing sessions with it.
+#[derive(Clone, Default)]
+pub struct ServerState {
+    pub(crate) connections: Connections,
+    pub(crate) one_to_one_sessions: one_to_one::Sessions,
+    pub(crate) waiting_users: WaitingUsers,
+    pub(crate) one_to_many_sessions: one_to_many::Sessions,
+    pub(crate) many_to_many_sessions: one_to_many::Sessions,
+    pub(crate) region_counts: RegionCounts,
+    pub(crate) tenants: Tenants,
+}
+
+impl ServerState {
+    /// One-to-one sessions and users waiting for a match of every namespace, the default one first.
+    pub(crate) fn one_to_one_namespaces(
+        &self,
+    ) -> Vec<(Option<String>, one_to_one::Sessions, WaitingUsers)> {
+        let tenants = tenant::snapshot(&self.tenants.one_to_one)
+            .into_iter()
+            .map(|(tenant, (sessions, waiting_users))| (Some(tenant), sessions, waiting_users));
+        std::iter::once((
+            None,
+            self.one_to_one_sessions.clone(),
+            self.waiting_users.clone(),
+        ))
+        .chain(tenants)
+        .collect()
+    }
+
+    /// Sessions of every namespace of a one-to-many or many-to-many topology, the default one first.
+    pub(crate) fn one_to_many_namespaces(
+        &self,
+        topology: Topology,
+    ) -> Vec<(Option<String>, one_to_many::Sessions)> {
+        let (default, namespaces) = if topology == Topology::ManyToMany {
+            (&self.many_to_many_sessions, &self.tenants.many_to_many)
+        } else {
+            (&self.one_to_many_sessions, &self.tenants.one_to_many)
+        };
+        let tenants = tenant::snapshot(namespaces)
+            .into_iter()
+            .map(|(tenant, sessions)| (Some(tenant), sessions));
+        std::iter::once((None, default.clone()))
+            .chain(tenants)
+            .collect()
+    }
+
+    pub(crate) async fn stats(&self, negotiation_limit: &NegotiationLimit) -> ServerStats {
+        let (mut one_to_one_sessions, mut waiting_users) = (0, 0);
+        let one_to_one_namespaces = self.one_to_one_namespaces();
+        for (_, sessions, waiting) in &one_to_one_namespaces {
+            one_to_one_sessions += sessions.read().await.len();
+            waiting_users += waiting.read().await.len();
+        }
+        let mut one_to_many_sessions = 0;
+        let one_to_many_namespaces = self.one_to_many_namespaces(Topology::OneToMany);
+        for (_, sessions) in &one_to_many_namespaces {
+            one_to_many_sessions += sessions.read().await.len();
+        }
+        let mut many_to_many_sessions = 0;
+        let many_to_many_namespaces = self.one_to_many_namespaces(Topology::ManyToMany);
+        for (_, sessions) in &many_to_many_namespaces {
+            many_to_many_sessions += sessions.read().await.len();
+        }
+        ServerStats {
+            connections: self.connections.read().await.len(),
+            connections_by_region: region::snapshot(&self.region_counts),
+            one_to_one_sessions,
+            negotiating_sessions: negotiation_limit.negotiating(),
+            sessions_waiting_to_negotiate: negotiation_limit.waiting(),
+            waiting_users,
+            one_to_many_sessions,
+            many_to_many_sessions,
+            // each list starts with the default namespace
+            tenants: one_to_one_namespaces.len()
+                + one_to_many_namespaces.len()
+                + many_to_many_namespaces.len()
+                - 3,
+        }
+    }
+}
+
+pub(crate) fn render_status_page(stats: &ServerStats) -> String {
+    // region tags are validated to be safe to show without escaping
+    let region_rows: String = stats
+        .connections_by_region
+        .iter()
+        .map(|(region, count)| {
+            format!(
+                "<tr><td>connections in region {}</td><td>{}</td></tr>",
+                region, count
+            )
+        })
+        .collect();
+    format!(
+        "<!DOCTYPE html>\
+        <html>\
+        <head><title>wasm-peers signaling server</title></head>\
+        <body>\
+        <h1>wasm-peers signaling server</h1>\
+        <table>\
+        <tr><td>connections</td><td>{}</td></tr>\
+        {}\
+        <tr><td>one-to-one sessions</td><td>{}</td></tr>\
+        <tr><td>one-to-one sessions negotiating</td><td>{}</td></tr>\
+        <tr><td>one-to-one sessions waiting to negotiate</td><td>{}</td></tr>\
+        <tr><td>users waiting for a match</td><td>{}</td></tr>\
+        <tr><td>one-to-many sessions</td><td>{}</td></tr>\
+        <tr><td>many-to-many sessions</td><td>{}</td></tr>\
+        <tr><td>tenants</td><td>{}</td></tr>\
+        </table>\
+        </body>\
+        </html>",
+        stats.connections,
+        region_rows,
+        stats.one_to_one_sessions,
+        stats.negotiating_sessions,
+        stats.sessions_waiting_to_negotiate,
+        stats.waiting_users,
+        stats.one_to_many_sessions,
+        stats.many_to_many_sessions,
+        stats.tenants,
+    )
+}
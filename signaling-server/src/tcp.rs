@@ -0,0 +1,184 @@
+/*!
+Raw TCP transport for one-to-one signaling, for native peers that don't use websockets.
+
+# Framing
+
+Each signaling message is sent as a frame consisting of:
+* length of the payload in bytes, as a 4-byte big-endian unsigned integer,
+* payload: `SignalMessage` serialized to JSON and encoded in UTF-8.
+
+Frames longer than [`MAX_FRAME_LENGTH`] close the connection.
+There are no pings, connection is considered alive until the TCP connection closes.
+Peers connected this way share sessions with the ones connected over websockets.
+*/
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use axum::extract::ws::Message;
+use futures_util::{sink, stream};
+use log::{error, info};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpListener;
+
+use crate::config::ServerConfig;
+use crate::matchmaking::WaitingUsers;
+use crate::one_to_one::{serve_user, ConnectionOptions, Connections, Sessions};
+use crate::region::{self, RegionCounts};
+
+/// Maximum length in bytes of a single frame's payload.
+pub const MAX_FRAME_LENGTH: usize = 1024 * 1024;
+
+/// How long accepting waits after failing to accept a connection, e.g. when running out of file descriptors.
+pub(crate) const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accepts one-to-one signaling connections on the listener.
+/// Failing to accept a connection is logged and accepting carries on after [`ACCEPT_ERROR_BACKOFF`].
+pub async fn serve(
+    listener: TcpListener,
+    connections: Connections,
+    sessions: Sessions,
+    waiting_users: WaitingUsers,
+    config: Arc<ServerConfig>,
+    region_counts: RegionCounts,
+) -> anyhow::Result<()> {
+    loop {
+        let (socket, address) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                error!("failed to accept TCP connection: {}", err);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        info!("new TCP connection from {}", address);
+        let (reader, writer) = socket.into_split();
+        let incoming = Box::pin(stream::unfold(reader, |mut reader| async move {
+            match read_frame(&mut reader).await {
+                Ok(Some(frame)) => Some((Ok(Message::Text(frame)), reader)),
+                Ok(None) => None,
+                Err(err) => Some((Err(err), reader)),
+            }
+        }));
+        let outgoing = Box::pin(sink::unfold(
+            writer,
+            |mut writer: OwnedWriteHalf, message: Message| async move {
+                match message {
+                    Message::Text(frame) => write_frame(&mut writer, &frame).await?,
+                    Message::Close(_) => writer.shutdown().await?,
+                    _ => {}
+                }
+                Ok::<_, anyhow::Error>(writer)
+            },
+        ));
+        let tracked = region::track(&region_counts, config.region.as_deref());
+        let user = serve_user(
+            outgoing,
+            incoming,
+            connections.clone(),
+            sessions.clone(),
+            waiting_users.clone(),
+            config.clone(),
+            ConnectionOptions {
+                region: config.region.clone(),
+                heartbeat: false,
+                remote_ip: Some(address.ip()),
+            },
+        );
+        tokio::task::spawn(async move {
+            user.await;
+            drop(tracked);
+        });
+    }
+}
+
+/// Returns `None` once the connection is closed between frames.
+async fn read_frame(reader: &mut OwnedReadHalf) -> anyhow::Result<Option<String>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_LENGTH {
+        error!("TCP frame too long: {} bytes", length);
+        return Err(anyhow!(
+            "frame too long: {} bytes, maximum is {}",
+            length,
+            MAX_FRAME_LENGTH
+        ));
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(String::from_utf8(payload)?))
+}
+
+async fn write_frame(writer: &mut OwnedWriteHalf, payload: &str) -> anyhow::Result<()> {
+    let length = u32::try_from(payload.len())?;
+    writer.write_all(&length.to_be_bytes()).await?;
+    writer.write_all(payload.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::TcpStream;
+    use wasm_peers_protocol::one_to_one::SignalMessage;
+    use wasm_peers_protocol::SessionId;
+
+    use super::*;
+
+    async fn send(stream: &mut TcpStream, message: &SignalMessage) {
+        let payload = serde_json::to_string(message).unwrap();
+        stream
+            .write_all(&(payload.len() as u32).to_be_bytes())
+            .await
+            .unwrap();
+        stream.write_all(payload.as_bytes()).await.unwrap();
+    }
+
+    async fn receive(stream: &mut TcpStream) -> SignalMessage {
+        let mut length = [0; 4];
+        stream.read_exact(&mut length).await.unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(length) as usize];
+        stream.read_exact(&mut payload).await.unwrap();
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tcp_peers_get_session_ready() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let sessions = Sessions::default();
+        tokio::task::spawn(serve(
+            listener,
+            Connections::default(),
+            sessions.clone(),
+            WaitingUsers::default(),
+            Arc::new(ServerConfig::default()),
+            RegionCounts::default(),
+        ));
+        let session_id = SessionId::new("dummy-session-id".to_string());
+
+        let mut first = TcpStream::connect(address).await.unwrap();
+        send(&mut first, &SignalMessage::SessionJoin(session_id.clone())).await;
+        // make sure first peer joins first
+        while !sessions.read().await.contains_key(&session_id) {
+            tokio::task::yield_now().await;
+        }
+        let mut second = TcpStream::connect(address).await.unwrap();
+        send(&mut second, &SignalMessage::SessionJoin(session_id)).await;
+
+        assert!(matches!(
+            receive(&mut first).await,
+            SignalMessage::SessionReady(_, true)
+        ));
+        assert!(matches!(
+            receive(&mut second).await,
+            SignalMessage::SessionReady(_, false)
+        ));
+    }
+}
@@ -0,0 +1,213 @@
+/*!
+Namespacing of session ids by tenant, so that tenants sharing one server instance
+can use the same session ids without ending up in each other's sessions.
+
+Tenant is taken from a query parameter of the websocket URL, see [`ServerConfig::tenant_parameter`].
+Each tenant gets its own sessions, so the effective session key is `(tenant, session_id)`,
+while users connecting without a tenant share the default namespace, the same as with namespacing disabled.
+Raw TCP connections always use the default namespace.
+
+Clients can send any tenant, so namespaces only live while they have connections: the namespace
+is dropped when its last connection closes, and connections of new tenants are rejected
+once [`ServerConfig::max_tenants`] namespaces of the topology are live.
+The status page counts sessions of every namespace, and [`crate::admin`] manages them by tenant.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::config::ServerConfig;
+use crate::matchmaking::WaitingUsers;
+use crate::{one_to_many, one_to_one};
+
+/// State of each tenant with open connections.
+pub(crate) type Namespaces<T> = Arc<Mutex<HashMap<String, Namespace<T>>>>;
+
+pub(crate) struct Namespace<T> {
+    state: T,
+    connections: usize,
+}
+
+/// Namespaces of each topology, shared with [`crate::status::ServerState`].
+#[derive(Clone, Default)]
+pub(crate) struct Tenants {
+    pub(crate) one_to_one: Namespaces<(one_to_one::Sessions, WaitingUsers)>,
+    pub(crate) one_to_many: Namespaces<one_to_many::Sessions>,
+    pub(crate) many_to_many: Namespaces<one_to_many::Sessions>,
+}
+
+/// Counts a connection in the tenant's namespace, dropping the namespace with its last connection.
+pub(crate) struct TenantGuard<T> {
+    namespaces: Namespaces<T>,
+    tenant: String,
+}
+
+impl<T> Drop for TenantGuard<T> {
+    fn drop(&mut self) {
+        let mut namespaces = self
+            .namespaces
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(namespace) = namespaces.get_mut(&self.tenant) {
+            namespace.connections -= 1;
+            if namespace.connections == 0 {
+                namespaces.remove(&self.tenant);
+            }
+        }
+    }
+}
+
+/// Returned by [`namespace`] when the connection would create a namespace over [`ServerConfig::max_tenants`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TooManyTenants;
+
+/// Tenant the user connects as, `None` if namespacing is disabled or the parameter is missing or empty.
+pub(crate) fn tenant(config: &ServerConfig, query: &HashMap<String, String>) -> Option<String> {
+    let parameter = config.tenant_parameter.as_ref()?;
+    query
+        .get(parameter)
+        .filter(|tenant| !tenant.is_empty())
+        .cloned()
+}
+
+/// State of the tenant's namespace, `default` one if there is no tenant.
+/// Namespace is kept at least as long as the returned guard.
+pub(crate) fn namespace<T: Clone + Default>(
+    namespaces: &Namespaces<T>,
+    tenant: Option<String>,
+    default: &T,
+    max_tenants: usize,
+) -> Result<(T, Option<TenantGuard<T>>), TooManyTenants> {
+    let tenant = match tenant {
+        Some(tenant) => tenant,
+        None => return Ok((default.clone(), None)),
+    };
+    let mut locked = namespaces
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if !locked.contains_key(&tenant) && locked.len() >= max_tenants {
+        return Err(TooManyTenants);
+    }
+    let namespace = locked.entry(tenant.clone()).or_insert_with(|| Namespace {
+        state: T::default(),
+        connections: 0,
+    });
+    namespace.connections += 1;
+    let state = namespace.state.clone();
+    drop(locked);
+    Ok((
+        state,
+        Some(TenantGuard {
+            namespaces: namespaces.clone(),
+            tenant,
+        }),
+    ))
+}
+
+/// State of every live namespace, for reading it without holding the lock.
+pub(crate) fn snapshot<T: Clone>(namespaces: &Namespaces<T>) -> Vec<(String, T)> {
+    namespaces
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .iter()
+        .map(|(tenant, namespace)| (tenant.clone(), namespace.state.clone()))
+        .collect()
+}
+
+/// State of the tenant's namespace if it's live, `default` one if there is no tenant.
+pub(crate) fn existing<T: Clone>(
+    namespaces: &Namespaces<T>,
+    tenant: Option<&str>,
+    default: &T,
+) -> Option<T> {
+    match tenant {
+        Some(tenant) => namespaces
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(tenant)
+            .map(|namespace| namespace.state.clone()),
+        None => Some(default.clone()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::RwLock;
+
+    use super::*;
+
+    type TestNamespaces = Namespaces<Arc<RwLock<Vec<&'static str>>>>;
+
+    fn query(parameter: &str, value: &str) -> HashMap<String, String> {
+        HashMap::from([(parameter.to_string(), value.to_string())])
+    }
+
+    fn config() -> ServerConfig {
+        ServerConfig {
+            tenant_parameter: Some("tenant".to_string()),
+            ..ServerConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_tenant_is_read_from_configured_parameter() {
+        assert_eq!(
+            tenant(&config(), &query("tenant", "a")),
+            Some("a".to_string())
+        );
+        assert_eq!(tenant(&config(), &query("other", "a")), None);
+        assert_eq!(tenant(&config(), &query("tenant", "")), None);
+        assert_eq!(
+            tenant(&ServerConfig::default(), &query("tenant", "a")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tenants_get_separate_namespaces() {
+        let namespaces = TestNamespaces::default();
+        let default = Arc::new(RwLock::new(Vec::new()));
+
+        let (first, _first_guard) =
+            namespace(&namespaces, Some("a".to_string()), &default, 10).unwrap();
+        first.write().await.push("room1");
+
+        let (same_tenant, _same_guard) =
+            namespace(&namespaces, Some("a".to_string()), &default, 10).unwrap();
+        let (other_tenant, _other_guard) =
+            namespace(&namespaces, Some("b".to_string()), &default, 10).unwrap();
+        assert_eq!(*same_tenant.read().await, vec!["room1"]);
+        assert!(other_tenant.read().await.is_empty());
+        let (default_namespace, default_guard) =
+            namespace(&namespaces, None, &default, 10).unwrap();
+        assert!(default_namespace.read().await.is_empty());
+        assert!(default_guard.is_none());
+    }
+
+    #[test]
+    fn test_namespace_is_dropped_with_its_last_connection() {
+        let namespaces = TestNamespaces::default();
+        let default = Arc::default();
+
+        let (_, first) = namespace(&namespaces, Some("a".to_string()), &default, 10).unwrap();
+        let (_, second) = namespace(&namespaces, Some("a".to_string()), &default, 10).unwrap();
+        drop(first);
+        assert_eq!(snapshot(&namespaces).len(), 1);
+        drop(second);
+
+        assert!(snapshot(&namespaces).is_empty());
+        assert!(existing(&namespaces, Some("a"), &default).is_none());
+    }
+
+    #[test]
+    fn test_new_tenants_are_rejected_over_limit() {
+        let namespaces = TestNamespaces::default();
+        let default = Arc::default();
+
+        let (_, _a) = namespace(&namespaces, Some("a".to_string()), &default, 1).unwrap();
+
+        assert!(namespace(&namespaces, Some("b".to_string()), &default, 1).is_err());
+        assert!(namespace(&namespaces, Some("a".to_string()), &default, 1).is_ok());
+        assert!(namespace(&namespaces, None, &default, 1).is_ok());
+    }
+}
@@ -0,0 +1,53 @@
+/*!
+Fixtures shared by the tests of the topologies, which connect users directly to the shared
+connections instead of going through a websocket.
+*/
+
+use axum::extract::ws::Message;
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc;
+use wasm_peers_protocol::UserId;
+
+use crate::one_to_one::Connections;
+
+/// Adds a connection of the user, returning the receiver of the messages sent to it.
+pub(crate) async fn connect(
+    connections: &Connections,
+    user_id: UserId,
+) -> mpsc::UnboundedReceiver<Message> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    connections.write().await.insert(user_id, tx);
+    rx
+}
+
+/// Next message sent to the user, if there already is one.
+fn received<T: DeserializeOwned>(rx: &mut mpsc::UnboundedReceiver<Message>) -> Option<T> {
+    match rx.try_recv() {
+        Ok(Message::Text(message)) => Some(serde_json::from_str(&message).unwrap()),
+        _ => None,
+    }
+}
+
+pub(crate) mod one_to_one {
+    use axum::extract::ws::Message;
+    use tokio::sync::mpsc;
+    use wasm_peers_protocol::one_to_one::SignalMessage;
+
+    pub(crate) fn received_message(
+        rx: &mut mpsc::UnboundedReceiver<Message>,
+    ) -> Option<SignalMessage> {
+        super::received(rx)
+    }
+}
+
+pub(crate) mod one_to_many {
+    use axum::extract::ws::Message;
+    use tokio::sync::mpsc;
+    use wasm_peers_protocol::one_to_many::SignalMessage;
+
+    pub(crate) fn received_message(
+        rx: &mut mpsc::UnboundedReceiver<Message>,
+    ) -> Option<SignalMessage> {
+        super::received(rx)
+    }
+}
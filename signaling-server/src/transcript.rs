@@ -0,0 +1,206 @@
+/*!
+Full signaling transcript of a flagged one-to-one session, for debugging one problematic session.
+
+Unlike [`crate::session_log`], the transcript holds every message users send for the session
+in the order the server handled them, including `SDP` and `ICE` candidates, which contain IP addresses.
+So it's only captured for sessions an operator flags on purpose, and only kept for
+[`ServerConfig::transcript_ttl`] after flagging, after which capturing stops and the transcript is dropped.
+
+Enabling [`ServerConfig::admin_token`] serves `POST /transcript/<session id>`, which flags the session,
+starting a fresh transcript, and `GET /transcript/<session id>`, which returns the transcript as `JSON`.
+Both take the token in an `Authorization: Bearer <token>` header, same as [`crate::broadcast`].
+
+```sh
+curl -X POST -H "Authorization: Bearer $ADMIN_TOKEN" http://localhost:9001/transcript/some-session-id
+curl -H "Authorization: Bearer $ADMIN_TOKEN" http://localhost:9001/transcript/some-session-id
+```
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::http::{HeaderMap, StatusCode};
+use log::info;
+use serde::Serialize;
+use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::{SessionId, UserId};
+
+use crate::broadcast;
+use crate::config::ServerConfig;
+
+/// Maximum number of messages kept per transcript, later ones are counted but dropped.
+pub const MAX_TRANSCRIPT_MESSAGES: usize = 10_000;
+
+/// Transcripts of flagged sessions, shared by all clones. No session is flagged by default.
+#[derive(Debug, Clone, Default)]
+pub struct Transcripts(Arc<Mutex<HashMap<SessionId, Transcript>>>);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Transcript {
+    pub session_id: SessionId,
+    pub messages: Vec<TranscriptMessage>,
+    /// Number of messages after the first [`MAX_TRANSCRIPT_MESSAGES`].
+    pub dropped_messages: usize,
+    #[serde(skip)]
+    flagged_at: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptMessage {
+    /// Milliseconds since the session was flagged.
+    pub at_ms: u64,
+    pub user_id: UserId,
+    pub message: serde_json::Value,
+}
+
+impl Transcripts {
+    /// Starts capturing a fresh transcript of the session, replacing the previous one.
+    pub fn flag(&self, session_id: SessionId) {
+        info!("capturing signaling transcript of session {:?}", session_id);
+        self.lock().insert(
+            session_id.clone(),
+            Transcript {
+                session_id,
+                messages: Vec::new(),
+                dropped_messages: 0,
+                flagged_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Returns `None` if the session isn't flagged or its transcript expired.
+    pub fn get(&self, ttl: Duration, session_id: &SessionId) -> Option<Transcript> {
+        let mut transcripts = self.lock();
+        expire(&mut transcripts, ttl);
+        transcripts.get(session_id).cloned()
+    }
+
+    /// Adds the message to the transcript of its session, if the session is flagged.
+    pub(crate) fn capture(&self, ttl: Duration, user_id: UserId, message: &SignalMessage) {
+        let session_id = match message_session_id(message) {
+            Some(session_id) => session_id,
+            None => return,
+        };
+        let mut transcripts = self.lock();
+        if transcripts.is_empty() {
+            return;
+        }
+        expire(&mut transcripts, ttl);
+        let transcript = match transcripts.get_mut(session_id) {
+            Some(transcript) => transcript,
+            None => return,
+        };
+        if transcript.messages.len() >= MAX_TRANSCRIPT_MESSAGES {
+            transcript.dropped_messages += 1;
+            return;
+        }
+        transcript.messages.push(TranscriptMessage {
+            at_ms: u64::try_from(transcript.flagged_at.elapsed().as_millis()).unwrap_or(u64::MAX),
+            user_id,
+            message: serde_json::to_value(message).unwrap_or_default(),
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SessionId, Transcript>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn expire(transcripts: &mut HashMap<SessionId, Transcript>, ttl: Duration) {
+    transcripts.retain(|_, transcript| transcript.flagged_at.elapsed() < ttl);
+}
+
+fn message_session_id(message: &SignalMessage) -> Option<&SessionId> {
+    match message {
+        SignalMessage::SessionJoin(session_id)
+        | SignalMessage::SessionReady(session_id, _)
+        | SignalMessage::SessionLeave(session_id)
+        | SignalMessage::PeerLeft(session_id)
+        | SignalMessage::Matched(session_id)
+        | SignalMessage::SdpOffer(session_id, _)
+        | SignalMessage::SdpAnswer(session_id, _)
+        | SignalMessage::IceCandidate(session_id, _)
+        | SignalMessage::PeerMetadata(session_id, _)
+        | SignalMessage::IceRestart(session_id)
+        | SignalMessage::NegotiationTimeout(session_id)
+        | SignalMessage::SameNetworkHint(session_id)
+        | SignalMessage::Error(session_id, _) => Some(session_id),
+        SignalMessage::FindMatch(_)
+        | SignalMessage::ServerNotice(_)
+        | SignalMessage::Migrate(_) => None,
+    }
+}
+
+/// Flags the session, responding with how long its transcript is kept.
+pub(crate) fn flag(
+    config: &ServerConfig,
+    headers: &HeaderMap,
+    session_id: String,
+) -> (StatusCode, String) {
+    if !broadcast::is_authorized(config, headers) {
+        return (StatusCode::UNAUTHORIZED, "invalid admin token".to_string());
+    }
+    config.transcripts.flag(SessionId::new(session_id));
+    (
+        StatusCode::OK,
+        format!("capturing for {} seconds", config.transcript_ttl.as_secs()),
+    )
+}
+
+/// Responds with the transcript as `JSON`.
+pub(crate) fn fetch(
+    config: &ServerConfig,
+    headers: &HeaderMap,
+    session_id: String,
+) -> (StatusCode, String) {
+    if !broadcast::is_authorized(config, headers) {
+        return (StatusCode::UNAUTHORIZED, "invalid admin token".to_string());
+    }
+    let transcript = config
+        .transcripts
+        .get(config.transcript_ttl, &SessionId::new(session_id));
+    match transcript.map(|transcript| serde_json::to_string(&transcript)) {
+        Some(Ok(transcript)) => (StatusCode::OK, transcript),
+        Some(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        None => (
+            StatusCode::NOT_FOUND,
+            "session isn't flagged or its transcript expired".to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session_id() -> SessionId {
+        SessionId::new("dummy-session-id".to_string())
+    }
+
+    #[test]
+    fn test_only_flagged_sessions_are_captured_until_they_expire() {
+        let transcripts = Transcripts::default();
+        let ttl = Duration::from_millis(50);
+        let offer = SignalMessage::SdpOffer(session_id(), "offer".to_string());
+        let other = SignalMessage::SessionJoin(SessionId::new("other".to_string()));
+
+        transcripts.capture(ttl, UserId::new(1), &offer);
+        transcripts.flag(session_id());
+        transcripts.capture(ttl, UserId::new(1), &offer);
+        transcripts.capture(ttl, UserId::new(2), &other);
+
+        let transcript = transcripts.get(ttl, &session_id()).unwrap();
+        assert_eq!(transcript.messages.len(), 1);
+        assert_eq!(transcript.messages[0].user_id, UserId::new(1));
+        assert!(transcript.messages[0].message.to_string().contains("offer"));
+        assert!(transcripts
+            .get(ttl, &SessionId::new("other".to_string()))
+            .is_none());
+
+        std::thread::sleep(ttl);
+        assert!(transcripts.get(ttl, &session_id()).is_none());
+    }
+}
@@ -0,0 +1,130 @@
+/*!
+Serving the signaling server over a Unix domain socket instead of TCP,
+for deployments where it sits behind a reverse proxy on the same host.
+It avoids TCP overhead and picking a free port.
+
+Connections accepted this way have no peer address,
+so the proxy is responsible for anything based on the client's IP address.
+*/
+
+use std::future::Future;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use anyhow::anyhow;
+use axum::Router;
+use hyper::server::accept::Accept;
+use log::{error, info};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::time::Sleep;
+
+use crate::tcp::ACCEPT_ERROR_BACKOFF;
+
+struct UnixAccept {
+    listener: UnixListener,
+    /// Set after failing to accept a connection, accepting carries on once it elapses.
+    backoff: Option<Pin<Box<Sleep>>>,
+}
+
+impl Accept for UnixAccept {
+    type Conn = UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        loop {
+            if let Some(backoff) = self.backoff.as_mut() {
+                if backoff.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.backoff = None;
+            }
+            match self.listener.poll_accept(cx) {
+                Poll::Ready(Ok((stream, _address))) => return Poll::Ready(Some(Ok(stream))),
+                Poll::Ready(Err(err)) => {
+                    error!("failed to accept unix socket connection: {}", err);
+                    self.backoff = Some(Box::pin(tokio::time::sleep(ACCEPT_ERROR_BACKOFF)));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Serves the router on a Unix domain socket at given path until it fails.
+/// Socket left over from a previous run is removed first, anything else at the path is an error.
+/// Failing to accept a connection is logged and accepting carries on after [`ACCEPT_ERROR_BACKOFF`].
+pub async fn serve(router: Router, path: &Path) -> anyhow::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(anyhow!(
+                "{} exists and isn't a socket, refusing to remove it",
+                path.display()
+            ))
+        }
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+        Err(_) => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("listening on unix:{}", path.display());
+    axum::Server::builder(UnixAccept {
+        listener,
+        backoff: None,
+    })
+    .serve(router.into_make_service())
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::router::create_router_with_config;
+
+    #[tokio::test]
+    async fn test_status_page_is_served_over_unix_socket() {
+        let path = std::env::temp_dir().join(format!("wasm-peers-{}.sock", std::process::id()));
+        let router = create_router_with_config(ServerConfig {
+            status_page: true,
+            ..ServerConfig::default()
+        });
+        let server_path = path.clone();
+        tokio::task::spawn(async move { serve(router, &server_path).await });
+
+        let mut stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_file_that_isnt_a_socket_is_kept() {
+        let path = std::env::temp_dir().join(format!("wasm-peers-{}.txt", std::process::id()));
+        std::fs::write(&path, "data").unwrap();
+
+        assert!(serve(Router::new(), &path).await.is_err());
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+        let _ = std::fs::remove_file(&path);
+    }
+}